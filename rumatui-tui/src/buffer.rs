@@ -8,6 +8,33 @@ use unicode_width::UnicodeWidthStr;
 use crate::layout::Rect;
 use crate::style::{Color, Modifier, Style};
 
+/// The number of columns a grapheme takes in the terminal.
+///
+/// `unicode-width` adds up the width of each code point, an emoji sequence joined with
+/// zero width joiners, a flag or an emoji with a presentation selector is drawn as one
+/// double width symbol.
+pub fn grapheme_width(symbol: &str) -> usize {
+    let mut chars = symbol.chars();
+    if chars.next().is_none() || chars.next().is_none() {
+        return symbol.width();
+    }
+    let emoji = symbol
+        .chars()
+        .any(|c| c == '\u{200d}' || c == '\u{fe0f}' || ('\u{1f1e6}'..='\u{1f1ff}').contains(&c));
+    if emoji {
+        2
+    } else {
+        symbol.width()
+    }
+}
+
+/// The number of columns `string` takes in the terminal.
+pub fn str_width(string: &str) -> usize {
+    UnicodeSegmentation::graphemes(string, true)
+        .map(grapheme_width)
+        .sum()
+}
+
 /// A buffer cell
 #[derive(Debug, Clone, PartialEq)]
 pub struct Cell {
@@ -124,7 +151,7 @@ impl fmt::Debug for Buffer {
                 } else {
                     overwritten.push((x, &c.symbol))
                 }
-                skip = std::cmp::max(skip, c.symbol.width()).saturating_sub(1);
+                skip = std::cmp::max(skip, grapheme_width(&c.symbol)).saturating_sub(1);
             }
             f.write_fmt(format_args!("{:?},", line))?;
             if !overwritten.is_empty() {
@@ -318,7 +345,7 @@ impl Buffer {
         let graphemes = UnicodeSegmentation::graphemes(string.as_ref(), true);
         let max_offset = min(self.area.right() as usize, width.saturating_add(x as usize));
         for s in graphemes {
-            let width = s.width();
+            let width = grapheme_width(s);
             // `x_offset + width > max_offset` could be integer overflow on 32-bit machines if we
             // change dimenstions to usize or u32 and someone resizes the terminal to 1x2^32.
             if width > max_offset.saturating_sub(x_offset) {
@@ -517,6 +544,21 @@ mod tests {
         assert_eq!(buffer, Buffer::with_lines(vec!["12345"]));
     }
 
+    #[test]
+    fn grapheme_width_counts_emoji_sequences_once() {
+        assert_eq!(1, grapheme_width("a"));
+        assert_eq!(2, grapheme_width("文"));
+        // woman, zero width joiner, laptop
+        assert_eq!(2, grapheme_width("👩\u{200d}💻"));
+        // a family of three joined by zero width joiners
+        assert_eq!(2, grapheme_width("👨\u{200d}👩\u{200d}👧"));
+        assert_eq!(2, grapheme_width("🇯🇵"));
+        assert_eq!(2, grapheme_width("❤\u{fe0f}"));
+        // a combining accent does not take a column
+        assert_eq!(1, grapheme_width("e\u{301}"));
+        assert_eq!(8, str_width("a文🇯🇵👨\u{200d}👩\u{200d}👧é"));
+    }
+
     #[test]
     fn buffer_set_string_double_width() {
         let area = Rect::new(0, 0, 5, 1);
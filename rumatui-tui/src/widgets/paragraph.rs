@@ -3,9 +3,8 @@ use std::rc::Rc;
 
 use either::Either;
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::UnicodeWidthStr;
 
-use crate::buffer::Buffer;
+use crate::buffer::{grapheme_width, Buffer};
 use crate::layout::{Alignment, Rect, ScrollMode};
 use crate::style::Style;
 use crate::widgets::reflow::{LineComposer, LineTruncator, Styled, WordWrapper};
@@ -205,7 +204,7 @@ where
                         buf.get_mut(text_area.left() + x, text_area.top() + y)
                             .set_symbol(symbol)
                             .set_style(style);
-                        x += symbol.width() as u16;
+                        x += grapheme_width(symbol) as u16;
                     }
                 }
                 Some(ScrolledLine::Overflow) => {
@@ -1,5 +1,5 @@
+use crate::buffer::grapheme_width;
 use crate::style::Style;
-use unicode_width::UnicodeWidthStr;
 
 const NBSP: &str = "\u{00a0}";
 
@@ -55,7 +55,7 @@ impl<'a, 'b> LineComposer<'a> for WordWrapper<'a, 'b> {
         let mut current_line_width = self
             .current_line
             .iter()
-            .map(|Styled(c, _)| c.width() as u16)
+            .map(|Styled(c, _)| grapheme_width(c) as u16)
             .sum();
 
         let mut symbols_to_last_word_end: usize = 0;
@@ -67,7 +67,7 @@ impl<'a, 'b> LineComposer<'a> for WordWrapper<'a, 'b> {
             let symbol_whitespace = symbol.chars().all(&char::is_whitespace);
 
             // Ignore characters wider that the total max width.
-            if symbol.width() as u16 > self.max_line_width
+            if grapheme_width(symbol) as u16 > self.max_line_width
                 // Skip leading whitespace.
                 || symbol_whitespace && symbol != "\n" && current_line_width == 0
             {
@@ -90,7 +90,7 @@ impl<'a, 'b> LineComposer<'a> for WordWrapper<'a, 'b> {
             }
 
             self.current_line.push(Styled(symbol, style));
-            current_line_width += symbol.width() as u16;
+            current_line_width += grapheme_width(symbol) as u16;
 
             if current_line_width > self.max_line_width {
                 // If there was no word break in the text, wrap at the end of the line.
@@ -163,7 +163,7 @@ impl<'a, 'b> LineComposer<'a> for LineTruncator<'a, 'b> {
             symbols_exhausted = false;
 
             // Ignore characters wider that the total max width.
-            if symbol.width() as u16 > self.max_line_width {
+            if grapheme_width(symbol) as u16 > self.max_line_width {
                 continue;
             }
 
@@ -172,13 +172,13 @@ impl<'a, 'b> LineComposer<'a> for LineTruncator<'a, 'b> {
                 break;
             }
 
-            if current_line_width + symbol.width() as u16 > self.max_line_width {
+            if current_line_width + grapheme_width(symbol) as u16 > self.max_line_width {
                 // Exhaust the remainder of the line.
                 skip_rest = true;
                 break;
             }
 
-            current_line_width += symbol.width() as u16;
+            current_line_width += grapheme_width(symbol) as u16;
             self.current_line.push(Styled(symbol, style));
         }
 
@@ -377,6 +377,23 @@ mod test {
         assert_eq!(word_wrapper_width, vec![width, width, width, width, 4]);
     }
 
+    #[test]
+    fn line_composer_emoji_sequences() {
+        let width = 4;
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let text = format!("{}{}🇯🇵", family, family);
+        let (word_wrapper, word_wrapper_width) = run_composer(Composer::WordWrapper, &text, width);
+        let (line_truncator, line_truncator_width) =
+            run_composer(Composer::LineTruncator, &text, width);
+        assert_eq!(
+            word_wrapper,
+            vec![format!("{}{}", family, family), "🇯🇵".into()]
+        );
+        assert_eq!(word_wrapper_width, vec![4, 2]);
+        assert_eq!(line_truncator, vec![format!("{}{}", family, family)]);
+        assert_eq!(line_truncator_width, vec![4]);
+    }
+
     #[test]
     fn line_composer_leading_whitespace_removal() {
         let width = 20;
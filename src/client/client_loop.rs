@@ -1,25 +1,28 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use matrix_sdk::{
-    api::r0::{
-        account::register,
-        directory::get_public_rooms_filtered::{self, RoomNetwork},
-        membership::{join_room_by_id, leave_room},
-        message::{create_message_event, get_message_events},
-        // receipt::create_receipt,
-        read_marker::set_read_marker,
-        session::login,
-        typing::create_typing_event,
+    api::{
+        r0::{
+            account::register,
+            directory::get_public_rooms_filtered::{self, RoomNetwork},
+            membership::{join_room_by_id, leave_room},
+            message::{create_message_event, get_message_events},
+            // receipt::create_receipt,
+            read_marker::set_read_marker,
+            typing::create_typing_event,
+        },
+        unversioned::get_supported_versions,
     },
     events::room::message::MessageEventContent,
-    identifiers::{EventId, RoomId, UserId},
+    identifiers::{EventId, RoomId, RoomIdOrAliasId, UserId},
     Room,
 };
 use tokio::{
@@ -33,7 +36,13 @@ use tokio::{
 use uuid::Uuid;
 
 use crate::{
-    client::{event_stream::EventStream, MatrixClient},
+    client::{
+        event_stream::EventStream,
+        record_gaps,
+        ruma_ext::{auth::stage::AuthData, knock},
+        summarize_sync, DebugInfo, MatrixClient, PinnedEvent, RoomTags,
+    },
+    config::UnencryptedFallback,
     error::{Error, Result},
 };
 
@@ -43,46 +52,165 @@ use crate::{
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum UserRequest {
+    CheckServer,
+    DebugInfo,
     Login(String, String),
-    Register(String, String),
+    /// Log in with the session saved by the last login of the client's account.
+    RestoreSession,
+    /// The user name, password and the optional email for the validation stage.
+    Register(String, String, Option<String>),
     SendMessage(RoomId, MessageEventContent, Uuid),
+    /// Upload the image file and send it as an `m.image` message, the result is a
+    /// `RequestResult::SendMessage`.
+    SendImage(RoomId, PathBuf, Uuid),
+    /// Send the message in the thread started by the event.
+    SendThreadReply(RoomId, EventId, String),
     RoomMsgs(RoomId),
+    /// A request the server rate limited, sent back to the request loop once the
+    /// delay is over and not retried again.
+    Retry(Box<UserRequest>),
+    ResetBackfill(RoomId),
+    FillGap(RoomId),
     AcceptInvite(RoomId),
     DeclineInvite(RoomId),
     JoinRoom(RoomId),
+    JoinRoomByIdOrAlias(RoomIdOrAliasId),
+    KnockRoom(RoomIdOrAliasId, Option<String>),
     LeaveRoom(RoomId),
+    /// Add the tag to the room.
+    SetRoomTag(RoomId, String),
+    RemoveRoomTag(RoomId, String),
+    /// Vote for the answer id in the poll started by the event.
+    PollVote(RoomId, EventId, String),
+    /// React to the event with the key.
+    SendReaction(RoomId, EventId, String),
+    /// Redact the event giving the optional reason.
+    Redact(RoomId, EventId, Option<String>),
+    /// Replace the text of the message.
+    EditMessage(RoomId, EventId, String),
+    /// Kick the user out of the room giving the optional reason.
+    Kick(RoomId, UserId, Option<String>),
+    PinnedEvents(RoomId),
+    /// Count the devices of the room's members that are not verified.
+    UnverifiedDevices(RoomId),
+    /// Pin the event when true, unpin it when false.
+    PinEvent(RoomId, EventId, bool),
     Typing(RoomId, UserId),
     ReadReceipt(RoomId, EventId),
-    RoomSearch(String, RoomNetwork, Option<String>),
+    /// The search term, room network, pagination token, the server whose directory
+    /// is searched, `None` searches the homeserver's, and the page size.
+    RoomSearch(
+        String,
+        RoomNetwork,
+        Option<String>,
+        Option<String>,
+        Option<u32>,
+    ),
     UiaaPing(String),
-    UiaaDummy(String),
+    /// Complete the current stage of registration.
+    UiaaAuth(AuthData),
+    /// The email address, client secret and send attempt of an email validation session.
+    RegisterEmailToken(String, String, u32),
+    /// Deactivate the account, `None` until the homeserver starts a UIAA session.
+    Deactivate(Option<AuthData>),
+    /// The new password, whether to log out the other devices and the UIAA auth.
+    ChangePassword(String, bool, Option<AuthData>),
+    /// The configs were reloaded with a different `unencrypted_fallback`.
+    SetUnencryptedFallback(UnencryptedFallback),
     Quit,
 }
-unsafe impl Send for UserRequest {}
 
 /// Either a `UserRequest` succeeds or fails with the given result.
 #[allow(clippy::type_complexity)]
 pub enum RequestResult {
-    Login(
-        Result<(
-            Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>,
-            login::Response,
-        )>,
+    CheckServer(Result<get_supported_versions::Response>),
+    DebugInfo(DebugInfo),
+    Login(Result<(Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>, UserId)>),
+    /// `None` when the account has no saved session and the password is needed, the
+    /// `bool` is true when the homeserver was out of reach and the rooms are cached.
+    RestoreSession(
+        Result<
+            Option<(
+                Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>,
+                UserId,
+                bool,
+            )>,
+        >,
     ),
     Register(Result<register::Response>),
-    SendMessage(Result<create_message_event::Response>),
+    /// The validation session id sent by the homeserver.
+    RegisterEmailToken(Result<String>),
+    Deactivate(Result<()>),
+    /// The result and whether the other devices were logged out.
+    ChangePassword(Result<()>, bool),
+    SendMessage(Result<create_message_event::Response>, Uuid),
+    /// The sent message and the thread root it was sent in.
+    SendThreadReply(Result<EventId>, EventId),
     RoomMsgs(Result<(get_message_events::Response, Arc<RwLock<Room>>)>),
+    FillGap(Result<(Vec<get_message_events::Response>, Arc<RwLock<Room>>)>),
     AcceptInvite(Result<join_room_by_id::Response>),
     DeclineInvite(Result<leave_room::Response>, RoomId),
     LeaveRoom(Result<leave_room::Response>, RoomId),
     JoinRoom(Result<RoomId>),
+    KnockRoom(Result<knock::Response>),
+    /// The tags of every room after logging in or the rooms whose tags changed in a sync.
+    RoomTags(Result<RoomTags>),
+    RoomTag(Result<()>),
+    /// The direct chats of `m.direct` after logging in or when a sync changed them.
+    DirectRooms(Result<HashSet<RoomId>>),
+    PollVote(Result<EventId>),
+    /// The reaction event, the room, the reacted to event and the key.
+    SendReaction(Result<EventId>, RoomId, EventId, String),
+    /// The redaction event, the room and the redacted event.
+    Redact(Result<EventId>, RoomId, EventId),
+    /// The edit event, the room, the edited message and its new text.
+    EditMessage(Result<EventId>, RoomId, EventId, String),
+    /// The kicked user.
+    Kick(Result<()>, UserId),
+    /// The pinned events of the room, also sent after pinning or unpinning an event.
+    PinnedEvents(Result<Vec<PinnedEvent>>, RoomId),
+    /// How many devices of the room's members are not verified.
+    UnverifiedDevices(Result<usize>, RoomId),
     Typing(Result<create_typing_event::Response>),
     ReadReceipt(Result<set_read_marker::Response>),
     RoomSearch(Result<get_public_rooms_filtered::Response>),
     Error(Error),
 }
 
-unsafe impl Send for RequestResult {}
+/// How often a cancellable request checks whether it was cancelled.
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// Resolves once the user cancels the request in flight.
+async fn cancelled(cancel: &AtomicBool) {
+    while !cancel.load(Ordering::SeqCst) {
+        tokio::time::delay_for(CANCEL_POLL).await;
+    }
+}
+
+/// The longest a rate limited request waits before it is retried.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// If the request failed because the server is rate limiting us returns how long
+/// to wait before retrying.
+fn rate_limited<T>(res: &Result<T>) -> Option<Duration> {
+    res.as_ref().err().and_then(Error::retry_after)
+}
+
+/// Sends the request back to the request loop after the delay, capped at
+/// `MAX_RETRY_DELAY`, so the loop keeps handling other requests meanwhile.
+fn retry_later(
+    exec_hndl: &Handle,
+    mut requests: Sender<UserRequest>,
+    delay: Duration,
+    request: UserRequest,
+) {
+    exec_hndl.spawn(async move {
+        tokio::time::delay_for(delay.min(MAX_RETRY_DELAY)).await;
+        if let Err(e) = requests.send(UserRequest::Retry(Box::new(request))).await {
+            tracing::warn!("the request loop stopped before the retry {}", e);
+        }
+    });
+}
 
 /// The main task event loop.
 ///
@@ -92,8 +220,9 @@ pub struct MatrixEventHandle {
     sync_jobs: JoinHandle<Result<()>>,
     start_sync: Arc<AtomicBool>,
     quit_flag: Arc<AtomicBool>,
+    /// Set to drop the backfill or room search request in flight.
+    cancel_flag: Arc<AtomicBool>,
 }
-unsafe impl Send for MatrixEventHandle {}
 
 impl MatrixEventHandle {
     pub async fn new(
@@ -101,13 +230,21 @@ impl MatrixEventHandle {
         mut to_app: Sender<RequestResult>,
         exec_hndl: Handle,
         homeserver: &str,
+        account: Option<&str>,
+        unencrypted_fallback: UnencryptedFallback,
     ) -> (Self, Sender<UserRequest>) {
         let (app_sender, mut recv) = mpsc::channel(1024);
 
-        let mut client = MatrixClient::new(homeserver).unwrap();
+        let mut client = MatrixClient::new(homeserver, account, unencrypted_fallback).unwrap();
+        // the sync loop reports its stats through the same channel as the emitter
+        let stats_stream = stream.clone();
         client.inner.add_event_emitter(Box::new(stream)).await;
 
         let cli = client.inner.clone();
+        let gaps = client.gaps();
+        let gaps_path = client.gaps_path();
+        let last_sync = client.last_sync();
+        let tags_to_app = to_app.clone();
         // when the ui loop logs in `start_sync` releases and starts `sync_forever`
         let start_sync = Arc::from(AtomicBool::from(false));
         let quit_flag = Arc::from(AtomicBool::from(false));
@@ -128,11 +265,56 @@ impl MatrixEventHandle {
                 return Ok(());
             }
             let set = matrix_sdk::SyncSettings::default();
-            cli.sync_forever(set.clone(), |_| async {}).await;
+            // the token each sync was requested with, used to find gaps in the timeline
+            let since = Arc::new(RwLock::new(cli.sync_token().await));
+            // sync_forever never returns, the flag stops it when the client is replaced
+            // by another account's or rumatui quits
+            tokio::select! {
+                _ = cli.sync_forever(set.clone(), |res| {
+                    let gaps = Arc::clone(&gaps);
+                    let gaps_path = gaps_path.clone();
+                    let since = Arc::clone(&since);
+                    let last_sync = Arc::clone(&last_sync);
+                    let mut to_app = tags_to_app.clone();
+                    let stats_stream = stats_stream.clone();
+                    async move {
+                        let prev = since.write().await.replace(res.next_batch.clone());
+                        record_gaps(&gaps, prev, &res, &gaps_path).await;
+                        *last_sync.write().await = Some(SystemTime::now());
+                        let summary = summarize_sync(&res);
+                        stats_stream.sync_stats(summary.stats).await;
+                        stats_stream.thread_replies(summary.thread_replies).await;
+                        stats_stream.encrypted_rooms(summary.encrypted_rooms).await;
+                        stats_stream.event_sources(summary.sources).await;
+                        stats_stream
+                            .third_party_invites(summary.third_party_invites)
+                            .await;
+                        stats_stream.invite_topics(summary.invite_topics).await;
+
+                        let tags = summary.tags;
+                        if !tags.is_empty() {
+                            if let Err(e) = to_app.send(RequestResult::RoomTags(Ok(tags))).await {
+                                tracing::error!("client event handler crashed {}", e);
+                            }
+                        }
+                        if let Some(direct) = summary.direct_rooms {
+                            let res = RequestResult::DirectRooms(Ok(direct));
+                            if let Err(e) = to_app.send(res).await {
+                                tracing::error!("client event handler crashed {}", e);
+                            }
+                        }
+                    }
+                }) => {}
+                _ = cancelled(&quitting) => tracing::info!("stopped syncing"),
+            }
             Ok(())
         });
 
         // this loop is shutdown with a channel message
+        let cancel_flag = Arc::from(AtomicBool::from(false));
+        let cancel = Arc::clone(&cancel_flag);
+        let requeue = app_sender.clone();
+        let retry_hndl = exec_hndl.clone();
         let cli_jobs = exec_hndl.spawn(async move {
             loop {
                 let input = recv.recv().await;
@@ -140,17 +322,77 @@ impl MatrixEventHandle {
                     return Ok(());
                 }
 
-                match input.unwrap() {
+                // a request is retried once, the second rate limit error is reported
+                let (input, retried) = match input.unwrap() {
+                    UserRequest::Retry(req) => (*req, true),
+                    req => (req, false),
+                };
+                match input {
+                    UserRequest::Retry(_) => unreachable!("retries are never nested"),
                     UserRequest::Quit => return Ok(()),
+                    UserRequest::CheckServer => {
+                        let res = client.check_server().await;
+                        if let Err(e) = to_app.send(RequestResult::CheckServer(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::SetUnencryptedFallback(fallback) => {
+                        client.set_unencrypted_fallback(fallback)
+                    }
+                    UserRequest::DebugInfo => {
+                        let info = client.debug_info().await;
+                        if let Err(e) = to_app.send(RequestResult::DebugInfo(info)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
                     UserRequest::Login(u, p) => {
-                        let res = client.login(u, p).await;
+                        let res = client
+                            .login(u, p)
+                            .await
+                            .map(|(rooms, res)| (rooms, res.user_id));
+                        let logged_in = res.is_ok();
                         if let Err(e) = to_app.send(RequestResult::Login(res)).await {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
                         }
+                        if logged_in {
+                            let tags = client.get_room_tags().await;
+                            if let Err(e) = to_app.send(RequestResult::RoomTags(tags)).await {
+                                tracing::error!("client event handler crashed {}", e);
+                                panic!("client event handler crashed {}", e)
+                            }
+                            let direct = client.get_direct_rooms().await;
+                            if let Err(e) = to_app.send(RequestResult::DirectRooms(direct)).await {
+                                tracing::error!("client event handler crashed {}", e);
+                                panic!("client event handler crashed {}", e)
+                            }
+                        }
                     }
-                    UserRequest::Register(u, p) => {
-                        let res = client.register_user(u, p).await;
+                    UserRequest::RestoreSession => {
+                        let res = client.restore_session().await;
+                        // the tags of the cached rooms are fetched by the sync loop
+                        let logged_in = matches!(res, Ok(Some((_, _, false))));
+                        if let Err(e) = to_app.send(RequestResult::RestoreSession(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                        if logged_in {
+                            let tags = client.get_room_tags().await;
+                            if let Err(e) = to_app.send(RequestResult::RoomTags(tags)).await {
+                                tracing::error!("client event handler crashed {}", e);
+                                panic!("client event handler crashed {}", e)
+                            }
+                            let direct = client.get_direct_rooms().await;
+                            if let Err(e) = to_app.send(RequestResult::DirectRooms(direct)).await {
+                                tracing::error!("client event handler crashed {}", e);
+                                panic!("client event handler crashed {}", e)
+                            }
+                        }
+                    }
+                    UserRequest::Register(u, p, email) => {
+                        let res = client.register_user(u, p, email).await;
                         if let Err(e) = to_app.send(RequestResult::Register(res)).await {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
@@ -170,8 +412,9 @@ impl MatrixEventHandle {
                             tracing::info!("ping UIAA endpoint");
                         }
                     }
-                    UserRequest::UiaaDummy(sess) => {
-                        let res = client.send_uiaa_dummy(sess).await;
+                    UserRequest::UiaaAuth(auth) => {
+                        let stage = auth.ev_type.clone();
+                        let res = client.send_uiaa_auth(auth).await;
                         if let Err(e) = to_app
                             .send(RequestResult::Register(res.map(Into::into)))
                             .await
@@ -179,67 +422,161 @@ impl MatrixEventHandle {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
                         } else {
-                            tracing::info!("sending the dummy UIAA request");
+                            tracing::info!("sending the {} UIAA request", stage);
+                        }
+                    }
+                    UserRequest::RegisterEmailToken(email, secret, attempt) => {
+                        let res = client.request_email_token(email, secret, attempt).await;
+                        if let Err(e) = to_app.send(RequestResult::RegisterEmailToken(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Deactivate(auth) => {
+                        let res = client.deactivate_account(auth).await;
+                        if let Err(e) = to_app.send(RequestResult::Deactivate(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::ChangePassword(new_password, logout, auth) => {
+                        let res = client.change_password(new_password, logout, auth).await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::ChangePassword(res, logout))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
                         }
                     }
                     UserRequest::SendMessage(room, msg, uuid) => {
-                        let res = client.send_message(&room, msg, uuid).await;
-                        if let Err(e) = to_app.send(RequestResult::SendMessage(res)).await {
+                        let res = client.send_message(&room, msg.clone(), uuid).await;
+                        if let Some(delay) = rate_limited(&res).filter(|_| !retried) {
+                            let req = UserRequest::SendMessage(room, msg, uuid);
+                            retry_later(&retry_hndl, requeue.clone(), delay, req);
+                            continue;
+                        }
+                        if let Err(e) = to_app.send(RequestResult::SendMessage(res, uuid)).await {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
                         }
                     }
-                    UserRequest::RoomMsgs(room_id) => match client.get_messages(&room_id).await {
-                        Ok(res) => {
-                            if let Err(e) = to_app
-                                .send(RequestResult::RoomMsgs(Ok((
-                                    res,
-                                    Arc::clone(
-                                        client
-                                            .inner
-                                            .joined_rooms()
-                                            .read()
-                                            .await
-                                            .get(&room_id)
-                                            .unwrap(),
-                                    ),
-                                ))))
-                                .await
-                            {
-                                tracing::error!("client event handler crashed {}", e);
-                                panic!("client event handler crashed {}", e)
-                            } else {
-                                // store state after receiving past events incase a sync_forever call only found a few messages
-                                if client.store_room_state(&room_id).await.is_err() {
-                                    // TODO log that an error happened at some point
+                    UserRequest::SendImage(room, path, uuid) => {
+                        let res = match client.upload_image(&room, &path).await {
+                            Ok(content) => {
+                                let res = client.send_message(&room, content.clone(), uuid).await;
+                                // the upload is kept, only the message is sent again
+                                if let Some(delay) = rate_limited(&res).filter(|_| !retried) {
+                                    let req = UserRequest::SendMessage(room, content, uuid);
+                                    retry_later(&retry_hndl, requeue.clone(), delay, req);
+                                    continue;
                                 }
+                                res
                             }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = to_app.send(RequestResult::SendMessage(res, uuid)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
                         }
-                        Err(get_msg_err) => {
-                            if let Err(e) = to_app.send(RequestResult::Error(get_msg_err)).await {
-                                tracing::error!("client event handler crashed {}", e);
-                                panic!("client event handler crashed {}", e)
+                    }
+                    UserRequest::SendThreadReply(room, root, body) => {
+                        let res = client.send_thread_reply(&room, root.clone(), body).await;
+                        if let Err(e) = to_app.send(RequestResult::SendThreadReply(res, root)).await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::RoomMsgs(room_id) => {
+                        // a cancel sent while waiting out the rate limit drops the retry,
+                        // one sent while nothing was loading does not count
+                        if cancel.swap(false, Ordering::SeqCst) && retried {
+                            tracing::info!("cancelled loading previous messages");
+                            continue;
+                        }
+                        let res = tokio::select! {
+                            res = client.get_messages(&room_id) => res,
+                            _ = cancelled(&cancel) => {
+                                tracing::info!("cancelled loading previous messages");
+                                continue;
                             }
+                        };
+                        if let Some(delay) = rate_limited(&res).filter(|_| !retried) {
+                            retry_later(&retry_hndl, requeue.clone(), delay, UserRequest::RoomMsgs(room_id));
+                            continue;
                         }
-                    },
-                    UserRequest::RoomSearch(filter, network, tkn) => {
-                        match client.get_rooms_filtered(&filter, network, tkn).await {
+                        match res {
                             Ok(res) => {
-                                if let Err(e) =
-                                    to_app.send(RequestResult::RoomSearch(Ok(res))).await
+                                if let Err(e) = to_app
+                                    .send(RequestResult::RoomMsgs(Ok((
+                                        res,
+                                        Arc::clone(
+                                            client
+                                                .inner
+                                                .joined_rooms()
+                                                .read()
+                                                .await
+                                                .get(&room_id)
+                                                .unwrap(),
+                                        ),
+                                    ))))
+                                    .await
                                 {
                                     tracing::error!("client event handler crashed {}", e);
                                     panic!("client event handler crashed {}", e)
+                                } else {
+                                    // store state after receiving past events incase a sync_forever call only found a few messages
+                                    if client.store_room_state(&room_id).await.is_err() {
+                                        // TODO log that an error happened at some point
+                                    }
                                 }
                             }
-                            Err(err) => {
-                                if let Err(e) = to_app.send(RequestResult::Error(err)).await {
+                            Err(get_msg_err) => {
+                                if let Err(e) =
+                                    to_app.send(RequestResult::RoomMsgs(Err(get_msg_err))).await
+                                {
                                     tracing::error!("client event handler crashed {}", e);
                                     panic!("client event handler crashed {}", e)
                                 }
                             }
                         }
                     }
+                    UserRequest::ResetBackfill(room_id) => client.reset_backfill(&room_id),
+                    UserRequest::FillGap(room_id) => {
+                        let res = client.fill_gap(&room_id).await;
+                        let room = client
+                            .inner
+                            .joined_rooms()
+                            .read()
+                            .await
+                            .get(&room_id)
+                            .cloned();
+                        // the room was left before the gap could be filled
+                        if let Some(room) = room {
+                            if let Err(e) = to_app
+                                .send(RequestResult::FillGap(res.map(|pages| (pages, room))))
+                                .await
+                            {
+                                tracing::error!("client event handler crashed {}", e);
+                                panic!("client event handler crashed {}", e)
+                            }
+                        }
+                    }
+                    UserRequest::RoomSearch(filter, network, tkn, server, limit) => {
+                        cancel.store(false, Ordering::SeqCst);
+                        let res = tokio::select! {
+                            res = client.get_rooms_filtered(&filter, network, tkn, server, limit) => res,
+                            _ = cancelled(&cancel) => {
+                                tracing::info!("cancelled the room search");
+                                continue;
+                            }
+                        };
+                        if let Err(e) = to_app.send(RequestResult::RoomSearch(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
                     UserRequest::AcceptInvite(room_id) => {
                         let res = client.join_room_by_id(&room_id).await;
                         if let Err(e) = to_app.send(RequestResult::AcceptInvite(res)).await {
@@ -295,6 +632,117 @@ impl MatrixEventHandle {
                             }
                         }
                     }
+                    UserRequest::JoinRoomByIdOrAlias(room) => {
+                        let res = client.join_room_by_id_or_alias(room).await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::JoinRoom(res.map(|res| res.room_id)))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::SetRoomTag(room_id, tag) => {
+                        let res = client.set_room_tag(&room_id, &tag).await;
+                        if let Err(e) = to_app.send(RequestResult::RoomTag(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::RemoveRoomTag(room_id, tag) => {
+                        let res = client.remove_room_tag(&room_id, &tag).await;
+                        if let Err(e) = to_app.send(RequestResult::RoomTag(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::PollVote(room_id, poll, answer) => {
+                        let res = client.send_poll_response(&room_id, &poll, answer).await;
+                        if let Err(e) = to_app.send(RequestResult::PollVote(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::SendReaction(room_id, relates_to, key) => {
+                        let res = client
+                            .send_reaction(&room_id, &relates_to, key.clone())
+                            .await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::SendReaction(res, room_id, relates_to, key))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Kick(room_id, user_id, reason) => {
+                        let res = client
+                            .kick_user(&room_id, &user_id, reason)
+                            .await
+                            .map(|_| ());
+                        if let Err(e) = to_app.send(RequestResult::Kick(res, user_id)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::EditMessage(room_id, event_id, body) => {
+                        let res = client
+                            .send_edit(&room_id, event_id.clone(), body.clone())
+                            .await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::EditMessage(res, room_id, event_id, body))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Redact(room_id, event_id, reason) => {
+                        let res = client.redact_event(&room_id, &event_id, reason).await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::Redact(res, room_id, event_id))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::PinnedEvents(room_id) => {
+                        let res = client.get_pinned_events(&room_id).await;
+                        if let Err(e) = to_app.send(RequestResult::PinnedEvents(res, room_id)).await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::UnverifiedDevices(room_id) => {
+                        let res = client.unverified_devices(&room_id).await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::UnverifiedDevices(res, room_id))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::PinEvent(room_id, event_id, pin) => {
+                        let res = match client.pin_event(&room_id, event_id, pin).await {
+                            Ok(()) => client.get_pinned_events(&room_id).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = to_app.send(RequestResult::PinnedEvents(res, room_id)).await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::KnockRoom(room, reason) => {
+                        let res = client.knock_room(room, reason).await;
+                        if let Err(e) = to_app.send(RequestResult::KnockRoom(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
                     UserRequest::ReadReceipt(room_id, event_id) => {
                         let res = client
                             .read_marker(&room_id, &event_id, Some(&event_id))
@@ -328,6 +776,7 @@ impl MatrixEventHandle {
                 sync_jobs,
                 start_sync,
                 quit_flag,
+                cancel_flag,
             },
             app_sender,
         )
@@ -339,9 +788,25 @@ impl MatrixEventHandle {
             .swap(true, std::sync::atomic::Ordering::SeqCst);
     }
 
+    /// Drop the backfill or room search request in flight, the next request is
+    /// handled as usual.
+    pub(crate) fn cancel_request(&self) {
+        self.cancel_flag.store(true, Ordering::SeqCst);
+    }
+
     /// This is called when the user quits to signal the `tokio::Runtime` to shutdown.
     pub(crate) fn quit_sync(&self) {
         self.quit_flag
             .swap(true, std::sync::atomic::Ordering::SeqCst);
     }
+
+    /// Wait for the sync and request loops to end after `quit_sync` and a
+    /// `UserRequest::Quit`, the client and its store are dropped once this returns.
+    pub(crate) async fn stopped(self) {
+        for jobs in vec![self.sync_jobs, self.cli_jobs] {
+            if let Err(e) = jobs.await {
+                tracing::warn!("a client task failed while stopping {}", e);
+            }
+        }
+    }
 }
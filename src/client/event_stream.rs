@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, convert::TryFrom, sync::Arc};
+use std::{collections::BTreeMap, convert::TryFrom, sync::Arc, time::SystemTime};
 
 use matrix_sdk::{
     self,
@@ -14,9 +14,7 @@ use matrix_sdk::{
             canonical_alias::CanonicalAliasEventContent,
             join_rules::JoinRulesEventContent,
             member::{MemberEventContent, MembershipChange},
-            message::{
-                feedback::FeedbackEventContent, MessageEventContent, TextMessageEventContent,
-            },
+            message::{feedback::FeedbackEventContent, MessageEventContent},
             name::NameEventContent,
             power_levels::PowerLevelsEventContent,
             redaction::SyncRedactionEvent,
@@ -33,11 +31,51 @@ use tokio::sync::mpsc;
 use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
 
-use crate::client::ruma_ext::{
-    message::EditEventContent, reaction::ReactionEventContent, ExtraMessageEventContent,
-    ExtraReactionEventContent, ExtraRoomEventContent, RumaUnsupportedEvent,
+use crate::client::{
+    ruma_ext::{
+        message::{EditEventContent, REPLACE},
+        reaction::ReactionEventContent,
+        ExtraMessageEventContent, ExtraReactionEventContent, ExtraRoomEventContent,
+        RumaUnsupportedEvent,
+    },
+    SyncStats,
+};
+use crate::widgets::{
+    message::{Message, Poll},
+    utils,
 };
-use crate::widgets::message::Message;
+
+/// The display name and avatar set by a member event, compared with the previous
+/// member event to describe profile changes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemberProfile {
+    pub displayname: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+impl From<&MemberEventContent> for MemberProfile {
+    fn from(content: &MemberEventContent) -> Self {
+        Self {
+            displayname: content.displayname.clone(),
+            avatar_url: content.avatar_url.clone(),
+        }
+    }
+}
+
+/// A member event, who it is about and what changed.
+pub struct MemberChange {
+    pub sender: UserId,
+    pub receiver: UserId,
+    pub room: Arc<RwLock<Room>>,
+    pub membership: MembershipChange,
+    pub profile: MemberProfile,
+    pub prev_profile: Option<MemberProfile>,
+    /// The id and time of the event when it came from a room's timeline, stripped invite
+    /// state has neither.
+    pub timeline: Option<(EventId, SystemTime)>,
+    /// The invite was sent to an email address the receiver has since claimed.
+    pub email_invite: bool,
+}
 
 /// The events sent from the `EventEmitter` are represented by this
 /// enum.
@@ -45,31 +83,42 @@ use crate::widgets::message::Message;
 /// Each variant represents an emitted event and is handled when sent
 /// every tick of the UI loop.
 pub enum StateResult {
-    Member {
-        sender: UserId,
-        receiver: UserId,
-        room: Arc<RwLock<Room>>,
-        membership: MembershipChange,
-        timeline_event: bool,
-    },
+    Member(MemberChange),
     Message(Message, RoomId),
     MessageEdit(String, RoomId, EventId),
     Name(String, RoomId),
     FullyRead(EventId, RoomId),
     ReadReceipt(RoomId, BTreeMap<EventId, Receipts>),
-    Reaction(EventId, EventId, RoomId, String),
-    Redact(EventId, RoomId),
+    Reaction(EventId, EventId, UserId, RoomId, String),
+    /// The message that started a poll and the poll.
+    Poll(Message, Poll, RoomId),
+    /// The poll voted in, the voter and the ids of the answers they selected.
+    PollResponse(EventId, UserId, Vec<String>, RoomId),
+    /// The poll that ended and who ended it.
+    PollEnd(EventId, UserId, RoomId),
+    /// The redacted event, the room and the user who redacted it.
+    Redact(EventId, RoomId, UserId),
     Typing(RoomId, String),
+    /// Sent after every sync, shows the sync loop is alive.
+    SyncStats(SyncStats),
+    /// The thread root and the reply sent in that thread.
+    ThreadReply(EventId, EventId, RoomId),
+    /// The room turned on encryption.
+    Encrypted(RoomId),
+    /// The id and JSON of the timeline events of a sync.
+    EventSources(Vec<(EventId, String)>),
+    /// The room, who sent the invite and the email address they invited.
+    ThirdPartyInvite(RoomId, UserId, String),
+    /// The topic of a room the user is invited to.
+    InviteTopic(RoomId, String),
     Err,
 }
-unsafe impl Send for StateResult {}
 
 #[derive(Clone, Debug)]
 pub struct EventStream {
     /// Send messages to the UI loop.
     send: Arc<Mutex<mpsc::Sender<StateResult>>>,
 }
-unsafe impl Send for EventStream {}
 
 impl EventStream {
     pub(crate) fn new() -> (Self, mpsc::Receiver<StateResult>) {
@@ -83,6 +132,157 @@ impl EventStream {
         )
     }
 
+    /// Report what the last sync brought in.
+    pub(crate) async fn sync_stats(&self, stats: SyncStats) {
+        if let Err(e) = self
+            .send
+            .lock()
+            .await
+            .send(StateResult::SyncStats(stats))
+            .await
+        {
+            tracing::error!("event stream channel closed {}", e);
+            panic!("{}", e)
+        }
+    }
+
+    /// Report the rooms that turned on encryption.
+    pub(crate) async fn encrypted_rooms(&self, rooms: Vec<RoomId>) {
+        for room_id in rooms {
+            if let Err(e) = self
+                .send
+                .lock()
+                .await
+                .send(StateResult::Encrypted(room_id))
+                .await
+            {
+                tracing::error!("event stream channel closed {}", e);
+                panic!("{}", e)
+            }
+        }
+    }
+
+    /// Send the JSON of the synced timeline events so it can be shown as is.
+    pub(crate) async fn event_sources(&self, sources: Vec<(EventId, String)>) {
+        if sources.is_empty() {
+            return;
+        }
+        if let Err(e) = self
+            .send
+            .lock()
+            .await
+            .send(StateResult::EventSources(sources))
+            .await
+        {
+            tracing::error!("event stream channel closed {}", e);
+            panic!("{}", e)
+        }
+    }
+
+    /// Report the invites sent to an email address, ruma has no emitter method for them.
+    pub(crate) async fn third_party_invites(&self, invites: Vec<(RoomId, UserId, String)>) {
+        for (room_id, sender, email) in invites {
+            if let Err(e) = self
+                .send
+                .lock()
+                .await
+                .send(StateResult::ThirdPartyInvite(room_id, sender, email))
+                .await
+            {
+                tracing::error!("event stream channel closed {}", e);
+                panic!("{}", e)
+            }
+        }
+    }
+
+    /// Report the topics of the rooms the user is invited to.
+    pub(crate) async fn invite_topics(&self, topics: Vec<(RoomId, String)>) {
+        for (room_id, topic) in topics {
+            if let Err(e) = self
+                .send
+                .lock()
+                .await
+                .send(StateResult::InviteTopic(room_id, topic))
+                .await
+            {
+                tracing::error!("event stream channel closed {}", e);
+                panic!("{}", e)
+            }
+        }
+    }
+
+    /// Report the messages sent in a thread, ruma drops the relation so the message
+    /// handler cannot tell them apart.
+    pub(crate) async fn thread_replies(&self, replies: Vec<(EventId, EventId, RoomId)>) {
+        for (root, reply, room_id) in replies {
+            if let Err(e) = self
+                .send
+                .lock()
+                .await
+                .send(StateResult::ThreadReply(root, reply, room_id))
+                .await
+            {
+                tracing::error!("event stream channel closed {}", e);
+                panic!("{}", e)
+            }
+        }
+    }
+
+    /// Handle the events ruma does not know about that are not edits or reactions,
+    /// stickers and polls are shown as messages and votes update their poll.
+    async fn handle_extra_event(&self, room: Arc<RwLock<Room>>, event: &RumaUnsupportedEvent) {
+        let (name, room_id) = {
+            let room = room.read().await;
+            let name = room
+                .joined_members
+                .get(&event.sender)
+                .map(|m| m.name())
+                .unwrap_or_else(|| event.sender.localpart().into());
+            (name, room.room_id.clone())
+        };
+        let message = |text| Message {
+            name,
+            user: event.sender.clone(),
+            text,
+            event_id: event.event_id.clone(),
+            timestamp: event.origin_server_ts,
+            uuid: Uuid::new_v4(),
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            emote: false,
+            membership: None,
+        };
+        let res = match &event.content {
+            ExtraRoomEventContent::Sticker { content } => StateResult::Message(
+                message(utils::sticker_text(&content.body, &content.url)),
+                room_id,
+            ),
+            // the text is filled in with the tallies when the poll is added
+            ExtraRoomEventContent::Poll { content } => {
+                StateResult::Poll(message(String::new()), Poll::from(content), room_id)
+            }
+            ExtraRoomEventContent::PollResponse { content } => StateResult::PollResponse(
+                content.relates_to.event_id.clone(),
+                event.sender.clone(),
+                content.selections.clone(),
+                room_id,
+            ),
+            ExtraRoomEventContent::PollEnd { content } => StateResult::PollEnd(
+                content.relates_to.event_id.clone(),
+                event.sender.clone(),
+                room_id,
+            ),
+            ExtraRoomEventContent::Message { .. } | ExtraRoomEventContent::Reaction { .. } => {
+                return
+            }
+        };
+        if let Err(e) = self.send.lock().await.send(res).await {
+            tracing::error!("event stream channel closed {}", e);
+            panic!("{}", e)
+        }
+    }
+
     async fn handle_room_member(
         &self,
         room: Arc<RwLock<Room>>,
@@ -91,19 +291,25 @@ impl EventStream {
         let SyncStateEvent {
             sender, state_key, ..
         } = event;
-        let receiver = UserId::try_from(state_key.as_str()).unwrap();
+        let receiver = match member_state_key(state_key) {
+            Some(user) => user,
+            None => return,
+        };
         let membership = event.membership_change();
         if let Err(e) = self
             .send
             .lock()
             .await
-            .send(StateResult::Member {
+            .send(StateResult::Member(MemberChange {
                 sender: sender.clone(),
                 receiver,
                 room,
                 membership,
-                timeline_event: true,
-            })
+                profile: MemberProfile::from(&event.content),
+                prev_profile: event.prev_content.as_ref().map(MemberProfile::from),
+                timeline: Some((event.event_id.clone(), event.origin_server_ts)),
+                email_invite: event.content.third_party_invite.is_some(),
+            }))
             .await
         {
             tracing::error!("event stream channel closed {}", e);
@@ -111,6 +317,22 @@ impl EventStream {
         }
     }
 }
+/// The user a member event is about, the state key comes from the server so a malformed
+/// one skips the event instead of panicking.
+fn member_state_key(state_key: &str) -> Option<UserId> {
+    match UserId::try_from(state_key) {
+        Ok(user) => Some(user),
+        Err(e) => {
+            tracing::warn!(
+                "skipping member event with invalid state key {:?}: {}",
+                state_key,
+                e
+            );
+            None
+        }
+    }
+}
+
 #[allow(clippy::eval_order_dependence)]
 #[async_trait::async_trait]
 impl EventEmitter for EventStream {
@@ -168,53 +390,40 @@ impl EventEmitter for EventStream {
             } else {
                 sender.localpart().into()
             };
-            match content {
-                MessageEventContent::Text(TextMessageEventContent {
-                    body, formatted, ..
-                }) => {
-                    let msg = if formatted
-                        .as_ref()
-                        .map(|f| f.body.to_string())
-                        .unwrap_or(body.to_string())
-                        != *body
-                    {
-                        // This is extremely expensive
-                        // TODO cache these results somehow
-                        crate::widgets::utils::markdown_to_terminal(body).unwrap_or(body.clone())
-                    } else {
-                        body.clone()
-                    };
-                    let txn_id = unsigned
-                        .transaction_id
-                        .as_ref()
-                        .cloned()
-                        .unwrap_or_default();
-
-                    if let Err(e) = self
-                        .send
-                        .lock()
-                        .await
-                        .send(StateResult::Message(
-                            Message {
-                                name,
-                                user: sender.clone(),
-                                text: msg,
-                                event_id: event_id.clone(),
-                                timestamp: *origin_server_ts,
-                                uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
-                                read: false,
-                                reactions: vec![],
-                                sent_receipt: false,
-                            },
-                            room.read().await.room_id.clone(),
-                        ))
-                        .await
-                    {
-                        tracing::error!("event stream channel closed {}", e);
-                        panic!("{}", e)
-                    }
-                }
-                _ => {}
+            let msg = match utils::message_text(content) {
+                Some(text) => text,
+                None => return,
+            };
+            let txn_id = unsigned
+                .transaction_id
+                .as_ref()
+                .cloned()
+                .unwrap_or_default();
+
+            if let Err(e) = self
+                .send
+                .lock()
+                .await
+                .send(StateResult::Message(
+                    Message {
+                        name,
+                        user: sender.clone(),
+                        text: msg,
+                        event_id: event_id.clone(),
+                        timestamp: *origin_server_ts,
+                        uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
+                        read: false,
+                        reactions: vec![],
+                        sent_receipt: false,
+                        emote: matches!(content, MessageEventContent::Emote(_)),
+                        membership: None,
+                    },
+                    room.read().await.room_id.clone(),
+                ))
+                .await
+            {
+                tracing::error!("event stream channel closed {}", e);
+                panic!("{}", e)
             }
         }
     }
@@ -235,6 +444,7 @@ impl EventEmitter for EventStream {
                 .send(StateResult::Redact(
                     event.redacts.clone(),
                     room.read().await.room_id.clone(),
+                    event.sender.clone(),
                 ))
                 .await
             {
@@ -281,7 +491,7 @@ impl EventEmitter for EventStream {
         &self,
         room: SyncRoom,
         event: &StrippedStateEvent<MemberEventContent>,
-        _prev_content: Option<MemberEventContent>,
+        prev_content: Option<MemberEventContent>,
     ) {
         // TODO only invite is handled as stripped state member
         match room {
@@ -290,19 +500,25 @@ impl EventEmitter for EventStream {
                     sender, state_key, ..
                 } = event;
 
-                let receiver = UserId::try_from(state_key.as_str()).unwrap();
+                let receiver = match member_state_key(state_key) {
+                    Some(user) => user,
+                    None => return,
+                };
                 let membership = event.membership_change();
                 if let Err(e) = self
                     .send
                     .lock()
                     .await
-                    .send(StateResult::Member {
+                    .send(StateResult::Member(MemberChange {
                         sender: sender.clone(),
                         receiver,
                         room,
                         membership,
-                        timeline_event: false,
-                    })
+                        profile: MemberProfile::from(&event.content),
+                        prev_profile: prev_content.as_ref().map(MemberProfile::from),
+                        timeline: None,
+                        email_invite: event.content.third_party_invite.is_some(),
+                    }))
                     .await
                 {
                     tracing::error!("event stream channel closed {}", e);
@@ -457,18 +673,23 @@ impl EventEmitter for EventStream {
                             match event.content {
                                 ExtraRoomEventContent::Message { content } => match content {
                                     ExtraMessageEventContent::EditEvent(EditEventContent {
-                                        body,
                                         new_content,
                                         relates_to,
+                                        ..
                                     }) => {
                                         if new_content.msgtype == "m.text"
-                                            && relates_to.rel_type == "m.replace"
+                                            && relates_to.rel_type == REPLACE
                                         {
+                                            // the body is the ` * ` fallback, show the new content
+                                            let body = new_content.body;
                                             let new_body = if body.contains('`') {
-                                                crate::widgets::utils::markdown_to_terminal(&body)
-                                                    // this shouldn't fail but as a back up we just use
-                                                    // the unformatted message body
-                                                    .unwrap_or(body.clone())
+                                                utils::markdown_to_terminal(
+                                                    &body,
+                                                    utils::message_pane_width(),
+                                                )
+                                                // this shouldn't fail but as a back up we just use
+                                                // the unformatted message body
+                                                .unwrap_or(body.clone())
                                             // None.unwrap_or(body.clone())
                                             } else {
                                                 body.to_string()
@@ -494,6 +715,7 @@ impl EventEmitter for EventStream {
                                     }
                                 },
                                 ExtraRoomEventContent::Reaction { content: _ } => {}
+                                _ => self.handle_extra_event(Arc::clone(&room), &event).await,
                             }
                         }
                     }
@@ -522,6 +744,7 @@ impl EventEmitter for EventStream {
                                             .send(StateResult::Reaction(
                                                 event_id,
                                                 event.event_id.clone(),
+                                                event.sender.clone(),
                                                 room_id,
                                                 key.to_string(),
                                             ))
@@ -531,6 +754,7 @@ impl EventEmitter for EventStream {
                                             panic!("{}", e)
                                         }
                                     }
+                                    _ => self.handle_extra_event(Arc::clone(&room), &event).await,
                                 }
                             }
                         }
@@ -546,3 +770,105 @@ impl EventEmitter for EventStream {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use matrix_sdk::events::EventJson;
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    fn event<T: DeserializeOwned>(json: &str) -> T {
+        serde_json::from_str::<EventJson<T>>(json)
+            .unwrap()
+            .deserialize()
+            .unwrap()
+    }
+
+    fn joined_room() -> (RoomId, SyncRoom) {
+        let room_id = RoomId::try_from("!SVkFJHzfwvuaIEawgC:localhost").unwrap();
+        let me = UserId::try_from("@example:localhost").unwrap();
+        let room = Arc::new(RwLock::new(Room::new(&room_id, &me)));
+        (room_id, SyncRoom::Joined(room))
+    }
+
+    #[tokio::test]
+    async fn room_messages_are_sent_to_the_ui() {
+        let (stream, mut recv) = EventStream::new();
+        let (room_id, room) = joined_room();
+
+        let ev: SyncMessageEvent<MessageEventContent> =
+            event(include_str!("../../test_data/message_text.json"));
+        stream.on_room_message(room, &ev).await;
+
+        match recv.recv().await {
+            Some(StateResult::Message(msg, room)) => {
+                assert_eq!(room_id, room);
+                assert_eq!("is dealt with", msg.text);
+                assert_eq!("$152037280074GZeOm:localhost", msg.event_id.as_str());
+                // the sender is not a loaded member so the localpart is shown
+                assert_eq!("example", msg.name);
+            }
+            _ => panic!("expected a message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn member_events_are_sent_to_the_ui() {
+        let (stream, mut recv) = EventStream::new();
+        let (room_id, room) = joined_room();
+
+        let ev: SyncStateEvent<MemberEventContent> =
+            event(include_str!("../../test_data/member_join.json"));
+        stream.on_room_member(room, &ev).await;
+
+        match recv.recv().await {
+            Some(StateResult::Member(MemberChange {
+                receiver,
+                room,
+                membership,
+                profile,
+                timeline,
+                ..
+            })) => {
+                assert_eq!("@example:localhost", receiver.as_str());
+                assert_eq!(room_id, room.read().await.room_id);
+                assert!(matches!(membership, MembershipChange::InvitationAccepted));
+                assert_eq!(Some("example".to_string()), profile.displayname);
+                assert_eq!(
+                    Some("$151800140517rfvjc:localhost"),
+                    timeline.as_ref().map(|(id, _)| id.as_str())
+                );
+            }
+            _ => panic!("expected a member change"),
+        }
+    }
+
+    #[tokio::test]
+    async fn redactions_are_sent_to_the_ui() {
+        let (stream, mut recv) = EventStream::new();
+        let (room_id, room) = joined_room();
+
+        let ev: SyncRedactionEvent = event(include_str!("../../test_data/redaction.json"));
+        stream.on_room_redaction(room, &ev).await;
+
+        match recv.recv().await {
+            Some(StateResult::Redact(redacted, room, sender)) => {
+                assert_eq!("$152037280074GZeOm:localhost", redacted.as_str());
+                assert_eq!(room_id, room);
+                assert_eq!("@example:localhost", sender.as_str());
+            }
+            _ => panic!("expected a redaction"),
+        }
+    }
+
+    #[test]
+    fn invalid_member_state_key_is_skipped() {
+        assert_eq!(
+            Some(UserId::try_from("@example:localhost").unwrap()),
+            member_state_key("@example:localhost")
+        );
+        assert_eq!(None, member_state_key(""));
+        assert_eq!(None, member_state_key("not a user id"));
+    }
+}
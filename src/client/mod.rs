@@ -1,36 +1,91 @@
-use std::{collections::HashMap, fmt, path::Path, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
+use futures::future::join_all;
 use matrix_sdk::{
     self,
-    api::r0::{
-        account::register::{self, RegistrationKind},
-        directory::get_public_rooms_filtered::{self, Filter, RoomNetwork},
-        membership::{forget_room, join_room_by_id, kick_user, leave_room},
-        message::{create_message_event, get_message_events},
-        read_marker::set_read_marker,
-        receipt::create_receipt,
-        session::login,
-        typing::create_typing_event,
+    api::{
+        error::ErrorKind,
+        r0::{
+            account::register::{self, RegistrationKind},
+            directory::get_public_rooms_filtered::{self, Filter, RoomNetwork},
+            filter::{LazyLoadOptions, RoomEventFilter},
+            membership::{forget_room, join_room_by_id, kick_user, leave_room},
+            message::{create_message_event, get_message_events},
+            read_marker::set_read_marker,
+            receipt::create_receipt,
+            session::login,
+            sync::sync_events,
+            typing::create_typing_event,
+        },
+        unversioned::get_supported_versions,
+        Error as ApiError,
+    },
+    events::room::message::{
+        ImageMessageEventContent, InReplyTo, MessageEventContent, RelatesTo as ReplyTo,
+        TextMessageEventContent,
     },
-    events::room::message::MessageEventContent,
-    identifiers::{EventId, RoomId, UserId},
-    Client, ClientConfig, JsonStore, RegistrationBuilder, Room, RoomListFilterBuilder,
-    SyncSettings,
+    identifiers::{EventId, RoomId, RoomIdOrAliasId, UserId},
+    Client, ClientConfig, Error as MatrixError, FromHttpResponseError, JsonStore,
+    RegistrationBuilder, Room, RoomListFilterBuilder, ServerError, Session, SyncSettings,
 };
 use tokio::{fs as async_fs, sync::RwLock};
 use url::Url;
 use uuid::Uuid;
 
-use crate::error::Result;
+use crate::{
+    config::UnencryptedFallback,
+    error::{Error, Result},
+};
 
-use ruma_ext::auth::{self, dummy, SessionObj};
+use ruma_ext::{
+    auth::{
+        self, change_password, deactivate, request_email_token,
+        stage::{self, AuthData},
+        SessionObj,
+    },
+    direct::get_direct_rooms,
+    join,
+    keys::get_keys,
+    knock,
+    message::{
+        send_edit, send_thread_reply, EditEventContent, RelatesTo, ThreadMessageEventContent,
+        ThreadedEvent,
+    },
+    pinned::{get_event, get_pinned_events, set_pinned_events},
+    poll::send_poll_response,
+    reaction::{send_reaction, ReactionEventContent},
+    tag::{create_tag, delete_tag, get_tags},
+    ExtraMessageEventContent, ExtraReactionEventContent, PollResponseEventContent,
+};
 
 pub mod client_loop;
 pub mod event_stream;
 pub mod ruma_ext;
+pub mod uiaa;
 
 const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// The most pages requested when filling a gap in a room's timeline at once.
+const MAX_GAP_PAGES: usize = 10;
+
+/// How often the pinned events are written before giving up when other clients keep
+/// replacing them.
+const PIN_WRITES: usize = 3;
+
+/// A hole in a room's timeline, the `from` token is the last sync the room was seen at
+/// and the `to` token is the start of the first timeline received after it.
+pub type TimelineGaps = Arc<RwLock<HashMap<RoomId, (String, String)>>>;
+
+/// The tags of each room, like `m.favourite`.
+pub type RoomTags = HashMap<RoomId, Vec<String>>;
+
 #[cfg(target_os = "linux")]
 const RUMATUI_ID: &str = "rumatui command line client (LINUX)";
 
@@ -40,16 +95,157 @@ const RUMATUI_ID: &str = "rumatui command line client (WINDOWS)";
 #[cfg(target_os = "macos")]
 const RUMATUI_ID: &str = "rumatui command line client (MAC)";
 
+/// A user name typed at login or registration, either a localpart or a full user id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Username {
+    pub localpart: String,
+    /// The server part of a full `@user:server` id.
+    pub server: Option<String>,
+}
+
+impl Username {
+    /// Normalize what the user typed, surrounding whitespace and a leading `@` are
+    /// dropped. The error is a hint to show next to the user name.
+    pub fn parse(input: &str) -> std::result::Result<Self, &'static str> {
+        let input = input.trim();
+        let input = input.strip_prefix('@').unwrap_or(input);
+        let mut parts = input.splitn(2, ':');
+        let localpart = parts.next().unwrap_or_default();
+        let server = parts.next();
+        if localpart.is_empty() {
+            return Err("enter a user name");
+        }
+        if localpart.contains(char::is_whitespace) {
+            return Err("user names can not contain spaces");
+        }
+        if server.map_or(false, |s| s.is_empty() || s.contains(char::is_whitespace)) {
+            return Err("use either your user name or the full @user:server id");
+        }
+        Ok(Self {
+            localpart: localpart.to_string(),
+            server: server.map(ToString::to_string),
+        })
+    }
+
+    /// New accounts may only use the characters the spec allows in user ids.
+    pub fn check_registerable(&self) -> std::result::Result<(), &'static str> {
+        if self
+            .localpart
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "._=-/".contains(c))
+        {
+            Ok(())
+        } else {
+            Err("user names may only use a-z, 0-9 and . _ = - /")
+        }
+    }
+
+    /// The id sent at login, the full user id when a server was given.
+    pub fn login_id(&self) -> String {
+        match self.server.as_ref() {
+            Some(server) => format!("@{}:{}", self.localpart, server),
+            None => self.localpart.clone(),
+        }
+    }
+}
+
+/// A light check of the optional registration email, the homeserver has the final say
+/// when it sends the validation link. The error is a hint to show next to the email.
+pub fn check_email(email: &str) -> std::result::Result<(), &'static str> {
+    let mut parts = email.trim().rsplitn(2, '@');
+    let domain = parts.next().unwrap_or_default();
+    match parts.next() {
+        Some(local)
+            if !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.trim().contains(char::is_whitespace) =>
+        {
+            Ok(())
+        }
+        _ => Err("that does not look like an email address"),
+    }
+}
+
+/// An event pinned in a room, with the text shown in the pinned messages overlay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PinnedEvent {
+    pub event_id: EventId,
+    /// `None` when the event could not be fetched.
+    pub sender: Option<UserId>,
+    /// The body of the event, or its type when it has no body.
+    pub body: String,
+}
+
+/// A snapshot of the client's sync state for the debug overlay.
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    pub sync_token: Option<String>,
+    /// The token each room's next backfill starts from.
+    pub backfill_tokens: HashMap<RoomId, String>,
+    pub last_sync: Option<SystemTime>,
+}
+
+/// What a single sync brought in, used to show the sync is alive.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SyncStats {
+    /// Joined rooms with new timeline events.
+    pub active_rooms: usize,
+    /// New `m.room.message` and `m.room.encrypted` events across the joined rooms.
+    pub new_messages: usize,
+}
+
+/// The file in the store directory the device id of the first login is kept in, later
+/// logins reuse the device.
+const DEVICE_ID_FILE: &str = ".device-id.txt";
+
+/// The file in the store directory the access token of the last login is kept in.
+const SESSION_FILE: &str = ".session.json";
+
+/// The file in the store directory the timeline gaps not filled yet are kept in, next to
+/// the sync token of the state store they start at.
+const GAPS_FILE: &str = ".gaps.json";
+
+/// The directory the state store, device id and session of `user_id` are kept in, each
+/// account has its own under `root`.
+///
+/// The user id is percent-encoded so two accounts never share a directory.
+pub fn account_dir(root: &Path, user_id: &str) -> PathBuf {
+    let mut name = String::with_capacity(user_id.len());
+    for byte in user_id.bytes() {
+        if byte.is_ascii_alphanumeric() || b"._=-".contains(&byte) {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    root.join("accounts").join(name)
+}
+
+/// What is needed to log in again without the password.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+struct SavedSession {
+    access_token: String,
+    user_id: UserId,
+    device_id: String,
+}
+
 #[derive(Clone)]
 pub struct MatrixClient {
     pub inner: Client,
     homeserver: Url,
+    /// Where the state store, the device id and the session are kept.
+    store_path: PathBuf,
     user: Option<UserId>,
-    settings: SyncSettings,
     next_batch: Option<String>,
     last_scroll: HashMap<RoomId, String>,
+    gaps: TimelineGaps,
+    /// When the last sync response was received.
+    last_sync: Arc<RwLock<Option<SystemTime>>>,
+    /// Whether events to encrypted rooms that can not be encrypted are sent in the clear.
+    unencrypted_fallback: UnencryptedFallback,
 }
-unsafe impl Send for MatrixClient {}
 
 impl fmt::Debug for MatrixClient {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -60,11 +256,31 @@ impl fmt::Debug for MatrixClient {
 }
 
 impl MatrixClient {
-    pub fn new(homeserver: &str) -> Result<Self> {
+    /// Create a client keeping its state in the directory of `account`, the full user
+    /// id, or in `~/.rumatui` before the account is known.
+    pub fn new(
+        homeserver: &str,
+        account: Option<&str>,
+        unencrypted_fallback: UnencryptedFallback,
+    ) -> Result<Self> {
+        let root: &Path = crate::RUMATUI_DIR.as_ref().unwrap();
+        let path = match account {
+            Some(user_id) => account_dir(root, user_id),
+            None => root.to_path_buf(),
+        };
+        std::fs::create_dir_all(&path)?;
+        Self::with_store(homeserver, &path, unencrypted_fallback)
+    }
+
+    /// Create a client for `homeserver` that keeps its state in `store_path`.
+    pub(crate) fn with_store(
+        homeserver: &str,
+        store_path: &Path,
+        unencrypted_fallback: UnencryptedFallback,
+    ) -> Result<Self> {
         let homeserver = Url::parse(&homeserver)?;
-        let path: &Path = crate::RUMATUI_DIR.as_ref().unwrap();
 
-        let store: Result<JsonStore> = JsonStore::open(path).map_err(Into::into);
+        let store: Result<JsonStore> = JsonStore::open(store_path).map_err(Into::into);
         // reset the client with the state store with username as part of the store path
         let client_config = ClientConfig::default()
             // .proxy("http://localhost:8080")? // for mitmproxy
@@ -77,19 +293,161 @@ impl MatrixClient {
         let client = Self {
             inner: inner?,
             homeserver,
+            store_path: store_path.to_path_buf(),
             user: None,
-            settings: SyncSettings::default(),
             next_batch: None,
             last_scroll: HashMap::new(),
+            gaps: TimelineGaps::default(),
+            last_sync: Arc::default(),
+            unencrypted_fallback,
         };
 
         Ok(client)
     }
 
+    pub(crate) fn set_unencrypted_fallback(&mut self, fallback: UnencryptedFallback) {
+        self.unencrypted_fallback = fallback;
+    }
+
+    fn session_path(&self) -> PathBuf {
+        self.store_path.join(SESSION_FILE)
+    }
+
+    /// Keep the access token so switching back to the account does not ask for the
+    /// password and the next start can show the cached rooms when the homeserver can not
+    /// be reached, the file is only readable by the user.
+    fn save_session(&self, session: &SavedSession) -> Result<()> {
+        use std::io::Write;
+
+        let json = serde_json::to_string(session)?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(self.session_path())?
+            .write_all(json.as_bytes())
+            .map_err(Into::into)
+    }
+
+    /// Log in with the session of the last login of this account and sync, `None` when
+    /// there is no saved session. A session the homeserver no longer knows is forgotten.
+    ///
+    /// When the homeserver can not be reached the rooms cached in the store are returned
+    /// and the `bool` is true, the sync loop catches up once the server is back.
+    pub(crate) async fn restore_session(
+        &mut self,
+    ) -> Result<
+        Option<(
+            Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>,
+            UserId,
+            bool,
+        )>,
+    > {
+        let json = match async_fs::read_to_string(self.session_path()).await {
+            Ok(json) => json,
+            Err(_) => return Ok(None),
+        };
+        let saved: SavedSession = serde_json::from_str(&json)?;
+        let user_id = saved.user_id.clone();
+        self.inner
+            .restore_login(Session {
+                access_token: saved.access_token,
+                user_id: saved.user_id,
+                device_id: saved.device_id.into(),
+            })
+            .await?;
+        self.user = Some(user_id.clone());
+
+        let offline = match self.initial_sync().await {
+            Ok(()) => false,
+            Err(e) if e.is_offline() => true,
+            Err(e) => {
+                if let Error::NeedAuth(_) = e {
+                    if let Err(err) = async_fs::remove_file(self.session_path()).await {
+                        tracing::warn!("failed to forget the expired session {}", err);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        Ok(Some((self.inner.joined_rooms(), user_id, offline)))
+    }
+
+    fn device_id_path(&self) -> PathBuf {
+        self.store_path.join(DEVICE_ID_FILE)
+    }
+
+    /// The device of the first login, `None` before anyone logged in with this store.
+    async fn device_id(&self) -> Option<String> {
+        async_fs::read_to_string(self.device_id_path()).await.ok()
+    }
+
+    /// Keep the device id of the first login so later logins reuse the device.
+    async fn remember_device(&self, device_id: &str) {
+        let path = self.device_id_path();
+        if path.exists() {
+            return;
+        }
+        if let Err(err) = async_fs::write(&path, device_id).await {
+            tracing::warn!("failed to record device_id {}", err)
+        }
+    }
+
     pub fn sync_token(&self) -> Option<String> {
         self.next_batch.clone()
     }
 
+    /// When the last sync finished, shared with the sync loop so it can be updated.
+    pub(crate) fn last_sync(&self) -> Arc<RwLock<Option<SystemTime>>> {
+        Arc::clone(&self.last_sync)
+    }
+
+    pub(crate) async fn debug_info(&self) -> DebugInfo {
+        DebugInfo {
+            sync_token: self.inner.sync_token().await,
+            backfill_tokens: self.last_scroll.clone(),
+            last_sync: *self.last_sync.read().await,
+        }
+    }
+
+    /// The gaps in room timelines, shared with the sync loop so it can record new ones.
+    pub(crate) fn gaps(&self) -> TimelineGaps {
+        Arc::clone(&self.gaps)
+    }
+
+    /// The file the gaps are saved in, shared with the sync loop so it can save new ones.
+    pub(crate) fn gaps_path(&self) -> PathBuf {
+        self.store_path.join(GAPS_FILE)
+    }
+
+    /// Add the gaps that were not filled before the last quit, a gap recorded since then
+    /// is newer and kept.
+    async fn load_gaps(&self) {
+        let json = match async_fs::read_to_string(self.gaps_path()).await {
+            Ok(json) => json,
+            Err(_) => return,
+        };
+        match serde_json::from_str::<HashMap<RoomId, (String, String)>>(&json) {
+            Ok(saved) => {
+                let mut gaps = self.gaps.write().await;
+                for (room_id, gap) in saved {
+                    gaps.entry(room_id).or_insert(gap);
+                }
+            }
+            Err(e) => tracing::warn!("failed to read the saved timeline gaps {}", e),
+        }
+    }
+
+    /// Check that the homeserver can be reached and is a Matrix server by asking
+    /// for the versions of the spec it supports.
+    pub(crate) async fn check_server(&self) -> Result<get_supported_versions::Response> {
+        self.inner
+            .send(get_supported_versions::Request {})
+            .await
+            .map_err(Into::into)
+    }
+
     /// Joins the specified room.
     ///
     /// # Arguments
@@ -111,19 +469,41 @@ impl MatrixClient {
         Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>,
         login::Response,
     )> {
-        // this would have caused `main()` to throw an error so unwrap here is ok
-        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-        path.push(".device-id.txt");
-        let device_id = async_fs::read_to_string(path).await.ok();
+        let device_id = self.device_id().await;
 
+        let username = Username::parse(&username).map_err(Error::Rumatui)?;
         let res = self
             .inner
-            .login(username, password, device_id, Some(RUMATUI_ID.to_string()))
+            .login(
+                username.login_id(),
+                password,
+                device_id,
+                Some(RUMATUI_ID.to_string()),
+            )
             .await?;
 
+        self.remember_device(&res.device_id).await;
         self.user = Some(res.user_id.clone());
+        let session = SavedSession {
+            access_token: res.access_token.clone(),
+            user_id: res.user_id.clone(),
+            device_id: res.device_id.to_string(),
+        };
+        if let Err(e) = self.save_session(&session) {
+            tracing::warn!("failed to save the session {}", e);
+        }
 
-        let _response = self
+        self.initial_sync().await?;
+        Ok((self.inner.joined_rooms(), res))
+    }
+
+    /// The first sync after logging in.
+    async fn initial_sync(&mut self) -> Result<()> {
+        // the sync token restored from the store, anything between this and the
+        // new timelines was missed while we were offline
+        let since = self.inner.sync_token().await;
+        self.load_gaps().await;
+        let response = self
             .inner
             .sync(
                 SyncSettings::default()
@@ -131,9 +511,11 @@ impl MatrixClient {
                     .full_state(false),
             )
             .await?;
+        record_gaps(&self.gaps, since, &response, &self.gaps_path()).await;
+        *self.last_sync.write().await = Some(SystemTime::now());
 
         self.next_batch = self.inner.sync_token().await;
-        Ok((self.inner.joined_rooms(), res))
+        Ok(())
     }
 
     /// Create an account for the Matrix server used when starting the app.
@@ -141,15 +523,19 @@ impl MatrixClient {
         &mut self,
         username: String,
         password: String,
+        email: Option<String>,
     ) -> Result<register::Response> {
+        let username = Username::parse(&username).map_err(Error::Rumatui)?;
+        username.check_registerable().map_err(Error::Rumatui)?;
+        // the email is only sent when the server asks for it during UIAA
+        if let Some(email) = email.as_deref() {
+            check_email(email).map_err(Error::Rumatui)?;
+        }
+        // only the localpart is sent, the server name of a full user id can differ from
+        // the homeserver's host when it is delegated with .well-known so it is not checked
         let mut req = RegistrationBuilder::default();
 
-        // this would have caused `main()` to throw an error so unwrap here is ok
-        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-        path.push(".device-id.txt");
-        let device_id = async_fs::read_to_string(path).await.ok();
-
-        if let Some(device) = device_id {
+        if let Some(device) = self.device_id().await {
             req.device_id(device);
         } else {
             tracing::info!("No device_id file found for register request");
@@ -157,30 +543,91 @@ impl MatrixClient {
 
         req.initial_device_display_name(RUMATUI_ID)
             .password(&password)
-            .username(&username)
+            .username(&username.localpart)
             .kind(RegistrationKind::User);
 
-        self.inner.register_user(req).await.map_err(Into::into)
+        let res = self.inner.register_user(req).await?;
+        if let Some(device) = res.device_id.as_deref() {
+            self.remember_device(device).await;
+        }
+        Ok(res)
     }
 
     pub(crate) async fn send_uiaa_ping(&mut self, session: String) -> Result<auth::Response> {
-        self.inner
+        let res = self
+            .inner
             .send_uiaa(auth::Request {
                 auth: SessionObj { session },
             })
+            .await?;
+        if let Some(device) = res.device_id.as_deref() {
+            self.remember_device(device).await;
+        }
+        Ok(res)
+    }
+
+    /// Complete one stage of registration's user interactive authentication.
+    pub(crate) async fn send_uiaa_auth(&mut self, auth: AuthData) -> Result<stage::Response> {
+        let res = self.inner.send_uiaa(stage::Request { auth }).await?;
+        if let Some(device) = res.device_id.as_deref() {
+            self.remember_device(device).await;
+        }
+        Ok(res)
+    }
+
+    /// Deactivate the logged in account, the first request is sent without `auth`
+    /// to start the UIAA session the password is then sent with.
+    pub(crate) async fn deactivate_account(&mut self, auth: Option<AuthData>) -> Result<()> {
+        self.inner
+            .send_uiaa(deactivate::Request { auth })
+            .await
+            .map_err(Error::from)?;
+
+        // the device went with the account so the next login needs a new one
+        if let Err(err) = async_fs::remove_file(self.device_id_path()).await {
+            tracing::info!("no device_id file removed after deactivating {}", err);
+        }
+        Ok(())
+    }
+
+    /// Change the account's password, like deactivating the first request starts the
+    /// UIAA session the current password is then sent with.
+    pub(crate) async fn change_password(
+        &mut self,
+        new_password: String,
+        logout_devices: bool,
+        auth: Option<AuthData>,
+    ) -> Result<()> {
+        self.inner
+            .send_uiaa(change_password::Request {
+                new_password,
+                logout_devices,
+                auth,
+            })
             .await
+            .map(|_| ())
             .map_err(Into::into)
     }
 
-    pub(crate) async fn send_uiaa_dummy(&mut self, session: String) -> Result<dummy::Response> {
+    /// Ask the homeserver to send a validation link to `email`, returns the
+    /// validation session id.
+    ///
+    /// `send_attempt` has to grow with each request of the same `client_secret` for
+    /// the homeserver to send the email again.
+    pub(crate) async fn request_email_token(
+        &mut self,
+        email: String,
+        client_secret: String,
+        send_attempt: u32,
+    ) -> Result<String> {
         self.inner
-            .send_uiaa(dummy::Request {
-                auth: dummy::Dummy {
-                    ev_type: "m.login.dummy".to_string(),
-                    session: session.to_string(),
-                },
+            .send(request_email_token::Request {
+                client_secret,
+                email,
+                send_attempt: js_int::UInt::from(send_attempt),
             })
             .await
+            .map(|res| res.sid)
             .map_err(Into::into)
     }
 
@@ -199,7 +646,36 @@ impl MatrixClient {
         Ok(())
     }
 
-    /// Sends a MessageEvent to the specified room.
+    /// True when the joined room `id` has `m.room.encryption` state.
+    async fn is_encrypted(&self, id: &RoomId) -> bool {
+        let rooms = self.inner.joined_rooms();
+        let room = rooms.read().await.get(id).cloned();
+        match room {
+            Some(room) => room.read().await.is_encrypted(),
+            None => false,
+        }
+    }
+
+    /// `room_send` only encrypts `m.room.message` events, anything else sent to an
+    /// encrypted room would go out in the clear so it is refused unless
+    /// `unencrypted_fallback` allows it.
+    async fn refuse_unencryptable(&self, id: &RoomId, what: &str) -> Result<()> {
+        if !self.is_encrypted(id).await {
+            return Ok(());
+        }
+        if self.unencrypted_fallback == UnencryptedFallback::Always {
+            tracing::warn!("sending {} unencrypted to the encrypted room {}", what, id);
+            return Ok(());
+        }
+        Err(Error::Encryption(format!(
+            r#"This room is encrypted and {} can not be encrypted yet.
+
+Nothing was sent, set `unencrypted_fallback` to "always" in the configs to send it in the clear."#,
+            what
+        )))
+    }
+
+    /// Sends a MessageEvent to the specified room, it is encrypted when the room is.
     ///
     /// # Arguments
     ///
@@ -218,6 +694,40 @@ impl MatrixClient {
             .map_err(Into::into)
     }
 
+    /// Uploads the image at `path` and returns the `m.image` content pointing at it.
+    ///
+    /// # Arguments
+    ///
+    /// * id - The room the image is sent to, the file would be uploaded in the clear
+    /// so encrypted rooms are checked before anything is uploaded.
+    /// * path - A png, jpeg, gif, webp or bmp file.
+    pub(crate) async fn upload_image(
+        &self,
+        id: &RoomId,
+        path: &Path,
+    ) -> Result<MessageEventContent> {
+        self.refuse_unencryptable(id, "an image").await?;
+        let content_type = image_mime(path).ok_or(Error::Rumatui(
+            "only png, jpeg, gif, webp and bmp images can be sent",
+        ))?;
+        let data = async_fs::read(path).await?;
+        let res = self
+            .inner
+            .upload(content_type, &mut data.as_slice())
+            .await?;
+
+        let body = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "image".to_string());
+        Ok(MessageEventContent::Image(ImageMessageEventContent {
+            body,
+            info: None,
+            url: Some(res.content_uri),
+            file: None,
+        }))
+    }
+
     /// Gets the `RoomEvent`s backwards in time, when user scrolls up.
     ///
     /// This uses the current sync token to look backwards from that point.
@@ -241,11 +751,8 @@ impl MatrixClient {
             to: None,
             dir: get_message_events::Direction::Backward,
             limit: js_int::UInt::new(30).unwrap(),
-            filter: None,
-            // filter: Some(RoomEventFilter {
-            //     lazy_load_options: LazyLoadOptions::Enabled { include_redundant_members: false, },
-            //     .. Default::default()
-            // }),
+            // only send the members of senders in this chunk instead of every member
+            filter: Some(lazy_load_filter()),
         };
 
         match self.inner.room_messages(request).await {
@@ -253,17 +760,85 @@ impl MatrixClient {
                 if let Some(end) = &res.end {
                     self.last_scroll.insert(id.clone(), end.clone());
                 }
+                // the size of each page shows what lazy loading saves, it is logged with -v
+                tracing::info!(
+                    "backfilled {} events of {} bytes with {} state events of {} bytes",
+                    res.chunk.len(),
+                    res.chunk
+                        .iter()
+                        .map(|ev| ev.json().get().len())
+                        .sum::<usize>(),
+                    res.state.len(),
+                    res.state
+                        .iter()
+                        .map(|ev| ev.json().get().len())
+                        .sum::<usize>(),
+                );
                 Ok(res)
             }
             err => err.map_err(Into::into),
         }
     }
 
+    /// Start the next backfill of a room from the live timeline again, used when
+    /// messages already loaded were dropped from memory.
+    pub(crate) fn reset_backfill(&mut self, id: &RoomId) {
+        self.last_scroll.remove(id);
+    }
+
+    /// Gets the `RoomEvent`s that were missed while offline, forward in time from
+    /// the last sync the room was seen at to the start of the live timeline.
+    ///
+    /// Returns no responses if the room has no gap in its timeline.
+    ///
+    /// # Arguments
+    ///
+    /// * id - A valid RoomId otherwise sending will fail.
+    pub(crate) async fn fill_gap(
+        &mut self,
+        id: &RoomId,
+    ) -> Result<Vec<get_message_events::Response>> {
+        let (mut from, to) = match self.gaps.read().await.get(id) {
+            Some(gap) => gap.clone(),
+            None => return Ok(vec![]),
+        };
+
+        let mut pages = vec![];
+        for _ in 0..MAX_GAP_PAGES {
+            let request = get_message_events::Request {
+                room_id: id.clone(),
+                from: from.clone(),
+                to: Some(to.clone()),
+                dir: get_message_events::Direction::Forward,
+                limit: js_int::UInt::new(50).unwrap(),
+                filter: Some(lazy_load_filter()),
+            };
+            let res = self.inner.room_messages(request).await?;
+            let done = res.chunk.is_empty() || res.end.as_ref().map_or(true, |end| *end == to);
+            if let Some(end) = &res.end {
+                from = end.clone();
+            }
+            pages.push(res);
+            if done {
+                self.gaps.write().await.remove(id);
+                save_gaps(&self.gaps, &self.gaps_path()).await;
+                return Ok(pages);
+            }
+        }
+
+        // the gap is too big to fill at once, the rest is filled next time the room is opened
+        self.gaps.write().await.insert(id.clone(), (from, to));
+        save_gaps(&self.gaps, &self.gaps_path()).await;
+        Ok(pages)
+    }
+
     pub(crate) async fn get_rooms_filtered(
         &mut self,
         filter: &str,
         network: RoomNetwork,
         token: Option<String>,
+        server: Option<String>,
+        limit: Option<u32>,
     ) -> Result<get_public_rooms_filtered::Response> {
         let filter = if filter.is_empty() {
             None
@@ -280,6 +855,13 @@ impl MatrixClient {
         if let Some(tkn) = token {
             request.since(tkn);
         }
+        // browse another server's directory through our homeserver
+        if let Some(server) = server {
+            request.server(server);
+        }
+        if let Some(limit) = limit {
+            request.limit(limit);
+        }
 
         self.inner
             .public_rooms_filtered(request)
@@ -302,6 +884,393 @@ impl MatrixClient {
             .map_err(Into::into)
     }
 
+    /// Joins a room by its id or one of its aliases.
+    ///
+    /// # Arguments
+    ///
+    /// * room - The id or alias of the room to join.
+    pub(crate) async fn join_room_by_id_or_alias(
+        &self,
+        room: RoomIdOrAliasId,
+    ) -> Result<join::Response> {
+        self.inner
+            .send(join::Request {
+                room_id_or_alias: room,
+            })
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Asks to join a room that uses the knock join rule.
+    ///
+    /// # Arguments
+    ///
+    /// * room - The id or alias of the room to knock on.
+    /// * reason - An optional message shown to the members who can let us in.
+    pub(crate) async fn knock_room(
+        &self,
+        room: RoomIdOrAliasId,
+        reason: Option<String>,
+    ) -> Result<knock::Response> {
+        self.inner
+            .send(knock::Request {
+                room_id_or_alias: room,
+                reason,
+            })
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The tags of every joined room.
+    ///
+    /// Tags are room account data which is only sent when it changes, so after a
+    /// restored session they are asked for, every room at once. A room whose tags can
+    /// not be fetched is left out and keeps its place in the room list.
+    pub(crate) async fn get_room_tags(&self) -> Result<RoomTags> {
+        let user_id = self.user_id()?;
+        let room_ids = self
+            .inner
+            .joined_rooms()
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let fetches = room_ids.into_iter().map(|room_id| {
+            let req = get_tags::Request {
+                user_id: user_id.clone(),
+                room_id: room_id.clone(),
+            };
+            async move {
+                match self.inner.send(req).await {
+                    Ok(res) => Some((
+                        room_id,
+                        res.tags.into_iter().map(|(t, _)| t).collect::<Vec<_>>(),
+                    )),
+                    Err(e) => {
+                        tracing::warn!("failed to fetch the tags of {} {}", room_id, e);
+                        None
+                    }
+                }
+            }
+        });
+        Ok(join_all(fetches).await.into_iter().flatten().collect())
+    }
+
+    /// The rooms the user's `m.direct` account data lists as direct chats.
+    ///
+    /// Like tags it is only sent when it changes, so after a restored session it is
+    /// asked for. An account that never had a direct chat has none.
+    pub(crate) async fn get_direct_rooms(&self) -> Result<HashSet<RoomId>> {
+        let req = get_direct_rooms::Request {
+            user_id: self.user_id()?,
+        };
+        match self.inner.send(req).await {
+            Ok(res) => Ok(res.rooms.into_iter().flat_map(|(_, rooms)| rooms).collect()),
+            Err(MatrixError::RumaResponse(FromHttpResponseError::Http(ServerError::Known(
+                ApiError {
+                    kind: ErrorKind::NotFound,
+                    ..
+                },
+            )))) => Ok(HashSet::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// How many devices of the room's joined members are not verified, the user's own
+    /// devices included.
+    ///
+    /// rumatui keeps no trust of its own, a device counts as verified when its owner
+    /// signed it with their self-signing key. Only the presence of the signature is
+    /// checked, the homeserver is trusted to hand out the right keys.
+    pub(crate) async fn unverified_devices(&self, room_id: &RoomId) -> Result<usize> {
+        let members = match self.inner.joined_rooms().read().await.get(room_id) {
+            Some(room) => room
+                .read()
+                .await
+                .joined_members
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>(),
+            None => return Ok(0),
+        };
+        let req = get_keys::Request {
+            device_keys: members.into_iter().map(|user| (user, vec![])).collect(),
+        };
+        let res = self.inner.send(req).await?;
+        Ok(count_unverified(&res.device_keys, &res.self_signing_keys))
+    }
+
+    /// Add `tag` to the room, the change comes back in the next sync.
+    pub(crate) async fn set_room_tag(&self, room_id: &RoomId, tag: &str) -> Result<()> {
+        self.inner
+            .send(create_tag::Request {
+                user_id: self.user_id()?,
+                room_id: room_id.clone(),
+                tag: tag.to_string(),
+                order: None,
+            })
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub(crate) async fn remove_room_tag(&self, room_id: &RoomId, tag: &str) -> Result<()> {
+        self.inner
+            .send(delete_tag::Request {
+                user_id: self.user_id()?,
+                room_id: room_id.clone(),
+                tag: tag.to_string(),
+            })
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    /// Vote for the answer with the id `answer` in the poll started by `poll`.
+    ///
+    /// `room_send` can not encrypt a poll response so encrypted rooms are refused unless
+    /// `unencrypted_fallback` allows it.
+    pub(crate) async fn send_poll_response(
+        &self,
+        room_id: &RoomId,
+        poll: &EventId,
+        answer: String,
+    ) -> Result<EventId> {
+        self.refuse_unencryptable(room_id, "a poll vote").await?;
+        self.inner
+            .send(send_poll_response::Request {
+                room_id: room_id.clone(),
+                txn_id: Uuid::new_v4().to_string(),
+                content: PollResponseEventContent {
+                    relates_to: RelatesTo {
+                        event_id: poll.clone(),
+                        rel_type: "m.reference".to_string(),
+                    },
+                    selections: vec![answer],
+                },
+            })
+            .await
+            .map(|res| res.event_id)
+            .map_err(Into::into)
+    }
+
+    /// Send `body` as a message in the thread started by `root`.
+    ///
+    /// In an encrypted room it is sent as a plain reply to `root`, `room_send` is the
+    /// only way to encrypt and its `MessageEventContent` has no thread relation. The app
+    /// still files the reply under the thread for this session, the server and other
+    /// clients only see a reply to the root.
+    pub(crate) async fn send_thread_reply(
+        &self,
+        room_id: &RoomId,
+        root: EventId,
+        body: String,
+    ) -> Result<EventId> {
+        if self.is_encrypted(room_id).await {
+            let content = MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: None,
+                relates_to: Some(ReplyTo {
+                    in_reply_to: InReplyTo { event_id: root },
+                }),
+            });
+            return self
+                .inner
+                .room_send(room_id, content, Some(Uuid::new_v4()))
+                .await
+                .map(|res| res.event_id)
+                .map_err(Into::into);
+        }
+        self.inner
+            .send(send_thread_reply::Request {
+                room_id: room_id.clone(),
+                txn_id: Uuid::new_v4().to_string(),
+                content: ThreadMessageEventContent::text(root, body),
+            })
+            .await
+            .map(|res| res.event_id)
+            .map_err(Into::into)
+    }
+
+    /// Replace the text of the message `original` with `body`.
+    ///
+    /// `room_send` can not carry the replacement so encrypted rooms are refused.
+    pub(crate) async fn send_edit(
+        &self,
+        room_id: &RoomId,
+        original: EventId,
+        body: String,
+    ) -> Result<EventId> {
+        self.refuse_unencryptable(room_id, "an edit").await?;
+        self.inner
+            .send(send_edit::Request {
+                room_id: room_id.clone(),
+                txn_id: Uuid::new_v4().to_string(),
+                content: ExtraMessageEventContent::EditEvent(EditEventContent::text(
+                    original, body,
+                )),
+            })
+            .await
+            .map(|res| res.event_id)
+            .map_err(Into::into)
+    }
+
+    /// The ids of the events pinned in a room, a room that never pinned an event has none.
+    pub(crate) async fn pinned_event_ids(&self, room_id: &RoomId) -> Result<Vec<EventId>> {
+        match self
+            .inner
+            .send(get_pinned_events::Request {
+                room_id: room_id.clone(),
+            })
+            .await
+        {
+            Ok(res) => Ok(res.pinned),
+            Err(MatrixError::RumaResponse(FromHttpResponseError::Http(ServerError::Known(
+                ApiError {
+                    kind: ErrorKind::NotFound,
+                    ..
+                },
+            )))) => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The events pinned in a room with the text of each event, fetched all at once. An
+    /// event that can not be fetched keeps its place with a placeholder.
+    pub(crate) async fn get_pinned_events(&self, room_id: &RoomId) -> Result<Vec<PinnedEvent>> {
+        let pinned = self.pinned_event_ids(room_id).await?;
+        let fetches = pinned.into_iter().map(|event_id| async move {
+            let req = get_event::Request {
+                room_id: room_id.clone(),
+                event_id: event_id.clone(),
+            };
+            match self.inner.send(req).await {
+                Ok(res) => PinnedEvent {
+                    event_id,
+                    sender: Some(res.sender),
+                    body: res
+                        .content
+                        .get("body")
+                        .and_then(|body| body.as_str())
+                        .unwrap_or("[event without text]")
+                        .to_string(),
+                },
+                Err(e) => {
+                    tracing::warn!("failed to fetch the pinned event {} {}", event_id, e);
+                    PinnedEvent {
+                        event_id,
+                        sender: None,
+                        body: "[event could not be loaded]".to_string(),
+                    }
+                }
+            }
+        });
+        Ok(join_all(fetches).await)
+    }
+
+    /// Redact the event, the returned event id is the redaction's.
+    ///
+    /// # Arguments
+    ///
+    /// * room_id - The room the event was sent in.
+    /// * event_id - The event to redact.
+    /// * reason - Why the event was redacted, shown to the other members.
+    pub(crate) async fn redact_event(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+        reason: Option<String>,
+    ) -> Result<EventId> {
+        self.inner
+            .room_redact(room_id, event_id, reason, Some(Uuid::new_v4()))
+            .await
+            .map(|res| res.event_id)
+            .map_err(Into::into)
+    }
+
+    /// React to the event `relates_to` with `key`, usually an emoji.
+    ///
+    /// `room_send` can not encrypt a `m.reaction` so encrypted rooms are refused unless
+    /// `unencrypted_fallback` allows it.
+    pub(crate) async fn send_reaction(
+        &self,
+        room_id: &RoomId,
+        relates_to: &EventId,
+        key: String,
+    ) -> Result<EventId> {
+        self.refuse_unencryptable(room_id, "a reaction").await?;
+        self.inner
+            .send(send_reaction::Request {
+                room_id: room_id.clone(),
+                txn_id: Uuid::new_v4().to_string(),
+                content: ExtraReactionEventContent {
+                    relates_to: ReactionEventContent::Annotation {
+                        event_id: relates_to.clone(),
+                        key,
+                    },
+                },
+            })
+            .await
+            .map(|res| res.event_id)
+            .map_err(Into::into)
+    }
+
+    /// Pin or unpin a single event keeping the rest of the room's pinned events.
+    ///
+    /// `m.room.pinned_events` is replaced as a whole and the homeserver has no
+    /// compare-and-swap for state events, so two clients pinning at once can lose one
+    /// of the pins. To keep the window small the pinned events are read right before
+    /// each write, and read again after it so a change another client's write replaced
+    /// is made again.
+    pub(crate) async fn pin_event(
+        &self,
+        room_id: &RoomId,
+        event_id: EventId,
+        pin: bool,
+    ) -> Result<()> {
+        let mut writes = 0;
+        loop {
+            let mut pinned = self.pinned_event_ids(room_id).await?;
+            if pinned.contains(&event_id) == pin {
+                return Ok(());
+            }
+            if writes == PIN_WRITES {
+                return Err(Error::Rumatui(
+                    "the pinned events kept changing, try pinning the message again",
+                ));
+            }
+            pinned.retain(|id| id != &event_id);
+            if pin {
+                pinned.push(event_id.clone());
+            }
+            self.set_pinned_events(room_id, pinned).await?;
+            writes += 1;
+        }
+    }
+
+    /// Replace the pinned events of a room, the events left out are unpinned.
+    pub(crate) async fn set_pinned_events(
+        &self,
+        room_id: &RoomId,
+        pinned: Vec<EventId>,
+    ) -> Result<()> {
+        self.inner
+            .send(set_pinned_events::Request {
+                room_id: room_id.clone(),
+                pinned,
+            })
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    fn user_id(&self) -> Result<UserId> {
+        self.user.clone().ok_or(Error::Rumatui(
+            "Room tags were used before logging in rumatui BUG",
+        ))
+    }
+
     /// Forgets the specified room.
     ///
     /// # Arguments
@@ -341,7 +1310,17 @@ impl MatrixClient {
         self.inner
             .kick_user(room_id, user_id, reason)
             .await
-            .map_err(Into::into)
+            .map_err(|error| match error {
+                // the server says why, usually the power level is too low
+                MatrixError::RumaResponse(FromHttpResponseError::Http(ServerError::Known(
+                    ApiError {
+                        kind: ErrorKind::Forbidden,
+                        message,
+                        ..
+                    },
+                ))) => Error::RumaResponse(message),
+                error => error.into(),
+            })
     }
 
     /// Send a request to notify the room of a user typing.
@@ -413,3 +1392,742 @@ impl MatrixClient {
             .map_err(Into::into)
     }
 }
+
+/// A filter asking the server to only include the member events of the senders in
+/// the returned chunk.
+fn lazy_load_filter() -> RoomEventFilter {
+    RoomEventFilter {
+        lazy_load_options: LazyLoadOptions::Enabled {
+            include_redundant_members: false,
+        },
+        ..Default::default()
+    }
+}
+
+/// Remember the rooms whose timeline was cut short in a sync response, the gaps are
+/// saved so the ones not filled before quitting are filled after the next start.
+///
+/// # Arguments
+///
+/// * since - The sync token the response was requested with.
+///
+/// * path - The file in the store directory the gaps are saved in.
+pub(crate) async fn record_gaps(
+    gaps: &TimelineGaps,
+    since: Option<String>,
+    response: &sync_events::Response,
+    path: &Path,
+) {
+    let since = match since {
+        Some(since) => since,
+        None => return,
+    };
+    let mut recorded = false;
+    {
+        let mut gaps = gaps.write().await;
+        for (room_id, room) in &response.rooms.join {
+            if !room.timeline.limited.unwrap_or(false) {
+                continue;
+            }
+            if let Some(prev_batch) = &room.timeline.prev_batch {
+                // keep the start of an older gap that has not been filled yet
+                let from = gaps
+                    .get(room_id)
+                    .map(|(from, _)| from.clone())
+                    .unwrap_or_else(|| since.clone());
+                gaps.insert(room_id.clone(), (from, prev_batch.clone()));
+                recorded = true;
+            }
+        }
+    }
+    if recorded {
+        save_gaps(gaps, path).await;
+    }
+}
+
+/// Write the gaps to `path`, a gap that can not be saved is still filled this run.
+async fn save_gaps(gaps: &TimelineGaps, path: &Path) {
+    let json = match serde_json::to_string(&*gaps.read().await) {
+        Ok(json) => json,
+        Err(e) => {
+            tracing::warn!("failed to serialize the timeline gaps {}", e);
+            return;
+        }
+    };
+    if let Err(e) = async_fs::write(path, json).await {
+        tracing::warn!("failed to save the timeline gaps {}", e);
+    }
+}
+
+/// Everything the app needs from a sync response that matrix-sdk does not emit, each
+/// event is parsed once for all of it.
+#[derive(Clone, Debug, Default)]
+pub struct SyncSummary {
+    pub stats: SyncStats,
+    /// The thread root, reply and room of every threaded message.
+    pub thread_replies: Vec<(EventId, EventId, RoomId)>,
+    /// The joined rooms that turned on encryption.
+    pub encrypted_rooms: Vec<RoomId>,
+    /// The id and JSON of every message.
+    pub sources: Vec<(EventId, String)>,
+    /// The room, sender and invited email of every invite by email.
+    pub third_party_invites: Vec<(RoomId, UserId, String)>,
+    /// The tags of the rooms whose `m.tag` account data changed.
+    pub tags: RoomTags,
+    /// The topic of each room the user was invited to that has one.
+    pub invite_topics: Vec<(RoomId, String)>,
+    /// The direct chats listed in `m.direct`, only set when the account data changed.
+    pub direct_rooms: Option<HashSet<RoomId>>,
+}
+
+/// The rooms of `m.direct` content, it maps each user to their direct chats.
+fn direct_rooms(content: &serde_json::Value) -> HashSet<RoomId> {
+    content
+        .as_object()
+        .into_iter()
+        .flat_map(|users| users.values())
+        .filter_map(|rooms| rooms.as_array())
+        .flatten()
+        .filter_map(|room| RoomId::try_from(room.as_str()?).ok())
+        .collect()
+}
+
+/// The devices not signed by the self-signing key of their owner, see
+/// `MatrixClient::unverified_devices`.
+fn count_unverified(
+    devices: &BTreeMap<UserId, BTreeMap<String, serde_json::Value>>,
+    self_signing: &BTreeMap<UserId, serde_json::Value>,
+) -> usize {
+    devices
+        .iter()
+        .map(|(user, devices)| {
+            // the ids of the key look like "ed25519:<public key>"
+            let key_ids = self_signing
+                .get(user)
+                .and_then(|key| key["keys"].as_object())
+                .map(|keys| keys.keys().cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+            devices
+                .values()
+                .filter(|device| {
+                    let signatures = &device["signatures"][user.as_str()];
+                    !key_ids.iter().any(|id| signatures[id.as_str()].is_string())
+                })
+                .count()
+        })
+        .sum()
+}
+
+/// Go through the joined rooms of a sync response once, see `SyncSummary`.
+pub(crate) fn summarize_sync(response: &sync_events::Response) -> SyncSummary {
+    let mut summary = SyncSummary::default();
+    for (room_id, room) in &response.rooms.join {
+        let mut encrypted = false;
+        for event in &room.state.events {
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(event.json().get()) {
+                encrypted |= json["type"] == "m.room.encryption";
+            }
+        }
+
+        if !room.timeline.events.is_empty() {
+            summary.stats.active_rooms += 1;
+        }
+        for event in &room.timeline.events {
+            let raw = event.json().get();
+            let json = match serde_json::from_str::<serde_json::Value>(raw) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            match json["type"].as_str().unwrap_or_default() {
+                "m.room.message" => {
+                    summary.stats.new_messages += 1;
+                    if let Some((root, reply)) = ThreadedEvent::parse_value(&json) {
+                        summary.thread_replies.push((root, reply, room_id.clone()));
+                    }
+                    if let Some(event_id) = event_id_of(&json) {
+                        summary.sources.push((event_id, raw.to_string()));
+                    }
+                }
+                // the message can not be read yet but it is still new
+                "m.room.encrypted" => summary.stats.new_messages += 1,
+                "m.room.encryption" => encrypted = true,
+                "m.room.third_party_invite" => {
+                    if let Some((sender, email)) = third_party_invite(&json) {
+                        summary
+                            .third_party_invites
+                            .push((room_id.clone(), sender, email));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if encrypted {
+            summary.encrypted_rooms.push(room_id.clone());
+        }
+
+        for event in &room.account_data.events {
+            let json = match serde_json::from_str::<serde_json::Value>(event.json().get()) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            if json["type"] != "m.tag" {
+                continue;
+            }
+            let names = json["content"]["tags"]
+                .as_object()
+                .map(|t| t.keys().cloned().collect())
+                .unwrap_or_default();
+            summary.tags.insert(room_id.clone(), names);
+        }
+    }
+
+    for event in &response.account_data.events {
+        let json = match serde_json::from_str::<serde_json::Value>(event.json().get()) {
+            Ok(json) => json,
+            Err(_) => continue,
+        };
+        if json["type"] == "m.direct" {
+            summary.direct_rooms = Some(direct_rooms(&json["content"]));
+        }
+    }
+
+    // matrix-sdk's `Room` has no topic and the emitter skips the stripped topic event
+    for (room_id, room) in &response.rooms.invite {
+        for event in &room.invite_state.events {
+            let json = match serde_json::from_str::<serde_json::Value>(event.json().get()) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            if json["type"] != "m.room.topic" {
+                continue;
+            }
+            if let Some(topic) = json["content"]["topic"].as_str().filter(|t| !t.is_empty()) {
+                summary
+                    .invite_topics
+                    .push((room_id.clone(), topic.to_string()));
+            }
+        }
+    }
+    summary
+}
+
+fn event_id_of(json: &serde_json::Value) -> Option<EventId> {
+    EventId::try_from(json["event_id"].as_str()?).ok()
+}
+
+/// The id of the event and the JSON it was parsed from, only messages have their source
+/// shown so other events are skipped.
+pub(crate) fn event_source(json: &str) -> Option<(EventId, String)> {
+    let value = serde_json::from_str::<serde_json::Value>(json).ok()?;
+    if value["type"] != "m.room.message" {
+        return None;
+    }
+    Some((event_id_of(&value)?, json.to_string()))
+}
+
+/// The sender and the name shown for the invited email of a `m.room.third_party_invite`
+/// event, a revoked invite has empty content.
+fn third_party_invite(json: &serde_json::Value) -> Option<(UserId, String)> {
+    if json["type"] != "m.room.third_party_invite" {
+        return None;
+    }
+    let display_name = json["content"]["display_name"].as_str()?.to_string();
+    let sender = UserId::try_from(json["sender"].as_str()?).ok()?;
+    Some((sender, display_name))
+}
+
+/// The content type of an image file, from its extension.
+fn image_mime(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use matrix_sdk::events::room::message::TextMessageEventContent;
+    use mockito::{mock, Matcher, Mock};
+
+    use super::*;
+
+    const ROOM: &str = "!SVkFJHzfwvuaIEawgC:localhost";
+
+    /// A store directory nothing else uses so tests never touch `~/.rumatui`, the
+    /// device id is kept there too. It is deleted when dropped.
+    fn store_path() -> tempfile::TempDir {
+        tempfile::Builder::new()
+            .prefix("rumatui-test-")
+            .tempdir()
+            .unwrap()
+    }
+
+    /// A client logged in to the mock homeserver, the mocks stop answering and the
+    /// store is deleted when dropped.
+    async fn logged_in() -> (MatrixClient, Vec<Mock>, tempfile::TempDir) {
+        let mocks = vec![
+            mock("POST", "/_matrix/client/r0/login")
+                .with_status(200)
+                .with_body(include_str!("../../test_data/login.json"))
+                .create(),
+            mock(
+                "GET",
+                Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            )
+            .with_status(200)
+            .with_body(include_str!("../../test_data/sync.json"))
+            .create(),
+        ];
+
+        let store = store_path();
+        let mut client = MatrixClient::with_store(
+            &mockito::server_url(),
+            store.path(),
+            UnencryptedFallback::Never,
+        )
+        .unwrap();
+        client
+            .login("example".to_string(), "wordpass".to_string())
+            .await
+            .unwrap();
+        (client, mocks, store)
+    }
+
+    fn json(event: &str) -> serde_json::Value {
+        serde_json::from_str(event).unwrap()
+    }
+
+    fn text(body: &str) -> MessageEventContent {
+        MessageEventContent::Text(TextMessageEventContent::new_plain(body))
+    }
+
+    #[test]
+    fn each_account_has_its_own_store() {
+        let root = Path::new("/store");
+        assert_eq!(
+            root.join("accounts").join("%40me%3Aexample.org"),
+            account_dir(root, "@me:example.org")
+        );
+        assert_ne!(
+            account_dir(root, "@me:example.org"),
+            account_dir(root, "@me:home.net")
+        );
+        // a localpart may contain a slash, it must not share the directory of an
+        // underscore
+        assert_eq!(
+            root.join("accounts").join("%40a%2Fb%3Aexample.org"),
+            account_dir(root, "@a/b:example.org")
+        );
+        assert_ne!(
+            account_dir(root, "@a/b:example.org"),
+            account_dir(root, "@a_b:example.org")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_saved_session_logs_in_without_the_password() {
+        let (client, _mocks, _store) = logged_in().await;
+
+        let mut restored = MatrixClient::with_store(
+            &mockito::server_url(),
+            &client.store_path,
+            UnencryptedFallback::Never,
+        )
+        .unwrap();
+        let (_, user, offline) = restored.restore_session().await.unwrap().unwrap();
+        assert_eq!("@example:localhost", user.as_str());
+        assert!(!offline);
+
+        let fresh_store = store_path();
+        let mut fresh = MatrixClient::with_store(
+            &mockito::server_url(),
+            fresh_store.path(),
+            UnencryptedFallback::Never,
+        )
+        .unwrap();
+        assert!(fresh.restore_session().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_server_starts_from_the_cached_rooms() {
+        let (client, _mocks, _store) = logged_in().await;
+
+        // nothing listens on port 1
+        let mut cached = MatrixClient::with_store(
+            "http://localhost:1",
+            &client.store_path,
+            UnencryptedFallback::Never,
+        )
+        .unwrap();
+        let (_, user, offline) = cached.restore_session().await.unwrap().unwrap();
+        assert_eq!("@example:localhost", user.as_str());
+        assert!(offline);
+    }
+
+    #[tokio::test]
+    async fn login_syncs_the_joined_rooms() {
+        let (client, _mocks, _store) = logged_in().await;
+
+        assert_eq!(
+            Some("@example:localhost"),
+            client.user.as_ref().map(|user| user.as_str())
+        );
+        assert_eq!(
+            Some("s526_47314_0_7_1_1_1_11444_1".to_string()),
+            client.sync_token()
+        );
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let rooms = client.inner.joined_rooms();
+        let rooms = rooms.read().await;
+        assert!(rooms.contains_key(&room_id));
+        // the first sync has no older sync token so nothing was missed
+        assert!(client.gaps.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unfilled_gaps_are_loaded_after_a_restart() {
+        let store = store_path();
+        let client = MatrixClient::with_store(
+            &mockito::server_url(),
+            store.path(),
+            UnencryptedFallback::Never,
+        )
+        .unwrap();
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let gap = ("s1_old".to_string(), "t2_new".to_string());
+        client
+            .gaps
+            .write()
+            .await
+            .insert(room_id.clone(), gap.clone());
+        save_gaps(&client.gaps, &client.gaps_path()).await;
+
+        let restarted = MatrixClient::with_store(
+            &mockito::server_url(),
+            store.path(),
+            UnencryptedFallback::Never,
+        )
+        .unwrap();
+        restarted.load_gaps().await;
+        assert_eq!(Some(&gap), restarted.gaps.read().await.get(&room_id));
+    }
+
+    #[tokio::test]
+    async fn devices_not_signed_by_their_owner_are_unverified() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _keys = mock("POST", "/_matrix/client/r0/keys/query")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "device_keys": {
+                        "@example:localhost": {
+                            "SIGNED": {
+                                "signatures": {
+                                    "@example:localhost": { "ed25519:SSK": "sig" }
+                                }
+                            },
+                            "UNSIGNED": { "signatures": {} }
+                        },
+                        "@alice:localhost": {
+                            "PHONE": {
+                                "signatures": {
+                                    "@alice:localhost": { "ed25519:PHONE": "self" }
+                                }
+                            }
+                        }
+                    },
+                    "self_signing_keys": {
+                        "@example:localhost": { "keys": { "ed25519:SSK": "SSK" } }
+                    }
+                }"#,
+            )
+            .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        // alice never set up cross-signing so her device counts too
+        assert_eq!(2, client.unverified_devices(&room_id).await.unwrap());
+        // a room that is not joined has nobody to check
+        let unknown = RoomId::try_from("!unknown:localhost").unwrap();
+        assert_eq!(0, client.unverified_devices(&unknown).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn direct_rooms_come_from_m_direct() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _direct = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$".to_string()),
+        )
+        .with_status(200)
+        .with_body(
+            r#"{
+                "@alice:localhost": ["!alice:localhost"],
+                "@bob:localhost": ["!bob:localhost", "!old-bob:localhost"]
+            }"#,
+        )
+        .create();
+
+        let direct = client.get_direct_rooms().await.unwrap();
+        assert_eq!(3, direct.len());
+        assert!(direct.contains(&RoomId::try_from("!old-bob:localhost").unwrap()));
+        assert!(!direct.contains(&RoomId::try_from(ROOM).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn send_message_returns_the_event_id() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _send = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.message/.*".to_string()),
+        )
+        .with_status(200)
+        .with_body(include_str!("../../test_data/event_id.json"))
+        .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let res = client
+            .send_message(&room_id, text("hello"), Uuid::new_v4())
+            .await
+            .unwrap();
+        assert_eq!("$h29iv0s8:localhost", res.event_id.as_str());
+    }
+
+    #[tokio::test]
+    async fn upload_image_points_at_the_uploaded_file() {
+        let (client, _mocks, store) = logged_in().await;
+        let _upload = mock("POST", "/_matrix/media/r0/upload")
+            .match_header("content-type", "image/png")
+            .with_status(200)
+            .with_body(r#"{ "content_uri": "mxc://localhost/AQwafuaFswefuhsfAFAgsw" }"#)
+            .create();
+
+        let path = store.path().join("Pic.PNG");
+        std::fs::write(&path, b"not really a png").unwrap();
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        match client.upload_image(&room_id, &path).await.unwrap() {
+            MessageEventContent::Image(image) => {
+                assert_eq!("Pic.PNG", image.body);
+                assert_eq!(
+                    Some("mxc://localhost/AQwafuaFswefuhsfAFAgsw"),
+                    image.url.as_deref()
+                );
+            }
+            _ => panic!("an image was uploaded"),
+        }
+
+        let text = store.path().join("notes.txt");
+        std::fs::write(&text, b"hello").unwrap();
+        assert!(client.upload_image(&room_id, &text).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_edit_replaces_the_original() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _edit = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.message/.*".to_string()),
+        )
+        .match_body(Matcher::Json(serde_json::json!({
+            "body": " * fixed",
+            "msgtype": "m.text",
+            "m.new_content": { "body": "fixed", "msgtype": "m.text" },
+            "m.relates_to": { "event_id": "$original:localhost", "rel_type": "m.replace" }
+        })))
+        .with_status(200)
+        .with_body(include_str!("../../test_data/event_id.json"))
+        .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let original = EventId::try_from("$original:localhost").unwrap();
+        let edit = client
+            .send_edit(&room_id, original, "fixed".to_string())
+            .await
+            .unwrap();
+        assert_eq!("$h29iv0s8:localhost", edit.as_str());
+    }
+
+    #[tokio::test]
+    async fn redact_event_sends_the_reason() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _redact = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/redact/.*/.*".to_string()),
+        )
+        .match_body(Matcher::Json(serde_json::json!({ "reason": "typo" })))
+        .with_status(200)
+        .with_body(include_str!("../../test_data/event_id.json"))
+        .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let event_id = EventId::try_from("$target:localhost").unwrap();
+        let redaction = client
+            .redact_event(&room_id, &event_id, Some("typo".to_string()))
+            .await
+            .unwrap();
+        assert_eq!("$h29iv0s8:localhost", redaction.as_str());
+    }
+
+    #[tokio::test]
+    async fn send_reaction_annotates_the_event() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _react = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/m.reaction/.*".to_string()),
+        )
+        .match_body(Matcher::Json(serde_json::json!({
+            "m.relates_to": {
+                "rel_type": "m.annotation",
+                "event_id": "$target:localhost",
+                "key": "\u{1f44d}"
+            }
+        })))
+        .with_status(200)
+        .with_body(include_str!("../../test_data/event_id.json"))
+        .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let target = EventId::try_from("$target:localhost").unwrap();
+        let reaction = client
+            .send_reaction(&room_id, &target, "\u{1f44d}".to_string())
+            .await
+            .unwrap();
+        assert_eq!("$h29iv0s8:localhost", reaction.as_str());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_sends_wait_the_requested_time() {
+        let (client, _mocks, _store) = logged_in().await;
+        let _send = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/m.room.message/.*".to_string()),
+        )
+        .with_status(429)
+        .with_body(include_str!("../../test_data/limit_exceeded.json"))
+        .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let err = client
+            .send_message(&room_id, text("hello"), Uuid::new_v4())
+            .await
+            .unwrap_err();
+        assert_eq!(Some(Duration::from_millis(2000)), err.retry_after());
+    }
+
+    #[tokio::test]
+    async fn get_messages_continues_from_the_last_batch() {
+        let (mut client, _mocks, _store) = logged_in().await;
+        let _first = mock(
+            "GET",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/rooms/.*/messages\?.*from=s526_47314_0_7_1_1_1_11444_1.*$"
+                    .to_string(),
+            ),
+        )
+        .with_status(200)
+        .with_body(include_str!("../../test_data/room_messages.json"))
+        .create();
+
+        let room_id = RoomId::try_from(ROOM).unwrap();
+        let res = client.get_messages(&room_id).await.unwrap();
+        assert_eq!(1, res.chunk.len());
+
+        // the next page starts where the last one ended
+        let _next = mock(
+            "GET",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/rooms/.*/messages\?.*from=t47409-4357353_219380_26003_2265.*$"
+                    .to_string(),
+            ),
+        )
+        .with_status(200)
+        .with_body(include_str!("../../test_data/room_messages.json"))
+        .create();
+        assert!(client.get_messages(&room_id).await.is_ok());
+
+        client.reset_backfill(&room_id);
+        assert!(!client.last_scroll.contains_key(&room_id));
+    }
+
+    // the client, the handle and everything sent over the channels move between
+    // tasks, this stops compiling if a field that is not `Send` sneaks in
+    #[test]
+    fn task_types_are_send() {
+        fn is_send<T: Send>() {}
+
+        is_send::<MatrixClient>();
+        is_send::<client_loop::MatrixEventHandle>();
+        is_send::<client_loop::UserRequest>();
+        is_send::<client_loop::RequestResult>();
+        is_send::<event_stream::EventStream>();
+        is_send::<event_stream::StateResult>();
+    }
+
+    #[test]
+    fn third_party_invites_show_the_invited_email() {
+        assert_eq!(
+            Some((
+                UserId::try_from("@example:localhost").unwrap(),
+                "ex...@example.org".to_string()
+            )),
+            third_party_invite(&json(include_str!(
+                "../../test_data/third_party_invite.json"
+            )))
+        );
+        // a revoked invite keeps the state key but drops the content
+        let revoked = serde_json::json!({
+            "content": {},
+            "sender": "@example:localhost",
+            "state_key": "pc98",
+            "type": "m.room.third_party_invite",
+        });
+        assert_eq!(None, third_party_invite(&revoked));
+        assert_eq!(
+            None,
+            third_party_invite(&json(include_str!("../../test_data/message_text.json")))
+        );
+    }
+
+    #[test]
+    fn only_message_sources_are_kept() {
+        assert!(event_source(include_str!("../../test_data/message_text.json")).is_some());
+        assert_eq!(
+            None,
+            event_source(include_str!("../../test_data/third_party_invite.json"))
+        );
+    }
+
+    #[test]
+    fn usernames_are_normalized() {
+        let user = Username::parse("  @devin:matrix.org ").unwrap();
+        assert_eq!("devin", user.localpart);
+        assert_eq!("@devin:matrix.org", user.login_id());
+        assert_eq!("devin", Username::parse("devin\n").unwrap().login_id());
+
+        assert_eq!(Err("enter a user name"), Username::parse(" @ "));
+        assert!(Username::parse("dev in").is_err());
+        assert!(Username::parse("devin:").is_err());
+
+        assert!(Username::parse("Devin")
+            .unwrap()
+            .check_registerable()
+            .is_err());
+        assert!(Username::parse("devin_r528")
+            .unwrap()
+            .check_registerable()
+            .is_ok());
+    }
+
+    #[test]
+    fn emails_are_checked() {
+        assert!(check_email(" devin@example.org ").is_ok());
+        assert!(check_email("devin@localhost").is_err());
+        assert!(check_email("@example.org").is_err());
+        assert!(check_email("devin@example.").is_err());
+        assert!(check_email("dev in@example.org").is_err());
+    }
+}
@@ -43,22 +43,68 @@ impl Into<RegisterResponse> for Response {
     }
 }
 
-pub mod dummy {
+pub mod stage {
     use matrix_sdk::{
         api::r0::{account::register::Response as RegisterResponse, uiaa::UiaaResponse},
         identifiers::{DeviceId, UserId},
     };
 
+    /// The id and secret of an email validation session.
     #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-    pub struct Dummy {
+    pub struct ThreepidCreds {
+        pub sid: String,
+        pub client_secret: String,
+    }
+
+    /// Who is authenticating with a password.
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+    pub struct UserIdentifier {
+        #[serde(rename = "type")]
+        pub id_type: String,
+        pub user: String,
+    }
+
+    /// The auth object completing one stage of UIAA.
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+    pub struct AuthData {
         #[serde(rename = "type")]
         pub ev_type: String,
         pub session: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub threepid_creds: Option<ThreepidCreds>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub identifier: Option<UserIdentifier>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub password: Option<String>,
+    }
+
+    impl AuthData {
+        pub fn new(ev_type: &str, session: String) -> Self {
+            Self {
+                ev_type: ev_type.to_string(),
+                session,
+                threepid_creds: None,
+                identifier: None,
+                password: None,
+            }
+        }
+
+        /// The `m.login.password` stage, used to authenticate again while logged in.
+        pub fn password(session: String, user: String, password: String) -> Self {
+            Self {
+                identifier: Some(UserIdentifier {
+                    id_type: "m.id.user".to_string(),
+                    user,
+                }),
+                password: Some(password),
+                ..Self::new("m.login.password", session)
+            }
+        }
     }
 
     ruma_api::ruma_api! {
         metadata: {
-            description: "Send session pings to the server during UIAA.",
+            description: "Complete a stage of UIAA while registering.",
             method: POST,
             name: "register",
             path: "/_matrix/client/r0/register?kind=user",
@@ -67,7 +113,7 @@ pub mod dummy {
         }
 
         request: {
-            pub auth: Dummy,
+            pub auth: AuthData,
         }
 
         response: {
@@ -90,3 +136,83 @@ pub mod dummy {
         }
     }
 }
+
+pub mod request_email_token {
+    use js_int::UInt;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Have the homeserver email a validation link before registering.",
+            method: POST,
+            name: "request_registration_token_via_email",
+            path: "/_matrix/client/r0/register/email/requestToken",
+            rate_limited: false,
+            requires_authentication: false,
+        }
+
+        request: {
+            pub client_secret: String,
+            pub email: String,
+            pub send_attempt: UInt,
+        }
+
+        response: {
+            pub sid: String,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod deactivate {
+    use super::stage::AuthData;
+    use matrix_sdk::api::r0::uiaa::UiaaResponse;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Deactivate the logged in account, this can not be undone.",
+            method: POST,
+            name: "deactivate",
+            path: "/_matrix/client/r0/account/deactivate",
+            rate_limited: true,
+            requires_authentication: true,
+        }
+
+        request: {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub auth: Option<AuthData>,
+        }
+
+        response: {}
+
+        error: UiaaResponse
+    }
+}
+
+pub mod change_password {
+    use super::stage::AuthData;
+    use matrix_sdk::api::r0::uiaa::UiaaResponse;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Change the password of the logged in account.",
+            method: POST,
+            name: "change_password",
+            path: "/_matrix/client/r0/account/password",
+            rate_limited: true,
+            requires_authentication: true,
+        }
+
+        request: {
+            pub new_password: String,
+            /// Log out every other device of the account.
+            pub logout_devices: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub auth: Option<AuthData>,
+        }
+
+        response: {}
+
+        error: UiaaResponse
+    }
+}
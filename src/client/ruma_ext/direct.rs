@@ -0,0 +1,32 @@
+//! The direct chats of the user, kept in the `m.direct` account data.
+
+pub mod get_direct_rooms {
+    use std::collections::BTreeMap;
+
+    use matrix_sdk::identifiers::{RoomId, UserId};
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the rooms the user marked as direct chats.",
+            method: GET,
+            name: "get_direct_rooms",
+            path: "/_matrix/client/r0/user/:user_id/account_data/m.direct",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The user the account data belongs to.
+            #[ruma_api(path)]
+            pub user_id: UserId,
+        }
+
+        response: {
+            /// The direct chat rooms with each user.
+            #[ruma_api(body)]
+            pub rooms: BTreeMap<UserId, Vec<RoomId>>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
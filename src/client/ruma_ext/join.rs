@@ -0,0 +1,25 @@
+use matrix_sdk::identifiers::{RoomId, RoomIdOrAliasId};
+
+ruma_api::ruma_api! {
+    metadata: {
+        description: "Join a room by its id or one of its aliases.",
+        method: POST,
+        name: "join_room_by_id_or_alias",
+        path: "/_matrix/client/r0/join/:room_id_or_alias",
+        rate_limited: true,
+        requires_authentication: true,
+    }
+
+    request: {
+        /// The room to join.
+        #[ruma_api(path)]
+        pub room_id_or_alias: RoomIdOrAliasId,
+    }
+
+    response: {
+        /// The room that was joined.
+        pub room_id: RoomId,
+    }
+
+    error: matrix_sdk::api::Error
+}
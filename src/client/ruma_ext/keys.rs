@@ -0,0 +1,38 @@
+//! The device keys of the room members, asked for to find the devices their owners
+//! have not verified.
+
+pub mod get_keys {
+    use std::collections::BTreeMap;
+
+    use matrix_sdk::identifiers::UserId;
+    use serde_json::Value as JsonValue;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the device keys and self-signing keys of users.",
+            method: POST,
+            name: "get_keys",
+            path: "/_matrix/client/r0/keys/query",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The users whose keys are wanted with their devices, no devices asks for
+            /// all of them.
+            pub device_keys: BTreeMap<UserId, Vec<String>>,
+        }
+
+        response: {
+            /// The keys of each device of each user keyed by device id.
+            #[serde(default)]
+            pub device_keys: BTreeMap<UserId, BTreeMap<String, JsonValue>>,
+
+            /// The key each user signs their own devices with once they verified them.
+            #[serde(default)]
+            pub self_signing_keys: BTreeMap<UserId, JsonValue>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
@@ -0,0 +1,29 @@
+use matrix_sdk::identifiers::{RoomId, RoomIdOrAliasId};
+
+ruma_api::ruma_api! {
+    metadata: {
+        description: "Ask to join a room with the knock join rule.",
+        method: POST,
+        name: "knock",
+        path: "/_matrix/client/r0/knock/:room_id_or_alias",
+        rate_limited: true,
+        requires_authentication: true,
+    }
+
+    request: {
+        /// The room the user is asking to join.
+        #[ruma_api(path)]
+        pub room_id_or_alias: RoomIdOrAliasId,
+
+        /// Shown to the members of the room that can accept the knock.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub reason: Option<String>,
+    }
+
+    response: {
+        /// The room that was knocked on.
+        pub room_id: RoomId,
+    }
+
+    error: matrix_sdk::api::Error
+}
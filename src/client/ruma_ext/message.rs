@@ -9,6 +9,140 @@ pub struct RelatesTo {
     pub rel_type: String,
 }
 
+/// The `rel_type` of a message sent in a thread.
+pub const THREAD: &str = "m.thread";
+
+/// The `rel_type` of an edit replacing an earlier message.
+pub const REPLACE: &str = "m.replace";
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct RelationContent {
+    #[serde(rename = "m.relates_to")]
+    relates_to: Option<RelatesTo>,
+}
+
+/// Just enough of a room event to tell whether it was sent in a thread, ruma drops
+/// relations it does not know about.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ThreadedEvent {
+    pub event_id: EventId,
+    content: RelationContent,
+}
+
+impl ThreadedEvent {
+    /// The thread root and the event id of the reply when the event was sent in a thread.
+    pub fn parse(json: &str) -> Option<(EventId, EventId)> {
+        serde_json::from_str::<Self>(json).ok()?.thread()
+    }
+
+    /// `parse` for an event already parsed as JSON.
+    pub fn parse_value(json: &serde_json::Value) -> Option<(EventId, EventId)> {
+        <Self as serde::Deserialize>::deserialize(json)
+            .ok()?
+            .thread()
+    }
+
+    fn thread(self) -> Option<(EventId, EventId)> {
+        match self.content.relates_to {
+            Some(RelatesTo { event_id, rel_type }) if rel_type == THREAD => {
+                Some((event_id, self.event_id))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct InReplyTo {
+    /// The event being replied to.
+    pub event_id: EventId,
+}
+
+/// A `m.thread` relation, the reply fields are for clients that do not show threads.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ThreadRelatesTo {
+    /// Holds the thread root and the `m.thread` relation.
+    #[serde(flatten)]
+    pub relation: RelatesTo,
+
+    /// True when `in_reply_to` is only a fallback and not a reply to that event.
+    pub is_falling_back: bool,
+
+    /// The event clients without thread support show this message as a reply to.
+    #[serde(rename = "m.in_reply_to")]
+    pub in_reply_to: InReplyTo,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ThreadMessageEventContent {
+    /// The plain text body of the message.
+    pub body: String,
+
+    /// Since this type is not an enum we just hold the message type directly.
+    pub msgtype: String,
+
+    /// The thread this message is sent in.
+    #[serde(rename = "m.relates_to")]
+    pub relates_to: ThreadRelatesTo,
+}
+
+impl ThreadMessageEventContent {
+    /// A text message sent in the thread started by `root`, falling back to a reply
+    /// to the root.
+    pub fn text(root: EventId, body: String) -> Self {
+        Self {
+            body,
+            msgtype: "m.text".to_string(),
+            relates_to: ThreadRelatesTo {
+                relation: RelatesTo {
+                    event_id: root.clone(),
+                    rel_type: THREAD.to_string(),
+                },
+                is_falling_back: true,
+                in_reply_to: InReplyTo { event_id: root },
+            },
+        }
+    }
+}
+
+pub mod send_thread_reply {
+    use matrix_sdk::identifiers::{EventId, RoomId};
+
+    use super::ThreadMessageEventContent;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Send a message in a thread.",
+            method: PUT,
+            name: "send_thread_reply",
+            path: "/_matrix/client/r0/rooms/:room_id/send/m.room.message/:txn_id",
+            rate_limited: true,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room the thread is in.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The transaction ID for this event.
+            #[ruma_api(path)]
+            pub txn_id: String,
+
+            /// The message.
+            #[ruma_api(body)]
+            pub content: ThreadMessageEventContent,
+        }
+
+        response: {
+            /// The unique identifier of the message.
+            pub event_id: EventId,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct MessageReplacement {
     /// The plain text body of the new message.
@@ -41,9 +175,67 @@ pub struct EditEventContent {
     pub relates_to: RelatesTo,
 }
 
+impl EditEventContent {
+    /// A text edit replacing the message `original` with `body`, clients that do not
+    /// show edits show the body starting with ` * ` as a new message.
+    pub fn text(original: EventId, body: String) -> Self {
+        Self {
+            body: format!(" * {}", body),
+            new_content: MessageReplacement {
+                body,
+                format: None,
+                formatted_body: None,
+                msgtype: "m.text".to_string(),
+            },
+            relates_to: RelatesTo {
+                event_id: original,
+                rel_type: REPLACE.to_string(),
+            },
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(tag = "msgtype")]
 pub enum ExtraMessageEventContent {
     #[serde(rename = "m.text")]
     EditEvent(EditEventContent),
 }
+
+pub mod send_edit {
+    use matrix_sdk::identifiers::{EventId, RoomId};
+
+    use super::ExtraMessageEventContent;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Send an edit of an earlier message.",
+            method: PUT,
+            name: "send_edit",
+            path: "/_matrix/client/r0/rooms/:room_id/send/m.room.message/:txn_id",
+            rate_limited: true,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room the edited message is in.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The transaction ID for this event.
+            #[ruma_api(path)]
+            pub txn_id: String,
+
+            /// The edit.
+            #[ruma_api(body)]
+            pub content: ExtraMessageEventContent,
+        }
+
+        response: {
+            /// The unique identifier of the edit.
+            pub event_id: EventId,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
@@ -6,11 +6,21 @@ use serde_json::Value as JsonValue;
 use matrix_sdk::identifiers::{EventId, RoomId, UserId};
 
 pub mod auth;
+pub mod direct;
+pub mod join;
+pub mod keys;
+pub mod knock;
 pub mod message;
+pub mod pinned;
+pub mod poll;
 pub mod reaction;
+pub mod sticker;
+pub mod tag;
 
 pub use message::ExtraMessageEventContent;
+pub use poll::{PollEndEventContent, PollResponseEventContent, PollStartEventContent};
 pub use reaction::ExtraReactionEventContent;
+pub use sticker::StickerEventContent;
 
 pub type RumaUnsupportedEvent = RumaUnsupportedRoomEvent<ExtraRoomEventContent>;
 
@@ -21,6 +31,14 @@ pub enum ExtraRoomEventContent {
     Message { content: ExtraMessageEventContent },
     #[serde(rename = "m.reaction")]
     Reaction { content: ExtraReactionEventContent },
+    #[serde(rename = "m.sticker")]
+    Sticker { content: StickerEventContent },
+    #[serde(rename = "m.poll.start")]
+    Poll { content: PollStartEventContent },
+    #[serde(rename = "m.poll.response")]
+    PollResponse { content: PollResponseEventContent },
+    #[serde(rename = "m.poll.end")]
+    PollEnd { content: PollEndEventContent },
 }
 
 #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -135,3 +153,96 @@ fn test_reaction_event() {
             .unwrap()
     )
 }
+
+#[test]
+fn test_sticker_event() {
+    use matrix_sdk::events::EventJson;
+
+    let ev = serde_json::from_str::<EventJson<RumaUnsupportedEvent>>(include_str!(
+        "../../../test_data/sticker.json"
+    ))
+    .unwrap()
+    .deserialize()
+    .unwrap();
+
+    let json = serde_json::to_string_pretty(&ev).unwrap();
+    assert_eq!(
+        ev,
+        serde_json::from_str::<EventJson<RumaUnsupportedEvent>>(&json)
+            .unwrap()
+            .deserialize()
+            .unwrap()
+    )
+}
+
+#[test]
+fn test_thread_reply_event() {
+    use matrix_sdk::identifiers::EventId;
+    use std::convert::TryFrom;
+
+    let (root, reply) =
+        message::ThreadedEvent::parse(include_str!("../../../test_data/thread_reply.json"))
+            .unwrap();
+    assert_eq!(
+        root,
+        EventId::try_from("$E2RPcyuMUiGt2Dd4cEXxGgZzEGVA2E1N3jKKvBENqtQ").unwrap()
+    );
+    assert_eq!(
+        reply,
+        EventId::try_from("$Kh1LWqTAqQJqG7DQ3eXLQHvpfBk6XoOo9dTKXkkeWD8").unwrap()
+    );
+    assert!(
+        message::ThreadedEvent::parse(include_str!("../../../test_data/sticker.json")).is_none()
+    );
+}
+
+#[test]
+fn test_thread_reply_content() {
+    use matrix_sdk::identifiers::EventId;
+    use std::convert::TryFrom;
+
+    let root = EventId::try_from("$E2RPcyuMUiGt2Dd4cEXxGgZzEGVA2E1N3jKKvBENqtQ").unwrap();
+    let content = message::ThreadMessageEventContent::text(root, "in the thread".into());
+    assert_eq!(
+        serde_json::to_value(&content).unwrap(),
+        serde_json::json!({
+            "body": "in the thread",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "event_id": "$E2RPcyuMUiGt2Dd4cEXxGgZzEGVA2E1N3jKKvBENqtQ",
+                "rel_type": "m.thread",
+                "is_falling_back": true,
+                "m.in_reply_to": { "event_id": "$E2RPcyuMUiGt2Dd4cEXxGgZzEGVA2E1N3jKKvBENqtQ" }
+            }
+        })
+    );
+}
+
+#[test]
+fn test_poll_start_event() {
+    use matrix_sdk::events::EventJson;
+
+    let ev = serde_json::from_str::<EventJson<RumaUnsupportedEvent>>(include_str!(
+        "../../../test_data/poll_start.json"
+    ))
+    .unwrap()
+    .deserialize()
+    .unwrap();
+
+    match &ev.content {
+        ExtraRoomEventContent::Poll { content } => {
+            assert_eq!("What should we order for the party?", content.question());
+            assert_eq!(vec!["Pizza", "Poutine"], content.answers());
+        }
+        _ => panic!("expected a poll"),
+    }
+
+    let json = serde_json::to_string_pretty(&ev).unwrap();
+    assert_eq!(
+        ev,
+        serde_json::from_str::<EventJson<RumaUnsupportedEvent>>(&json)
+            .unwrap()
+            .deserialize()
+            .unwrap()
+    )
+}
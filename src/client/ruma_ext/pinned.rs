@@ -0,0 +1,96 @@
+//! The events pinned in a room, kept in the room's `m.room.pinned_events` state.
+
+pub mod get_pinned_events {
+    use matrix_sdk::identifiers::{EventId, RoomId};
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the events pinned in a room.",
+            method: GET,
+            name: "get_pinned_events",
+            path: "/_matrix/client/r0/rooms/:room_id/state/m.room.pinned_events/",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room to get the pinned events of.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+        }
+
+        response: {
+            /// The pinned events, in the order they were pinned.
+            pub pinned: Vec<EventId>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod set_pinned_events {
+    use matrix_sdk::identifiers::{EventId, RoomId};
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Replace the events pinned in a room.",
+            method: PUT,
+            name: "set_pinned_events",
+            path: "/_matrix/client/r0/rooms/:room_id/state/m.room.pinned_events/",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room to pin the events in.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// Every event that should be pinned, events left out are unpinned.
+            pub pinned: Vec<EventId>,
+        }
+
+        response: {
+            /// The unique identifier of the state event.
+            pub event_id: EventId,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod get_event {
+    use matrix_sdk::identifiers::{EventId, RoomId, UserId};
+    use serde_json::Value as JsonValue;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get a single event of a room.",
+            method: GET,
+            name: "get_event",
+            path: "/_matrix/client/r0/rooms/:room_id/event/:event_id",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room the event was sent in.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The event to get.
+            #[ruma_api(path)]
+            pub event_id: EventId,
+        }
+
+        response: {
+            /// The user who sent the event.
+            pub sender: UserId,
+
+            /// The content of the event, left as json since it can be any event type.
+            pub content: JsonValue,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
@@ -0,0 +1,141 @@
+use super::message::RelatesTo;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct TextRepresentation {
+    /// The text itself.
+    pub body: String,
+
+    /// The format of `body`, plain text when missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mimetype: Option<String>,
+}
+
+/// Pick the plain text out of the representations of an extensible event text block.
+pub fn plain_text(text: &[TextRepresentation]) -> &str {
+    text.iter()
+        .find(|t| t.mimetype.as_deref().map_or(true, |m| m == "text/plain"))
+        .or_else(|| text.first())
+        .map(|t| t.body.as_str())
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PollQuestion {
+    #[serde(rename = "m.text")]
+    pub text: Vec<TextRepresentation>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PollAnswer {
+    /// The id a response uses to select this answer.
+    #[serde(rename = "m.id")]
+    pub id: String,
+
+    #[serde(rename = "m.text")]
+    pub text: Vec<TextRepresentation>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PollContent {
+    /// Either `m.disclosed` or `m.undisclosed`, undisclosed polls hide the results until the
+    /// poll ends.
+    pub kind: String,
+
+    /// How many answers each user can select.
+    #[serde(default = "default_max_selections")]
+    pub max_selections: u64,
+
+    pub question: PollQuestion,
+
+    pub answers: Vec<PollAnswer>,
+}
+
+fn default_max_selections() -> u64 {
+    1
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PollStartEventContent {
+    /// The actual event content is nested within the field with an event type as it's
+    /// JSON field name "m.poll".
+    #[serde(rename = "m.poll")]
+    pub poll: PollContent,
+
+    /// The fallback text for clients that do not understand polls.
+    #[serde(rename = "m.text", default, skip_serializing_if = "Vec::is_empty")]
+    pub text: Vec<TextRepresentation>,
+}
+
+impl PollStartEventContent {
+    pub fn question(&self) -> &str {
+        plain_text(&self.poll.question.text)
+    }
+
+    pub fn answers(&self) -> Vec<&str> {
+        self.poll
+            .answers
+            .iter()
+            .map(|answer| plain_text(&answer.text))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PollResponseEventContent {
+    /// The RelatesTo struct, holds the poll start EventId and a `m.reference` relation.
+    #[serde(rename = "m.relates_to")]
+    pub relates_to: RelatesTo,
+
+    /// The ids of the selected answers.
+    #[serde(rename = "m.selections")]
+    pub selections: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PollEndEventContent {
+    /// The RelatesTo struct, holds the poll start EventId and a `m.reference` relation.
+    #[serde(rename = "m.relates_to")]
+    pub relates_to: RelatesTo,
+
+    /// The fallback text for clients that do not understand polls.
+    #[serde(rename = "m.text", default, skip_serializing_if = "Vec::is_empty")]
+    pub text: Vec<TextRepresentation>,
+}
+
+pub mod send_poll_response {
+    use matrix_sdk::identifiers::{EventId, RoomId};
+
+    use super::PollResponseEventContent;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Send a vote in a poll to a room.",
+            method: PUT,
+            name: "send_poll_response",
+            path: "/_matrix/client/r0/rooms/:room_id/send/m.poll.response/:txn_id",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room the poll was started in.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The transaction ID for this event.
+            #[ruma_api(path)]
+            pub txn_id: String,
+
+            /// The vote.
+            #[ruma_api(body)]
+            pub content: PollResponseEventContent,
+        }
+
+        response: {
+            /// The unique identifier of the response event.
+            pub event_id: EventId,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
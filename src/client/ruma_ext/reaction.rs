@@ -19,3 +19,41 @@ pub struct ExtraReactionEventContent {
     #[serde(rename = "m.relates_to")]
     pub relates_to: ReactionEventContent,
 }
+
+pub mod send_reaction {
+    use matrix_sdk::identifiers::{EventId, RoomId};
+
+    use super::ExtraReactionEventContent;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Send a reaction to an event in a room.",
+            method: PUT,
+            name: "send_reaction",
+            path: "/_matrix/client/r0/rooms/:room_id/send/m.reaction/:txn_id",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The room the reacted to event is in.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The transaction ID for this event.
+            #[ruma_api(path)]
+            pub txn_id: String,
+
+            /// The annotation of the reacted to event.
+            #[ruma_api(body)]
+            pub content: ExtraReactionEventContent,
+        }
+
+        response: {
+            /// The unique identifier of the reaction event.
+            pub event_id: EventId,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
@@ -0,0 +1,14 @@
+use serde_json::Value as JsonValue;
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct StickerEventContent {
+    /// A textual representation of the image, shown in place of the sticker.
+    pub body: String,
+
+    /// Metadata about the image, its size and mimetype.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<JsonValue>,
+
+    /// The `mxc://` URL of the sticker image.
+    pub url: String,
+}
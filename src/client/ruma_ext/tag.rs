@@ -0,0 +1,113 @@
+//! Room tags, like favourite or low priority, kept in each room's account data.
+
+/// The tag of rooms the user marked as favourite.
+pub const FAVOURITE: &str = "m.favourite";
+/// The tag of rooms the user wants listed last.
+pub const LOW_PRIORITY: &str = "m.lowpriority";
+/// The tag the homeserver sets on the room it sends server notices to.
+pub const SERVER_NOTICE: &str = "m.server_notice";
+
+pub mod get_tags {
+    use std::collections::BTreeMap;
+
+    use matrix_sdk::identifiers::{RoomId, UserId};
+    use serde_json::Value as JsonValue;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the tags the user has set on a room.",
+            method: GET,
+            name: "get_tags",
+            path: "/_matrix/client/r0/user/:user_id/rooms/:room_id/tags",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The user the tags belong to.
+            #[ruma_api(path)]
+            pub user_id: UserId,
+
+            /// The tagged room.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+        }
+
+        response: {
+            /// The tag names mapped to their ordering info.
+            pub tags: BTreeMap<String, JsonValue>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod create_tag {
+    use matrix_sdk::identifiers::{RoomId, UserId};
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Add a tag to a room.",
+            method: PUT,
+            name: "create_tag",
+            path: "/_matrix/client/r0/user/:user_id/rooms/:room_id/tags/:tag",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The user the tag belongs to.
+            #[ruma_api(path)]
+            pub user_id: UserId,
+
+            /// The room to tag.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The name of the tag.
+            #[ruma_api(path)]
+            pub tag: String,
+
+            /// Where the room is sorted among rooms with the same tag.
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub order: Option<f64>,
+        }
+
+        response: {}
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod delete_tag {
+    use matrix_sdk::identifiers::{RoomId, UserId};
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Remove a tag from a room.",
+            method: DELETE,
+            name: "delete_tag",
+            path: "/_matrix/client/r0/user/:user_id/rooms/:room_id/tags/:tag",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The user the tag belongs to.
+            #[ruma_api(path)]
+            pub user_id: UserId,
+
+            /// The tagged room.
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+
+            /// The name of the tag.
+            #[ruma_api(path)]
+            pub tag: String,
+        }
+
+        response: {}
+
+        error: matrix_sdk::api::Error
+    }
+}
@@ -0,0 +1,164 @@
+//! Picks the next stage of the user interactive authentication (UIAA) the
+//! homeserver asks for while registering or changing the account.
+use serde_json::Value as JsonValue;
+
+pub const DUMMY: &str = "m.login.dummy";
+pub const TERMS: &str = "m.login.terms";
+pub const EMAIL: &str = "m.login.email.identity";
+pub const PASSWORD: &str = "m.login.password";
+
+/// The stages rumatui can complete without a browser.
+const SUPPORTED: &[&str] = &[DUMMY, TERMS, EMAIL, PASSWORD];
+
+/// A policy the user has to accept for the `m.login.terms` stage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    pub url: String,
+}
+
+/// What has to be done to complete the next stage of registration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UiaaStage {
+    /// Nothing to show, send the dummy auth.
+    Dummy,
+    /// The user must accept the policies.
+    Terms(Vec<Policy>),
+    /// The user must click a link emailed to them.
+    Email,
+    /// The logged in user must enter their password again.
+    Password,
+    /// Any other stage, completed in the browser using the fallback page.
+    Fallback(String),
+}
+
+/// The next stage to complete, `None` when every stage of the chosen flow is done.
+///
+/// A flow rumatui can finish on its own is preferred, otherwise the flow with the
+/// fewest browser stages is used.
+pub fn next_stage(
+    flows: &[Vec<String>],
+    completed: &[String],
+    params: &JsonValue,
+) -> Option<UiaaStage> {
+    let flow = flows
+        .iter()
+        // a flow has to start with the stages already done
+        .filter(|flow| {
+            flow.iter()
+                .zip(completed)
+                .all(|(stage, done)| stage == done)
+        })
+        .min_by_key(|flow| {
+            flow.iter()
+                .filter(|stage| !SUPPORTED.contains(&stage.as_str()))
+                .count()
+        })?;
+
+    let stage = flow.iter().find(|stage| !completed.contains(stage))?;
+    Some(match stage.as_str() {
+        DUMMY => UiaaStage::Dummy,
+        TERMS => UiaaStage::Terms(policies(params)),
+        EMAIL => UiaaStage::Email,
+        PASSWORD => UiaaStage::Password,
+        other => UiaaStage::Fallback(other.to_string()),
+    })
+}
+
+/// The policies listed in the stage params, in the English translation when there is one.
+fn policies(params: &JsonValue) -> Vec<Policy> {
+    let policies = match params
+        .get(TERMS)
+        .and_then(|terms| terms.get("policies"))
+        .and_then(JsonValue::as_object)
+    {
+        Some(policies) => policies,
+        None => return vec![],
+    };
+
+    policies
+        .iter()
+        .filter_map(|(id, policy)| {
+            let translation = policy.get("en").or_else(|| {
+                policy
+                    .as_object()?
+                    .iter()
+                    .find(|(key, val)| *key != "version" && val.is_object())
+                    .map(|(_, val)| val)
+            })?;
+            Some(Policy {
+                name: translation
+                    .get("name")
+                    .and_then(JsonValue::as_str)
+                    .unwrap_or(id)
+                    .to_string(),
+                url: translation.get("url")?.as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn flows(flows: &[&[&str]]) -> Vec<Vec<String>> {
+        flows
+            .iter()
+            .map(|flow| flow.iter().map(|s| s.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn stages_follow_the_supported_flow() {
+        let params = serde_json::json!({
+            "m.login.terms": {
+                "policies": {
+                    "privacy_policy": {
+                        "version": "1.0",
+                        "en": { "name": "Privacy Policy", "url": "https://example.org/privacy" }
+                    }
+                }
+            }
+        });
+        let flows = flows(&[
+            &["m.login.recaptcha", "m.login.terms"],
+            &["m.login.terms", "m.login.email.identity"],
+        ]);
+
+        assert_eq!(
+            next_stage(&flows, &[], &params),
+            Some(UiaaStage::Terms(vec![Policy {
+                name: "Privacy Policy".into(),
+                url: "https://example.org/privacy".into(),
+            }]))
+        );
+        assert_eq!(
+            next_stage(&flows, &["m.login.terms".to_string()], &params),
+            Some(UiaaStage::Email)
+        );
+        assert_eq!(
+            next_stage(
+                &flows,
+                &[
+                    "m.login.terms".to_string(),
+                    "m.login.email.identity".to_string()
+                ],
+                &params
+            ),
+            None
+        );
+
+        let reauth = self::flows(&[&["m.login.password"], &["m.login.sso"]]);
+        assert_eq!(
+            next_stage(&reauth, &[], &JsonValue::Null),
+            Some(UiaaStage::Password)
+        );
+
+        let captcha = self::flows(&[&["m.login.recaptcha"]]);
+        assert_eq!(
+            next_stage(&captcha, &[], &JsonValue::Null),
+            Some(UiaaStage::Fallback("m.login.recaptcha".into()))
+        );
+    }
+}
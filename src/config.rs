@@ -1,15 +1,338 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::format::{Item, StrftimeItems};
+use matrix_sdk::api::r0::directory::get_public_rooms_filtered::RoomNetwork;
 use serde::{Deserialize, Serialize};
 use tokio::fs as async_fs;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
+
+/// The text macros every user has, a macro in the configs with the same name replaces
+/// the default.
+const DEFAULT_MACROS: &[(&str, &str)] = &[
+    ("/shrug", "¯\\_(ツ)_/¯"),
+    ("/tableflip", "(╯°□°)╯︵ ┻━┻"),
+    ("/unflip", "┬─┬ノ( º _ ºノ)"),
+];
+
+/// Hours and minutes in the local time zone.
+const DEFAULT_TIME_FORMAT: &str = "%H:%M";
+
+/// The narrowest and widest the room list can be, in percent of the screen width.
+pub const SIDEBAR_WIDTHS: (u16, u16) = (10, 50);
+
+/// How much space is left around messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    /// No blank lines, reactions are not indented.
+    Compact,
+    Normal,
+    /// A blank line between messages from different senders.
+    Cozy,
+}
+
+impl Default for Density {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+/// Which networks the public room search covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchNetwork {
+    /// Only rooms on the matrix network.
+    Matrix,
+    /// Matrix rooms and rooms bridged from every third party network.
+    All,
+}
+
+impl Default for SearchNetwork {
+    fn default() -> Self {
+        Self::Matrix
+    }
+}
+
+impl From<SearchNetwork> for RoomNetwork {
+    fn from(network: SearchNetwork) -> Self {
+        match network {
+            SearchNetwork::Matrix => RoomNetwork::Matrix,
+            SearchNetwork::All => RoomNetwork::All,
+        }
+    }
+}
+
+/// What happens to an event sent to an encrypted room that the matrix SDK can not
+/// encrypt, like an image or an edit. Messages are always encrypted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnencryptedFallback {
+    /// Refuse to send and show an error.
+    Never,
+    /// Send the event unencrypted and say so.
+    Always,
+}
+
+impl Default for UnencryptedFallback {
+    fn default() -> Self {
+        Self::Never
+    }
+}
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// An account rumatui has logged in to, kept so it can be switched back to.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SavedAccount {
+    /// The full user id, `@user:server`.
+    pub user_id: String,
+    /// The homeserver URL the account logs in through.
+    pub homeserver: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Configs {
     device_id: String,
     db_version: usize,
+    /// Ring the terminal bell when the user is mentioned or receives a direct message.
+    pub mention_bell: bool,
+    /// Messages longer than this many lines are collapsed until expanded, 0 never collapses.
+    pub collapse_lines: usize,
+    /// The most messages kept in memory for each room, older ones are dropped and
+    /// loaded again when scrolling back, 0 keeps everything.
+    pub message_history: usize,
+    /// How often in milliseconds the UI checks for input and redraws. Lower is more
+    /// responsive, higher uses less CPU.
+    pub tick_rate: u64,
+    /// How much space is left around messages.
+    pub density: Density,
+    /// Search for public rooms as the search term is typed instead of only on Enter.
+    pub live_search: bool,
+    /// The most public rooms each search request returns, 0 leaves it to the server.
+    pub search_limit: u32,
+    /// The networks searched for public rooms.
+    pub search_network: SearchNetwork,
+    /// Recent room search terms, newest last, saved on quit.
+    pub search_history: Vec<String>,
+    /// Words replaced by their text when a message is sent, like `/shrug`.
+    pub macros: BTreeMap<String, String>,
+    /// Whether events to encrypted rooms that can not be encrypted are sent in the clear.
+    pub unencrypted_fallback: UnencryptedFallback,
+    /// Draw on the terminal's alternate screen so quitting restores what was on
+    /// screen before.
+    pub alternate_screen: bool,
+    /// Set the terminal window title to the current room and unread count.
+    pub window_title: bool,
+    /// How the time a message was sent is shown, in strftime format, empty hides it.
+    pub time_format: String,
+    /// Show the date between messages sent on different days.
+    pub date_separators: bool,
+    /// How much of the screen width the room list takes, in percent.
+    pub sidebar_width: u16,
+    /// Show the room list next to the messages.
+    pub show_sidebar: bool,
+    /// How many pages of older messages are loaded the first time a room is opened,
+    /// 0 only loads them when scrolling back.
+    pub prefetch_pages: usize,
+    /// Send a pasted single line right away when the message box was empty, longer
+    /// pastes always wait to be sent.
+    pub send_single_line_paste: bool,
+    /// The accounts logged in to, switched between with Alt-s or `:switch`.
+    pub accounts: Vec<SavedAccount>,
+    /// The file could not be parsed so the defaults are used, it is never overwritten
+    /// so the user can fix it.
+    #[serde(skip)]
+    load_failed: bool,
+}
+
+impl Default for Configs {
+    fn default() -> Self {
+        Self {
+            device_id: String::new(),
+            db_version: 0,
+            mention_bell: false,
+            collapse_lines: 25,
+            message_history: 1000,
+            tick_rate: 60,
+            density: Density::default(),
+            live_search: true,
+            search_limit: 50,
+            search_network: SearchNetwork::default(),
+            search_history: vec![],
+            macros: BTreeMap::new(),
+            unencrypted_fallback: UnencryptedFallback::default(),
+            alternate_screen: true,
+            window_title: false,
+            time_format: DEFAULT_TIME_FORMAT.to_string(),
+            date_separators: true,
+            sidebar_width: 20,
+            show_sidebar: true,
+            prefetch_pages: 1,
+            send_single_line_paste: false,
+            accounts: vec![],
+            load_failed: false,
+        }
+    }
 }
 
 impl Configs {
+    /// The UI tick rate, never faster than every 10 milliseconds.
+    pub fn tick_rate(&self) -> Duration {
+        Duration::from_millis(self.tick_rate.max(10))
+    }
+
+    /// The public room search page size, `None` uses the server's default.
+    pub fn search_limit(&self) -> Option<u32> {
+        Some(self.search_limit).filter(|limit| *limit > 0)
+    }
+
+    /// The room list width in percent, kept between `SIDEBAR_WIDTHS`.
+    pub fn sidebar_width(&self) -> u16 {
+        self.sidebar_width
+            .max(SIDEBAR_WIDTHS.0)
+            .min(SIDEBAR_WIDTHS.1)
+    }
+
+    /// The format of message times, `None` when they are hidden. A format chrono can
+    /// not understand falls back to the default.
+    pub fn time_format(&self) -> Option<&str> {
+        if self.time_format.is_empty() {
+            return None;
+        }
+        if StrftimeItems::new(&self.time_format).any(|item| matches!(item, Item::Error)) {
+            tracing::warn!("invalid time_format {:?}", self.time_format);
+            return Some(DEFAULT_TIME_FORMAT);
+        }
+        Some(&self.time_format)
+    }
+
+    /// Remember an account after logging in to it, an account already known gets the
+    /// new homeserver URL.
+    pub fn remember_account(&mut self, user_id: &str, homeserver: &str) {
+        match self.accounts.iter_mut().find(|acc| acc.user_id == user_id) {
+            Some(account) => account.homeserver = homeserver.to_string(),
+            None => self.accounts.push(SavedAccount {
+                user_id: user_id.to_string(),
+                homeserver: homeserver.to_string(),
+            }),
+        }
+    }
+
+    /// The first saved account of `homeserver`.
+    pub fn account_of(&self, homeserver: &str) -> Option<&SavedAccount> {
+        self.accounts
+            .iter()
+            .find(|acc| acc.homeserver == homeserver)
+    }
+
+    /// Drop the saved account, it was deactivated.
+    pub fn forget_account(&mut self, user_id: &str) {
+        self.accounts.retain(|acc| acc.user_id != user_id);
+    }
+
+    /// The saved account of `homeserver` a login as `username` is for, the full user id
+    /// or a bare user name matching the localpart of one.
+    pub fn saved_user_id(&self, homeserver: &str, username: &str) -> Option<&str> {
+        let username = username.trim();
+        self.accounts
+            .iter()
+            .filter(|acc| acc.homeserver == homeserver)
+            .find(|acc| {
+                acc.user_id == username
+                    || (!username.contains(':')
+                        && acc.user_id.trim_start_matches('@').split(':').next()
+                            == Some(username.trim_start_matches('@')))
+            })
+            .map(|acc| acc.user_id.as_str())
+    }
+
+    /// The account to switch to from `current`, the one named by `name` when given
+    /// otherwise the account after `current`, wrapping around.
+    pub fn switch_account(
+        &self,
+        current: Option<&str>,
+        name: Option<&str>,
+    ) -> Option<&SavedAccount> {
+        if let Some(name) = name {
+            // a bare localpart is enough when it is not ambiguous
+            return self
+                .accounts
+                .iter()
+                .find(|acc| acc.user_id == name)
+                .or_else(|| {
+                    let mut matches = self.accounts.iter().filter(|acc| {
+                        acc.user_id.trim_start_matches('@').split(':').next()
+                            == Some(name.trim_start_matches('@'))
+                    });
+                    matches.next().filter(|_| matches.next().is_none())
+                });
+        }
+        let idx = self
+            .accounts
+            .iter()
+            .position(|acc| Some(acc.user_id.as_str()) == current)
+            .map_or(0, |idx| idx + 1);
+        self.accounts
+            .get(idx % self.accounts.len().max(1))
+            .filter(|acc| Some(acc.user_id.as_str()) != current)
+    }
+
+    /// Replace each whitespace separated word of `text` that names a macro with the
+    /// macro's text, words in code spans and code blocks are left as they are.
+    pub fn expand_macros(&self, text: &str) -> String {
+        let mut expanded = String::with_capacity(text.len());
+        let mut in_code = false;
+        let mut start = 0;
+        for (idx, ch) in text.char_indices() {
+            if ch.is_whitespace() {
+                expanded.push_str(self.expand_word(&text[start..idx], &mut in_code));
+                expanded.push(ch);
+                start = idx + ch.len_utf8();
+            }
+        }
+        expanded.push_str(self.expand_word(&text[start..], &mut in_code));
+        expanded
+    }
+
+    /// The macro text of `word`, `in_code` is flipped by each odd run of backticks.
+    fn expand_word<'a>(&'a self, word: &'a str, in_code: &mut bool) -> &'a str {
+        if word.contains('`') {
+            if word.matches('`').count() % 2 == 1 {
+                *in_code = !*in_code;
+            }
+            return word;
+        }
+        if *in_code {
+            return word;
+        }
+        self.macros
+            .get(word)
+            .map(String::as_str)
+            .or_else(|| {
+                DEFAULT_MACROS
+                    .iter()
+                    .find(|(name, _)| *name == word)
+                    .map(|(_, text)| *text)
+            })
+            .unwrap_or(word)
+    }
+
+    /// The configs of `~/.rumatui/.configs.json`, the defaults when the file is missing
+    /// or can not be parsed.
+    pub(crate) async fn load_or_default() -> Self {
+        match Self::load().await {
+            Ok(configs) => configs,
+            Err(Error::SerdeJson(e)) => {
+                tracing::warn!("the configs could not be parsed, using the defaults {}", e);
+                Self {
+                    load_failed: true,
+                    ..Self::default()
+                }
+            }
+            Err(_) => Self::default(),
+        }
+    }
+
     pub(crate) async fn load() -> Result<Self> {
         let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
         path.push(".configs.json");
@@ -17,4 +340,134 @@ impl Configs {
         let json = async_fs::read_to_string(path).await?;
         serde_json::from_str(&json).map_err(Into::into)
     }
+
+    /// True when the configs file could not be parsed and the defaults are used.
+    pub(crate) fn load_failed(&self) -> bool {
+        self.load_failed
+    }
+
+    /// Write the configs, unless the file could not be parsed when it was loaded.
+    pub(crate) async fn save(&self) -> Result<()> {
+        if self.load_failed {
+            tracing::warn!("the configs file could not be parsed, it is left as it is");
+            return Ok(());
+        }
+        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
+        path.push(".configs.json");
+
+        let json = serde_json::to_string_pretty(self)?;
+        async_fs::write(path, json).await.map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn macros_expand_whole_words() {
+        let mut configs = Configs::default();
+        configs
+            .macros
+            .insert("/tableflip".into(), "no flipping".into());
+
+        assert_eq!(
+            "oh well ¯\\_(ツ)_/¯",
+            configs.expand_macros("oh well /shrug")
+        );
+        assert_eq!("no flipping\n", configs.expand_macros("/tableflip\n"));
+        assert_eq!("a/shrug /shrugs", configs.expand_macros("a/shrug /shrugs"));
+        assert_eq!("`/shrug`", configs.expand_macros("`/shrug`"));
+        assert_eq!(
+            "run ` /tableflip ` then no flipping",
+            configs.expand_macros("run ` /tableflip ` then /tableflip")
+        );
+        assert_eq!(
+            "```\n/tableflip\n```\nno flipping",
+            configs.expand_macros("```\n/tableflip\n```\n/tableflip")
+        );
+    }
+
+    #[test]
+    fn invalid_time_formats_use_the_default() {
+        let mut configs = Configs::default();
+        assert_eq!(Some("%H:%M"), configs.time_format());
+
+        configs.time_format = "%l:%M %p".into();
+        assert_eq!(Some("%l:%M %p"), configs.time_format());
+        configs.time_format = "%Q".into();
+        assert_eq!(Some("%H:%M"), configs.time_format());
+        configs.time_format = String::new();
+        assert_eq!(None, configs.time_format());
+    }
+
+    #[test]
+    fn sidebar_width_stays_in_range() {
+        let mut configs = Configs::default();
+        assert_eq!(20, configs.sidebar_width());
+
+        configs.sidebar_width = 0;
+        assert_eq!(10, configs.sidebar_width());
+        configs.sidebar_width = 100;
+        assert_eq!(50, configs.sidebar_width());
+    }
+
+    #[test]
+    fn accounts_switch_in_turn() {
+        let mut configs = Configs::default();
+        assert_eq!(None, configs.switch_account(None, None));
+
+        configs.remember_account("@me:work.org", "https://work.org");
+        configs.remember_account("@me:home.net", "https://matrix.home.net");
+        configs.remember_account("@me:work.org", "https://matrix.work.org");
+        assert_eq!(2, configs.accounts.len());
+        assert_eq!("https://matrix.work.org", configs.accounts[0].homeserver);
+
+        let next = |current| {
+            configs
+                .switch_account(current, None)
+                .map(|acc| acc.user_id.as_str())
+        };
+        assert_eq!(Some("@me:home.net"), next(Some("@me:work.org")));
+        assert_eq!(Some("@me:work.org"), next(Some("@me:home.net")));
+
+        // the localpart is ambiguous, the full id is not
+        assert_eq!(None, configs.switch_account(None, Some("me")));
+        assert_eq!(
+            Some(&configs.accounts[1]),
+            configs.switch_account(None, Some("@me:home.net"))
+        );
+    }
+
+    #[test]
+    fn unencrypted_fallback_defaults_to_never() {
+        let configs: Configs = serde_json::from_str(r#"{ "mention_bell": true }"#).unwrap();
+        assert_eq!(UnencryptedFallback::Never, configs.unencrypted_fallback);
+
+        let configs: Configs =
+            serde_json::from_str(r#"{ "unencrypted_fallback": "always" }"#).unwrap();
+        assert_eq!(UnencryptedFallback::Always, configs.unencrypted_fallback);
+    }
+
+    #[test]
+    fn logins_find_the_saved_account_of_the_homeserver() {
+        let mut configs = Configs::default();
+        configs.remember_account("@me:example.org", "https://matrix.example.org");
+        configs.remember_account("@me:home.net", "https://home.net");
+
+        // a delegated homeserver, the server name is not the homeserver's host
+        assert_eq!(
+            Some("@me:example.org"),
+            configs.saved_user_id("https://matrix.example.org", "me")
+        );
+        assert_eq!(
+            Some("@me:home.net"),
+            configs.saved_user_id("https://home.net", "@me:home.net")
+        );
+        assert_eq!(
+            None,
+            configs.saved_user_id("https://home.net", "@me:other.net")
+        );
+        assert_eq!(None, configs.saved_user_id("https://other.net", "me"));
+    }
 }
@@ -1,9 +1,9 @@
 //! Error conditions.
 
-use std::{fmt, io};
+use std::{fmt, io, time::Duration};
 
 use matrix_sdk::{
-    api::{error::ErrorKind, Error as RumaApiError},
+    api::{error::ErrorKind, r0::uiaa::UiaaResponse, Error as RumaApiError},
     BaseError as MatrixBaseError, Error as MatrixError, FromHttpResponseError as RumaResponseError,
     IntoHttpError, ServerError,
 };
@@ -27,6 +27,18 @@ const LOGIN_MSG: &str = r#"The user name or password entered did not match any k
 
 Make sure you are logging in on the correct server (rumatui defaults to 'http://matrix.org')."#;
 
+const DEACTIVATED_MSG: &str = r#"This account has been deactivated.
+
+Contact the administrator of your homeserver if you think this is a mistake."#;
+
+const TOKEN_MSG: &str = r#"The access token for this session is no longer valid.
+
+The session may have been logged out from another device, log in again to continue."#;
+
+const WEAK_PASSWORD_MSG: &str = r#"The homeserver rejected the password as too weak.
+
+Use a longer password mixing upper and lower case letters, numbers and symbols."#;
+
 /// Internal representation of errors.
 #[derive(Debug)]
 pub enum Error {
@@ -43,9 +55,28 @@ pub enum Error {
     Unknown(String),
     Channel(String),
     MatrixUiaaError(MatrixError),
+    /// The server is rate limiting requests, the `Duration` is how long it asked us to wait.
+    LimitExceeded(String, Option<Duration>),
+    /// The homeserver could not be reached.
+    Offline(String),
     Rumatui(&'static str),
 }
 
+impl Error {
+    /// How long the server asked us to wait before trying again, if it was rate limiting us.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::LimitExceeded(_, delay) => *delay,
+            _ => None,
+        }
+    }
+
+    /// True when the request failed because the homeserver could not be reached.
+    pub fn is_offline(&self) -> bool {
+        matches!(self, Self::Offline(_))
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -85,6 +116,19 @@ impl fmt::Display for Error {
                 msg
             ),
             Self::MatrixUiaaError(err) => write!(f, "whoaaaa {}", err),
+            Self::LimitExceeded(msg, Some(delay)) => write!(
+                f,
+                "Too many requests have been sent to the server.\n\nTry again in {} seconds.\n{}",
+                // round up so we never say "0 seconds"
+                (delay.as_millis() + 999) / 1000,
+                msg
+            ),
+            Self::LimitExceeded(msg, None) => write!(
+                f,
+                "Too many requests have been sent to the server.\n\nWait a moment and try again.\n{}",
+                msg
+            ),
+            Self::Offline(msg) => write!(f, "The homeserver could not be reached.\n{}", msg),
             Self::Rumatui(msg) => write!(f, "An error occurred in `rumatui`.\n{}", msg),
         }
     }
@@ -100,14 +144,7 @@ impl From<MatrixError> for Error {
             MatrixError::AuthenticationRequired => Error::NeedAuth(AUTH_MSG.to_string()),
             MatrixError::RumaResponse(http) => match http {
                 RumaResponseError::Http(server) => match server {
-                    // This should be the most common error kind and some should be recoverable.
-                    // TODO there are numerous ErrorKind's for `match kind { ... } deal with them
-                    // fix the LOGIN_MSG it is not always accurate
-                    ServerError::Known(RumaApiError { kind, message, .. }) => match kind {
-                        ErrorKind::Forbidden => Error::RumaResponse(LOGIN_MSG.to_string()),
-                        ErrorKind::UserInUse => Error::RumaResponse(format!("{}", message)),
-                        _ => Error::RumaResponse(format!("{}", message)),
-                    },
+                    ServerError::Known(err) => Error::from(err),
                     ServerError::Unknown(err) => Error::Unknown(format!("{}", err)),
                 },
                 RumaResponseError::Deserialization(err) => Error::SerDeError(format!("{}", err)),
@@ -123,12 +160,44 @@ impl From<MatrixError> for Error {
                 MatrixBaseError::MegolmError(err) => Error::Encryption(format!("{}", err)),
                 MatrixBaseError::OlmError(err) => Error::Encryption(format!("{}", err)),
             },
+            // registration failed for a reason other than an unfinished UIAA stage
+            MatrixError::UiaaError(RumaResponseError::Http(ServerError::Known(
+                UiaaResponse::MatrixError(err),
+            ))) => match err.kind {
+                // not a bad login, registration is closed or the user name is reserved
+                ErrorKind::Forbidden => Error::RumaResponse(err.message),
+                _ => Error::from(err),
+            },
             MatrixError::UiaaError(_) => Error::MatrixUiaaError(error),
+            MatrixError::Reqwest(err) => Error::Offline(format!("{}", err)),
             _ => Error::Unknown("connection to the server was lost or not established".into()),
         }
     }
 }
 
+/// The errors the homeserver knows about, this should be the most common error kind and
+/// some should be recoverable.
+impl From<RumaApiError> for Error {
+    fn from(RumaApiError { kind, message, .. }: RumaApiError) -> Self {
+        match kind {
+            ErrorKind::Forbidden => Error::RumaResponse(LOGIN_MSG.to_string()),
+            ErrorKind::UserDeactivated => Error::RumaResponse(DEACTIVATED_MSG.to_string()),
+            ErrorKind::UnknownToken | ErrorKind::MissingToken => {
+                Error::NeedAuth(TOKEN_MSG.to_string())
+            }
+            ErrorKind::LimitExceeded { retry_after_ms } => {
+                Error::LimitExceeded(message, retry_after_ms)
+            }
+            ErrorKind::UserInUse => Error::RumaResponse(message),
+            // not every version of the error kinds has a variant for this errcode
+            kind if kind.to_string() == "M_WEAK_PASSWORD" => {
+                Error::RumaResponse(format!("{}\n\n{}", WEAK_PASSWORD_MSG, message))
+            }
+            _ => Error::RumaResponse(message),
+        }
+    }
+}
+
 impl From<MatrixBaseError> for Error {
     fn from(err: MatrixBaseError) -> Self {
         match err {
@@ -5,13 +5,20 @@
     clippy::single_match
 )]
 
-use std::{env, fs, io, path::Path, process, time::Duration};
+use std::{
+    env, fs,
+    io::{self, Write},
+    panic,
+    path::Path,
+    process, thread,
+};
 
 use rumatui_tui::{backend::TermionBackend, Terminal};
 use termion::{
     event::{Event as TermEvent, Key, MouseButton, MouseEvent},
     input::MouseTerminal,
-    raw::IntoRawMode,
+    raw::{IntoRawMode, RawTerminal},
+    screen::{ToAlternateScreen, ToMainScreen},
 };
 use tracing_subscriber::{self as tracer, EnvFilter};
 
@@ -19,14 +26,26 @@ mod client;
 mod config;
 mod error;
 mod log;
+mod outbox;
 mod ui_loop;
 mod widgets;
 
+use config::Configs;
 use ui_loop::{Config, Event, UiEventHandle};
 use widgets::{app::AppWidget, DrawWidget};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The escape codes termion's `MouseTerminal` uses to start and stop mouse reporting.
+const MOUSE_ON: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h";
+const MOUSE_OFF: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+/// The escape codes that turn bracketed paste on and off.
+const PASTE_ON: &str = "\x1b[?2004h";
+const PASTE_OFF: &str = "\x1b[?2004l";
+/// The escape codes that save and restore the terminal window title.
+const TITLE_PUSH: &str = "\x1b[22;0t";
+const TITLE_POP: &str = "\x1b[23;0t";
+
 lazy_static::lazy_static! {
     pub static ref RUMATUI_DIR: std::io::Result<std::path::PathBuf> = {
         let mut path = dirs::home_dir()
@@ -39,6 +58,102 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Switches to the terminal's alternate screen and back to the main screen when dropped,
+/// so returning early with an error still restores the user's terminal.
+struct AlternateScreen;
+
+impl AlternateScreen {
+    fn enter() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        write!(stdout, "{}", ToAlternateScreen)?;
+        stdout.flush()?;
+
+        // the panic message would be printed to the alternate screen and lost, panics in
+        // the client tasks leave the UI running so only the UI thread restores the screen
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().name() == Some("main") {
+                let mut stdout = io::stdout();
+                let _ = write!(
+                    stdout,
+                    "{}{}{}",
+                    MOUSE_OFF,
+                    ToMainScreen,
+                    termion::cursor::Show
+                );
+                let _ = stdout.flush();
+            }
+            default_hook(info)
+        }));
+        Ok(Self)
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", ToMainScreen);
+        let _ = stdout.flush();
+    }
+}
+
+/// Has the terminal mark pasted text until dropped, so a paste is not read as typed keys.
+struct BracketedPaste;
+
+impl BracketedPaste {
+    fn enable() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        write!(stdout, "{}", PASTE_ON)?;
+        stdout.flush()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for BracketedPaste {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", PASTE_OFF);
+        let _ = stdout.flush();
+    }
+}
+
+/// Keeps the terminal window title in sync with the app and puts back the title the
+/// terminal had before when dropped.
+struct WindowTitle {
+    current: String,
+}
+
+impl WindowTitle {
+    fn save() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        write!(stdout, "{}", TITLE_PUSH)?;
+        stdout.flush()?;
+        Ok(Self {
+            current: String::new(),
+        })
+    }
+
+    /// Only writes the escape code when the title changed.
+    fn set(&mut self, title: String) -> io::Result<()> {
+        if title == self.current {
+            return Ok(());
+        }
+        let mut stdout = io::stdout();
+        write!(stdout, "{}", widgets::utils::window_title_escape(&title))?;
+        stdout.flush()?;
+        self.current = title;
+        Ok(())
+    }
+}
+
+impl Drop for WindowTitle {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", TITLE_POP);
+        let _ = stdout.flush();
+    }
+}
+
 // TODO create a versioning scheme for the "DB"
 /// Check for and create if needed the `/.rumatui` folder
 fn create_rumatui_folder() -> Result<(), failure::Error> {
@@ -111,66 +226,127 @@ fn main() -> Result<(), failure::Error> {
 
     let executor = runtime.handle().clone();
     runtime.block_on(async {
-        let mut app = AppWidget::new(executor, &server).await;
+        // a missing or malformed config file falls back to the defaults
+        let configs = Configs::load_or_default().await;
+        let tick_rate = configs.tick_rate();
+        let alternate_screen = configs.alternate_screen;
+        let window_title = configs.window_title;
+        let mut app = AppWidget::new(executor, &server, configs).await;
         let events = UiEventHandle::with_config(Config {
-            tick_rate: Duration::from_millis(60),
+            tick_rate,
             exit_key: termion::event::Key::Ctrl('q'),
         });
-        let stdout = io::stdout().into_raw_mode()?;
-        let stdout = MouseTerminal::from(stdout);
+        // raw mode is kept separate from the backend so it can be suspended for `$EDITOR`
+        let raw = io::stdout().into_raw_mode()?;
+        // dropped after the terminal so mouse capture is off before leaving the screen
+        let _screen = if alternate_screen {
+            Some(AlternateScreen::enter()?)
+        } else {
+            None
+        };
+        let _paste = BracketedPaste::enable()?;
+        let mut window_title = if window_title {
+            Some(WindowTitle::save()?)
+        } else {
+            None
+        };
+        let stdout = MouseTerminal::from(io::stdout());
         let backend = TermionBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
         terminal.clear()?;
         terminal.hide_cursor()?;
+        let mut size = terminal.size()?;
         loop {
-            app.draw(&mut terminal)?;
+            // a resized terminal has to be redrawn even if nothing else changed
+            let new_size = terminal.size()?;
+            if new_size != size {
+                size = new_size;
+                app.set_dirty();
+            }
+            if app.is_dirty() {
+                app.draw(&mut terminal)?;
+                if let Some(title) = window_title.as_mut() {
+                    title.set(app.window_title())?;
+                }
+            }
 
             if let Some(_er) = app.error.take() {
                 while let Event::Tick = events.next()? {}
+                // the key that dismissed the error is not handled so redraw here
+                app.set_dirty();
             }
 
             match events.next()? {
-                Event::Input(event) => match event {
-                    TermEvent::Key(key) => {
-                        app.on_notifications().await;
-
-                        match key {
-                            Key::Ctrl(c) if c == 'c' => app.should_quit = true,
-                            Key::Ctrl(c) if c == 'q' => app.should_quit = true,
-                            Key::Ctrl(c) if c == 's' => app.on_send().await,
-                            Key::Ctrl(c) if c == 'd' => app.on_ctrl_d().await,
-                            Key::Ctrl(c) if c == 'k' => app.on_ctrl_k().await,
-                            Key::Up => app.on_up().await,
-                            Key::Down => app.on_down().await,
-                            Key::Left => app.on_left(),
-                            Key::Right => app.on_right(),
-                            Key::Backspace => app.on_backspace(),
-                            Key::Delete => app.on_delete().await,
-                            Key::Char(c) if c == '\t' => app.on_down().await,
-                            Key::Char(c) => app.on_key(c).await,
-                            Key::Esc => app.should_quit = true,
-                            _ => {}
-                        }
-                    }
-                    TermEvent::Mouse(m) => {
-                        app.on_notifications().await;
+                Event::Input(event) => {
+                    app.set_dirty();
+                    match event {
+                        TermEvent::Key(key) => {
+                            app.on_notifications().await;
 
-                        match m {
-                            MouseEvent::Press(btn, x, y) if btn == MouseButton::WheelUp => {
-                                app.on_scroll_up(x, y).await
+                            match key {
+                                Key::Ctrl(c) if c == 'c' => app.should_quit = true,
+                                Key::Ctrl(c) if c == 'q' => app.on_quit_request(),
+                                Key::Ctrl(c) if c == 's' => app.on_send().await,
+                                Key::Ctrl(c) if c == 'd' => app.on_ctrl_d().await,
+                                Key::Ctrl(c) if c == 'k' => app.on_ctrl_k().await,
+                                Key::Ctrl(c) if c == 'x' => app.on_ctrl_x(),
+                                Key::Ctrl(c) if c == 'n' => app.on_ctrl_n(),
+                                Key::Ctrl(c) if c == 'e' => app.on_ctrl_e(),
+                                Key::Ctrl(c) if c == 'o' => app.on_ctrl_o(),
+                                Key::Ctrl(c) if c == 'r' => app.on_ctrl_r(),
+                                Key::Ctrl(c) if c == 't' => app.on_ctrl_t(),
+                                Key::Ctrl(c) if c == 'v' => app.on_ctrl_v(),
+                                Key::Alt(c) => app.on_alt(c).await,
+                                Key::F(12) => app.on_f12().await,
+                                Key::PageUp => app.on_page_up(),
+                                Key::PageDown => app.on_page_down(),
+                                Key::Up => app.on_up().await,
+                                Key::Down => app.on_down().await,
+                                Key::Left => app.on_left(),
+                                Key::Right => app.on_right(),
+                                Key::Backspace => app.on_backspace(),
+                                Key::Delete => app.on_delete().await,
+                                Key::Char(c) if c == '\t' => app.on_tab().await,
+                                Key::Char(c) => app.on_key(c).await,
+                                Key::Esc => app.on_quit_request(),
+                                _ => {}
                             }
-                            MouseEvent::Press(btn, x, y) if btn == MouseButton::WheelDown => {
-                                app.on_scroll_down(x, y).await
+                        }
+                        TermEvent::Mouse(m) => {
+                            app.on_notifications().await;
+
+                            match m {
+                                MouseEvent::Press(btn, x, y) if btn == MouseButton::WheelUp => {
+                                    app.on_scroll_up(x, y).await
+                                }
+                                MouseEvent::Press(btn, x, y) if btn == MouseButton::WheelDown => {
+                                    app.on_scroll_down(x, y).await
+                                }
+                                MouseEvent::Press(btn, x, y) => app.on_click(btn, x, y).await,
+                                MouseEvent::Release(_, _) => {}
+                                MouseEvent::Hold(_, _) => {}
                             }
-                            MouseEvent::Press(btn, x, y) => app.on_click(btn, x, y).await,
-                            MouseEvent::Release(_, _) => {}
-                            MouseEvent::Hold(_, _) => {}
                         }
+                        TermEvent::Unsupported(_) => {}
                     }
-                    TermEvent::Unsupported(_) => {}
-                },
+                }
+                Event::Paste(text) => {
+                    app.set_dirty();
+                    app.on_paste(text).await;
+                }
                 Event::Tick => {
-                    app.on_tick(&events).await;
+                    app.on_tick().await;
+                }
+            }
+
+            if app.compose_requested {
+                app.compose_requested = false;
+                let draft = app.chat.draft();
+                match compose_in_editor(&mut terminal, &raw, &events, &draft, alternate_screen) {
+                    Ok(msg) => app.chat.set_draft(msg),
+                    Err(e) => app
+                        .chat
+                        .add_notify(&format!("failed to run $EDITOR: {}", e)),
                 }
             }
 
@@ -184,6 +360,72 @@ fn main() -> Result<(), failure::Error> {
     })
 }
 
+/// Suspend the TUI, open `draft` in the user's `$EDITOR` and return the edited text.
+///
+/// Raw mode, mouse capture and the input thread are all paused while the editor runs.
+fn compose_in_editor<W: Write>(
+    terminal: &mut Terminal<TermionBackend<W>>,
+    raw: &RawTerminal<io::Stdout>,
+    events: &UiEventHandle,
+    draft: &str,
+    alternate_screen: bool,
+) -> io::Result<String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let mut args = editor.split_whitespace();
+    let program = args.next().unwrap_or("vi");
+
+    // created with a random name only the user can read, it is deleted when dropped
+    let mut file = tempfile::Builder::new()
+        .prefix("rumatui-draft-")
+        .suffix(".md")
+        .tempfile()?;
+    file.write_all(draft.as_bytes())?;
+    file.flush()?;
+    let path = file.path().to_path_buf();
+
+    events.pause();
+    write!(terminal.backend_mut(), "{}", MOUSE_OFF)?;
+    if alternate_screen {
+        // editors use the alternate screen themselves and leave it when they exit
+        write!(terminal.backend_mut(), "{}", ToMainScreen)?;
+    }
+    terminal.show_cursor()?;
+    terminal.backend_mut().flush()?;
+    raw.suspend_raw_mode()?;
+
+    let status = process::Command::new(program)
+        .args(args)
+        .arg(&path)
+        .status();
+
+    raw.activate_raw_mode()?;
+    if alternate_screen {
+        write!(terminal.backend_mut(), "{}", ToAlternateScreen)?;
+    }
+    // editors turn bracketed paste off when they exit
+    write!(terminal.backend_mut(), "{}{}", MOUSE_ON, PASTE_ON)?;
+    terminal.hide_cursor()?;
+    // the editor drew over everything so redraw the whole screen
+    terminal.clear()?;
+    events.resume();
+
+    let edited = status.and_then(|status| {
+        if status.success() {
+            fs::read_to_string(&path)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} exited with {}", program, status),
+            ))
+        }
+    });
+    drop(file);
+    // editors add a newline at the end of the file
+    edited.map(|msg| msg.trim_end_matches('\n').to_string())
+}
+
 #[rustfmt::skip]
 #[allow(clippy::print_literal)]
 fn print_help() {
@@ -197,13 +439,49 @@ fn print_help() {
         "   -v, --verbose   Will create a log of the session at '~/.rumatui/logs.json'\n\n",
         "KEY-BINDINGS:",
 r#"
-    * Esc will exit `rumatui`
-    * Enter still works for all buttons except the decline/accept invite
+    * Esc will exit `rumatui`, if messages are still sending press Esc again to quit anyway
+    * Ctrl-c exits immediately
+    * Ctrl-r reveals or masks the password when logging in or registering
+    * PageUp/PageDown moves the message cursor through the messages of the current room
+    * `:react <emoji>` reacts to the message under the message cursor, Alt-r reacts with 👍
+    * `:redact [reason]` or Alt-x redacts the message under the message cursor, other people's messages need a high enough power level
+    * Alt-e loads your message under the message cursor into the send box to edit it, sending replaces its text and Esc cancels
+    * Ctrl-x expands or collapses the selected message or run of joins and leaves
+    * Ctrl-e writes the current message in $EDITOR
+    * Ctrl-o follows a link in the selected message, matrix.to links offer to join the room, open the DM or go to the linked event
+    * Ctrl-n opens the notification history, up/down arrows scroll it and Esc closes it
+    * Ctrl-t opens the thread of the selected message, messages sent while it is open reply in the thread and Esc closes it
+    * Ctrl-v shows the JSON source of the selected message, up/down arrows scroll it and Esc closes it
+    * `:` with an empty message box starts a command, `:q` quits and `:search` opens room search, `:search example.org` searches the public rooms of another server
+    * `:fav` and `:lowpriority` mark the current room as a favourite or low priority, running it again removes the mark
+    * `:knock <room> [reason]` asks to join a room that uses the knock join rule
+    * `:join <room>` joins a room by id or alias and `:dm <user>` opens the direct message room with a user
+    * `:goto <permalink>` switches to the room of a matrix.to event link and scrolls back to the event
+    * `:vote <number>` votes for an answer of the poll under the message cursor
+    * `:location <latitude>,<longitude> [description]` shares a location, Ctrl-o on a location message opens it on a map
+    * `/image <path>` in the send box uploads and sends an image, it shows as `[image: name]`
+    * `/me <action>` in the send box sends an emote, shown as `* name action`
+    * `/kick <user id> [reason]` in the send box kicks a member out of the room
+    * `:reload` reads the configuration file again and applies it without logging out
+    * `:retry` sends the messages of the current room the homeserver refused again
+    * `:accounts` lists the accounts logged in to before, `:switch [user id]` or Alt-s switches to one, the password is only asked for when its session expired
+    * `:pinned` lists the pinned messages of the current room, `:pin` and `:unpin` pin or unpin the message under the message cursor
+    * `:deactivate <your full user id>` permanently deactivates the account after asking for the password
+    * `:passwd` changes the account password, `:passwd logout` also logs out the other sessions
+    * Tab completes `:` commands and `@` mentions of room members, otherwise it moves like the down arrow
+    * Enter works for all buttons, an invite is accepted with Alt-a and declined with Alt-d
+    * Alt-1 to Alt-9 switch to the room at that position in the room list
+    * Alt-< and Alt-> narrow and widen the room list, Alt-b hides it so messages fill the screen and shows it again
+    * Alt-m shows or hides the members of the current room next to its messages, offline members are grayed out
+    * Ctrl-k filters the room list to quickly switch rooms, it shows a hidden room list until a room is picked
     * Ctrl-s sends a message
     * Delete leaves and forgets the selected room
     * Left/right arrows, while at the login window, toggles login/register window
+    * Esc cancels registration while it waits on terms, an email address or the browser
+    * Esc stops loading previous messages or a room search
     * Left arrow, while at the main chat window, brings up the room search window
     * Enter, while in the room search window, starts the search
+    * Up/Down, while the room search box is empty, go through recent searches
     * Ctrl-d, while a room is selected in the room search window, joins the room
 "#,
     )
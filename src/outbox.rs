@@ -0,0 +1,99 @@
+//! Messages that were not sent before rumatui quit, sent again after the next login.
+use std::path::PathBuf;
+
+use matrix_sdk::{
+    events::room::message::MessageEventContent,
+    identifiers::{RoomId, UserId},
+};
+use serde::{Deserialize, Serialize};
+use tokio::fs as async_fs;
+
+use crate::error::Result;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UnsentMessage {
+    /// The transaction id the message was first sent with, the server ignores the
+    /// message if an earlier attempt did make it.
+    pub txn_id: String,
+    pub room_id: RoomId,
+    pub content: MessageEventContent,
+    /// The server refused the message, it is only sent again with `:retry`.
+    #[serde(default)]
+    pub failed: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Outbox {
+    /// The user who wrote the messages.
+    pub user_id: Option<UserId>,
+    /// The unsent messages, oldest first.
+    pub messages: Vec<UnsentMessage>,
+}
+
+impl Outbox {
+    fn path() -> PathBuf {
+        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
+        path.push(".outbox.json");
+        path
+    }
+
+    pub(crate) async fn load() -> Result<Self> {
+        let json = async_fs::read_to_string(Self::path()).await?;
+        serde_json::from_str(&json).map_err(Into::into)
+    }
+
+    /// Write the outbox, an empty outbox removes the file.
+    pub(crate) async fn save(&self) -> Result<()> {
+        if self.messages.is_empty() {
+            return match async_fs::remove_file(Self::path()).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        async_fs::write(Self::path(), json)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use matrix_sdk::events::room::message::TextMessageEventContent;
+
+    use super::*;
+
+    #[test]
+    fn outbox_keeps_the_message_order() {
+        let room_id = RoomId::try_from("!room:localhost").unwrap();
+        let outbox = Outbox {
+            user_id: Some(UserId::try_from("@example:localhost").unwrap()),
+            messages: ["first", "second"]
+                .iter()
+                .map(|body| UnsentMessage {
+                    txn_id: body.to_string(),
+                    room_id: room_id.clone(),
+                    content: MessageEventContent::Text(TextMessageEventContent::new_plain(*body)),
+                    failed: false,
+                })
+                .collect(),
+        };
+
+        let json = serde_json::to_string(&outbox).unwrap();
+        let outbox: Outbox = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            vec!["first", "second"],
+            outbox
+                .messages
+                .iter()
+                .map(|msg| msg.txn_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        match &outbox.messages[1].content {
+            MessageEventContent::Text(text) => assert_eq!("second", text.body),
+            _ => panic!("the message content changed"),
+        }
+    }
+}
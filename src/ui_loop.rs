@@ -1,13 +1,29 @@
-use std::{io, sync::mpsc, thread, time::Duration};
+use std::{
+    io::{self, Read},
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
 
 use termion::{
-    event::{Event as TermEvent, Key},
-    input::{MouseTerminal, TermRead},
+    event::{self as term_event, Event as TermEvent, Key},
+    input::MouseTerminal,
     raw::IntoRawMode,
 };
 
+/// Sent by the terminal before and after pasted text once bracketed paste is on.
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
 pub enum Event<I> {
     Input(I),
+    /// Text pasted into the terminal, sent in one piece so its newlines are not
+    /// taken as key presses.
+    Paste(String),
     Tick,
 }
 
@@ -15,6 +31,8 @@ pub enum Event<I> {
 /// type is handled in its own thread and returned to a common `Receiver`
 pub struct UiEventHandle {
     recv: mpsc::Receiver<Event<TermEvent>>,
+    /// When set the input thread stops reading stdin so another program can use it.
+    paused: Arc<AtomicBool>,
     input_handle: thread::JoinHandle<()>,
     tick_handle: thread::JoinHandle<()>,
 }
@@ -32,19 +50,43 @@ impl UiEventHandle {
         let stdout = io::stdout().into_raw_mode().unwrap();
         let _stdout = MouseTerminal::from(stdout);
 
+        let paused = Arc::new(AtomicBool::new(false));
         let input_handle = {
             let send = send.clone();
+            let paused = Arc::clone(&paused);
             thread::spawn(move || {
-                let stdin = io::stdin();
-                for ev in stdin.events() {
-                    let ev = ev.unwrap();
-
-                    if let TermEvent::Key(Key::Char('q')) = ev {
-                        return;
+                // the tty is read unbuffered so polling it never misses input
+                // that was already read into a buffer
+                let mut tty = termion::get_tty().unwrap();
+                let fd = tty.as_raw_fd();
+                let mut buf = [0; 1024];
+                // pasted text is collected until the terminal ends the paste
+                let mut paste = None;
+                loop {
+                    // only read once input is waiting so a paused thread never takes
+                    // keys meant for another program
+                    if paused.load(Ordering::SeqCst) {
+                        thread::sleep(cfg.tick_rate);
+                        continue;
                     }
+                    if !input_ready(fd, cfg.tick_rate) {
+                        continue;
+                    }
+                    let read = match tty.read(&mut buf) {
+                        Ok(0) => return,
+                        Ok(read) => read,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => return,
+                    };
+
+                    for ev in parse_input(&buf[..read], &mut paste) {
+                        if let Event::Input(TermEvent::Key(Key::Char('q'))) = ev {
+                            return;
+                        }
 
-                    if send.send(Event::Input(ev)).is_err() {
-                        return;
+                        if send.send(ev).is_err() {
+                            return;
+                        }
                     }
                 }
             })
@@ -60,6 +102,7 @@ impl UiEventHandle {
 
         UiEventHandle {
             recv,
+            paused,
             input_handle,
             tick_handle,
         }
@@ -69,9 +112,107 @@ impl UiEventHandle {
         self.recv.recv()
     }
 
+    /// Stop reading input until `resume` is called.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
     #[allow(dead_code)]
     pub fn shutdown(self) {
         let _ = self.input_handle.join();
         let _ = self.tick_handle.join();
     }
 }
+
+/// Wait up to `timeout` for input to be readable on `fd`.
+fn input_ready(fd: std::os::unix::io::RawFd, timeout: Duration) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // Safety: `pollfd` is a single valid pollfd for the duration of the call
+    let ready = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+    if ready < 0 {
+        // interrupted, try again later
+        thread::sleep(timeout);
+    }
+    ready > 0
+}
+
+/// Split what was read from the tty into events, pasted text is collected in `paste`
+/// until the end of the paste is read.
+fn parse_input(mut bytes: &[u8], paste: &mut Option<Vec<u8>>) -> Vec<Event<TermEvent>> {
+    let mut events = vec![];
+    while !bytes.is_empty() {
+        if let Some(text) = paste.as_mut() {
+            // the end of the paste can be split between reads
+            let searched = text.len().saturating_sub(PASTE_END.len() - 1);
+            let collected = text.len();
+            text.extend_from_slice(bytes);
+            let end = match text[searched..]
+                .windows(PASTE_END.len())
+                .position(|w| w == PASTE_END)
+            {
+                Some(idx) => searched + idx,
+                None => break,
+            };
+            bytes = &bytes[end + PASTE_END.len() - collected..];
+            text.truncate(end);
+            let text = paste.take().unwrap_or_default();
+            events.push(Event::Paste(String::from_utf8_lossy(&text).into_owned()));
+        } else if bytes.starts_with(PASTE_START) {
+            *paste = Some(vec![]);
+            bytes = &bytes[PASTE_START.len()..];
+        } else if bytes == b"\x1b" {
+            // nothing follows so this is the Esc key and not an escape sequence
+            events.push(Event::Input(TermEvent::Key(Key::Esc)));
+            break;
+        } else {
+            // termion parses one event from the front and leaves the rest
+            let mut rest = bytes[1..].iter().map(|b| Ok(*b));
+            if let Ok(ev) = term_event::parse_event(bytes[0], &mut rest) {
+                events.push(Event::Input(ev));
+            }
+            bytes = &bytes[bytes.len() - rest.len()..];
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(events: &[Event<TermEvent>]) -> Vec<Option<&str>> {
+        events
+            .iter()
+            .map(|ev| match ev {
+                Event::Paste(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pastes_are_one_event() {
+        let mut paste = None;
+        let events = parse_input(b"a\x1b[200~one\ntwo\x1b[201~b", &mut paste);
+        assert_eq!(vec![None, Some("one\ntwo"), None], keys(&events));
+        assert!(matches!(
+            events[2],
+            Event::Input(TermEvent::Key(Key::Char('b')))
+        ));
+
+        // the end of the paste split between two reads
+        assert!(parse_input(b"\x1b[200~/join\n\x1b[20", &mut paste).is_empty());
+        let events = parse_input(b"1~\x1b", &mut paste);
+        assert_eq!(vec![Some("/join\n"), None], keys(&events));
+        assert!(matches!(events[1], Event::Input(TermEvent::Key(Key::Esc))));
+        assert!(paste.is_none());
+    }
+}
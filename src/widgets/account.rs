@@ -0,0 +1,214 @@
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+
+use crate::{
+    client::{client_loop::UserRequest, ruma_ext::auth::stage::AuthData},
+    widgets::{
+        login::Loading,
+        register::{password_strength, Strength},
+        RenderWidget,
+    },
+};
+
+/// The account change waiting on the user's password.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountAction {
+    Deactivate,
+    /// Log out the other devices when true.
+    ChangePassword(bool),
+}
+
+/// Which text box typing goes to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountField {
+    Password,
+    NewPassword,
+}
+
+/// Asks for the password again before changing the account, opened with
+/// `:deactivate` or `:passwd`.
+#[derive(Clone, Debug)]
+pub struct AccountWidget {
+    pub action: AccountAction,
+    /// The full user id of the account being changed.
+    pub user: String,
+    pub password: String,
+    pub new_password: String,
+    pub selected: AccountField,
+    /// The UIAA session, once the homeserver started one.
+    pub session: Option<String>,
+    /// The password was sent for the current session.
+    pub sent_password: bool,
+    /// A request is waiting on the homeserver.
+    pub waiting: bool,
+    pub loading: Loading,
+    /// Why the last attempt failed.
+    pub notice: Option<String>,
+}
+
+impl AccountWidget {
+    pub fn new(action: AccountAction, user: String) -> Self {
+        Self {
+            action,
+            user,
+            password: String::new(),
+            new_password: String::new(),
+            selected: AccountField::Password,
+            session: None,
+            sent_password: false,
+            waiting: false,
+            loading: Loading::default(),
+            notice: None,
+        }
+    }
+
+    pub(crate) fn selected_text(&mut self) -> &mut String {
+        match self.selected {
+            AccountField::Password => &mut self.password,
+            AccountField::NewPassword => &mut self.new_password,
+        }
+    }
+
+    /// Switch between the current and new password when changing it.
+    pub(crate) fn toggle_field(&mut self) {
+        if let AccountAction::ChangePassword(_) = self.action {
+            self.selected = match self.selected {
+                AccountField::Password => AccountField::NewPassword,
+                AccountField::NewPassword => AccountField::Password,
+            };
+        }
+    }
+
+    /// Enter on the current password moves on to the new one when changing it.
+    pub(crate) fn next_field(&mut self) -> bool {
+        if let (AccountAction::ChangePassword(_), AccountField::Password) =
+            (&self.action, self.selected)
+        {
+            self.selected = AccountField::NewPassword;
+            return true;
+        }
+        false
+    }
+
+    /// Enter sends the request once the passwords are typed.
+    pub(crate) fn try_submit(&self) -> bool {
+        !self.waiting
+            && !self.password.is_empty()
+            && (self.action == AccountAction::Deactivate || !self.new_password.is_empty())
+    }
+
+    /// The request for the action, with the password once the homeserver
+    /// started a UIAA session.
+    pub(crate) fn request(&mut self) -> UserRequest {
+        let auth = self.session.clone().map(|session| {
+            self.sent_password = true;
+            AuthData::password(session, self.user.clone(), self.password.clone())
+        });
+        self.request_with(auth)
+    }
+
+    pub(crate) fn request_with(&mut self, auth: Option<AuthData>) -> UserRequest {
+        self.waiting = true;
+        self.notice = None;
+        match self.action {
+            AccountAction::Deactivate => UserRequest::Deactivate(auth),
+            AccountAction::ChangePassword(logout) => {
+                UserRequest::ChangePassword(self.new_password.clone(), logout, auth)
+            }
+        }
+    }
+}
+
+impl RenderWidget for AccountWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let field = |field| {
+            if self.selected == field {
+                Style::default().fg(Color::Cyan).modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Cyan)
+            }
+        };
+        let masked = |password: &str| format!("{}\n\n", "*".repeat(password.chars().count()));
+
+        let (title, mut text) = match self.action {
+            AccountAction::Deactivate => (
+                "Deactivate Account (Esc to cancel)",
+                vec![
+                    Text::styled(
+                        format!(
+                            "This permanently deactivates {}, it can not be undone.\n\n",
+                            self.user
+                        ),
+                        Style::default().fg(Color::Red).modifier(Modifier::BOLD),
+                    ),
+                    Text::raw("Password: "),
+                    Text::styled(masked(&self.password), field(AccountField::Password)),
+                ],
+            ),
+            AccountAction::ChangePassword(logout) => {
+                let (strength, hint) = password_strength(&self.new_password);
+                let strength = match strength {
+                    Strength::Weak => Text::styled("weak", Style::default().fg(Color::Red)),
+                    Strength::Fair => Text::styled("fair", Style::default().fg(Color::Yellow)),
+                    Strength::Strong => Text::styled("strong", Style::default().fg(Color::Green)),
+                };
+                let mut text = vec![
+                    Text::raw(format!("Changing the password of {}", self.user)),
+                    Text::raw(if logout {
+                        ", your other sessions will be logged out.\n\n"
+                    } else {
+                        ".\n\n"
+                    }),
+                    Text::raw("Current password: "),
+                    Text::styled(masked(&self.password), field(AccountField::Password)),
+                    Text::raw("New password: "),
+                    Text::styled(masked(&self.new_password), field(AccountField::NewPassword)),
+                ];
+                if !self.new_password.is_empty() {
+                    text.push(Text::raw("Strength: "));
+                    text.push(strength);
+                    text.push(Text::raw(format!(
+                        "{}\n\n",
+                        hint.map(|h| format!(", {}", h)).unwrap_or_default()
+                    )));
+                }
+                ("Change Password (Esc to cancel)", text)
+            }
+        };
+        if self.waiting {
+            self.loading.tick(20);
+            text.push(Text::styled(
+                "*".repeat(self.loading.count),
+                Style::default().fg(Color::Magenta),
+            ));
+        } else {
+            text.push(Text::raw("Press Enter to continue"));
+        }
+        if let Some(notice) = self.notice.as_ref() {
+            text.push(Text::styled(
+                format!("\n\n{}", notice),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+
+        let p = Paragraph::new(text.iter())
+            .block(
+                Block::default()
+                    .title(title)
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Red).modifier(Modifier::BOLD))
+                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+            )
+            .alignment(Alignment::Center)
+            .wrap(true);
+        f.render_widget(p, area)
+    }
+}
@@ -1,19 +1,25 @@
-use std::{io, ops::Deref, sync::Arc, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::TryFrom,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use matrix_sdk::{
     api::r0::{
-        directory::get_public_rooms_filtered::RoomNetwork,
         message::get_message_events,
         uiaa::{UiaaInfo, UiaaResponse},
     },
     events::{
         room::{
             member::MembershipChange,
-            message::{MessageEventContent, TextMessageEventContent},
+            message::{ImageMessageEventContent, LocationMessageEventContent, MessageEventContent},
         },
-        AnySyncMessageEvent, AnySyncRoomEvent, SyncMessageEvent,
+        AnySyncMessageEvent, AnySyncRoomEvent, AnySyncStateEvent, EventType, SyncMessageEvent,
     },
-    identifiers::{RoomId, UserId},
+    identifiers::{EventId, RoomId, RoomIdOrAliasId, UserId},
     Error as MatrixError, Room,
 };
 use rumatui_tui::{
@@ -29,26 +35,93 @@ use tokio::{
     runtime::Handle,
     sync::{mpsc, RwLock},
 };
+use url::Url;
 use uuid::Uuid;
 
 use crate::{
     client::{
+        account_dir,
         client_loop::{MatrixEventHandle, RequestResult, UserRequest},
-        event_stream::{EventStream, StateResult},
+        event_source,
+        event_stream::{EventStream, MemberChange, MemberProfile, StateResult},
+        ruma_ext::{
+            auth::stage::{AuthData, ThreepidCreds},
+            message::ThreadedEvent,
+            tag::{FAVOURITE, LOW_PRIORITY},
+        },
+        uiaa::{self, UiaaStage},
+        DebugInfo, PinnedEvent, RoomTags, SyncStats, Username,
     },
+    config::{Configs, UnencryptedFallback},
     error::Error,
-    ui_loop::{Event, UiEventHandle},
+    outbox::{Outbox, UnsentMessage},
     widgets::{
+        account::{AccountAction, AccountWidget},
         chat::ChatWidget,
+        debug::DebugWidget,
         error::ErrorWidget,
-        login::{Login, LoginSelect, LoginWidget},
-        message::Message,
-        register::{Register, RegisterSelect, RegisterWidget},
+        login::{Loading, LoginSelect, LoginWidget},
+        message::{MemberNotice, Message, SendState},
+        pinned::PinnedWidget,
+        register::{RegisterWidget, UiaaPrompt},
         rooms::Invite,
+        utils::{self, MatrixLink, SendCommand},
         DrawWidget, RenderWidget,
     },
 };
 
+/// The `:` commands, completed with Tab.
+const COMMANDS: &[&str] = &[
+    "accounts",
+    "deactivate",
+    "dm",
+    "fav",
+    "goto",
+    "join",
+    "knock",
+    "location",
+    "lowpriority",
+    "passwd",
+    "pin",
+    "pinned",
+    "quit",
+    "react",
+    "redact",
+    "reload",
+    "retry",
+    "search",
+    "switch",
+    "unpin",
+    "vote",
+];
+
+/// The reaction Alt-r sends.
+const QUICK_REACTION: &str = "\u{1f44d}";
+
+/// The smallest terminal, in columns and rows, the screens fit in.
+const MIN_TERMINAL_SIZE: (u16, u16) = (60, 16);
+
+/// The widest the backfill loading spinner grows.
+const BACKFILL_SPINNER_WIDTH: u16 = 10;
+
+/// A sync takes at most 30 seconds, longer than this without one and sync is stalled.
+const SYNC_STALLED: Duration = Duration::from_secs(90);
+
+/// How long the room search term has to stay the same before it is searched for.
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(400);
+
+/// The details of an unfinished UIAA stage, any other error is given back.
+fn uiaa_info(error: Error) -> std::result::Result<UiaaInfo, Error> {
+    match error {
+        Error::MatrixUiaaError(MatrixError::UiaaError(
+            matrix_sdk::FromHttpResponseError::Http(matrix_sdk::ServerError::Known(
+                UiaaResponse::AuthResponse(info),
+            )),
+        )) => Ok(info),
+        error => Err(error),
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum LoginOrRegister {
     Login,
@@ -62,10 +135,16 @@ pub struct AppWidget {
     pub homeserver: String,
     /// When user quits this is true,
     pub should_quit: bool,
+    /// The user asked to quit while messages were still being sent.
+    ///
+    /// Quitting again or the last pending message finishing sets `should_quit`.
+    pub quit_requested: bool,
     /// Have we started the sync loop yet.
     pub sync_started: bool,
     /// Have we started a scroll request.
     pub scrolling: bool,
+    /// Animates the banner while previous messages are loading.
+    pub backfill_waiting: Loading,
     /// The client is sending a typing notice to the server.
     pub typing_notice: bool,
     /// The last interaction the user had with the app.
@@ -80,6 +159,12 @@ pub struct AppWidget {
     pub chat: ChatWidget,
     /// the event loop for MatrixClient tasks to run on.
     pub ev_loop: MatrixEventHandle,
+    /// The runtime the client tasks run on, a new client is started on it when
+    /// switching accounts.
+    rt: Handle,
+    /// The full user id of the account the client keeps its store for, `None` until
+    /// someone logs in.
+    client_account: Option<String>,
     /// Send MatrixClient jobs to the event handler
     pub send_jobs: mpsc::Sender<UserRequest>,
     /// The result of any MatrixClient job.
@@ -87,11 +172,50 @@ pub struct AppWidget {
     /// The result of any MatrixClient job.
     pub emitter_msgs: mpsc::Receiver<StateResult>,
     pub error: Option<Error>,
+    /// The user's settings loaded from the `.configs.json` file.
+    pub configs: Configs,
+    /// The text of the `:` command being typed, `None` when not in command mode.
+    pub command: Option<String>,
+    /// The user asked to write the current message in `$EDITOR`.
+    pub compose_requested: bool,
+    /// The client's sync state, shown instead of the chat while set.
+    pub debug: Option<DebugInfo>,
+    /// The pinned messages of the current room, shown instead of the chat while set.
+    pub pinned: Option<Vec<PinnedEvent>>,
+    /// What the last sync brought in and when it finished.
+    last_sync: Option<(SyncStats, SystemTime)>,
+    /// Flips every sync so the status line pulses.
+    sync_beat: bool,
+    /// The unread notifications and highlights of all rooms, shown in the title.
+    unread: (u64, u64),
+    /// The sync loop lost the homeserver, messages are queued until it is back.
+    offline: bool,
+    /// Messages the server has not confirmed, oldest first, kept so they can be sent again.
+    unsent: Vec<(Uuid, RoomId, MessageEventContent)>,
+    /// The unsent messages to send once the connection is back, oldest first.
+    outbox: VecDeque<Uuid>,
+    /// The messages of each room being sent, oldest first. Only the first is handed to
+    /// the client loop so a room's messages arrive in the order they were written.
+    sending: HashMap<RoomId, VecDeque<Uuid>>,
+    /// Asking for the password before changing the account, shown instead of the chat while set.
+    account: Option<AccountWidget>,
+    /// Something changed since the last draw.
+    dirty: bool,
+    /// The last profile change seen for each user, so a change is only announced once.
+    profiles: HashMap<UserId, MemberProfile>,
+    /// The pages of older messages still to load for each opened room, a room is only
+    /// prefetched the first time it is opened.
+    prefetch: HashMap<RoomId, usize>,
+    /// The direct chats listed in the account's `m.direct`, `None` until it is loaded.
+    direct_rooms: Option<HashSet<RoomId>>,
+    /// The encrypted room whose devices were last counted, they are counted again
+    /// each time the room is opened.
+    devices_checked: Option<RoomId>,
     registration: Option<String>,
 }
 
 impl AppWidget {
-    pub async fn new(rt: Handle, homeserver: &str) -> Self {
+    pub async fn new(rt: Handle, homeserver: &str, configs: Configs) -> Self {
         let homeserver = if homeserver.is_empty() {
             "https://matrix.org"
         } else {
@@ -102,25 +226,66 @@ impl AppWidget {
 
         let (emitter, emitter_msgs) = EventStream::new();
 
-        let (ev_loop, send_jobs) = MatrixEventHandle::new(emitter, send, rt, homeserver).await;
+        let (ev_loop, mut send_jobs) = MatrixEventHandle::new(
+            emitter,
+            send,
+            rt.clone(),
+            homeserver,
+            None,
+            configs.unencrypted_fallback,
+        )
+        .await;
+        // make sure the homeserver is reachable before the user fills out the login form
+        if let Err(e) = send_jobs.send(UserRequest::CheckServer).await {
+            tracing::error!("failed to check the homeserver {}", e);
+        }
+        let mut chat = ChatWidget::default();
+        chat.apply_configs(&configs);
+        if configs.load_failed() {
+            chat.add_notify(
+                "the configs file could not be parsed, the defaults are used until it is fixed",
+            );
+        }
         Self {
             title: "rumatui".to_string(),
             homeserver: homeserver.to_string(),
             should_quit: false,
+            quit_requested: false,
             sync_started: false,
             scrolling: false,
+            backfill_waiting: Loading::default(),
             typing_notice: false,
             last_interaction: SystemTime::now(),
             login_w: LoginWidget::default(),
             register: RegisterWidget::default(),
             login_or_register: LoginOrRegister::Login,
-            chat: ChatWidget::default(),
+            chat,
             ev_loop,
+            rt,
+            client_account: None,
             send_jobs,
             ev_msgs: recv,
             emitter_msgs,
             error: None,
+            configs,
+            command: None,
+            compose_requested: false,
+            debug: None,
+            pinned: None,
+            dirty: true,
+            profiles: HashMap::new(),
+            prefetch: HashMap::new(),
+            direct_rooms: None,
+            devices_checked: None,
             registration: None,
+            last_sync: None,
+            unread: (0, 0),
+            sync_beat: false,
+            offline: false,
+            unsent: vec![],
+            outbox: VecDeque::new(),
+            sending: HashMap::new(),
+            account: None,
         }
     }
 
@@ -131,32 +296,76 @@ impl AppWidget {
         if self.chat.msgs_on_click(btn, x, y) {
             self.on_send().await;
         }
-        if let Some(room_id) = self.chat.as_invite().map(|i| i.room_id.clone()) {
-            match self.chat.room_on_click(btn, x, y) {
-                Invite::Accept => {
-                    if let Err(e) = self
-                        .send_jobs
-                        .send(UserRequest::AcceptInvite(room_id))
-                        .await
-                    {
-                        self.set_error(e.into())
-                    } else {
-                        self.chat.set_joining_room(true);
-                        self.chat.remove_invite();
+        if self.chat.as_invite().is_some() {
+            let answer = self.chat.room_on_click(btn, x, y);
+            self.answer_invite(answer).await;
+        }
+    }
+
+    /// Accept or decline the invitation currently shown in the rooms pane.
+    async fn answer_invite(&mut self, answer: Invite) {
+        let room_id = match self.chat.as_invite() {
+            Some(invite) => invite.room_id.clone(),
+            None => return,
+        };
+        match answer {
+            Invite::Accept => {
+                if let Err(e) = self
+                    .send_jobs
+                    .send(UserRequest::AcceptInvite(room_id))
+                    .await
+                {
+                    self.set_error(e.into())
+                } else {
+                    self.chat.set_joining_room(true);
+                    self.chat.remove_invite();
+                }
+            }
+            Invite::Decline => {
+                if let Err(e) = self
+                    .send_jobs
+                    .send(UserRequest::DeclineInvite(room_id))
+                    .await
+                {
+                    self.set_error(e.into())
+                } else {
+                    self.chat.remove_invite();
+                }
+            }
+            Invite::NoClick => {}
+        }
+    }
+
+    /// Alt-a accepts and Alt-d declines a pending invitation, Alt-1 to Alt-9 switch
+    /// to the room at that position in the room list. Alt-< and Alt-> resize the room
+    /// list, Alt-b hides or shows it and Alt-s switches to the next saved account.
+    pub async fn on_alt(&mut self, c: char) {
+        if self.chat.is_main_screen() {
+            match c {
+                'a' => self.answer_invite(Invite::Accept).await,
+                'd' => self.answer_invite(Invite::Decline).await,
+                '<' => self.chat.resize_sidebar(-5),
+                '>' => self.chat.resize_sidebar(5),
+                'b' => self.chat.toggle_sidebar(),
+                's' => self.switch_account(None).await,
+                'r' => self.react_to_selected(QUICK_REACTION.to_string()).await,
+                'x' => self.redact_selected(None).await,
+                'm' => self.chat.toggle_member_list().await,
+                'e' => {
+                    if !self.chat.edit_selected() {
+                        self.chat.add_notify(
+                            "select one of your sent messages with PageUp/PageDown to edit it",
+                        );
                     }
                 }
-                Invite::Decline => {
-                    if let Err(e) = self
-                        .send_jobs
-                        .send(UserRequest::DeclineInvite(room_id))
-                        .await
-                    {
-                        self.set_error(e.into())
-                    } else {
-                        self.chat.remove_invite();
+                '1'..='9' if !self.chat.is_room_search() => {
+                    let idx = c.to_digit(10).unwrap() as usize - 1;
+                    if self.chat.room_select_nth(idx) {
+                        self.chat.room_changed();
+                        self.fill_gap().await
                     }
                 }
-                Invite::NoClick => {}
+                _ => {}
             }
         }
     }
@@ -166,17 +375,34 @@ impl AppWidget {
         if self.chat.is_main_screen() {
             if self.chat.msgs_on_scroll_up(x, y) {
                 if !self.scrolling {
-                    self.scrolling = true;
                     if let Some(room_id) = self.chat.to_current_room_id() {
-                        if let Err(e) = self.send_jobs.send(UserRequest::RoomMsgs(room_id)).await {
-                            self.set_error(e.into())
+                        // there is nothing left to load once the start of the room is reached
+                        if !self.chat.reached_start(&room_id) {
+                            self.scrolling = true;
+                            // dropped messages are loaded again from the newest backwards
+                            if self.chat.take_trimmed(&room_id) {
+                                if let Err(e) = self
+                                    .send_jobs
+                                    .send(UserRequest::ResetBackfill(room_id.clone()))
+                                    .await
+                                {
+                                    self.set_error(e.into())
+                                }
+                            }
+                            if let Err(e) =
+                                self.send_jobs.send(UserRequest::RoomMsgs(room_id)).await
+                            {
+                                self.set_error(e.into())
+                            }
                         }
                     }
                 }
             } else if self.chat.room_on_scroll_up(x, y) {
-                self.chat.reset_scroll()
+                self.chat.room_changed()
             } else if self.chat.room_search_scroll_up(x, y) {
                 // TODO any UI updates while scrolled up
+            } else {
+                self.chat.member_list_scroll_up(x, y);
             }
         }
     }
@@ -188,12 +414,21 @@ impl AppWidget {
                 if self.chat.room_search_scroll_down(x, y) {
                     if let Some((filter, network, next_tkn)) = self.chat.room_search_next_request()
                     {
+                        let server = self.chat.room_search_server();
                         if let Err(e) = self
                             .send_jobs
-                            .send(UserRequest::RoomSearch(filter, network, Some(next_tkn)))
+                            .send(UserRequest::RoomSearch(
+                                filter,
+                                network,
+                                Some(next_tkn),
+                                server,
+                                self.configs.search_limit(),
+                            ))
                             .await
                         {
                             self.set_error(e.into())
+                        } else {
+                            self.chat.set_room_searching(true);
                         }
                     }
                 }
@@ -201,13 +436,19 @@ impl AppWidget {
                 self.chat.msgs_on_scroll_down(x, y);
                 // TODO make each widget's scroll method more similar to messages or room?
                 if self.chat.room_on_scroll_down(x, y) {
-                    self.chat.reset_scroll()
+                    self.chat.room_changed()
+                } else {
+                    self.chat.member_list_scroll_down(x, y);
                 }
             }
         }
     }
 
     pub async fn on_up(&mut self) {
+        if let Some(account) = self.account.as_mut() {
+            account.toggle_field();
+            return;
+        }
         if !self.login_w.logged_in {
             match self.login_or_register {
                 LoginOrRegister::Login => {
@@ -217,25 +458,30 @@ impl AppWidget {
                         self.login_w.login.selected = LoginSelect::Username;
                     }
                 }
-                LoginOrRegister::Register => {
-                    if let RegisterSelect::Username = self.register.register.selected {
-                        self.register.register.selected = RegisterSelect::Password;
-                    } else {
-                        self.register.register.selected = RegisterSelect::Username;
-                    }
-                }
+                LoginOrRegister::Register => self.register.select_previous(),
             }
         } else if self.chat.is_main_screen() {
-            if self.chat.is_room_search() {
+            if self.chat.is_showing_source() {
+                self.chat.source_scroll_up();
+            } else if self.chat.is_showing_history() {
+                self.chat.history_scroll_up();
+            } else if self.chat.is_browsing_search_history() {
+                self.chat.search_history_previous();
+            } else if self.chat.is_room_search() {
                 self.chat.room_search_select_previous();
             } else {
                 self.chat.room_select_previous();
-                self.chat.reset_scroll()
+                self.chat.room_changed();
+                self.fill_gap().await
             }
         }
     }
 
     pub async fn on_down(&mut self) {
+        if let Some(account) = self.account.as_mut() {
+            account.toggle_field();
+            return;
+        }
         if !self.login_w.logged_in {
             match self.login_or_register {
                 LoginOrRegister::Login => {
@@ -245,24 +491,204 @@ impl AppWidget {
                         self.login_w.login.selected = LoginSelect::Username;
                     }
                 }
-                LoginOrRegister::Register => {
-                    if let RegisterSelect::Username = self.register.register.selected {
-                        self.register.register.selected = RegisterSelect::Password;
-                    } else {
-                        self.register.register.selected = RegisterSelect::Username;
-                    }
-                }
+                LoginOrRegister::Register => self.register.select_next(),
             }
         } else if self.chat.is_main_screen() {
-            if self.chat.is_room_search() {
+            if self.chat.is_showing_source() {
+                self.chat.source_scroll_down();
+            } else if self.chat.is_showing_history() {
+                self.chat.history_scroll_down();
+            } else if self.chat.is_browsing_search_history() {
+                self.chat.search_history_next();
+            } else if self.chat.is_room_search() {
                 self.chat.room_search_select_next()
             } else {
                 self.chat.room_select_next();
-                self.chat.reset_scroll()
+                self.chat.room_changed();
+                self.fill_gap().await
+            }
+        }
+    }
+
+    /// Move the message cursor up to older messages.
+    pub fn on_page_up(&mut self) {
+        if self.chat.is_main_screen() && !self.chat.is_room_search() {
+            self.chat.select_previous_message()
+        }
+    }
+
+    /// Move the message cursor down to newer messages.
+    pub fn on_page_down(&mut self) {
+        if self.chat.is_main_screen() && !self.chat.is_room_search() {
+            self.chat.select_next_message()
+        }
+    }
+
+    /// Returns true if the app needs to be redrawn, either because its state changed
+    /// or a spinner or notification is on screen.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+            || self.scrolling
+            || self.login_w.logging_in
+            || self.register.registering
+            || self.chat.has_notification()
+    }
+
+    /// Mark the app as needing a redraw, called for every input event.
+    pub fn set_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Add or remove `tag` on the current room, a room never has both `tag` and `other`.
+    async fn toggle_room_tag(&mut self, tag: &str, other: &str) {
+        let room_id = match self.chat.to_current_room_id() {
+            Some(id) => id,
+            None => return,
+        };
+        let mut tags = self.chat.room_tags(&room_id).to_vec();
+        let mut requests = vec![];
+        if tags.iter().any(|t| t == tag) {
+            tags.retain(|t| t != tag);
+            requests.push(UserRequest::RemoveRoomTag(room_id.clone(), tag.to_string()));
+        } else {
+            if tags.iter().any(|t| t == other) {
+                tags.retain(|t| t != other);
+                requests.push(UserRequest::RemoveRoomTag(
+                    room_id.clone(),
+                    other.to_string(),
+                ));
+            }
+            tags.push(tag.to_string());
+            requests.push(UserRequest::SetRoomTag(room_id.clone(), tag.to_string()));
+        }
+
+        // move the room right away, the next sync sends the same tags back
+        let mut changed = RoomTags::new();
+        changed.insert(room_id, tags);
+        self.chat.set_room_tags(changed);
+        for req in requests {
+            if let Err(e) = self.send_jobs.send(req).await {
+                self.set_error(e.into());
+                return;
+            }
+        }
+    }
+
+    /// Replace the room search results with the public rooms matching `filter`.
+    async fn room_search(&mut self, filter: String) {
+        let server = self.chat.room_search_server();
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::RoomSearch(
+                filter,
+                self.chat.room_search_network(),
+                None,
+                server,
+                self.configs.search_limit(),
+            ))
+            .await
+        {
+            self.set_error(Error::from(e));
+        } else {
+            self.chat.clear_room_search();
+            self.chat.set_room_searching(true);
+        }
+    }
+
+    /// Show or hide the debug overlay.
+    pub async fn on_f12(&mut self) {
+        if self.debug.take().is_none() {
+            if let Err(e) = self.send_jobs.send(UserRequest::DebugInfo).await {
+                self.set_error(e.into())
+            }
+        }
+    }
+
+    /// Ask for the current message to be written in `$EDITOR`.
+    pub fn on_ctrl_e(&mut self) {
+        if self.chat.is_main_screen()
+            && !self.chat.is_room_search()
+            && !self.chat.is_quick_select()
+            && self.chat.to_current_room_id().is_some()
+        {
+            self.compose_requested = true;
+        }
+    }
+
+    /// Follow the first link in the selected message, matrix links fill in a `:join`
+    /// or `:dm` command to confirm and any other link is opened in the browser.
+    pub fn on_ctrl_o(&mut self) {
+        if !self.chat.is_main_screen() || self.chat.is_room_search() {
+            return;
+        }
+        let links = match self.chat.selected_text() {
+            Some(text) => utils::find_links(text),
+            None => return,
+        };
+        if let Some(link) = links.iter().find_map(|l| utils::matrix_link(l)) {
+            self.command = Some(match link {
+                MatrixLink::Room(room) => format!("join {}", room),
+                MatrixLink::User(user) => format!("dm {}", user),
+                MatrixLink::Event(room, event) => format!("goto {} {}", room, event),
+            });
+        } else if let Some(link) = links.first() {
+            // locations are shown on a map and stickers are downloaded from the homeserver
+            let link = utils::geo_map_url(link)
+                .or_else(|| utils::media_url(link, &self.homeserver))
+                .unwrap_or_else(|| link.clone());
+            if webbrowser::open(&link).is_err() {
+                self.chat.add_notify(&format!("failed to open {}", link));
             }
         }
     }
 
+    /// Reveal or mask the password on the login and register screens.
+    pub fn on_ctrl_r(&mut self) {
+        if self.login_w.logged_in {
+            return;
+        }
+        match self.login_or_register {
+            LoginOrRegister::Login => self.login_w.toggle_show_password(),
+            LoginOrRegister::Register => self.register.toggle_show_password(),
+        }
+    }
+
+    /// Open or close the history of past notifications.
+    pub fn on_ctrl_n(&mut self) {
+        if self.chat.is_main_screen() {
+            self.chat.toggle_notify_history()
+        }
+    }
+
+    /// Expand or collapse the selected message.
+    pub fn on_ctrl_x(&mut self) {
+        if self.chat.is_main_screen() && !self.chat.is_room_search() {
+            self.chat.toggle_expand_selected()
+        }
+    }
+
+    /// Ctrl-v shows the JSON of the selected message.
+    pub fn on_ctrl_v(&mut self) {
+        if self.chat.is_main_screen()
+            && !self.chat.is_room_search()
+            && !self.chat.show_selected_source()
+        {
+            self.chat
+                .add_notify("select a message received since starting rumatui to see its source");
+        }
+    }
+
+    /// Show the thread the selected message started or was sent in.
+    pub fn on_ctrl_t(&mut self) {
+        if self.chat.is_main_screen()
+            && !self.chat.is_room_search()
+            && !self.chat.open_selected_thread()
+        {
+            self.chat
+                .add_notify("the selected message is not part of a thread");
+        }
+    }
+
     pub fn on_right(&mut self) {
         if !self.login_w.logged_in {
             if self.login_or_register == LoginOrRegister::Login {
@@ -295,17 +721,51 @@ impl AppWidget {
 
     async fn add_char(&mut self, c: char) {
         if self.error.is_none() {
+            if let Some(account) = self.account.as_mut() {
+                if c != '\n' {
+                    account.selected_text().push(c);
+                } else if !account.next_field() && account.try_submit() {
+                    let req = account.request();
+                    if let Err(e) = self.send_jobs.send(req).await {
+                        self.set_error(e.into())
+                    }
+                }
+                return;
+            }
+            if let Some(cmd) = self.command.as_mut() {
+                if c == '\n' {
+                    let cmd = self.command.take().unwrap_or_default();
+                    self.run_command(cmd.trim()).await;
+                } else {
+                    cmd.push(c);
+                }
+                return;
+            }
+            if c == ':'
+                && self.login_w.logged_in
+                && self.chat.is_main_screen()
+                && !self.chat.is_room_search()
+                && !self.chat.is_quick_select()
+                && self.chat.is_input_empty()
+            {
+                self.command = Some(String::new());
+                return;
+            }
             if !self.login_w.logged_in {
                 match self.login_or_register {
                     LoginOrRegister::Login => {
+                        if c == '\n' && self.login_w.server_error.is_some() {
+                            self.check_server().await;
+                            return;
+                        }
                         if c == '\n' && self.login_w.try_login() {
-                            let Login {
-                                username, password, ..
-                            } = &self.login_w.login;
+                            let username = self.login_w.login.username.clone();
+                            let password = self.login_w.login.password.clone();
+                            self.use_account_store(&username).await;
                             self.login_w.logging_in = true;
                             if let Err(e) = self
                                 .send_jobs
-                                .send(UserRequest::Login(username.into(), password.into()))
+                                .send(UserRequest::Login(username, password))
                                 .await
                             {
                                 self.set_error(Error::from(e));
@@ -321,42 +781,37 @@ impl AppWidget {
                         }
                     }
                     LoginOrRegister::Register => {
+                        if self.register.uiaa.is_some() {
+                            self.on_uiaa_key(c).await;
+                            return;
+                        }
+                        if c == '\n' && self.register.server_error.is_some() {
+                            self.check_server().await;
+                            return;
+                        }
                         if c == '\n' && self.register.try_register() {
-                            let Register {
-                                username, password, ..
-                            } = &self.register.register;
+                            let username = self.register.register.username.clone();
+                            let password = self.register.register.password.clone();
+                            self.use_account_store(&username).await;
+                            let req =
+                                UserRequest::Register(username, password, self.register.email());
                             self.register.registering = true;
-                            if let Err(e) = self
-                                .send_jobs
-                                .send(UserRequest::Register(username.into(), password.into()))
-                                .await
-                            {
+                            if let Err(e) = self.send_jobs.send(req).await {
                                 self.set_error(Error::from(e));
                             } else {
                                 self.register.clear_register();
                             }
                             return;
                         }
-                        if let RegisterSelect::Username = self.register.register.selected {
-                            self.register.register.username.push(c);
-                        } else {
-                            self.register.register.password.push(c);
-                        }
+                        self.register.selected_text().push(c);
                     }
                 }
             } else if self.chat.is_main_screen() {
                 if self.chat.is_room_search() {
                     if c == '\n' && self.chat.try_room_search() {
                         let filter = self.chat.search_term().to_string();
-                        if let Err(e) = self
-                            .send_jobs
-                            .send(UserRequest::RoomSearch(filter, RoomNetwork::Matrix, None))
-                            .await
-                        {
-                            self.set_error(Error::from(e));
-                        } else {
-                            self.chat.clear_room_search();
-                        }
+                        self.chat.remember_room_search();
+                        self.room_search(filter).await;
                         return;
                     }
                     self.chat.push_search_text(c)
@@ -384,11 +839,121 @@ impl AppWidget {
         }
     }
 
+    /// Tab completes the command being typed or the `@` mention at the end of the
+    /// message, otherwise it moves to the next field, room or search result. A tab
+    /// is never typed.
+    pub async fn on_tab(&mut self) {
+        if let Some(cmd) = self.command.as_mut() {
+            // only the command name, its arguments are free text
+            if !cmd.contains(' ') {
+                if let Some(done) = utils::complete_word(cmd, COMMANDS.iter().copied()) {
+                    *cmd = done;
+                }
+            }
+            return;
+        }
+        if self.login_w.logged_in
+            && self.chat.is_main_screen()
+            && self.account.is_none()
+            && !self.chat.is_room_search()
+            && !self.chat.is_quick_select()
+        {
+            let draft = self.chat.draft();
+            let word = draft.rsplit(char::is_whitespace).next().unwrap_or_default();
+            if word.starts_with('@') {
+                if let Some(done) = self.complete_mention(word).await {
+                    let start = draft.len() - word.len();
+                    self.chat.set_draft(format!("{}{}", &draft[..start], done));
+                }
+                return;
+            }
+        }
+        self.on_down().await
+    }
+
+    /// Complete `word` to the user id of a member of the current room.
+    async fn complete_mention(&self, word: &str) -> Option<String> {
+        let room_id = self.chat.to_current_room_id()?;
+        let room = self.chat.rooms().get(&room_id)?.read().await;
+        let members = room
+            .joined_members
+            .keys()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        utils::complete_word(word, members.iter().map(String::as_str))
+    }
+
     pub async fn on_key(&mut self, c: char) {
+        // any typing cancels a pending quit
+        self.quit_requested = false;
         self.add_char(c).await;
     }
 
+    /// Insert pasted text as it is, a newline in a paste never sends a message, runs
+    /// a command or submits a form.
+    pub async fn on_paste(&mut self, text: String) {
+        if self.error.is_some() {
+            return;
+        }
+        self.quit_requested = false;
+        // terminals paste line breaks as carriage returns
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        // form fields and commands are a single line
+        let line = text.trim_end_matches('\n').replace('\n', " ");
+        if let Some(account) = self.account.as_mut() {
+            account.selected_text().push_str(&line);
+            return;
+        }
+        if let Some(cmd) = self.command.as_mut() {
+            cmd.push_str(&line);
+            return;
+        }
+        if !self.login_w.logged_in {
+            match self.login_or_register {
+                LoginOrRegister::Login => {
+                    if let LoginSelect::Username = self.login_w.login.selected {
+                        self.login_w.login.username.push_str(&line);
+                    } else {
+                        self.login_w.login.password.push_str(&line);
+                    }
+                }
+                LoginOrRegister::Register => match self.register.uiaa.as_mut() {
+                    Some(UiaaPrompt::EmailAddress { email, .. }) => email.push_str(&line),
+                    // the other prompts take single key answers
+                    Some(_) => {}
+                    None => self.register.selected_text().push_str(&line),
+                },
+            }
+        } else if self.chat.is_main_screen() {
+            if self.chat.is_room_search() {
+                line.chars().for_each(|c| self.chat.push_search_text(c));
+            } else if self.chat.is_quick_select() {
+                line.chars().for_each(|c| self.chat.add_char(c));
+            } else if self.configs.send_single_line_paste
+                && self.chat.draft().is_empty()
+                && !text.trim_end_matches('\n').contains('\n')
+                && !line.trim().is_empty()
+            {
+                line.chars().for_each(|c| self.chat.add_char(c));
+                self.on_send().await;
+            } else {
+                text.chars().for_each(|c| self.chat.add_char(c));
+            }
+        }
+    }
+
     pub fn on_backspace(&mut self) {
+        if let Some(account) = self.account.as_mut() {
+            account.selected_text().pop();
+            return;
+        }
+        if let Some(cmd) = self.command.as_mut() {
+            // deleting past the `:` leaves command mode
+            if cmd.pop().is_none() {
+                self.command = None;
+            }
+            return;
+        }
         if !self.login_w.logged_in {
             match self.login_or_register {
                 LoginOrRegister::Login => {
@@ -399,10 +964,12 @@ impl AppWidget {
                     }
                 }
                 LoginOrRegister::Register => {
-                    if let RegisterSelect::Username = self.register.register.selected {
-                        self.register.register.username.pop();
+                    if let Some(prompt) = self.register.uiaa.as_mut() {
+                        if let UiaaPrompt::EmailAddress { email, .. } = prompt {
+                            email.pop();
+                        }
                     } else {
-                        self.register.register.password.pop();
+                        self.register.selected_text().pop();
                     }
                 }
             }
@@ -419,6 +986,12 @@ impl AppWidget {
         if self.chat.is_main_screen() {
             let id = self.chat.to_current_room_id();
             if let Some(room_id) = id {
+                if self.chat.is_server_notice_room(&room_id) {
+                    self.chat.add_notify(
+                        "server notices can not be left, the homeserver keeps you in the room",
+                    );
+                    return;
+                }
                 if let Err(e) = self.send_jobs.send(UserRequest::LeaveRoom(room_id)).await {
                     self.set_error(e.into())
                 } else {
@@ -429,42 +1002,80 @@ impl AppWidget {
     }
 
     pub async fn on_send(&mut self) {
+        // the send box replaces the text of the message being edited
+        if let (Some(room_id), Some(event_id)) =
+            (self.chat.to_current_room_id(), self.chat.editing().cloned())
+        {
+            let body = self.chat.draft();
+            if body.trim().is_empty() {
+                return;
+            }
+            self.warn_unencrypted("the edit");
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::EditMessage(room_id, event_id, body))
+                .await
+            {
+                self.set_error(e.into());
+            } else {
+                self.chat.cancel_editing();
+            }
+            return;
+        }
+        if let Some(room_id) = self.chat.to_current_room_id() {
+            let draft = self.chat.draft();
+            match utils::send_command(&draft) {
+                Some(SendCommand::Image(Some(path))) => {
+                    self.warn_unencrypted("the image");
+                    self.send_image(room_id, utils::expand_home(path)).await;
+                    return;
+                }
+                Some(SendCommand::Image(None)) => {
+                    self.chat.add_notify("usage /image <path>");
+                    return;
+                }
+                Some(SendCommand::Kick(user, reason)) => {
+                    self.kick(room_id, user, reason.map(String::from)).await;
+                    return;
+                }
+                // emotes are sent like any other message
+                Some(SendCommand::Emote(_)) | None => {}
+            }
+        }
+        // macros only expand in the text of a new message, not in commands or edits
+        let draft = self.chat.draft();
+        let expanded = self.configs.expand_macros(&draft);
+        if expanded != draft {
+            self.chat.set_draft(expanded);
+        }
+        // the send box replies in the open thread
+        if let (Some(room_id), Some(root)) = (
+            self.chat.to_current_room_id(),
+            self.chat.open_thread().cloned(),
+        ) {
+            let body = self.chat.draft();
+            if body.trim().is_empty() {
+                return;
+            }
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::SendThreadReply(room_id, root, body))
+                .await
+            {
+                self.set_error(e.into());
+            } else {
+                self.chat.clear_send_msg();
+            }
+            return;
+        }
         // unfortunately we have to do it this way or we have a mutable borrow in the scope of immutable
         let res = if let Some(room_id) = self.chat.to_current_room_id() {
             match self.chat.get_sending_message() {
                 Ok(msg) => {
-                    self.chat.set_sending_message(true);
-                    let uuid = Uuid::new_v4();
-                    let message = msg.clone();
-                    if let Err(e) = self
-                        .send_jobs
-                        .send(UserRequest::SendMessage(room_id.clone(), msg, uuid))
-                        .await
-                    {
-                        Err(e.into())
-                    } else {
-                        // find the room the message was just sent to
-                        let local_message = if let Some(room) = self.chat.rooms().get(&room_id) {
-                            let r = room.read().await;
-                            let matrix_sdk::Room { joined_members, .. } = r.deref();
-                            let name = if let Some(mem) =
-                                joined_members.get(self.chat.as_current_user().unwrap())
-                            {
-                                mem.name()
-                            } else {
-                                self.chat.as_current_user().unwrap().localpart().into()
-                            };
-                            Some(name)
-                        } else {
-                            None
-                        };
-
-                        if let Some(name) = local_message {
-                            self.chat.echo_sent_msg(&room_id, name, uuid, message);
-                        }
+                    if self.send_content(room_id, msg).await {
                         self.chat.clear_send_msg();
-                        Ok(())
                     }
+                    Ok(())
                 }
                 Err(e) => Err(e),
             }
@@ -477,140 +1088,104 @@ impl AppWidget {
     }
 
     /// This checks once then continues returns to continue the ui loop.
-    pub async fn on_tick(&mut self, event_hndl: &UiEventHandle) {
+    pub async fn on_tick(&mut self) {
         if self.login_w.logged_in && !self.sync_started {
             self.sync_started = true;
             self.ev_loop.start_sync();
         }
 
+        if !self.offline && self.sync_stalled() {
+            self.go_offline();
+        }
+
+        // the member list follows the current room
+        if self.chat.is_member_list_stale() {
+            self.chat.refresh_member_list().await;
+        }
+
+        // the send box warns about the unverified devices of the current room
+        if self.login_w.logged_in && !self.offline && self.chat.is_current_room_encrypted() {
+            let current = self.chat.to_current_room_id();
+            if current.is_some() && current != self.devices_checked {
+                self.devices_checked = current.clone();
+                if let Some(room_id) = current {
+                    if let Err(e) = self
+                        .send_jobs
+                        .send(UserRequest::UnverifiedDevices(room_id))
+                        .await
+                    {
+                        self.set_error(e.into());
+                    }
+                }
+            }
+        }
+
+        // the user is waiting to quit and the last message has been sent
+        if self.quit_requested && !self.chat.is_sending_message() {
+            self.should_quit = true;
+        }
+
+        if self.configs.live_search && self.chat.is_room_search() {
+            if let Some(filter) = self.chat.debounced_room_search(SEARCH_DEBOUNCE) {
+                self.room_search(filter).await;
+            }
+        }
+
         // this will login, send messages, and any other user initiated requests
-        match self.ev_msgs.try_recv() {
+        let res = self.ev_msgs.try_recv();
+        if res.is_ok() {
+            self.dirty = true;
+        }
+        match res {
             Ok(res) => match res {
+                RequestResult::DebugInfo(info) => self.debug = Some(info),
+                RequestResult::CheckServer(res) => {
+                    let offline = res.as_ref().err().map_or(false, Error::is_offline);
+                    let server_error = res.err().map(|e| {
+                        tracing::warn!("homeserver check failed {}", e);
+                        format!("Cannot reach {}", self.homeserver)
+                    });
+                    self.login_w.server_error = server_error.clone();
+                    self.register.server_error = server_error;
+                    if offline && !self.login_w.logged_in && !self.login_w.logging_in {
+                        self.start_cached().await;
+                    }
+                }
                 RequestResult::Login(res) => match res {
                     Err(e) => {
                         self.login_w.logging_in = false;
                         self.set_error(e);
                     }
-                    Ok((rooms, resp)) => {
-                        // this would have caused `main()` to throw an error so unwrap here is ok
-                        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-                        path.push(".device-id.txt");
-                        if !path.exists() {
-                            let file = async_fs::OpenOptions::new()
-                                .write(true)
-                                .create(true)
-                                .open(&path)
-                                .await;
-
-                            if let Ok(mut file) = file {
-                                use tokio::io::AsyncWriteExt;
-
-                                if let Err(err) = file.write(resp.device_id.as_bytes()).await {
-                                    tracing::warn!("failed to record device_id {}", err)
-                                }
-                            } else {
-                                tracing::warn!("failed to create a device_id file {:?}", file);
-                            }
+                    Ok((rooms, user_id)) => self.on_logged_in(rooms, user_id).await,
+                },
+                RequestResult::RestoreSession(res) => match res {
+                    Ok(Some((rooms, user_id, offline))) => {
+                        // offline first so the saved messages go to the outbox
+                        if offline {
+                            self.go_offline();
                         }
-
+                        self.on_logged_in(rooms, user_id).await;
+                    }
+                    // the login form is filled in, only the password is missing
+                    Ok(None) => self.login_w.logging_in = false,
+                    Err(e) => {
                         self.login_w.logging_in = false;
-                        self.login_w.logged_in = true;
-                        self.chat.set_main_screen(true);
-                        self.chat.set_current_user(&resp.user_id);
-                        self.chat.set_room_state(rooms).await;
+                        self.set_error(e);
                     }
                 },
                 RequestResult::Register(res) => match res {
-                    Err(error) => match &error {
-                        Error::MatrixUiaaError(MatrixError::UiaaError(
-                            matrix_sdk::FromHttpResponseError::Http(
-                                matrix_sdk::ServerError::Known(UiaaResponse::AuthResponse(
-                                    UiaaInfo {
-                                        // params,
-                                        // auth_error, // TODO should this be checked every time
-                                        flows,
-                                        completed,
-                                        session,
-                                        ..
-                                    },
-                                )),
-                            ),
-                        )) => {
-                            if let Some(session) = session {
-                                let stages = flows
-                                    .iter()
-                                    .find(|f| f.stages.contains(&"m.login.dummy".to_string()))
-                                    .map(|f| f.stages.clone())
-                                    .unwrap_or_else(|| flows[0].stages.clone());
-
-                                for auth in stages.iter().filter(|auth| !completed.contains(auth)) {
-                                    if auth == "m.login.dummy" {
-                                        if let Err(e) = self
-                                            .send_jobs
-                                            .send(UserRequest::UiaaDummy(session.clone()))
-                                            .await
-                                        {
-                                            tracing::error!(
-                                                "channel closed while registering {}",
-                                                e
-                                            );
-                                            panic!("channel closed while registering")
-                                        }
-                                        // we are done Yay, the next response will be a Ok(response) from register
-                                        return;
-                                    }
-
-                                    let fallback = format!(
-                                        "{}/_matrix/client/r0/auth/{}/fallback/web?session={}",
-                                        self.homeserver, auth, session
-                                    );
-                                    if webbrowser::open(&fallback).is_ok() {
-                                        // wait here for the user to finish registration stage in the browser
-                                        // then on interaction send Uiaa ping
-                                        while let Ok(Event::Tick) = event_hndl.next() {}
-
-                                        let _ = self
-                                            .send_jobs
-                                            .send(UserRequest::UiaaPing(session.clone()))
-                                            .await;
-                                        // we bail out until completed filters all flow stages
-                                        // meaning we are done registering
-                                        return;
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            self.login_w.logging_in = false;
+                    Err(error) => match uiaa_info(error) {
+                        Ok(info) => self.next_uiaa_stage(info).await,
+                        Err(error) => {
+                            self.register.registering = false;
+                            self.register.uiaa = None;
+                            self.register.uiaa_notice = None;
                             self.set_error(error);
                         }
                     },
                     Ok(resp) => {
-                        // same reason as above unwrap is ok
-                        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-                        path.push(".device-id.txt");
-                        if !path.exists() {
-                            let file = async_fs::OpenOptions::new()
-                                .write(true)
-                                .create(true)
-                                .open(&path)
-                                .await;
-
-                            if let Ok(mut file) = file {
-                                if let Some(id) = resp.device_id {
-                                    use tokio::io::AsyncWriteExt;
-
-                                    if let Err(err) = file.write(id.as_bytes()).await {
-                                        tracing::warn!("failed to record device_id {}", err)
-                                    }
-                                } else {
-                                    tracing::info!("register response with NO device id")
-                                }
-                            } else {
-                                tracing::warn!("failed to create a device_id file {:?}", file);
-                            }
-                        }
-
+                        self.register.uiaa = None;
+                        self.register.uiaa_notice = None;
                         self.login_w.logging_in = false;
                         self.login_w.logged_in = true;
                         self.chat.set_main_screen(true);
@@ -619,15 +1194,104 @@ impl AppWidget {
                     }
                 },
                 // TODO this has the EventId which we need to keep
-                RequestResult::SendMessage(res) => match res {
-                    Err(e) => self.set_error(e),
-                    Ok(_res) => self.chat.set_sending_message(false),
+                RequestResult::Deactivate(res) => match res {
+                    Ok(()) => self.on_deactivated().await,
+                    Err(error) => self.on_account_error(error).await,
+                },
+                RequestResult::ChangePassword(res, logout) => match res {
+                    Ok(()) => {
+                        self.account = None;
+                        self.chat.add_notify(if logout {
+                            "Your password was changed and your other sessions were logged out"
+                        } else {
+                            "Your password was changed"
+                        });
+                    }
+                    Err(error) => self.on_account_error(error).await,
+                },
+                RequestResult::RegisterEmailToken(res) => match res {
+                    Ok(sid) => {
+                        self.register.registering = false;
+                        if let Some(UiaaPrompt::EmailAddress {
+                            session,
+                            email,
+                            client_secret,
+                            ..
+                        }) = self.register.uiaa.take()
+                        {
+                            self.register.uiaa = Some(UiaaPrompt::EmailSent {
+                                session,
+                                email,
+                                sid,
+                                client_secret,
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        self.register.registering = false;
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::SendMessage(res, uuid) => match res {
+                    // keep the message and send it again when the connection is back
+                    Err(e) if e.is_offline() => {
+                        self.go_offline();
+                        // the messages of the room waiting behind it are queued after it
+                        for uuid in self.take_sending(uuid) {
+                            // images are not queued, they are uploaded again by hand
+                            if !self.send_unsent(uuid).await {
+                                self.chat.finish_sending_message(&uuid);
+                                self.chat.set_send_state(uuid, Some(SendState::Failed));
+                            }
+                        }
+                    }
+                    // keep the message so `:retry` can send it again
+                    Err(e) => {
+                        self.chat.finish_sending_message(&uuid);
+                        self.chat.set_send_state(uuid, Some(SendState::Failed));
+                        self.send_next(uuid).await;
+                        self.save_outbox().await;
+                        self.set_error(e);
+                    }
+                    Ok(_) => {
+                        self.chat.finish_sending_message(&uuid);
+                        self.unsent.retain(|(id, ..)| *id != uuid);
+                        self.send_next(uuid).await;
+                        self.save_outbox().await;
+                    }
                 },
                 RequestResult::RoomMsgs(res) => match res {
-                    Err(e) => self.set_error(e),
+                    Err(e) => {
+                        // stop the loading spinner
+                        self.scrolling = false;
+                        self.chat.cancel_jump();
+                        // the next page would most likely fail too
+                        self.prefetch.values_mut().for_each(|left| *left = 0);
+                        self.set_error(e)
+                    }
                     Ok((res, room)) => {
+                        let room_id = room.read().await.room_id.clone();
+                        // an empty chunk or a token that did not move means the server has no older events
+                        if res.chunk.is_empty() || res.end.is_none() || res.end == res.start {
+                            self.chat.set_reached_start(&room_id);
+                        }
                         self.process_room_events(res, room).await;
-                        self.scrolling = false
+                        self.scrolling = false;
+                        self.prefetch_page(room_id.clone()).await;
+                        // keep backfilling until a permalink's event is loaded
+                        if let Some(event_id) = self.chat.pending_jump().cloned() {
+                            if self.chat.is_current_room(&room_id) {
+                                self.jump_to_event(room_id, event_id).await;
+                            }
+                        }
+                    }
+                },
+                RequestResult::FillGap(res) => match res {
+                    Err(e) => self.set_error(e),
+                    Ok((pages, room)) => {
+                        for res in pages {
+                            self.process_room_events(res, Arc::clone(&room)).await;
+                        }
                     }
                 },
                 RequestResult::AcceptInvite(res) => match res {
@@ -643,6 +1307,95 @@ impl AppWidget {
                         }
                     }
                 },
+                RequestResult::RoomTags(res) => match res {
+                    Ok(tags) => self.chat.set_room_tags(tags),
+                    Err(e) => {
+                        // the rooms are still usable, just not grouped
+                        tracing::warn!("failed to load room tags {}", e);
+                        self.chat.add_notify("Could not load favourite rooms")
+                    }
+                },
+                RequestResult::PinnedEvents(res, room_id) => match res {
+                    // the room may have been left while the events were fetched
+                    Ok(events) if self.chat.is_current_room(&room_id) => self.pinned = Some(events),
+                    Ok(_) => {}
+                    Err(e) => self.set_error(e),
+                },
+                RequestResult::UnverifiedDevices(res, room_id) => match res {
+                    Ok(count) => self.chat.set_unverified_devices(room_id, count),
+                    // the send box only loses its warning
+                    Err(e) => tracing::warn!("failed to count the devices of {} {}", room_id, e),
+                },
+                RequestResult::SendThreadReply(res, root) => match res {
+                    // group the reply before the sync brings it in
+                    Ok(reply) => self.chat.add_thread_reply(root, reply),
+                    Err(e) => {
+                        self.chat.add_notify("your thread reply was not sent");
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::SendReaction(res, room_id, relates_to, key) => match res {
+                    // show the reaction now, the sync echo has the same event id
+                    Ok(event_id) => {
+                        if let Some(me) = self.chat.to_current_user() {
+                            self.chat.set_reaction_event(
+                                &room_id,
+                                &relates_to,
+                                &event_id,
+                                &me,
+                                &key,
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        self.chat.add_notify("your reaction was not sent");
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::Kick(res, user_id) => match res {
+                    Ok(()) => self.chat.add_notify(&format!("kicked {}", user_id)),
+                    Err(e) => self.set_error(e),
+                },
+                RequestResult::EditMessage(res, room_id, event_id, body) => match res {
+                    // show the new text now, the sync repeats it
+                    Ok(_) => self.chat.edit_message(&room_id, &event_id, body),
+                    Err(e) => {
+                        self.chat.add_notify("your edit was not sent");
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::Redact(res, room_id, event_id) => match res {
+                    // clear the message now, the sync only repeats it
+                    Ok(_) => {
+                        self.chat.redaction_event(&room_id, &event_id);
+                    }
+                    Err(e) => {
+                        self.chat.add_notify("the message was not redacted");
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::PollVote(res) => {
+                    if let Err(e) = res {
+                        self.chat.add_notify("your vote was not sent");
+                        self.set_error(e);
+                    }
+                }
+                RequestResult::RoomTag(res) => {
+                    if let Err(e) = res {
+                        self.set_error(e);
+                    }
+                }
+                RequestResult::DirectRooms(res) => match res {
+                    Ok(rooms) => self.direct_rooms = Some(rooms),
+                    // the member count still tells most direct chats apart
+                    Err(e) => tracing::warn!("failed to load the direct chats {}", e),
+                },
+                RequestResult::KnockRoom(res) => match res {
+                    Ok(res) => self
+                        .chat
+                        .add_notify(&format!("knock sent to {}", res.room_id)),
+                    Err(e) => self.set_error(e),
+                },
                 RequestResult::DeclineInvite(res, room_id) => {
                     if let Err(e) = res {
                         self.set_error(e);
@@ -676,7 +1429,10 @@ impl AppWidget {
                     }
                 }
                 RequestResult::RoomSearch(res) => match res {
-                    Err(e) => self.set_error(e),
+                    Err(e) => {
+                        self.chat.set_room_searching(false);
+                        self.set_error(e)
+                    }
                     Ok(res) => self.chat.room_search_results(res),
                 },
                 // sync error
@@ -687,44 +1443,64 @@ impl AppWidget {
 
         // this updates the state of the UI based on events from the server
         // non user initiated events.
-        match self.emitter_msgs.try_recv() {
+        let res = self.emitter_msgs.try_recv();
+        if res.is_ok() {
+            self.dirty = true;
+        }
+        match res {
             Ok(res) => match res {
-                StateResult::Member {
-                    sender,
-                    receiver,
-                    room,
-                    membership,
-                    timeline_event,
-                } => {
-                    let invitation = matches!(membership, MembershipChange::Invited);
+                StateResult::Member(change) => {
+                    let invitation = matches!(change.membership, MembershipChange::Invited);
 
-                    let room_id = room.read().await.room_id.clone();
+                    let (room_id, members) = {
+                        let room = change.room.read().await;
+                        (room.room_id.clone(), room.joined_members.len())
+                    };
+                    // the room header counts the members
+                    self.chat.set_member_count(room_id.clone(), members);
+                    if self.chat.is_member_list() && self.chat.is_current_room(&room_id) {
+                        self.chat.refresh_member_list().await;
+                    }
 
-                    // only display notifications for the current room
-                    if self.chat.is_current_room(&room_id)
+                    // joins and leaves of other users are shown between the messages of their
+                    // room, other changes are only notified for the current room
+                    let notice = match change.membership {
+                        MembershipChange::Joined => Some(MemberNotice::Joined),
+                        MembershipChange::Left => Some(MemberNotice::Left),
+                        _ => None,
+                    }
+                    .filter(|_| Some(&change.receiver) != self.chat.as_current_user());
+                    if let (Some(notice), Some((event_id, timestamp))) =
+                        (notice, change.timeline.clone())
+                    {
+                        self.chat.add_message(
+                            Message::membership(change.receiver, event_id, timestamp, notice),
+                            &room_id,
+                        );
+                    } else if self.chat.is_current_room(&room_id)
                         // unless this is an invitation
                         || invitation
                         // or it is an event directed towards the user
-                        || Some(&receiver) == self.chat.as_current_user()
+                        || Some(&change.receiver) == self.chat.as_current_user()
                         // or it is an event we directed at another user
-                        || Some(&sender) == self.chat.as_current_user()
+                        || Some(&change.sender) == self.chat.as_current_user()
                     {
                         // when the event is directed at ourselves but from another room show the room name
                         let show_room_name = !self.chat.is_current_room(&room_id);
 
-                        self.handle_membership(
-                            membership,
-                            receiver,
-                            sender,
-                            room,
-                            timeline_event,
-                            show_room_name,
-                        )
-                        .await;
+                        self.handle_membership(change, show_room_name).await;
                     }
                 }
                 StateResult::Name(name, room_id) => self.chat.update_room(&name, &room_id),
                 StateResult::Message(msg, room) => {
+                    if self.configs.mention_bell && self.should_ring_for(&msg, &room).await {
+                        crate::widgets::utils::ring_bell();
+                    }
+                    // notices can warn about the account so they are never missed
+                    if self.chat.is_server_notice_room(&room) && !self.chat.is_current_room(&room) {
+                        self.chat
+                            .add_notify("new server notice from the homeserver");
+                    }
                     self.chat.add_message(msg, &room);
                     if let Some(event) = self.chat.read_receipt(self.last_interaction, &room) {
                         if let Err(e) = self
@@ -739,11 +1515,42 @@ impl AppWidget {
                 StateResult::MessageEdit(msg, room_id, event_id) => {
                     self.chat.edit_message(&room_id, &event_id, msg);
                 }
+                // the room was read up to its newest message, possibly on another device
                 StateResult::FullyRead(event_id, room_id) => {
-                    if self.chat.read_to_end(&room_id, &event_id)
-                        && self.chat.is_current_room(&room_id)
+                    if self.chat.read_to_end(&room_id, &event_id) {
+                        self.chat.clear_unread(&room_id).await;
+                        self.unread = self.chat.unread_counts().await;
+                    }
+                }
+                StateResult::Encrypted(room_id) => self.chat.set_encrypted(room_id),
+                StateResult::EventSources(sources) => {
+                    for (event_id, json) in sources {
+                        self.chat.add_source(event_id, json);
+                    }
+                }
+                StateResult::ThirdPartyInvite(room_id, sender, email) => {
+                    if self.chat.is_current_room(&room_id)
+                        || Some(&sender) == self.chat.as_current_user()
                     {
-                        // TODO what should be done for fully read events
+                        self.chat.add_notify(&format!(
+                            "{} invited {} by email",
+                            sender.localpart(),
+                            email
+                        ));
+                    }
+                }
+                StateResult::InviteTopic(room_id, topic) => {
+                    self.chat.set_invite_topic(&room_id, topic)
+                }
+                StateResult::ThreadReply(root, reply, _room_id) => {
+                    self.chat.add_thread_reply(root, reply)
+                }
+                StateResult::SyncStats(stats) => {
+                    self.last_sync = Some((stats, SystemTime::now()));
+                    self.sync_beat = !self.sync_beat;
+                    self.unread = self.chat.unread_counts().await;
+                    if self.offline {
+                        self.go_online().await;
                     }
                 }
                 StateResult::Typing(room_id, msg) => {
@@ -752,6 +1559,25 @@ impl AppWidget {
                     }
                 }
                 StateResult::ReadReceipt(room_id, events) => {
+                    // receipts sent by the user's other sessions
+                    let me = self.chat.to_current_user();
+                    let own_receipts = events
+                        .iter()
+                        .filter(|(_, rec)| {
+                            rec.read.as_ref().map_or(false, |map| {
+                                me.as_ref().map_or(false, |me| map.contains_key(me))
+                            })
+                        })
+                        .map(|(event_id, _)| event_id.clone())
+                        .collect::<Vec<_>>();
+                    for event_id in own_receipts {
+                        if self.chat.mark_read_up_to(&room_id, &event_id)
+                            && self.chat.read_to_end(&room_id, &event_id)
+                        {
+                            self.chat.clear_unread(&room_id).await;
+                            self.unread = self.chat.unread_counts().await;
+                        }
+                    }
                     let mut notices = vec![];
                     if self.chat.is_current_room(&room_id) {
                         for e_id in self.chat.last_3_msg_event_ids(&room_id) {
@@ -760,12 +1586,13 @@ impl AppWidget {
                                     // TODO keep track so we don't emit duplicate notices for
                                     // the same user with different EventIds
                                     for (user, receipt) in map {
-                                        if receipt
-                                            .ts
-                                            .and_then(|ts| ts.elapsed().ok())
-                                            // only show read receipts for the last 10 minutes
-                                            .map(|dur| dur.as_secs() < 600)
-                                            == Some(true)
+                                        if Some(user) != me.as_ref()
+                                            && receipt
+                                                .ts
+                                                .and_then(|ts| ts.elapsed().ok())
+                                                // only show read receipts for the last 10 minutes
+                                                .map(|dur| dur.as_secs() < 600)
+                                                == Some(true)
                                         {
                                             notices.push(format!(
                                                 "{} has seen the latest messages",
@@ -781,11 +1608,32 @@ impl AppWidget {
                         self.chat.add_notify(&notice);
                     }
                 }
-                StateResult::Reaction(relates_to, event_id, room_id, msg) => self
+                StateResult::Reaction(relates_to, event_id, sender, room_id, msg) => self
                     .chat
-                    .set_reaction_event(&room_id, &relates_to, &event_id, &msg),
-                StateResult::Redact(event_id, room_id) => {
-                    self.chat.redaction_event(&room_id, &event_id)
+                    .set_reaction_event(&room_id, &relates_to, &event_id, &sender, &msg),
+                StateResult::Poll(msg, poll, room_id) => self.chat.add_poll(msg, poll, &room_id),
+                StateResult::PollResponse(poll, sender, selections, room_id) => {
+                    self.chat.poll_vote(&room_id, &poll, &sender, selections);
+                }
+                StateResult::PollEnd(poll, sender, room_id) => {
+                    // anyone allowed to redact the poll may end it
+                    let moderator = self
+                        .reaches_redact_level(&room_id, &sender)
+                        .await
+                        .unwrap_or(false);
+                    self.chat.end_poll(&room_id, &poll, &sender, moderator)
+                }
+                StateResult::Redact(event_id, room_id, sender) => {
+                    // the redacted event may be state, like a membership change, or a
+                    // message that was never loaded
+                    if !self.chat.redaction_event(&room_id, &event_id)
+                        && self.chat.is_current_room(&room_id)
+                    {
+                        self.chat.add_notify(&format!(
+                            "{} redacted an event that is not shown",
+                            sender.localpart()
+                        ))
+                    }
                 }
                 _ => {}
             },
@@ -793,7 +1641,1335 @@ impl AppWidget {
         }
     }
 
+    /// Fetch any messages the current room missed while we were offline.
+    async fn fill_gap(&mut self) {
+        if let Some(room_id) = self.chat.to_current_room_id() {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::FillGap(room_id.clone()))
+                .await
+            {
+                self.set_error(e.into())
+            }
+            if !self.prefetch.contains_key(&room_id) {
+                self.prefetch
+                    .insert(room_id.clone(), self.configs.prefetch_pages);
+                self.prefetch_page(room_id).await;
+            }
+        }
+    }
+
+    /// Load the next page of older messages while the room has prefetch pages left,
+    /// stopping early at the start of the room.
+    async fn prefetch_page(&mut self, room_id: RoomId) {
+        let left = match self.prefetch.get_mut(&room_id) {
+            Some(left) if *left > 0 && !self.chat.reached_start(&room_id) => left,
+            _ => return,
+        };
+        *left -= 1;
+        if let Err(e) = self.send_jobs.send(UserRequest::RoomMsgs(room_id)).await {
+            self.set_error(e.into())
+        }
+    }
+
+    /// Ask the homeserver which versions of the spec it supports, this fails when
+    /// the server is not reachable or is not a Matrix server.
+    async fn check_server(&mut self) {
+        self.login_w.server_error = None;
+        self.register.server_error = None;
+        if let Err(e) = self.send_jobs.send(UserRequest::CheckServer).await {
+            self.set_error(e.into())
+        }
+    }
+
+    /// Move registration to the next stage the homeserver asks for, stages that
+    /// need the user show a prompt in place of the register form.
+    async fn next_uiaa_stage(&mut self, info: UiaaInfo) {
+        let session = match info.session {
+            Some(session) => session,
+            None => {
+                self.register.registering = false;
+                self.set_error(Error::Rumatui(
+                    "the homeserver did not start a registration session",
+                ));
+                return;
+            }
+        };
+        let flows = info
+            .flows
+            .into_iter()
+            .map(|flow| flow.stages)
+            .collect::<Vec<_>>();
+        let params = serde_json::from_str(info.params.get()).unwrap_or_default();
+
+        self.register.uiaa_notice = None;
+        let prompt = match uiaa::next_stage(&flows, &info.completed, &params) {
+            // every stage is done, the ping finishes registering
+            None => {
+                self.send_uiaa(UserRequest::UiaaPing(session)).await;
+                return;
+            }
+            Some(UiaaStage::Dummy) => {
+                let auth = AuthData::new(uiaa::DUMMY, session);
+                self.send_uiaa(UserRequest::UiaaAuth(auth)).await;
+                return;
+            }
+            Some(UiaaStage::Terms(policies)) => UiaaPrompt::Terms { session, policies },
+            Some(UiaaStage::Email) => match self.register.uiaa.take() {
+                // the link has not been opened yet
+                Some(prompt @ UiaaPrompt::EmailSent { .. }) => {
+                    self.register.uiaa_notice =
+                        Some("The email address has not been validated yet".to_string());
+                    prompt
+                }
+                _ => {
+                    let email = self.register.email().unwrap_or_default();
+                    let client_secret = Uuid::new_v4().to_string();
+                    // the email typed when registering skips asking for it
+                    let send_attempt = if email.is_empty() { 0 } else { 1 };
+                    self.register.uiaa = Some(UiaaPrompt::EmailAddress {
+                        session,
+                        email: email.clone(),
+                        client_secret: client_secret.clone(),
+                        send_attempt,
+                    });
+                    if email.is_empty() {
+                        self.register.registering = false;
+                    } else {
+                        let req =
+                            UserRequest::RegisterEmailToken(email, client_secret, send_attempt);
+                        self.send_uiaa(req).await;
+                    }
+                    return;
+                }
+            },
+            Some(UiaaStage::Fallback(stage)) => {
+                let url = format!(
+                    "{}/_matrix/client/r0/auth/{}/fallback/web?session={}",
+                    self.homeserver, stage, session
+                );
+                if webbrowser::open(&url).is_err() {
+                    tracing::warn!("failed to open the UIAA fallback for {}", stage);
+                }
+                UiaaPrompt::Fallback {
+                    session,
+                    stage,
+                    url,
+                }
+            }
+        };
+        self.register.registering = false;
+        self.register.uiaa = Some(prompt);
+    }
+
+    /// Move an account change to the next stage the homeserver asks for, only the
+    /// password stage can be done while logged in.
+    async fn next_account_stage(&mut self, info: UiaaInfo) {
+        let account = match self.account.as_mut() {
+            Some(account) => account,
+            None => return,
+        };
+        account.waiting = false;
+        let session = match info.session {
+            Some(session) => session,
+            None => {
+                account.notice = Some("The homeserver did not start a session".to_string());
+                return;
+            }
+        };
+        let flows = info
+            .flows
+            .into_iter()
+            .map(|flow| flow.stages)
+            .collect::<Vec<_>>();
+        let params = serde_json::from_str(info.params.get()).unwrap_or_default();
+
+        let stage = match uiaa::next_stage(&flows, &info.completed, &params) {
+            // the password for this session was already sent
+            Some(UiaaStage::Password) | None
+                if account.sent_password && account.session.as_ref() == Some(&session) =>
+            {
+                account.sent_password = false;
+                account.password.clear();
+                account.notice = Some("The password was not accepted".to_string());
+                return;
+            }
+            Some(UiaaStage::Password) | None => {
+                account.session = Some(session);
+                let req = account.request();
+                if let Err(e) = self.send_jobs.send(req).await {
+                    self.set_error(e.into())
+                }
+                return;
+            }
+            Some(UiaaStage::Dummy) => {
+                let req = account.request_with(Some(AuthData::new(uiaa::DUMMY, session)));
+                if let Err(e) = self.send_jobs.send(req).await {
+                    self.set_error(e.into())
+                }
+                return;
+            }
+            Some(UiaaStage::Terms(_)) => uiaa::TERMS.to_string(),
+            Some(UiaaStage::Email) => uiaa::EMAIL.to_string(),
+            Some(UiaaStage::Fallback(stage)) => stage,
+        };
+        account.notice = Some(format!(
+            "The homeserver asks for {} which rumatui can not do, use another client",
+            stage
+        ));
+    }
+
+    /// Either the homeserver wants another UIAA stage or the account change failed.
+    async fn on_account_error(&mut self, error: Error) {
+        match uiaa_info(error) {
+            Ok(info) => self.next_account_stage(info).await,
+            Err(error) => {
+                if let Some(account) = self.account.as_mut() {
+                    account.waiting = false;
+                }
+                self.set_error(error);
+            }
+        }
+    }
+
+    /// The account is gone, stop its client, delete its store and saved session and go
+    /// back to the login screen.
+    async fn on_deactivated(&mut self) {
+        let account = self.client_account.clone();
+        let homeserver = self.homeserver.clone();
+        // the old client has to be dropped before its store can be deleted
+        self.start_client(&homeserver, None).await.stopped().await;
+        if let Some(user_id) = account {
+            let root: &Path = crate::RUMATUI_DIR.as_ref().unwrap();
+            if let Err(e) = async_fs::remove_dir_all(account_dir(root, &user_id)).await {
+                tracing::warn!("failed to delete the store of {} {}", user_id, e);
+            }
+            self.configs.forget_account(&user_id);
+            if let Err(e) = self.configs.save().await {
+                tracing::warn!("failed to save configs {}", e);
+            }
+        }
+        self.reset_session();
+    }
+
+    /// Forget everything about the logged in account and show the login screen.
+    fn reset_session(&mut self) {
+        self.account = None;
+        self.pinned = None;
+        self.debug = None;
+        self.login_w.logged_in = false;
+        self.login_w.logging_in = false;
+        self.login_w.login.password.clear();
+        self.login_or_register = LoginOrRegister::Login;
+        let mut chat = ChatWidget::default();
+        chat.apply_configs(&self.configs);
+        self.chat = chat;
+        self.prefetch.clear();
+        self.direct_rooms = None;
+        self.devices_checked = None;
+        self.sync_started = false;
+        self.scrolling = false;
+        self.offline = false;
+        self.last_sync = None;
+        self.unread = (0, 0);
+        self.unsent.clear();
+        self.outbox.clear();
+        self.sending.clear();
+        self.profiles.clear();
+    }
+
+    /// Stop the current client and start one for `homeserver` that keeps its store in
+    /// the directory of `account`, the stopping client's handle is returned.
+    async fn start_client(
+        &mut self,
+        homeserver: &str,
+        account: Option<String>,
+    ) -> MatrixEventHandle {
+        self.ev_loop.quit_sync();
+        if let Err(e) = self.send_jobs.send(UserRequest::Quit).await {
+            tracing::warn!("the client stopped before it was replaced {}", e);
+        }
+
+        let (send, recv) = mpsc::channel(1024);
+        let (emitter, emitter_msgs) = EventStream::new();
+        let (ev_loop, send_jobs) = MatrixEventHandle::new(
+            emitter,
+            send,
+            self.rt.clone(),
+            homeserver,
+            account.as_deref(),
+            self.configs.unencrypted_fallback,
+        )
+        .await;
+        self.send_jobs = send_jobs;
+        self.ev_msgs = recv;
+        self.emitter_msgs = emitter_msgs;
+        self.client_account = account;
+        std::mem::replace(&mut self.ev_loop, ev_loop)
+    }
+
+    /// Before logging in or registering as `username` make sure the client keeps its
+    /// store, and so its device id, in the directory of that account.
+    async fn use_account_store(&mut self, username: &str) {
+        let account = match self.configs.saved_user_id(&self.homeserver, username) {
+            Some(user_id) => Some(user_id.to_string()),
+            // a new account, its id is assumed to be on the homeserver
+            None => Username::parse(username).ok().and_then(|name| {
+                let server = match name.server {
+                    Some(server) => server,
+                    None => Url::parse(&self.homeserver).ok()?.host_str()?.to_string(),
+                };
+                Some(format!("@{}:{}", name.localpart, server))
+            }),
+        };
+        if account.is_some() && account != self.client_account {
+            let homeserver = self.homeserver.clone();
+            self.start_client(&homeserver, account).await;
+        }
+    }
+
+    /// Stop the client of the current account and start one for the saved account
+    /// named `name`, or the next saved account. The saved session of the account logs
+    /// in, when it has none or it expired the login form is filled in so only the
+    /// password is needed.
+    async fn switch_account(&mut self, name: Option<&str>) {
+        let current = self.chat.as_current_user().map(ToString::to_string);
+        let account = match self.configs.switch_account(current.as_deref(), name) {
+            Some(account) => account.clone(),
+            None => {
+                let msg = match name {
+                    Some(name) => format!("no saved account {}, see :accounts", name),
+                    None => "there is no other saved account to switch to".to_string(),
+                };
+                self.chat.add_notify(&msg);
+                return;
+            }
+        };
+
+        if self.login_w.logged_in {
+            self.save_outbox().await;
+        }
+        self.start_client(&account.homeserver, Some(account.user_id.clone()))
+            .await;
+
+        self.reset_session();
+        self.homeserver = account.homeserver;
+        // the login screen fills these in from the new homeserver
+        self.login_w.homeserver = None;
+        self.register.homeserver = None;
+        self.login_w.login.username = account.user_id;
+        self.login_w.login.selected = LoginSelect::Password;
+        self.check_server().await;
+        self.login_w.logging_in = true;
+        if let Err(e) = self.send_jobs.send(UserRequest::RestoreSession).await {
+            self.login_w.logging_in = false;
+            self.set_error(e.into());
+        }
+    }
+
+    /// Typing while a registration stage is waiting on the user.
+    async fn on_uiaa_key(&mut self, c: char) {
+        let prompt = match self.register.uiaa.as_mut() {
+            Some(prompt) if !self.register.registering => prompt,
+            _ => return,
+        };
+        if c != '\n' {
+            if let UiaaPrompt::EmailAddress { email, .. } = prompt {
+                email.push(c);
+            }
+            return;
+        }
+
+        let req = match prompt {
+            UiaaPrompt::Terms { session, .. } => {
+                UserRequest::UiaaAuth(AuthData::new(uiaa::TERMS, session.clone()))
+            }
+            UiaaPrompt::EmailAddress {
+                email,
+                client_secret,
+                send_attempt,
+                ..
+            } => {
+                if email.trim().is_empty() {
+                    return;
+                }
+                // a failed request is sent again as the next attempt
+                *send_attempt += 1;
+                UserRequest::RegisterEmailToken(
+                    email.trim().to_string(),
+                    client_secret.clone(),
+                    *send_attempt,
+                )
+            }
+            UiaaPrompt::EmailSent {
+                session,
+                sid,
+                client_secret,
+                ..
+            } => UserRequest::UiaaAuth(AuthData {
+                threepid_creds: Some(ThreepidCreds {
+                    sid: sid.clone(),
+                    client_secret: client_secret.clone(),
+                }),
+                ..AuthData::new(uiaa::EMAIL, session.clone())
+            }),
+            UiaaPrompt::Fallback { session, .. } => UserRequest::UiaaPing(session.clone()),
+        };
+        self.send_uiaa(req).await;
+    }
+
+    async fn send_uiaa(&mut self, req: UserRequest) {
+        self.register.registering = true;
+        if let Err(e) = self.send_jobs.send(req).await {
+            tracing::error!("channel closed while registering {}", e);
+            panic!("channel closed while registering")
+        }
+    }
+
+    /// Run a command typed after `:` in the status line.
+    async fn run_command(&mut self, cmd: &str) {
+        let mut args = cmd.splitn(2, ' ');
+        match (args.next().unwrap_or_default(), args.next()) {
+            ("", _) => {}
+            ("q", _) | ("quit", _) => self.on_quit_request(),
+            ("search", server) => {
+                // `:search example.org` browses the public rooms of another server
+                let server = server.map(str::trim).filter(|s| !s.is_empty());
+                self.chat
+                    .set_room_search_server(server.map(ToString::to_string));
+                self.chat.set_room_search(true)
+            }
+            ("fav", _) => self.toggle_room_tag(FAVOURITE, LOW_PRIORITY).await,
+            ("lowpriority", _) => self.toggle_room_tag(LOW_PRIORITY, FAVOURITE).await,
+            ("knock", Some(args)) => {
+                let mut args = args.splitn(2, ' ');
+                let room = args.next().unwrap_or_default();
+                let reason = args.next().map(|r| r.trim().to_string());
+                match RoomIdOrAliasId::try_from(room) {
+                    Ok(room) => {
+                        if let Err(e) = self
+                            .send_jobs
+                            .send(UserRequest::KnockRoom(room, reason))
+                            .await
+                        {
+                            self.set_error(e.into())
+                        }
+                    }
+                    Err(_) => self
+                        .chat
+                        .add_notify(&format!("{} is not a room id or alias", room)),
+                }
+            }
+            ("knock", None) => self.chat.add_notify("usage :knock <room> [reason]"),
+            ("join", Some(room)) => match RoomIdOrAliasId::try_from(room.trim()) {
+                Ok(room) => {
+                    if let Err(e) = self
+                        .send_jobs
+                        .send(UserRequest::JoinRoomByIdOrAlias(room))
+                        .await
+                    {
+                        self.set_error(e.into())
+                    } else {
+                        self.chat.set_joining_room(true);
+                    }
+                }
+                Err(_) => self
+                    .chat
+                    .add_notify(&format!("{} is not a room id or alias", room)),
+            },
+            ("dm", Some(user)) => match UserId::try_from(user.trim()) {
+                Ok(user) => match self.direct_room_with(&user).await {
+                    Some(room_id) => {
+                        self.chat.set_current_room_id(&room_id);
+                        self.chat.room_changed();
+                    }
+                    None => self
+                        .chat
+                        .add_notify(&format!("no direct message room with {}", user)),
+                },
+                Err(_) => self.chat.add_notify(&format!("{} is not a user id", user)),
+            },
+            ("pinned", _) => {
+                if self.pinned.take().is_none() {
+                    if let Some(room_id) = self.chat.to_current_room_id() {
+                        if let Err(e) = self
+                            .send_jobs
+                            .send(UserRequest::PinnedEvents(room_id))
+                            .await
+                        {
+                            self.set_error(e.into())
+                        }
+                    }
+                }
+            }
+            ("pin", _) => self.pin_selected(true).await,
+            ("unpin", _) => self.pin_selected(false).await,
+            ("deactivate", confirm) => match self.chat.to_current_user() {
+                // the full user id has to be typed out, there is no undoing this
+                Some(me) if confirm.map(str::trim) == Some(me.to_string().as_str()) => {
+                    self.account = Some(AccountWidget::new(
+                        AccountAction::Deactivate,
+                        me.to_string(),
+                    ))
+                }
+                _ => self.chat.add_notify(
+                    "type :deactivate <your full user id> to confirm, deactivating can not be undone",
+                ),
+            },
+            ("passwd", logout) => match (self.chat.to_current_user(), logout.map(str::trim)) {
+                (Some(me), None) | (Some(me), Some("")) => {
+                    self.account = Some(AccountWidget::new(
+                        AccountAction::ChangePassword(false),
+                        me.to_string(),
+                    ))
+                }
+                (Some(me), Some("logout")) => {
+                    self.account = Some(AccountWidget::new(
+                        AccountAction::ChangePassword(true),
+                        me.to_string(),
+                    ))
+                }
+                _ => self.chat.add_notify("usage :passwd [logout]"),
+            },
+            ("vote", Some(number)) => self.vote_in_selected_poll(number.trim()).await,
+            ("vote", None) => self.chat.add_notify("usage :vote <answer number>"),
+            ("react", Some(key)) => self.react_to_selected(key.trim().to_string()).await,
+            ("react", None) => self.chat.add_notify("usage :react <emoji>"),
+            ("redact", reason) => {
+                let reason = reason.map(str::trim).filter(|reason| !reason.is_empty());
+                self.redact_selected(reason.map(String::from)).await
+            }
+            ("goto", Some(args)) => {
+                // either a pasted permalink or the room and event ids
+                let mut args = args.split_whitespace();
+                let link = match (args.next(), args.next()) {
+                    (Some(link), None) => utils::matrix_link(link),
+                    (Some(room), Some(event)) => {
+                        match (RoomIdOrAliasId::try_from(room), EventId::try_from(event)) {
+                            (Ok(room), Ok(event)) => Some(MatrixLink::Event(room, event)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                match link {
+                    Some(MatrixLink::Event(room, event)) => match self.joined_room(&room).await {
+                        Some(room_id) => self.jump_to_event(room_id, event).await,
+                        None => self
+                            .chat
+                            .add_notify(&format!("you have not joined {}", room)),
+                    },
+                    _ => self
+                        .chat
+                        .add_notify("usage :goto <permalink> or :goto <room> <event>"),
+                }
+            }
+            ("retry", _) => self.retry_failed().await,
+            ("reload", _) => self.reload_configs().await,
+            ("switch", name) => {
+                let name = name.map(str::trim).filter(|name| !name.is_empty());
+                self.switch_account(name).await
+            }
+            ("accounts", _) => {
+                let accounts = self
+                    .configs
+                    .accounts
+                    .iter()
+                    .map(|acc| acc.user_id.as_str())
+                    .collect::<Vec<_>>();
+                self.chat
+                    .add_notify(&format!("saved accounts: {}", accounts.join(", ")))
+            }
+            ("location", Some(args)) => {
+                let mut args = args.trim().splitn(2, ' ');
+                let coords = args.next().unwrap_or_default();
+                let geo_uri = format!("geo:{}", coords);
+                match (self.chat.to_current_room_id(), utils::geo_map_url(&geo_uri)) {
+                    (Some(room_id), Some(_)) => {
+                        let body = args
+                            .next()
+                            .map(str::trim)
+                            .filter(|desc| !desc.is_empty())
+                            .map(ToString::to_string)
+                            .unwrap_or_else(|| format!("Location {}", coords));
+                        let content = MessageEventContent::Location(LocationMessageEventContent {
+                            body,
+                            geo_uri,
+                            info: None,
+                        });
+                        self.send_content(room_id, content).await;
+                    }
+                    (None, _) => {}
+                    (_, None) => self
+                        .chat
+                        .add_notify("usage :location <latitude>,<longitude> [description]"),
+                }
+            }
+            ("location", None) => self
+                .chat
+                .add_notify("usage :location <latitude>,<longitude> [description]"),
+            _ => self.chat.add_notify(&format!("unknown command :{}", cmd)),
+        }
+    }
+
+    /// Read the configs file again and apply it without logging out, the settings
+    /// only read at startup keep their old value until rumatui is restarted.
+    async fn reload_configs(&mut self) {
+        let mut configs = match Configs::load().await {
+            Ok(configs) => configs,
+            Err(e) => {
+                self.chat
+                    .add_notify(&format!("failed to reload the configs: {}", e));
+                return;
+            }
+        };
+        // the searches of this session are only written on quit
+        configs.search_history = self.chat.room_search_history().to_vec();
+        if configs.unencrypted_fallback != self.configs.unencrypted_fallback {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::SetUnencryptedFallback(
+                    configs.unencrypted_fallback,
+                ))
+                .await
+            {
+                self.set_error(e.into());
+            }
+        }
+        let restart = configs.tick_rate != self.configs.tick_rate
+            || configs.alternate_screen != self.configs.alternate_screen
+            || configs.window_title != self.configs.window_title;
+
+        self.chat.apply_configs(&configs);
+        self.configs = configs;
+        self.chat.add_notify(if restart {
+            "configs reloaded, tick_rate, alternate_screen and window_title change after a restart"
+        } else {
+            "configs reloaded"
+        });
+    }
+
+    /// Pin or unpin the message under the message cursor, the pinned messages are shown
+    /// once the room's state is updated.
+    async fn pin_selected(&mut self, pin: bool) {
+        let (room_id, event_id) = match (self.chat.to_current_room_id(), self.chat.selected_event())
+        {
+            (Some(room), Some(event)) => (room, event.clone()),
+            _ => {
+                self.chat
+                    .add_notify("select a message with PageUp/PageDown to pin it");
+                return;
+            }
+        };
+        if !self.can_pin(&room_id).await {
+            self.chat
+                .add_notify("you do not have permission to pin messages in this room");
+            return;
+        }
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::PinEvent(room_id, event_id, pin))
+            .await
+        {
+            self.set_error(e.into())
+        }
+    }
+
+    /// Is the user's power level high enough to change the room's pinned events, when the
+    /// power levels are not known the server decides.
+    async fn can_pin(&self, room_id: &RoomId) -> bool {
+        let (me, room) = match (self.chat.as_current_user(), self.chat.rooms().get(room_id)) {
+            (Some(me), Some(room)) => (me, room),
+            _ => return false,
+        };
+        let room = room.read().await;
+        let levels = match room.power_levels.as_ref() {
+            Some(levels) => levels,
+            None => return true,
+        };
+        let needed = levels
+            .events
+            .get(&EventType::RoomPinnedEvents)
+            .copied()
+            .unwrap_or(levels.state_default);
+        let mine = room
+            .joined_members
+            .get(me)
+            .and_then(|member| member.power_level)
+            .unwrap_or(levels.users_default);
+        mine >= needed
+    }
+
+    /// Redact the message under the message cursor, only the user's own messages unless
+    /// their power level lets them redact anyone's.
+    async fn redact_selected(&mut self, reason: Option<String>) {
+        let (room_id, event_id, sender) =
+            match (self.chat.to_current_room_id(), self.chat.selected_message()) {
+                (Some(room), Some(msg)) if !msg.is_local_echo() => {
+                    (room, msg.event_id.clone(), msg.user.clone())
+                }
+                (Some(_), Some(_)) => {
+                    self.chat
+                        .add_notify("the message has not been sent yet, it can not be redacted");
+                    return;
+                }
+                _ => {
+                    self.chat
+                        .add_notify("select a message with PageUp/PageDown to redact it");
+                    return;
+                }
+            };
+        if !self.can_redact(&room_id, &sender).await {
+            self.chat
+                .add_notify("you do not have permission to redact other people's messages");
+            return;
+        }
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::Redact(room_id, event_id, reason))
+            .await
+        {
+            self.set_error(e.into())
+        }
+    }
+
+    /// Can the user redact a message of `sender`, their own always and anyone else's
+    /// when their power level reaches the room's redact level. When the power levels
+    /// are not known the server decides.
+    async fn can_redact(&self, room_id: &RoomId, sender: &UserId) -> bool {
+        let me = match self.chat.as_current_user() {
+            Some(me) if self.chat.rooms().contains_key(room_id) => me,
+            _ => return false,
+        };
+        if me == sender {
+            return true;
+        }
+        self.reaches_redact_level(room_id, me).await.unwrap_or(true)
+    }
+
+    /// Whether `user`'s power level reaches the redact level of the room, `None` when
+    /// the room or its power levels are not known.
+    async fn reaches_redact_level(&self, room_id: &RoomId, user: &UserId) -> Option<bool> {
+        let room = self.chat.rooms().get(room_id)?.read().await;
+        let levels = room.power_levels.as_ref()?;
+        let level = room
+            .joined_members
+            .get(user)
+            .and_then(|member| member.power_level)
+            .unwrap_or(levels.users_default);
+        Some(level >= levels.redact)
+    }
+
+    /// React with `key` to the message under the message cursor.
+    async fn react_to_selected(&mut self, key: String) {
+        let (room_id, relates_to) =
+            match (self.chat.to_current_room_id(), self.chat.selected_event()) {
+                (Some(room), Some(event)) => (room, event.clone()),
+                _ => {
+                    self.chat
+                        .add_notify("select a message with PageUp/PageDown to react");
+                    return;
+                }
+            };
+        if self.chat.has_reacted(&relates_to, &key) {
+            self.chat
+                .add_notify(&format!("you already reacted with {}", key));
+            return;
+        }
+        self.warn_unencrypted("the reaction");
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::SendReaction(room_id, relates_to, key))
+            .await
+        {
+            self.set_error(e.into())
+        }
+    }
+
+    /// Vote for the `number`th answer of the poll under the message cursor, the tally is
+    /// updated right away and corrected by the response when it syncs.
+    async fn vote_in_selected_poll(&mut self, number: &str) {
+        let (room_id, poll_id) = match (self.chat.to_current_room_id(), self.chat.selected_event())
+        {
+            (Some(room), Some(event)) => (room, event.clone()),
+            _ => {
+                self.chat
+                    .add_notify("select a poll with PageUp/PageDown to vote");
+                return;
+            }
+        };
+        let answer = match self.chat.poll(&poll_id) {
+            None => {
+                self.chat.add_notify("the selected message is not a poll");
+                return;
+            }
+            Some(poll) if poll.closed => {
+                self.chat.add_notify("this poll has ended");
+                return;
+            }
+            Some(poll) => match number.parse().ok().and_then(|n| poll.answer_id(n)) {
+                Some(answer) => answer.to_string(),
+                None => {
+                    self.chat
+                        .add_notify(&format!("{} is not an answer of this poll", number));
+                    return;
+                }
+            },
+        };
+        if let Some(me) = self.chat.to_current_user() {
+            self.chat
+                .poll_vote(&room_id, &poll_id, &me, vec![answer.clone()]);
+        }
+        self.warn_unencrypted("the vote");
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::PollVote(room_id, poll_id, answer))
+            .await
+        {
+            self.set_error(e.into())
+        }
+    }
+
+    /// Find the joined room a room id or alias refers to.
+    async fn joined_room(&self, room: &RoomIdOrAliasId) -> Option<RoomId> {
+        let room = room.to_string();
+        for (id, joined) in self.chat.rooms() {
+            if id.as_str() == room
+                || joined
+                    .read()
+                    .await
+                    .room_name
+                    .canonical_alias
+                    .as_ref()
+                    .map_or(false, |alias| alias.as_str() == room)
+            {
+                return Some(id.clone());
+            }
+        }
+        None
+    }
+
+    /// Switch to `room_id` and scroll to `event_id`, backfilling the room until
+    /// the event is loaded.
+    async fn jump_to_event(&mut self, room_id: RoomId, event_id: EventId) {
+        if !self.chat.is_current_room(&room_id) {
+            self.chat.set_current_room_id(&room_id);
+            self.chat.room_changed();
+        }
+        if self.chat.scroll_to_event(event_id) {
+            return;
+        }
+        if self.chat.reached_start(&room_id) {
+            self.chat.cancel_jump();
+            self.chat.add_notify("the linked event is not in this room");
+            return;
+        }
+        self.scrolling = true;
+        if self.chat.take_trimmed(&room_id) {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::ResetBackfill(room_id.clone()))
+                .await
+            {
+                self.set_error(e.into())
+            }
+        }
+        if let Err(e) = self.send_jobs.send(UserRequest::RoomMsgs(room_id)).await {
+            self.chat.cancel_jump();
+            self.set_error(e.into())
+        }
+    }
+
+    /// Find a joined room with only us and `user` in it.
+    async fn direct_room_with(&self, user: &UserId) -> Option<RoomId> {
+        for (id, room) in self.chat.rooms() {
+            let room = room.read().await;
+            if room.joined_members.len() <= 2 && room.joined_members.contains_key(user) {
+                return Some(id.clone());
+            }
+        }
+        None
+    }
+
+    /// The mode shown in the status line.
+    fn mode(&self) -> &'static str {
+        if self.command.is_some() {
+            "command"
+        } else if self.chat.is_showing_source() {
+            "source"
+        } else if self.chat.is_showing_history() {
+            "history"
+        } else if self.chat.is_room_search() {
+            "search"
+        } else if self.chat.is_quick_select() {
+            "quick-select"
+        } else if self.chat.is_focused() {
+            "focus"
+        } else {
+            "normal"
+        }
+    }
+
+    /// Show the rooms of the account that just logged in and remember the account.
+    async fn on_logged_in(
+        &mut self,
+        rooms: Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>,
+        user_id: UserId,
+    ) {
+        self.login_w.logging_in = false;
+        self.login_w.logged_in = true;
+        self.chat.set_main_screen(true);
+        self.chat.set_current_user(&user_id);
+        self.chat.set_room_state(rooms).await;
+        self.fill_gap().await;
+        self.unread = self.chat.unread_counts().await;
+        self.restore_outbox(&user_id).await;
+        self.configs
+            .remember_account(user_id.as_str(), &self.homeserver);
+        if let Err(e) = self.configs.save().await {
+            tracing::warn!("failed to save configs {}", e);
+        }
+    }
+
+    /// The homeserver can not be reached, show the rooms cached by the saved session of
+    /// the homeserver's account so they can be read and messages queued.
+    async fn start_cached(&mut self) {
+        let user_id = match self.configs.account_of(&self.homeserver) {
+            Some(account) => account.user_id.clone(),
+            None => return,
+        };
+        if self.client_account.as_ref() != Some(&user_id) {
+            let homeserver = self.homeserver.clone();
+            self.start_client(&homeserver, Some(user_id.clone())).await;
+        }
+        self.login_w.login.username = user_id;
+        self.login_w.logging_in = true;
+        if let Err(e) = self.send_jobs.send(UserRequest::RestoreSession).await {
+            self.login_w.logging_in = false;
+            self.set_error(e.into());
+        }
+    }
+
+    /// True when the last sync finished so long ago the server must be out of reach.
+    fn sync_stalled(&self) -> bool {
+        self.login_w.logged_in
+            && self.last_sync.as_ref().map_or(false, |(_, at)| {
+                at.elapsed().map_or(false, |since| since > SYNC_STALLED)
+            })
+    }
+
+    fn go_offline(&mut self) {
+        if !self.offline {
+            self.offline = true;
+            self.chat
+                .add_notify("the homeserver can not be reached, messages are queued until it can");
+        }
+    }
+
+    /// The sync loop reached the server again, send everything that was queued in
+    /// the order it was written.
+    async fn go_online(&mut self) {
+        self.offline = false;
+        while let Some(uuid) = self.outbox.pop_front() {
+            self.send_unsent(uuid).await;
+        }
+    }
+
+    /// Send the unsent message `uuid`, while offline it is queued until the connection
+    /// is back. It waits for the earlier messages of its room to be answered. Returns
+    /// false when the message could not be handed to the client loop.
+    async fn send_unsent(&mut self, uuid: Uuid) -> bool {
+        let room_id = match self.unsent.iter().find(|(id, ..)| *id == uuid) {
+            Some((_, room_id, _)) => room_id.clone(),
+            None => return false,
+        };
+        self.chat.start_sending_message(uuid);
+        if self.offline && !self.sending.contains_key(&room_id) {
+            self.outbox.push_back(uuid);
+            self.chat.set_send_state(uuid, Some(SendState::Queued));
+            self.save_outbox().await;
+            return true;
+        }
+        let queue = self.sending.entry(room_id).or_default();
+        queue.push_back(uuid);
+        if queue.len() > 1 {
+            let state = if self.offline {
+                Some(SendState::Queued)
+            } else {
+                None
+            };
+            self.chat.set_send_state(uuid, state);
+            return true;
+        }
+        self.chat.set_send_state(uuid, None);
+        if self.dispatch_unsent(uuid).await {
+            true
+        } else {
+            self.send_next(uuid).await;
+            false
+        }
+    }
+
+    /// Hand the unsent message `uuid` to the client loop. Returns false when the client
+    /// loop is gone, the message is dropped.
+    async fn dispatch_unsent(&mut self, uuid: Uuid) -> bool {
+        let (room_id, msg) = match self.unsent.iter().find(|(id, ..)| *id == uuid) {
+            Some((_, room_id, msg)) => (room_id.clone(), msg.clone()),
+            None => return false,
+        };
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::SendMessage(room_id, msg, uuid))
+            .await
+        {
+            self.chat.finish_sending_message(&uuid);
+            self.unsent.retain(|(id, ..)| *id != uuid);
+            self.set_error(e.into());
+            return false;
+        }
+        true
+    }
+
+    /// The server answered `uuid`, hand the next message of its room to the client loop.
+    async fn send_next(&mut self, uuid: Uuid) {
+        let room_id = match self
+            .sending
+            .iter()
+            .find(|(_, queue)| queue.front() == Some(&uuid))
+        {
+            Some((room_id, _)) => room_id.clone(),
+            None => return,
+        };
+        loop {
+            let next = match self.sending.get_mut(&room_id) {
+                Some(queue) => {
+                    queue.pop_front();
+                    queue.front().copied()
+                }
+                None => return,
+            };
+            match next {
+                Some(next) if self.dispatch_unsent(next).await => return,
+                Some(_) => {}
+                None => {
+                    self.sending.remove(&room_id);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Stop sending the room `uuid` is the first message of, returns `uuid` and the
+    /// messages waiting behind it, oldest first.
+    fn take_sending(&mut self, uuid: Uuid) -> Vec<Uuid> {
+        let room_id = self
+            .sending
+            .iter()
+            .find(|(_, queue)| queue.front() == Some(&uuid))
+            .map(|(room_id, _)| room_id.clone());
+        match room_id.and_then(|room_id| self.sending.remove(&room_id)) {
+            Some(queue) => queue.into_iter().collect(),
+            None => vec![uuid],
+        }
+    }
+
+    /// Queue a message for `room_id`, send it and show it until the server echoes it
+    /// back. Returns false when it could not be handed to the client.
+    async fn send_content(&mut self, room_id: RoomId, content: MessageEventContent) -> bool {
+        let uuid = Uuid::new_v4();
+        self.unsent.push((uuid, room_id.clone(), content.clone()));
+        if self.send_unsent(uuid).await {
+            self.echo_sent(&room_id, uuid, content).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Kick `user` out of the room, the reason is shown to them and the room.
+    async fn kick(&mut self, room_id: RoomId, user: Option<&str>, reason: Option<String>) {
+        let user = match user {
+            Some(user) => user,
+            None => {
+                self.chat.add_notify("usage /kick <user id> [reason]");
+                return;
+            }
+        };
+        let user_id = match UserId::try_from(user) {
+            Ok(user_id) => user_id,
+            Err(_) => {
+                self.set_error(Error::Unknown(format!(
+                    "{} is not a user id, use the full @user:server form",
+                    user
+                )));
+                return;
+            }
+        };
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::Kick(room_id, user_id, reason))
+            .await
+        {
+            self.set_error(e.into());
+        } else {
+            self.chat.clear_send_msg();
+        }
+    }
+
+    /// With `unencrypted_fallback` set to `always` say that `what` goes out in the
+    /// clear, only messages are encrypted.
+    fn warn_unencrypted(&mut self, what: &str) {
+        if self.configs.unencrypted_fallback == UnencryptedFallback::Always
+            && self.chat.is_current_room_encrypted()
+        {
+            self.chat.add_notify(&format!(
+                "this room is encrypted, {} is sent unencrypted",
+                what
+            ));
+        }
+    }
+
+    /// Upload and send the image at `path`, its name is shown until the server echoes
+    /// the message back.
+    async fn send_image(&mut self, room_id: RoomId, path: PathBuf) {
+        if self.offline {
+            self.chat
+                .add_notify("images can not be sent while offline, try again when connected");
+            return;
+        }
+        let uuid = Uuid::new_v4();
+        self.chat.start_sending_message(uuid);
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::SendImage(room_id.clone(), path.clone(), uuid))
+            .await
+        {
+            self.chat.finish_sending_message(&uuid);
+            self.set_error(e.into());
+            return;
+        }
+        self.chat.clear_send_msg();
+        let placeholder = MessageEventContent::Image(ImageMessageEventContent {
+            body: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "image".to_string()),
+            info: None,
+            url: None,
+            file: None,
+        });
+        self.echo_sent(&room_id, uuid, placeholder).await;
+    }
+
+    /// Show the message `uuid` in `room_id` before the server has confirmed it.
+    async fn echo_sent(&mut self, room_id: &RoomId, uuid: Uuid, msg: MessageEventContent) {
+        let me = match self.chat.to_current_user() {
+            Some(me) => me,
+            None => return,
+        };
+        let name = match self.chat.rooms().get(room_id) {
+            Some(room) => {
+                let room = room.read().await;
+                room.joined_members
+                    .get(&me)
+                    .map(|member| member.name())
+                    .unwrap_or_else(|| me.localpart().into())
+            }
+            None => return,
+        };
+        self.chat.echo_sent_msg(room_id, name, uuid, msg);
+    }
+
+    /// Send the failed messages of the current room again.
+    async fn retry_failed(&mut self) {
+        let room_id = match self.chat.to_current_room_id() {
+            Some(room_id) => room_id,
+            None => return,
+        };
+        let failed = self
+            .unsent
+            .iter()
+            .filter(|(uuid, id, _)| *id == room_id && self.chat.is_send_failed(uuid))
+            .map(|(uuid, ..)| *uuid)
+            .collect::<Vec<_>>();
+        if failed.is_empty() {
+            self.chat.add_notify("no failed messages in this room");
+            return;
+        }
+        for uuid in failed {
+            self.send_unsent(uuid).await;
+        }
+    }
+
+    /// Write the messages that were not sent so they survive quitting.
+    async fn save_outbox(&self) {
+        let outbox = Outbox {
+            user_id: self.chat.to_current_user(),
+            messages: self
+                .unsent
+                .iter()
+                .map(|(uuid, room_id, content)| UnsentMessage {
+                    txn_id: uuid.to_string(),
+                    room_id: room_id.clone(),
+                    content: content.clone(),
+                    failed: self.chat.is_send_failed(uuid),
+                })
+                .collect(),
+        };
+        if let Err(e) = outbox.save().await {
+            tracing::warn!("failed to save the outbox {}", e);
+        }
+    }
+
+    /// Send the messages left over from the last time `user` used rumatui, the ones the
+    /// server refused stay failed.
+    async fn restore_outbox(&mut self, user: &UserId) {
+        let outbox = match Outbox::load().await {
+            Ok(outbox) if outbox.user_id.as_ref() == Some(user) => outbox,
+            _ => return,
+        };
+        for UnsentMessage {
+            txn_id,
+            room_id,
+            content,
+            failed,
+        } in outbox.messages
+        {
+            let uuid = Uuid::parse_str(&txn_id).unwrap_or_else(|_| Uuid::new_v4());
+            self.unsent.push((uuid, room_id.clone(), content.clone()));
+            self.echo_sent(&room_id, uuid, content).await;
+            if failed {
+                // `:retry` sends it again
+                self.chat.set_send_state(uuid, Some(SendState::Failed));
+            } else {
+                self.send_unsent(uuid).await;
+            }
+        }
+    }
+
+    /// A dot that changes with every sync and the new messages it brought in, shown
+    /// after the connection state.
+    fn sync_heartbeat(&self) -> String {
+        let (stats, _) = match self.last_sync.as_ref() {
+            Some(last) if self.login_w.logged_in => last,
+            _ => return String::new(),
+        };
+        if self.offline {
+            return match self.outbox.len() {
+                0 => " (offline)".to_string(),
+                queued => format!(" (offline, {} queued)", queued),
+            };
+        }
+        let beat = if self.sync_beat { '•' } else { '·' };
+        if stats.new_messages == 0 {
+            format!(" {}", beat)
+        } else {
+            format!(
+                " {} {} new in {} room(s)",
+                beat, stats.new_messages, stats.active_rooms
+            )
+        }
+    }
+
+    /// The title with the unread count of all rooms, mentions are counted separately.
+    fn title_text(&self) -> String {
+        if !self.login_w.logged_in {
+            return self.title.clone();
+        }
+        match self.unread {
+            (0, _) => self.title.clone(),
+            (unread, 0) => format!("{} ({} unread)", self.title, unread),
+            (unread, highlights) => format!(
+                "{} ({} unread, {} mentions)",
+                self.title, unread, highlights
+            ),
+        }
+    }
+
+    /// The terminal window title, the current room and the unread count of all rooms.
+    pub fn window_title(&self) -> String {
+        let room = match self.chat.current_room_name() {
+            Some(room) if self.login_w.logged_in => room,
+            _ => return self.title.clone(),
+        };
+        match self.unread.0 {
+            0 => format!("{}: {}", self.title, room),
+            unread => format!("{}: {} ({} unread)", self.title, room, unread),
+        }
+    }
+
+    /// The connection state shown in the status line.
+    fn connection_state(&self) -> &'static str {
+        if self.login_w.server_error.is_some() {
+            "disconnected"
+        } else if self.login_w.logged_in && self.sync_started {
+            "connected"
+        } else if self.login_w.logged_in || self.login_w.logging_in {
+            "connecting"
+        } else {
+            "logged out"
+        }
+    }
+
+    /// Quit unless messages are still waiting on a response from the server,
+    /// in that case the user is warned and must quit a second time.
+    pub fn on_quit_request(&mut self) {
+        // Esc backs out of command mode before quitting
+        if self.command.take().is_some() {
+            return;
+        }
+        // Esc stops loading previous messages or a room search
+        if self.scrolling || self.chat.is_room_searching() {
+            self.ev_loop.cancel_request();
+            self.scrolling = false;
+            self.chat.cancel_jump();
+            self.chat.set_room_searching(false);
+            return;
+        }
+        if self.chat.close_source() {
+            return;
+        }
+        if self.chat.is_showing_history() {
+            self.chat.toggle_notify_history();
+            return;
+        }
+        if self.chat.cancel_editing() {
+            return;
+        }
+        if self.chat.close_thread() {
+            return;
+        }
+        if self.pinned.take().is_some() {
+            return;
+        }
+        if self.account.take().is_some() {
+            return;
+        }
+        // Esc cancels registration
+        if self.register.uiaa.take().is_some() {
+            self.register.registering = false;
+            self.register.uiaa_notice = None;
+            return;
+        }
+        if self.quit_requested || !self.chat.is_sending_message() {
+            self.should_quit = true;
+        } else {
+            self.quit_requested = true;
+        }
+    }
+
     pub async fn on_quit(&mut self) {
+        self.configs.search_history = self.chat.room_search_history().to_vec();
+        let (sidebar_width, show_sidebar) = self.chat.sidebar();
+        self.configs.sidebar_width = sidebar_width;
+        self.configs.show_sidebar = show_sidebar;
+        if let Err(e) = self.configs.save().await {
+            tracing::warn!("failed to save configs {}", e);
+        }
+        if self.login_w.logged_in {
+            self.save_outbox().await;
+        }
+
         self.ev_loop.quit_sync();
         if self.send_jobs.send(UserRequest::Quit).await.is_err() {
             // TODO what should happen when a send fails
@@ -834,6 +3010,8 @@ impl AppWidget {
     pub async fn on_ctrl_d(&mut self) {
         if self.chat.is_room_search() {
             if let Some(room_id) = self.chat.selected_room_search() {
+                // the term found a room worth joining, keep it even if Enter was never pressed
+                self.chat.remember_room_search();
                 if let Err(err) = self
                     .send_jobs
                     .send(UserRequest::JoinRoom(room_id))
@@ -861,13 +3039,29 @@ impl AppWidget {
     /// When a request is made to get previous room events (by scrolling up)
     /// the underlying client does not process them so we must deal with them.
     ///
+    /// If the response holds no more events the room is marked as fully backfilled.
+    ///
     /// TODO: this only handles messages currently.
     async fn process_room_events(
         &mut self,
         events: get_message_events::Response,
         room: Arc<RwLock<Room>>,
     ) {
+        // with lazy loading the members that sent the events are only in the response state,
+        // add them to the room so display names resolve
+        for ev in &events.state {
+            if let Ok(ref e) = serde_json::from_str::<AnySyncStateEvent>(ev.json().get()) {
+                room.write().await.receive_state_event(e);
+            }
+        }
+
         for ev in events.chunk {
+            if let Some((root, reply)) = ThreadedEvent::parse(ev.json().get()) {
+                self.chat.add_thread_reply(root, reply);
+            }
+            if let Some((event_id, json)) = event_source(ev.json().get()) {
+                self.chat.add_source(event_id, json);
+            }
             if let Ok(ref e) = serde_json::from_str::<AnySyncRoomEvent>(ev.json().get()) {
                 // matrix-sdk does not mutate the room on past events
                 // rooms are only mutated for present events, so we must handle the past
@@ -893,46 +3087,30 @@ impl AppWidget {
                                 .unwrap_or(sender.localpart().to_string())
                         };
 
-                        match content {
-                            MessageEventContent::Text(TextMessageEventContent {
-                                body,
-                                formatted,
-                                ..
-                            }) => {
-                                let msg = if formatted
-                                    .as_ref()
-                                    .map(|f| f.body.to_string())
-                                    .unwrap_or(body.to_string())
-                                    != *body
-                                {
-                                    // This is extremely expensive
-                                    // TODO cache these results somehow
-                                    crate::widgets::utils::markdown_to_terminal(&body)
-                                        .unwrap_or(body.clone())
-                                } else {
-                                    body.clone()
-                                };
-                                let txn_id = unsigned
-                                    .transaction_id
-                                    .as_ref()
-                                    .cloned()
-                                    .unwrap_or_default();
-
-                                let msg = Message {
-                                    name,
-                                    user: sender.clone(),
-                                    text: msg,
-                                    event_id: event_id.clone(),
-                                    timestamp: *origin_server_ts,
-                                    uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
-                                    read: false,
-                                    reactions: vec![],
-                                    sent_receipt: false,
-                                };
-                                self.chat.add_message(msg, &room.read().await.room_id)
-                            }
-                            _ => {}
-                        }
+                        let msg = match utils::message_text(&content) {
+                            Some(text) => text,
+                            None => continue,
+                        };
+                        let txn_id = unsigned
+                            .transaction_id
+                            .as_ref()
+                            .cloned()
+                            .unwrap_or_default();
+
+                        let msg = Message {
+                            name,
+                            user: sender.clone(),
+                            text: msg,
+                            event_id: event_id.clone(),
+                            timestamp: *origin_server_ts,
+                            uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
+                            read: false,
+                            reactions: vec![],
+                            sent_receipt: false,
+                            emote: matches!(content, MessageEventContent::Emote(_)),
+                            membership: None,
+                        };
+                        self.chat.add_message(msg, &room.read().await.room_id)
                     }
                     _ => {}
                 }
@@ -940,15 +3118,17 @@ impl AppWidget {
         }
     }
 
-    async fn handle_membership(
-        &mut self,
-        membership: MembershipChange,
-        receiver: UserId,
-        sender: UserId,
-        room: Arc<RwLock<Room>>,
-        timeline_event: bool,
-        show_room_name: bool,
-    ) {
+    async fn handle_membership(&mut self, change: MemberChange, show_room_name: bool) {
+        let MemberChange {
+            sender,
+            receiver,
+            room,
+            membership,
+            profile,
+            prev_profile,
+            timeline,
+            email_invite,
+        } = change;
         let for_me = Some(&receiver) == self.chat.as_current_user();
         let room_id = room.read().await.room_id.clone();
         let room_name = if show_room_name {
@@ -957,9 +3137,21 @@ impl AppWidget {
             "the room".to_string()
         };
         match membership {
-            MembershipChange::ProfileChanged { .. } => self
-                .chat
-                .add_notify(&format!("{} updated their profile", receiver.localpart())),
+            MembershipChange::ProfileChanged { .. } => {
+                // the same change arrives once for every room shared with the user
+                if self.profiles.get(&receiver) == Some(&profile) {
+                    return;
+                }
+                let notice = crate::widgets::utils::profile_change_notice(
+                    &receiver,
+                    prev_profile.as_ref(),
+                    &profile,
+                );
+                self.profiles.insert(receiver, profile);
+                if let Some(notice) = notice {
+                    self.chat.add_notify(&notice)
+                }
+            }
             MembershipChange::Joined => {
                 if for_me {
                     self.chat.set_current_room_id(&room_id);
@@ -978,7 +3170,13 @@ impl AppWidget {
                 if for_me {
                     // if this is a RoomEvent from the joined rooms timeline it is not
                     // an actual invitation
-                    if !timeline_event {
+                    if timeline.is_none() {
+                        if email_invite {
+                            self.chat.add_notify(&format!(
+                                "you have a pending email invite to \"{}\"",
+                                room.read().await.display_name()
+                            ));
+                        }
                         self.chat.invited(sender, room).await;
                     }
                 } else {
@@ -1018,7 +3216,7 @@ impl AppWidget {
                     &room_id,
                     for_me,
                     format!("you left {}", room_name),
-                    format!("{} left {}", receiver.localpart(), room_name,),
+                    format!("{} left {}", receiver.localpart(), room_name),
                 );
             }
             MembershipChange::Banned => {
@@ -1058,8 +3256,44 @@ impl AppWidget {
                 );
             }
             MembershipChange::None => {}
-            MembershipChange::Error => panic!("membership error"),
-            _ => panic!("MembershipChange::NotImplemented is never valid BUG"),
+            // `Error` and `NotImplemented` come from member events the server sent,
+            // they must not be able to crash the app
+            change => {
+                tracing::warn!(
+                    "invalid membership change {:?} for {} in {} from {}",
+                    change,
+                    receiver,
+                    room_id,
+                    sender
+                );
+                self.chat
+                    .add_notify("could not interpret a membership change");
+            }
+        }
+    }
+
+    /// Is the message from someone else and either mentions the user or was
+    /// sent in a direct message room, one listed in the account's `m.direct`.
+    async fn should_ring_for(&self, msg: &Message, room_id: &RoomId) -> bool {
+        let me = match self.chat.as_current_user() {
+            Some(me) if me != &msg.user => me,
+            _ => return false,
+        };
+        if let Some(room) = self.chat.rooms().get(room_id) {
+            let room = room.read().await;
+            let direct = match self.direct_rooms.as_ref() {
+                Some(direct) => direct.contains(room_id),
+                // until `m.direct` is loaded a room with only us and one other user is
+                // treated as a direct message
+                None => room.joined_members.len() <= 2,
+            };
+            if direct {
+                return true;
+            }
+            let display_name = room.joined_members.get(me).map(|mem| mem.name());
+            crate::widgets::utils::mentions_user(&msg.text, me, display_name.as_deref())
+        } else {
+            crate::widgets::utils::mentions_user(&msg.text, me, None)
         }
     }
 
@@ -1080,16 +3314,57 @@ impl AppWidget {
 
 impl DrawWidget for AppWidget {
     fn draw<B: Backend + Send>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        self.dirty = false;
         terminal.draw(|mut f| {
+            let size = f.size();
+            // the layouts collapse into nothing below this, say so instead
+            if size.width < MIN_TERMINAL_SIZE.0 || size.height < MIN_TERMINAL_SIZE.1 {
+                let text = [Text::styled(
+                    format!(
+                        "terminal too small (need at least {}x{})",
+                        MIN_TERMINAL_SIZE.0, MIN_TERMINAL_SIZE.1
+                    ),
+                    Style::new().fg(Color::Red).modifier(Modifier::BOLD),
+                )];
+                let para = Paragraph::new(text.iter())
+                    .alignment(Alignment::Center)
+                    .wrap(true);
+                f.render_widget(para, size);
+                return;
+            }
+
             let chunks = Layout::default()
-                .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Length(3),
+                        Constraint::Min(0),
+                        Constraint::Length(1),
+                    ]
+                    .as_ref(),
+                )
                 .split(f.size());
 
-            let text = if self.scrolling {
+            let text = if self.quit_requested {
                 vec![Text::styled(
-                    "Loading previous messages",
-                    Style::new().fg(Color::Green),
+                    format!(
+                        "{} message(s) still sending, press Esc again to quit anyway",
+                        self.chat.pending_send_count()
+                    ),
+                    Style::new().fg(Color::Red).modifier(Modifier::BOLD),
                 )]
+            } else if self.scrolling {
+                self.backfill_waiting.tick(BACKFILL_SPINNER_WIDTH);
+                vec![
+                    Text::styled("Loading previous messages ", Style::new().fg(Color::Green)),
+                    Text::styled(
+                        format!(
+                            "{:<width$}",
+                            "*".repeat(self.backfill_waiting.count),
+                            width = BACKFILL_SPINNER_WIDTH as usize
+                        ),
+                        Style::new().fg(Color::Magenta),
+                    ),
+                ]
             } else if !self.login_w.logged_in {
                 vec![Text::styled(
                     "Login or hit the left or right arrow keys to register!",
@@ -1109,12 +3384,13 @@ impl DrawWidget for AppWidget {
             } else {
                 vec![Text::styled("", Style::new().fg(Color::Green))]
             };
+            let title = self.title_text();
             let para = Paragraph::new(text.iter())
                 .block(
                     Block::default()
                         .borders(Borders::ALL)
                         .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                        .title(&self.title)
+                        .title(&title)
                         .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
                 )
                 .alignment(Alignment::Center);
@@ -1127,6 +3403,12 @@ impl DrawWidget for AppWidget {
 
             if let Some(err) = self.error.as_ref() {
                 ErrorWidget::new(err).render(&mut f, chunks2[0])
+            } else if let Some(info) = self.debug.as_ref() {
+                DebugWidget::new(info, self.chat.loaded_counts()).render(&mut f, chunks2[0])
+            } else if let Some(pinned) = self.pinned.as_ref() {
+                PinnedWidget::new(pinned).render(&mut f, chunks2[0])
+            } else if let Some(account) = self.account.as_mut() {
+                account.render(&mut f, chunks2[0])
             } else if !self.login_w.logged_in {
                 if self.login_w.homeserver.is_none() {
                     let domain = url::Url::parse(&self.homeserver)
@@ -1134,7 +3416,8 @@ impl DrawWidget for AppWidget {
                         .and_then(|url| url.domain().map(|s| s.to_string()))
                         // this is probably an error at this point
                         .unwrap_or(String::from("matrix.org"));
-                    self.login_w.homeserver = Some(domain);
+                    self.login_w.homeserver = Some(domain.clone());
+                    self.register.homeserver = Some(domain);
                 }
                 match self.login_or_register {
                     LoginOrRegister::Login => self.login_w.render(&mut f, chunks2[0]),
@@ -1143,6 +3426,34 @@ impl DrawWidget for AppWidget {
             } else {
                 self.chat.render(&mut f, chunks2[0])
             }
+
+            let status = if let Some(cmd) = self.command.as_ref() {
+                vec![Text::raw(format!(":{}", cmd))]
+            } else {
+                vec![
+                    Text::styled(
+                        format!(" {} ", self.mode()),
+                        Style::new().fg(Color::Black).bg(Color::Green),
+                    ),
+                    // which account is in use, for people logged in on more than one server
+                    Text::styled(
+                        self.chat
+                            .as_current_user()
+                            .map(|me| format!(" {} |", me))
+                            .unwrap_or_default(),
+                        Style::new().fg(Color::Cyan),
+                    ),
+                    Text::raw(format!(
+                        " {} | {}{}",
+                        self.chat
+                            .current_room_name()
+                            .unwrap_or_else(|| String::from("no room")),
+                        self.connection_state(),
+                        self.sync_heartbeat(),
+                    )),
+                ]
+            };
+            f.render_widget(Paragraph::new(status.iter()), chunks[2]);
         })
     }
 }
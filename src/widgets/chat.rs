@@ -1,16 +1,17 @@
 use std::{
     cell::{Ref, RefCell},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ops::Deref,
     rc::Rc,
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use matrix_sdk::{
     api::r0::directory::get_public_rooms_filtered::{self, RoomNetwork},
     events::room::message::MessageEventContent,
     identifiers::{EventId, RoomId, UserId},
+    js_int::UInt,
     Room,
 };
 use rumatui_tui::{
@@ -23,9 +24,12 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
+    client::RoomTags,
+    config::{Configs, SIDEBAR_WIDTHS},
     error::Result,
     widgets::{
-        message::{Message, MessageWidget},
+        member_list::{ListedMember, MemberListWidget},
+        message::{Message, MessageWidget, Poll, SendState},
         room_search::RoomSearchWidget,
         rooms::{Invitation, Invite, RoomsWidget},
         RenderWidget,
@@ -40,13 +44,71 @@ pub struct ChatWidget {
     messages_widget: MessageWidget,
     room_search_widget: RoomSearchWidget,
     room_search: bool,
+    member_list_widget: MemberListWidget,
+    /// Show the members of the current room next to its messages.
+    member_list: bool,
     main_screen: bool,
-    sending_message: bool,
+    /// The local transaction ids of messages that have been sent but the server
+    /// has not yet responded to.
+    pending_sends: HashSet<Uuid>,
     joining_room: bool,
     leaving_room: bool,
+    /// The room list width in percent of the screen.
+    sidebar_width: u16,
+    /// When false the messages fill the whole screen.
+    show_sidebar: bool,
 }
 
 impl ChatWidget {
+    pub(crate) fn is_member_list(&self) -> bool {
+        self.member_list
+    }
+
+    pub(crate) async fn toggle_member_list(&mut self) {
+        self.member_list = !self.member_list;
+        if self.member_list {
+            self.refresh_member_list().await;
+        } else {
+            self.member_list_widget.clear_area();
+        }
+    }
+
+    /// List the joined members of the current room again.
+    pub(crate) async fn refresh_member_list(&mut self) {
+        let room_id = match self.to_current_room_id() {
+            Some(room_id) => room_id,
+            None => return,
+        };
+        let members = match self.rooms().get(&room_id) {
+            Some(room) => room
+                .read()
+                .await
+                .joined_members
+                .iter()
+                .map(|(user_id, member)| ListedMember {
+                    user_id: user_id.clone(),
+                    name: member.name(),
+                    presence: member.presence.clone(),
+                })
+                .collect(),
+            None => vec![],
+        };
+        self.member_list_widget.set_members(room_id, members);
+    }
+
+    /// The member list shows another room than the current one.
+    pub(crate) fn is_member_list_stale(&self) -> bool {
+        self.member_list && self.member_list_widget.room() != self.current_room.borrow().as_ref()
+    }
+
+    pub(crate) fn member_list_scroll_up(&mut self, x: u16, y: u16) -> bool {
+        self.member_list && self.member_list_widget.on_scroll_up(x, y)
+    }
+
+    pub(crate) fn member_list_scroll_down(&mut self, x: u16, y: u16) -> bool {
+        self.member_list && self.member_list_widget.on_scroll_down(x, y)
+    }
+
     pub(crate) fn is_room_search(&self) -> bool {
         self.room_search
     }
@@ -83,6 +145,50 @@ impl ChatWidget {
         self.room_search_widget.room_search_results(resp)
     }
 
+    pub(crate) fn room_search_network(&self) -> RoomNetwork {
+        self.room_search_widget.network()
+    }
+
+    pub(crate) fn room_search_server(&self) -> Option<String> {
+        self.room_search_widget.server().map(ToString::to_string)
+    }
+
+    pub(crate) fn set_room_search_server(&mut self, server: Option<String>) {
+        self.room_search_widget.set_server(server)
+    }
+
+    pub(crate) fn room_search_history(&self) -> &[String] {
+        self.room_search_widget.history()
+    }
+
+    pub(crate) fn remember_room_search(&mut self) {
+        self.room_search_widget.remember_search()
+    }
+
+    pub(crate) fn is_browsing_search_history(&self) -> bool {
+        self.room_search_widget.is_browsing_history()
+    }
+
+    pub(crate) fn search_history_previous(&mut self) {
+        self.room_search_widget.history_previous()
+    }
+
+    pub(crate) fn search_history_next(&mut self) {
+        self.room_search_widget.history_next()
+    }
+
+    pub(crate) fn debounced_room_search(&mut self, delay: Duration) -> Option<String> {
+        self.room_search_widget.debounced_search(delay)
+    }
+
+    pub(crate) fn is_room_searching(&self) -> bool {
+        self.room_search_widget.is_searching()
+    }
+
+    pub(crate) fn set_room_searching(&mut self, searching: bool) {
+        self.room_search_widget.set_searching(searching)
+    }
+
     pub(crate) fn room_search_scroll_up(&mut self, x: u16, y: u16) -> bool {
         self.room_search_widget.on_scroll_up(x, y)
     }
@@ -105,6 +211,37 @@ impl ChatWidget {
 }
 
 impl ChatWidget {
+    /// Update the widgets that depend on the user's settings.
+    pub(crate) fn apply_configs(&mut self, configs: &Configs) {
+        self.messages_widget.apply_configs(configs);
+        self.room_search_widget
+            .set_history(configs.search_history.clone());
+        self.room_search_widget.set_network(configs.search_network);
+        self.sidebar_width = configs.sidebar_width();
+        self.show_sidebar = configs.show_sidebar;
+    }
+
+    /// The room list width and whether it is shown, to save in the configs.
+    pub(crate) fn sidebar(&self) -> (u16, bool) {
+        (self.sidebar_width, self.show_sidebar)
+    }
+
+    /// Change the room list width by `step` percent, showing it if it was hidden.
+    pub(crate) fn resize_sidebar(&mut self, step: i16) {
+        let width = (self.sidebar_width as i16 + step).max(0) as u16;
+        self.sidebar_width = width.max(SIDEBAR_WIDTHS.0).min(SIDEBAR_WIDTHS.1);
+        self.show_sidebar = true;
+    }
+
+    pub(crate) fn toggle_sidebar(&mut self) {
+        self.show_sidebar = !self.show_sidebar;
+    }
+
+    /// True when the room list is hidden and the messages fill the screen.
+    pub(crate) fn is_focused(&self) -> bool {
+        !self.show_sidebar
+    }
+
     pub(crate) fn is_main_screen(&self) -> bool {
         self.main_screen
     }
@@ -118,7 +255,12 @@ impl ChatWidget {
     }
 
     pub(crate) fn is_sending_message(&self) -> bool {
-        self.sending_message
+        !self.pending_sends.is_empty()
+    }
+
+    /// The number of messages still waiting on a response from the server.
+    pub(crate) fn pending_send_count(&self) -> usize {
+        self.pending_sends.len()
     }
 
     pub(crate) fn set_main_screen(&mut self, value: bool) {
@@ -133,8 +275,12 @@ impl ChatWidget {
         self.leaving_room = value;
     }
 
-    pub(crate) fn set_sending_message(&mut self, value: bool) {
-        self.sending_message = value;
+    pub(crate) fn start_sending_message(&mut self, uuid: Uuid) {
+        self.pending_sends.insert(uuid);
+    }
+
+    pub(crate) fn finish_sending_message(&mut self, uuid: &Uuid) {
+        self.pending_sends.remove(uuid);
     }
 
     pub(crate) fn is_current_room(&self, room: &RoomId) -> bool {
@@ -195,13 +341,24 @@ impl ChatWidget {
     }
 
     pub(crate) fn as_invite(&self) -> Option<&Invitation> {
-        self.rooms_widget.invite.as_ref()
+        self.rooms_widget.invite()
     }
 
     pub(crate) fn rooms(&self) -> &HashMap<RoomId, Arc<RwLock<Room>>> {
         &self.rooms_widget.rooms
     }
 
+    /// The unread notifications and highlights of every joined room added up.
+    pub(crate) async fn unread_counts(&self) -> (u64, u64) {
+        let mut counts = (0, 0);
+        for room in self.rooms().values() {
+            let room = room.read().await;
+            counts.0 += room.unread_notifications.map_or(0, u64::from);
+            counts.1 += room.unread_highlight.map_or(0, u64::from);
+        }
+        counts
+    }
+
     pub(crate) fn update_room(&mut self, name: &str, room: &RoomId) {
         tracing::info!("update room state {}", &room);
         self.rooms_widget.update_room(name, room)
@@ -227,6 +384,10 @@ impl ChatWidget {
         self.rooms_widget.select_next()
     }
 
+    pub(crate) fn room_select_nth(&mut self, idx: usize) -> bool {
+        self.rooms_widget.select_nth(idx).is_some()
+    }
+
     pub(crate) fn remove_invite(&mut self) {
         self.rooms_widget.remove_invite()
     }
@@ -237,6 +398,18 @@ impl ChatWidget {
         self.rooms_widget.add_room(room).await
     }
 
+    pub(crate) fn room_tags(&self, room: &RoomId) -> &[String] {
+        self.rooms_widget.tags(room)
+    }
+
+    pub(crate) fn is_server_notice_room(&self, room: &RoomId) -> bool {
+        self.rooms_widget.is_server_notice(room)
+    }
+
+    pub(crate) fn set_room_tags(&mut self, tags: RoomTags) {
+        self.rooms_widget.set_tags(tags)
+    }
+
     pub(crate) fn remove_room(&mut self, room: &RoomId) {
         tracing::info!("removing room to room list");
         self.rooms_widget.remove_room(room)
@@ -247,6 +420,10 @@ impl ChatWidget {
         self.rooms_widget.invited(sender, room).await
     }
 
+    pub(crate) fn set_invite_topic(&mut self, room_id: &RoomId, topic: String) {
+        self.rooms_widget.set_invite_topic(room_id, topic)
+    }
+
     pub(crate) fn msgs_on_click(&mut self, btn: MouseButton, x: u16, y: u16) -> bool {
         self.messages_widget.on_click(btn, x, y)
     }
@@ -259,8 +436,145 @@ impl ChatWidget {
         self.messages_widget.on_scroll_down(x, y)
     }
 
-    pub(crate) fn reset_scroll(&mut self) {
-        self.messages_widget.reset_scroll()
+    pub(crate) fn room_changed(&mut self) {
+        self.messages_widget.room_changed()
+    }
+
+    pub(crate) fn scroll_to_event(&mut self, event: EventId) -> bool {
+        self.messages_widget.scroll_to_event(event)
+    }
+
+    pub(crate) fn pending_jump(&self) -> Option<&EventId> {
+        self.messages_widget.pending_jump()
+    }
+
+    pub(crate) fn cancel_jump(&mut self) {
+        self.messages_widget.cancel_jump()
+    }
+
+    /// Has backfilling reached the first event of the room.
+    pub(crate) fn reached_start(&self, room: &RoomId) -> bool {
+        self.messages_widget.reached_start(room)
+    }
+
+    pub(crate) fn has_notification(&self) -> bool {
+        self.messages_widget.has_notification()
+    }
+
+    pub(crate) fn loaded_counts(&self) -> Vec<(RoomId, usize)> {
+        self.messages_widget.loaded_counts()
+    }
+
+    pub(crate) fn take_trimmed(&mut self, room: &RoomId) -> bool {
+        self.messages_widget.take_trimmed(room)
+    }
+
+    pub(crate) fn set_reached_start(&mut self, room: &RoomId) {
+        self.messages_widget.set_reached_start(room)
+    }
+
+    pub(crate) fn select_previous_message(&mut self) {
+        self.messages_widget.select_previous_message()
+    }
+
+    pub(crate) fn select_next_message(&mut self) {
+        self.messages_widget.select_next_message()
+    }
+
+    pub(crate) fn selected_event(&self) -> Option<&EventId> {
+        self.messages_widget.selected_event()
+    }
+
+    pub(crate) fn has_reacted(&self, event_id: &EventId, key: &str) -> bool {
+        self.messages_widget.has_reacted(event_id, key)
+    }
+
+    pub(crate) fn add_thread_reply(&mut self, root: EventId, reply: EventId) {
+        self.messages_widget.add_thread_reply(root, reply)
+    }
+
+    pub(crate) fn open_selected_thread(&mut self) -> bool {
+        self.messages_widget.open_selected_thread()
+    }
+
+    pub(crate) fn set_member_count(&mut self, room: RoomId, count: usize) {
+        self.messages_widget.set_member_count(room, count)
+    }
+
+    pub(crate) fn set_encrypted(&mut self, room: RoomId) {
+        self.messages_widget.set_encrypted(room)
+    }
+
+    pub(crate) fn is_current_room_encrypted(&self) -> bool {
+        self.messages_widget.is_current_room_encrypted()
+    }
+
+    pub(crate) fn set_unverified_devices(&mut self, room: RoomId, count: usize) {
+        self.messages_widget.set_unverified_devices(room, count)
+    }
+
+    pub(crate) fn set_send_state(&mut self, uuid: Uuid, state: Option<SendState>) {
+        self.messages_widget.set_send_state(uuid, state)
+    }
+
+    pub(crate) fn is_send_failed(&self, uuid: &Uuid) -> bool {
+        self.messages_widget.is_send_failed(uuid)
+    }
+
+    pub(crate) fn open_thread(&self) -> Option<&EventId> {
+        self.messages_widget.open_thread()
+    }
+
+    pub(crate) fn close_thread(&mut self) -> bool {
+        self.messages_widget.close_thread()
+    }
+
+    pub(crate) fn edit_selected(&mut self) -> bool {
+        self.messages_widget.edit_selected()
+    }
+
+    pub(crate) fn editing(&self) -> Option<&EventId> {
+        self.messages_widget.editing()
+    }
+
+    pub(crate) fn cancel_editing(&mut self) -> bool {
+        self.messages_widget.cancel_editing()
+    }
+
+    pub(crate) fn selected_message(&self) -> Option<&Message> {
+        self.messages_widget.selected_message()
+    }
+
+    pub(crate) fn selected_text(&self) -> Option<&str> {
+        self.messages_widget.selected_text()
+    }
+
+    pub(crate) fn toggle_expand_selected(&mut self) {
+        self.messages_widget.toggle_expand_selected()
+    }
+
+    pub(crate) fn draft(&self) -> String {
+        self.messages_widget.draft()
+    }
+
+    pub(crate) fn set_draft(&mut self, draft: String) {
+        self.messages_widget.set_draft(draft)
+    }
+
+    pub(crate) fn is_input_empty(&self) -> bool {
+        self.messages_widget.is_input_empty()
+    }
+
+    /// The display name of the room currently being shown.
+    pub(crate) fn current_room_name(&self) -> Option<String> {
+        let current = self.as_current_room_id();
+        let current = current.as_ref()?;
+        self.rooms_widget
+            .names
+            .items
+            .iter()
+            .find(|(_, id)| id == current)
+            .map(|(name, _)| name.clone())
     }
 
     pub(crate) fn add_char(&mut self, ch: char) {
@@ -283,16 +597,57 @@ impl ChatWidget {
         self.messages_widget.add_notify(msg)
     }
 
+    pub(crate) fn add_source(&mut self, event_id: EventId, json: String) {
+        self.messages_widget.add_source(event_id, json)
+    }
+
+    pub(crate) fn show_selected_source(&mut self) -> bool {
+        self.messages_widget.show_selected_source()
+    }
+
+    pub(crate) fn is_showing_source(&self) -> bool {
+        self.messages_widget.is_showing_source()
+    }
+
+    pub(crate) fn close_source(&mut self) -> bool {
+        self.messages_widget.close_source()
+    }
+
+    pub(crate) fn source_scroll_up(&mut self) {
+        self.messages_widget.source_scroll_up()
+    }
+
+    pub(crate) fn source_scroll_down(&mut self) {
+        self.messages_widget.source_scroll_down()
+    }
+
+    pub(crate) fn is_showing_history(&self) -> bool {
+        self.messages_widget.is_showing_history()
+    }
+
+    pub(crate) fn toggle_notify_history(&mut self) {
+        self.messages_widget.toggle_notify_history()
+    }
+
+    pub(crate) fn history_scroll_up(&mut self) {
+        self.messages_widget.history_scroll_up()
+    }
+
+    pub(crate) fn history_scroll_down(&mut self) {
+        self.messages_widget.history_scroll_down()
+    }
+
     pub(crate) fn set_reaction_event(
         &mut self,
         room: &RoomId,
         relates_to: &EventId,
         event_id: &EventId,
+        sender: &UserId,
         reaction: &str,
     ) {
         tracing::info!("setting reaction");
         self.messages_widget
-            .set_reaction_event(room, relates_to, event_id, reaction)
+            .set_reaction_event(room, relates_to, event_id, sender, reaction)
     }
 
     pub(crate) fn add_message(&mut self, msg: Message, room: &RoomId) {
@@ -311,12 +666,43 @@ impl ChatWidget {
         self.messages_widget.echo_sent_msg(id, name, uuid, content)
     }
 
+    pub(crate) fn add_poll(&mut self, msg: Message, poll: Poll, room: &RoomId) {
+        self.messages_widget.add_poll(msg, poll, room)
+    }
+
+    pub(crate) fn poll(&self, event: &EventId) -> Option<&Poll> {
+        self.messages_widget.poll(event)
+    }
+
+    pub(crate) fn poll_vote(
+        &mut self,
+        room: &RoomId,
+        event: &EventId,
+        user: &UserId,
+        selections: Vec<String>,
+    ) -> bool {
+        self.messages_widget
+            .poll_vote(room, event, user, selections)
+    }
+
+    pub(crate) fn end_poll(
+        &mut self,
+        room: &RoomId,
+        event: &EventId,
+        sender: &UserId,
+        moderator: bool,
+    ) {
+        self.messages_widget
+            .end_poll(room, event, sender, moderator)
+    }
+
     pub(crate) fn edit_message(&mut self, room: &RoomId, event: &EventId, new_msg: String) {
         tracing::info!("message edit in {}", &room);
         self.messages_widget.edit_message(room, event, new_msg)
     }
 
-    pub(crate) fn redaction_event(&mut self, room: &RoomId, event: &EventId) {
+    /// Returns false if the redacted event is not a loaded message or reaction.
+    pub(crate) fn redaction_event(&mut self, room: &RoomId, event: &EventId) -> bool {
         tracing::info!("redaction event in {}", &room);
         self.messages_widget.redaction_event(room, event)
     }
@@ -345,10 +731,27 @@ impl ChatWidget {
         self.messages_widget.read_receipt(last_interaction, room)
     }
 
+    pub(crate) fn mark_read_up_to(&mut self, room: &RoomId, event: &EventId) -> bool {
+        self.messages_widget.mark_read_up_to(room, event)
+    }
+
     pub(crate) fn read_to_end(&mut self, room: &RoomId, event: &EventId) -> bool {
         self.messages_widget.read_to_end(room, event)
     }
 
+    /// Zero the unread count of a room read to the end, the count is only updated
+    /// by the next sync otherwise.
+    pub(crate) async fn clear_unread(&mut self, room_id: &RoomId) {
+        if let Some(room) = self.rooms().get(room_id) {
+            let mut room = room.write().await;
+            room.unread_notifications = Some(UInt::MIN);
+            room.unread_highlight = Some(UInt::MIN);
+        }
+        if self.is_current_room(room_id) {
+            self.messages_widget.clear_unread();
+        }
+    }
+
     pub(crate) fn last_3_msg_event_ids(&self, room: &RoomId) -> Vec<&EventId> {
         self.messages_widget.last_3_msg_event_ids(room)
     }
@@ -359,17 +762,37 @@ impl RenderWidget for ChatWidget {
     where
         B: Backend,
     {
-        let chunks = Layout::default()
-            .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
-            .direction(Direction::Horizontal)
-            .split(area);
-
-        self.rooms_widget.render(f, chunks[0]);
+        // a hidden room list comes back while quick selecting so the matches can be seen
+        let area = if self.show_sidebar || self.is_quick_select() {
+            let chunks = Layout::default()
+                .constraints(
+                    [
+                        Constraint::Percentage(self.sidebar_width),
+                        Constraint::Percentage(100 - self.sidebar_width),
+                    ]
+                    .as_ref(),
+                )
+                .direction(Direction::Horizontal)
+                .split(area);
+
+            self.rooms_widget.render(f, chunks[0]);
+            chunks[1]
+        } else {
+            self.rooms_widget.clear_areas();
+            area
+        };
 
         if self.is_room_search() {
-            self.room_search_widget.render(f, chunks[1]);
+            self.room_search_widget.render(f, area);
+        } else if self.member_list {
+            let chunks = Layout::default()
+                .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
+                .direction(Direction::Horizontal)
+                .split(area);
+            self.messages_widget.render(f, chunks[0]);
+            self.member_list_widget.render(f, chunks[1]);
         } else {
-            self.messages_widget.render(f, chunks[1]);
+            self.messages_widget.render(f, area);
         }
     }
 }
@@ -0,0 +1,75 @@
+use std::time::SystemTime;
+
+use matrix_sdk::identifiers::RoomId;
+use rumatui_tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+
+use crate::{client::DebugInfo, widgets::RenderWidget};
+
+/// Shows the sync state of the client, toggled with F12.
+#[derive(Debug)]
+pub struct DebugWidget<'d> {
+    info: &'d DebugInfo,
+    /// The number of messages loaded for each room.
+    loaded: Vec<(RoomId, usize)>,
+}
+
+impl<'d> DebugWidget<'d> {
+    pub fn new(info: &'d DebugInfo, loaded: Vec<(RoomId, usize)>) -> Self {
+        Self { info, loaded }
+    }
+}
+
+impl<'d> RenderWidget for DebugWidget<'d> {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let heading = Style::default().fg(Color::Yellow).modifier(Modifier::BOLD);
+        let last_sync = self
+            .info
+            .last_sync
+            .and_then(|time| SystemTime::now().duration_since(time).ok())
+            .map(|ago| format!("{}s ago", ago.as_secs()))
+            .unwrap_or_else(|| String::from("never"));
+
+        let mut text = vec![
+            Text::styled("sync token: ", heading),
+            Text::raw(format!(
+                "{}\n",
+                self.info.sync_token.as_deref().unwrap_or("none")
+            )),
+            Text::styled("last sync: ", heading),
+            Text::raw(format!("{}\n\n", last_sync)),
+            Text::styled("room / loaded messages / backfill token\n", heading),
+        ];
+        self.loaded.sort();
+        for (room, count) in &self.loaded {
+            text.push(Text::raw(format!(
+                "{} / {} / {}\n",
+                room,
+                count,
+                self.info
+                    .backfill_tokens
+                    .get(room)
+                    .map(|tkn| tkn.as_str())
+                    .unwrap_or("none")
+            )));
+        }
+
+        let p = Paragraph::new(text.iter())
+            .block(
+                Block::default()
+                    .title("Debug (F12 to close)")
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Magenta)),
+            )
+            .wrap(true);
+        f.render_widget(p, area)
+    }
+}
@@ -0,0 +1,120 @@
+//! The selection state shared by the list widgets.
+use std::ops::{Index, IndexMut};
+
+use matrix_sdk::identifiers::RoomId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ListState<I> {
+    pub items: Vec<I>,
+    pub selected: usize,
+}
+
+impl<I: std::fmt::Debug> Default for ListState<I> {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl<I: std::fmt::Debug> ListState<I> {
+    pub fn new(items: Vec<I>) -> ListState<I> {
+        ListState { items, selected: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.selected = 0;
+        self.items.clear();
+    }
+
+    /// Scrolls back up the list
+    pub fn select_previous(&mut self) {
+        if self.selected != 0 {
+            self.selected -= 1;
+        }
+    }
+
+    /// Scrolls down the list
+    pub fn select_next(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        if self.selected < self.len() - 1 {
+            self.selected += 1
+        }
+    }
+    /// Gets the index of the selected item.
+    pub fn selected_idx(&self) -> usize {
+        self.selected
+    }
+
+    pub fn get_selected(&self) -> Option<&I> {
+        self.items.get(self.selected)
+    }
+    pub fn get_selected_mut(&mut self) -> Option<&mut I> {
+        self.items.get_mut(self.selected)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &I> {
+        self.items.iter()
+    }
+}
+
+impl ListState<(String, RoomId)> {
+    pub(crate) fn add_unique(&mut self, name: String, room_id: RoomId) {
+        // TODO not great to clone for a Eq...
+        if !self.items.contains(&(name.clone(), room_id.clone())) {
+            self.items.push((name, room_id));
+        }
+    }
+}
+
+impl<I> Index<usize> for ListState<I> {
+    type Output = I;
+    fn index(&self, idx: usize) -> &Self::Output {
+        &self.items[idx]
+    }
+}
+impl<I> IndexMut<usize> for ListState<I> {
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        &mut self.items[idx]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn selection_stays_in_the_list() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut list = ListState::default();
+        list.select_next();
+        assert_eq!(0, list.selected_idx());
+
+        list.add_unique("room".to_string(), room.clone());
+        list.add_unique("room".to_string(), room);
+        assert_eq!(1, list.len());
+
+        list.select_next();
+        list.select_previous();
+        list.select_previous();
+        assert_eq!(
+            Some("room"),
+            list.get_selected().map(|(name, _)| name.as_str())
+        );
+
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(None, list.get_selected());
+    }
+}
@@ -7,7 +7,10 @@ use rumatui_tui::{
 };
 use termion::event::MouseButton;
 
-use crate::widgets::RenderWidget;
+use crate::{
+    client::Username,
+    widgets::{too_small, RenderWidget},
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Loading {
@@ -68,13 +71,32 @@ pub struct LoginWidget {
     pub logged_in: bool,
     pub waiting: Loading,
     pub homeserver: Option<String>,
+    /// Set when the homeserver could not be reached.
+    pub server_error: Option<String>,
+    /// Show the password as typed instead of masked.
+    show_password: bool,
 }
 
 impl LoginWidget {
     pub(crate) fn try_login(&self) -> bool {
-        LoginSelect::Password == self.login.selected
+        self.server_error.is_none()
+            && LoginSelect::Password == self.login.selected
             && !self.login.password.is_empty()
             && !self.login.username.is_empty()
+            && self.username_hint().is_none()
+    }
+
+    /// What is wrong with the user name, shown before the request is sent.
+    fn username_hint(&self) -> Option<&'static str> {
+        if self.login.username.is_empty() {
+            return None;
+        }
+        Username::parse(&self.login.username).err()
+    }
+
+    /// Reveal or mask the password.
+    pub(crate) fn toggle_show_password(&mut self) {
+        self.show_password = !self.show_password;
     }
 
     pub(crate) fn clear_login(&mut self) {
@@ -100,8 +122,14 @@ impl RenderWidget for LoginWidget {
     where
         B: Backend,
     {
+        if too_small(area) {
+            self.user_area = Rect::default();
+            self.password_area = Rect::default();
+            return;
+        }
         let chunks = Layout::default()
-            .horizontal_margin(40)
+            // narrow terminals keep half their width for the form
+            .horizontal_margin((area.width / 4).min(40))
             .constraints(
                 [
                     Constraint::Percentage(15),
@@ -133,6 +161,23 @@ impl RenderWidget for LoginWidget {
             )
             .split(chunks[1]);
 
+        if let Some(err) = self.server_error.as_ref() {
+            let t = [Text::styled(
+                format!("{}, press Enter to try again", err),
+                Style::default().fg(Color::Red).modifier(Modifier::BOLD),
+            )];
+            let p = Paragraph::new(t.iter())
+                .alignment(Alignment::Center)
+                .wrap(true);
+            f.render_widget(p, height_chunk[3]);
+        } else if let Some(hint) = self.username_hint() {
+            let t = [Text::styled(hint, Style::default().fg(Color::Yellow))];
+            let p = Paragraph::new(t.iter())
+                .alignment(Alignment::Center)
+                .wrap(true);
+            f.render_widget(p, height_chunk[3]);
+        }
+
         let width_chunk1 = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(
@@ -206,10 +251,12 @@ impl RenderWidget for LoginWidget {
             f.render_widget(p, width_chunk1[1]);
 
             // Password from here down
-            let t2 = [Text::styled(
-                "*".repeat(self.login.password.len()),
-                Style::default().fg(Color::Cyan),
-            )];
+            let password = if self.show_password {
+                self.login.password.clone()
+            } else {
+                "*".repeat(self.login.password.len())
+            };
+            let t2 = [Text::styled(password, Style::default().fg(Color::Cyan))];
             let p2 = Paragraph::new(t2.iter()).block(high_pass);
 
             f.render_widget(p2, width_chunk2[1])
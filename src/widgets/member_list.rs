@@ -0,0 +1,195 @@
+use matrix_sdk::{
+    events::presence::PresenceState,
+    identifiers::{RoomId, UserId},
+};
+use rumatui_tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, Text},
+    Frame,
+};
+
+use crate::widgets::{list_state::ListState, too_small, RenderWidget};
+
+/// A joined member of the room shown in the member list.
+#[derive(Clone, Debug)]
+pub struct ListedMember {
+    pub user_id: UserId,
+    /// The display name, or the localpart when the member has none.
+    pub name: String,
+    /// `None` until the homeserver sends the member's presence.
+    pub presence: Option<PresenceState>,
+}
+
+/// Lists the joined members of the current room next to its messages, toggled with Alt-m.
+#[derive(Clone, Debug, Default)]
+pub struct MemberListWidget {
+    /// The room the members were listed for.
+    room: Option<RoomId>,
+    members: ListState<ListedMember>,
+    area: Rect,
+}
+
+impl MemberListWidget {
+    pub(crate) fn room(&self) -> Option<&RoomId> {
+        self.room.as_ref()
+    }
+
+    /// List the members of `room` by name, the selection stays on the same member when
+    /// they are still in the room.
+    pub(crate) fn set_members(&mut self, room: RoomId, mut members: Vec<ListedMember>) {
+        members.sort_by(|a, b| {
+            a.name
+                .to_lowercase()
+                .cmp(&b.name.to_lowercase())
+                .then_with(|| a.user_id.cmp(&b.user_id))
+        });
+        let same_room = self.room.as_ref() == Some(&room);
+        let selected = self
+            .members
+            .get_selected()
+            .map(|member| member.user_id.clone())
+            .filter(|_| same_room);
+        self.room = Some(room);
+        self.members = ListState::new(members);
+        if let Some(idx) = selected.and_then(|user| {
+            self.members
+                .iter()
+                .position(|member| member.user_id == user)
+        }) {
+            self.members.selected = idx;
+        }
+    }
+
+    pub(crate) fn clear_area(&mut self) {
+        self.area = Rect::default();
+    }
+
+    pub fn on_scroll_up(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.members.select_previous();
+            return true;
+        }
+        false
+    }
+
+    pub fn on_scroll_down(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.members.select_next();
+            return true;
+        }
+        false
+    }
+}
+
+impl RenderWidget for MemberListWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        if too_small(area) {
+            self.clear_area();
+            return;
+        }
+        self.area = area;
+
+        // the borders take two rows
+        let list_height = area.height.saturating_sub(2) as usize;
+        let selected = self.members.selected_idx();
+        let offset = if selected >= list_height {
+            selected - list_height + 1
+        } else {
+            0
+        };
+
+        let items = self
+            .members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| {
+                let mut style = match member.presence {
+                    Some(PresenceState::Offline) => {
+                        Style::default().fg(Color::DarkGray).modifier(Modifier::DIM)
+                    }
+                    Some(PresenceState::Unavailable) => Style::default().fg(Color::Yellow),
+                    _ => Style::default().fg(Color::Blue),
+                };
+                let away = match member.presence {
+                    Some(PresenceState::Unavailable) => " (away)",
+                    Some(PresenceState::Offline) => " (offline)",
+                    _ => "",
+                };
+                if i == selected {
+                    style = style.fg(Color::LightGreen).modifier(Modifier::BOLD);
+                    Text::styled(format!(">> {}{}", member.name, away), style)
+                } else {
+                    Text::styled(format!(" {}{}", member.name, away), style)
+                }
+            })
+            .skip(offset);
+
+        let title = format!("Members ({})", self.members.len());
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(&title)
+                .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+        );
+        f.render_widget(list, area);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn member(user_id: &str, name: &str) -> ListedMember {
+        ListedMember {
+            user_id: UserId::try_from(user_id).unwrap(),
+            name: name.to_string(),
+            presence: None,
+        }
+    }
+
+    #[test]
+    fn members_are_listed_by_name() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MemberListWidget::default();
+        widget.set_members(
+            room.clone(),
+            vec![
+                member("@zed:localhost", "zed"),
+                member("@amy:localhost", "Amy"),
+                member("@bob:localhost", "bob"),
+            ],
+        );
+        assert_eq!(
+            vec!["Amy", "bob", "zed"],
+            widget
+                .members
+                .iter()
+                .map(|m| m.name.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        // the selection follows the member when someone joins before them
+        widget.members.select_next();
+        widget.set_members(
+            room,
+            vec![
+                member("@zed:localhost", "zed"),
+                member("@amy:localhost", "Amy"),
+                member("@bob:localhost", "bob"),
+                member("@al:localhost", "al"),
+            ],
+        );
+        assert_eq!(
+            Some("bob"),
+            widget.members.get_selected().map(|m| m.name.as_str())
+        );
+    }
+}
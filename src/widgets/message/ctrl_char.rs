@@ -1,10 +1,13 @@
 use std::fmt;
 
+use itertools::Itertools;
+use matrix_sdk::identifiers::UserId;
 use muncher::Muncher;
 use rumatui_tui::style::{Color, Modifier, Style};
 use rumatui_tui::widgets::Text;
 
 use super::Message;
+use crate::{config::Density, widgets::utils};
 
 #[derive(Clone, Debug, Default)]
 pub struct CtrlChunk {
@@ -320,39 +323,122 @@ impl CtrlChars {
     }
 }
 
-/// Parses CSI codes and converts them into `Vec<tui::widgets::Text>` chunks.
-pub fn process_text<'a>(message: &'a Message) -> Vec<Text<'a>> {
-    use itertools::Itertools;
+/// How a single `Message` should be displayed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplayOptions<'a> {
+    /// The current user, their reactions are highlighted.
+    pub me: Option<&'a UserId>,
+    /// The message is highlighted by the message cursor.
+    pub selected: bool,
+    /// Only show this many lines of the message body, `None` shows every line.
+    pub max_lines: Option<usize>,
+    pub density: Density,
+    /// Show the time the message was sent in this format before the sender.
+    pub time_format: Option<&'a str>,
+}
 
-    let name = format!("{}: ", message.name);
-    let mut msg = message.text.to_string();
+/// Emotes are shown in italics, the colors of a formatted body are kept.
+fn italic(text: Text<'_>) -> Text<'_> {
+    match text {
+        Text::Raw(text) => Text::Styled(text, Style::default().modifier(Modifier::ITALIC)),
+        Text::Styled(text, style) => {
+            Text::Styled(text, style.modifier(style.modifier | Modifier::ITALIC))
+        }
+    }
+}
+
+/// Parses CSI codes and converts them into `Vec<tui::widgets::Text>` chunks.
+pub fn process_text<'a>(message: &'a Message, opts: DisplayOptions<'_>) -> Vec<Text<'a>> {
+    let name = if message.emote {
+        format!("* {} ", message.name)
+    } else {
+        format!("{}: ", message.name)
+    };
+    let (mut msg, hidden) = collapse_lines(&message.text, opts.max_lines);
     if msg.contains("    ") {
         msg = msg.replace("    ", "\u{2800}   ");
     }
-    let msg = if msg.ends_with('\n') {
+    let msg = if opts.density == Density::Compact {
+        // markdown output ends with a blank line
+        format!("{}\n", msg.trim_end_matches('\n'))
+    } else if msg.ends_with('\n') {
         msg
     } else {
         format!("{}\n", msg)
     };
 
-    let body = CtrlChars::parse(msg).into_text();
+    let mut body = CtrlChars::parse(msg).into_text();
+    if message.emote {
+        body = body.into_iter().map(italic).collect();
+    }
 
-    let mut formatted = vec![Text::styled(name, Style::default().fg(Color::Magenta))];
+    let name_style = if opts.selected {
+        Style::default()
+            .fg(Color::Magenta)
+            .modifier(Modifier::REVERSED)
+    } else {
+        Style::default().fg(Color::Magenta)
+    };
+    let mut formatted = vec![];
+    if let Some(format) = opts.time_format {
+        formatted.push(Text::styled(
+            format!("{} ", utils::format_time(message.timestamp, format)),
+            Style::default().modifier(Modifier::DIM),
+        ));
+    }
+    formatted.push(Text::styled(name, name_style));
     formatted.extend(body);
+    if hidden > 0 {
+        formatted.push(Text::styled(
+            format!(
+                "\u{2800}   … ({} more lines, press Ctrl-x to expand)\n",
+                hidden
+            ),
+            Style::default().modifier(Modifier::DIM),
+        ));
+    }
     // add the reactions
     if !message.reactions.is_empty() {
-        let reactions = format!(
-            "\u{2800}   {}\n",
-            message.reactions.iter().dedup().join(" ")
-        );
-        formatted.push(Text::raw(reactions));
+        let indent = if opts.density == Density::Compact {
+            ""
+        } else {
+            "\u{2800}   "
+        };
+        formatted.push(Text::raw(indent));
+        for (idx, group) in message.reaction_groups(opts.me).into_iter().enumerate() {
+            let sep = if idx == 0 { "" } else { " " };
+            let style = if group.mine {
+                Style::default().fg(Color::Cyan).modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            formatted.push(Text::styled(
+                format!("{}{} {}", sep, group.key, group.count),
+                style,
+            ));
+        }
+        formatted.push(Text::raw("\n"));
     }
     formatted
 }
 
+/// Keep at most `max_lines` lines of `text`, returning the kept text and the number
+/// of lines that were removed.
+fn collapse_lines(text: &str, max_lines: Option<usize>) -> (String, usize) {
+    match max_lines {
+        Some(max) if max > 0 && text.lines().count() > max => {
+            let total = text.lines().count();
+            (text.lines().take(max).join("\n"), total - max)
+        }
+        _ => (text.to_string(), 0),
+    }
+}
+
 // TODO why do all but `failed_message` work locally and fail in travis CI?
 #[cfg(test)]
 mod test {
+    use std::convert::TryFrom;
+
     use super::*;
     use mdcat::{self, ResourceAccess, Settings, TerminalCapabilities, TerminalSize};
     use pulldown_cmark::{Options, Parser};
@@ -384,6 +470,45 @@ mod test {
         }
     }
 
+    #[test]
+    fn emotes_show_as_an_action() {
+        let message = Message {
+            name: "example".into(),
+            text: "waves hello".into(),
+            user: UserId::try_from("@example:localhost").unwrap(),
+            event_id: matrix_sdk::identifiers::EventId::try_from("$event:localhost").unwrap(),
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            emote: true,
+            membership: None,
+            timestamp: std::time::SystemTime::now(),
+            uuid: uuid::Uuid::new_v4(),
+        };
+        let text = process_text(&message, DisplayOptions::default());
+        let shown = text
+            .iter()
+            .map(|text| match text {
+                Text::Raw(text) | Text::Styled(text, _) => text.as_ref(),
+            })
+            .collect::<String>();
+        assert_eq!("* example waves hello\n", shown);
+        assert!(text[1..].iter().all(|text| match text {
+            Text::Styled(_, style) => style.modifier.contains(Modifier::ITALIC),
+            Text::Raw(_) => false,
+        }));
+    }
+
+    #[test]
+    fn collapse_long_message() {
+        let text = (0..10).map(|i| i.to_string()).join("\n");
+
+        assert_eq!(("0\n1\n2".to_string(), 7), collapse_lines(&text, Some(3)));
+        assert_eq!((text.clone(), 0), collapse_lines(&text, Some(10)));
+        assert_eq!((text.clone(), 0), collapse_lines(&text, Some(0)));
+        assert_eq!((text.clone(), 0), collapse_lines(&text, None));
+    }
+
     #[test]
     #[ignore] // the ignored tests work perfectly fine locally but fail in CI great x(
     fn test_formatter() {
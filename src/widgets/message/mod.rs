@@ -1,4 +1,4 @@
 pub mod ctrl_char;
 pub mod msgs;
 
-pub use msgs::{Message, MessageWidget};
+pub use msgs::{MemberNotice, Message, MessageWidget, Poll, SendState};
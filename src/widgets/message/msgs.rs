@@ -1,6 +1,6 @@
 use std::{
     cell::{Cell, RefCell},
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     convert::TryFrom,
     fmt,
     ops::Deref,
@@ -12,7 +12,8 @@ use std::{
 use matrix_sdk::{
     events::{
         room::message::{
-            FormattedBody, MessageEventContent, MessageFormat, RelatesTo, TextMessageEventContent,
+            EmoteMessageEventContent, FormattedBody, MessageEventContent, MessageFormat, RelatesTo,
+            TextMessageEventContent,
         },
         AnyPossiblyRedactedSyncMessageEvent, AnySyncMessageEvent, SyncMessageEvent,
     },
@@ -22,6 +23,7 @@ use matrix_sdk::{
 };
 use rumatui_tui::{
     backend::Backend,
+    buffer::str_width,
     layout::{Constraint, Direction, Layout, Rect, ScrollMode},
     style::{Color, Modifier, Style},
     widgets::{Block, Borders, Paragraph, Text},
@@ -32,16 +34,72 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
+    client::ruma_ext::{poll::plain_text, PollStartEventContent},
+    config::{Configs, Density},
     error::{Error, Result},
-    widgets::{message::ctrl_char, utils::markdown_to_html, RenderWidget},
+    widgets::{
+        message::ctrl_char::{self, DisplayOptions},
+        too_small,
+        utils::{self, markdown_to_html, SendCommand},
+        RenderWidget,
+    },
 };
 
+/// The event id given to messages shown before the server has responded with the real one.
+const ECHO_EVENT_ID: &str = "$fakeeventid:rumatui.client";
+
+/// How many past notifications are kept for the notification history.
+const NOTIFY_HISTORY_LEN: usize = 100;
+
+/// The most rows of text the send box grows to, longer drafts scroll.
+const MAX_SEND_ROWS: u16 = 8;
+
+/// The fewest rows left to the messages when the terminal has room for them.
+const MIN_MSG_ROWS: u16 = 3;
+
+/// The heights of the messages, notifications and send box for an area `height` rows
+/// tall when the draft takes `send_rows` rows, they never add up to more than `height`.
+fn split_heights(height: u16, send_rows: u16) -> (u16, u16, u16) {
+    let notify = if height < 25 { 0 } else { height * 15 / 100 };
+    let send = (send_rows.min(MAX_SEND_ROWS) + 2)
+        .min(height.saturating_sub(notify + MIN_MSG_ROWS))
+        // a bordered box with one row of text
+        .max(3.min(height));
+    let msgs = height.saturating_sub(notify + send);
+    (msgs, notify, send)
+}
+
+/// How many rows `text` takes wrapped to `width` columns, including the cursor
+/// after the last character.
+fn wrapped_rows(text: &str, width: u16) -> u16 {
+    let width = usize::from(width.max(1));
+    let last = text.split('\n').count() - 1;
+    text.split('\n')
+        .enumerate()
+        .map(|(idx, line)| {
+            let cols = str_width(line) + if idx == last { 1 } else { 0 };
+            // an empty line still takes a row
+            ((cols + width - 1) / width).max(1) as u16
+        })
+        .sum()
+}
+
 /// A reaction event containing the string (emoji) and the event id for the reaction
 /// event not the event it relates to.
 #[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Reaction {
     pub key: String,
     pub event_id: EventId,
+    pub sender: UserId,
+}
+
+/// All the reactions to a message with the same key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReactionGroup<'a> {
+    pub key: &'a str,
+    pub count: usize,
+    /// The current user is one of the senders.
+    pub mine: bool,
 }
 
 impl fmt::Display for Reaction {
@@ -50,6 +108,38 @@ impl fmt::Display for Reaction {
     }
 }
 
+/// A join or leave shown between the messages of a room.
+#[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq)]
+pub enum MemberNotice {
+    Joined,
+    Left,
+}
+
+/// The joins and leaves of a run of consecutive membership changes.
+#[derive(Clone, Debug, Default)]
+struct MembershipBurst {
+    joined: usize,
+    left: usize,
+}
+
+impl MembershipBurst {
+    fn add(&mut self, notice: MemberNotice) {
+        match notice {
+            MemberNotice::Joined => self.joined += 1,
+            MemberNotice::Left => self.left += 1,
+        }
+    }
+
+    fn summary(&self) -> String {
+        let users = |n: usize| if n == 1 { "user" } else { "users" };
+        match (self.joined, self.left) {
+            (joined, 0) => format!("{} {} joined", joined, users(joined)),
+            (0, left) => format!("{} {} left", left, users(left)),
+            (joined, left) => format!("{} {} joined, {} left", joined, users(joined), left),
+        }
+    }
+}
+
 /// A wrapper to abstract a `RoomEvent::RoomMessage` and the MessageEvent queue
 /// from `matrix_sdk::Room`.
 #[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
@@ -67,10 +157,123 @@ pub struct Message {
     pub reactions: Vec<Reaction>,
     /// Has the read_receipt been sent.
     pub sent_receipt: bool,
+    /// The message is an `m.emote`, shown as something the sender does.
+    pub emote: bool,
+    /// This is not a message but a join or leave of `user`.
+    pub membership: Option<MemberNotice>,
     pub timestamp: SystemTime,
     pub uuid: Uuid,
 }
 
+impl Message {
+    /// A join or leave of `user`, shown as a line between the messages.
+    pub(crate) fn membership(
+        user: UserId,
+        event_id: EventId,
+        timestamp: SystemTime,
+        notice: MemberNotice,
+    ) -> Self {
+        let action = match notice {
+            MemberNotice::Joined => "joined",
+            MemberNotice::Left => "left",
+        };
+        Self {
+            name: user.localpart().to_string(),
+            text: format!("{} {}", user.localpart(), action),
+            user,
+            event_id,
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            emote: false,
+            membership: Some(notice),
+            timestamp,
+            uuid: Uuid::new_v4(),
+        }
+    }
+
+    /// Group this message's reactions by key, in the order each key was first seen.
+    pub(crate) fn reaction_groups(&self, me: Option<&UserId>) -> Vec<ReactionGroup<'_>> {
+        let mut groups: Vec<ReactionGroup<'_>> = vec![];
+        for reaction in &self.reactions {
+            let mine = me == Some(&reaction.sender);
+            if let Some(group) = groups.iter_mut().find(|g| g.key == reaction.key) {
+                group.count += 1;
+                group.mine |= mine;
+            } else {
+                groups.push(ReactionGroup {
+                    key: &reaction.key,
+                    count: 1,
+                    mine,
+                });
+            }
+        }
+        groups
+    }
+
+    /// Is this message the local echo of a message the server has not yet confirmed.
+    pub(crate) fn is_local_echo(&self) -> bool {
+        self.event_id.to_string() == ECHO_EVENT_ID
+    }
+}
+
+/// A poll started in a room and the votes it has received.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Poll {
+    pub question: String,
+    /// The id and text of each answer.
+    pub answers: Vec<(String, String)>,
+    /// Undisclosed polls only show the results once the poll has ended.
+    pub disclosed: bool,
+    /// The answers each user selected, only the newest response of a user counts.
+    pub votes: HashMap<UserId, Vec<String>>,
+    /// An ended poll takes no more votes.
+    pub closed: bool,
+}
+
+impl Poll {
+    /// How many users selected the answer with the id `answer`.
+    pub(crate) fn tally(&self, answer: &str) -> usize {
+        self.votes
+            .values()
+            .filter(|selections| selections.iter().any(|s| s == answer))
+            .count()
+    }
+
+    /// The id of the `number`th answer, counting from one as the answers are shown.
+    pub(crate) fn answer_id(&self, number: usize) -> Option<&str> {
+        number
+            .checked_sub(1)
+            .and_then(|idx| self.answers.get(idx))
+            .map(|(id, _)| id.as_str())
+    }
+}
+
+/// A response or end that arrived before the poll it belongs to.
+#[derive(Clone, Debug)]
+enum EarlyPollEvent {
+    Vote(UserId, Vec<String>),
+    /// The sender and whether they may end any poll of the room.
+    End(UserId, bool),
+}
+
+impl From<&PollStartEventContent> for Poll {
+    fn from(content: &PollStartEventContent) -> Self {
+        Self {
+            question: content.question().to_string(),
+            answers: content
+                .poll
+                .answers
+                .iter()
+                .map(|answer| (answer.id.clone(), plain_text(&answer.text).to_string()))
+                .collect(),
+            disclosed: content.poll.kind != "m.undisclosed",
+            votes: HashMap::new(),
+            closed: false,
+        }
+    }
+}
+
 pub enum MsgType {
     PlainText,
     FormattedText,
@@ -84,6 +287,18 @@ pub enum MsgType {
     Video,
 }
 
+/// How far a room's messages are scrolled back, kept for each room so switching
+/// rooms and back returns to the same place.
+#[derive(Clone, Debug, Default)]
+struct RoomScroll {
+    /// Lines scrolled up from the newest message.
+    pos: usize,
+    /// Set by the paragraph when the messages are taller than the pane.
+    did_overflow: Rc<Cell<bool>>,
+    /// Set by the paragraph when the oldest message is in view.
+    at_top: Rc<Cell<bool>>,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct MessageWidget {
     msg_area: Rect,
@@ -96,17 +311,134 @@ pub struct MessageWidget {
     pub unread_notifications: UInt,
     send_msgs: HashMap<RoomId, String>,
     notifications: VecDeque<(Option<SystemTime>, String)>,
-    scroll_pos: usize,
-    did_overflow: Option<Rc<Cell<bool>>>,
-    at_top: Option<Rc<Cell<bool>>>,
+    /// The most recent notifications, newest last, kept after they stop being displayed.
+    notify_history: VecDeque<String>,
+    /// Show the notification history over the messages pane.
+    show_history: bool,
+    /// How many of the newest notifications are scrolled past in the history.
+    history_scroll: usize,
+    scroll: HashMap<RoomId, RoomScroll>,
+    /// The message highlighted by the message cursor in each room.
+    selected: HashMap<RoomId, EventId>,
+    /// Long messages the user has chosen to show in full.
+    expanded: HashSet<EventId>,
+    /// Messages longer than this many lines are collapsed, 0 never collapses.
+    collapse_lines: usize,
+    /// Rooms that have been backfilled all the way to their first event.
+    reached_start: HashSet<RoomId>,
+    /// The most messages kept for each room, 0 keeps everything.
+    message_history: usize,
+    /// Rooms that dropped old messages, the next backfill has to start over.
+    trimmed: HashSet<RoomId>,
+    /// How much space is left around messages.
+    density: Density,
+    /// How message times are shown, `None` hides them.
+    time_format: Option<String>,
+    /// Show the date between messages sent on different days.
+    date_separators: bool,
+    /// The event a permalink points to, kept until backfilling loads it.
+    jump_to: Option<EventId>,
+    /// The polls started in any room, keyed by the event that started them.
+    polls: HashMap<EventId, Poll>,
+    /// Votes and ends of polls whose start has not arrived yet, like while
+    /// backfilling, applied in order once it does.
+    early_poll_events: HashMap<EventId, Vec<EarlyPollEvent>>,
+    /// The replies of each thread, keyed by the event that started it.
+    threads: HashMap<EventId, Vec<EventId>>,
+    /// The thread each reply belongs to.
+    thread_of: HashMap<EventId, EventId>,
+    /// The thread shown in place of the room's messages.
+    open_thread: Option<EventId>,
+    /// The user's message the send box replaces the text of.
+    editing: Option<EventId>,
+    /// The rooms with encryption turned on.
+    encrypted: HashSet<RoomId>,
+    /// How many devices of each encrypted room's members are not verified.
+    unverified_devices: HashMap<RoomId, usize>,
+    /// How many members have joined each room, shown in the header.
+    member_counts: HashMap<RoomId, usize>,
+    /// The local echoes that are not on their way to the server.
+    send_states: HashMap<Uuid, SendState>,
+    /// The JSON of each event as the server sent it.
+    sources: HashMap<EventId, String>,
+    /// The body of the latest edit of each edited message, the source has the original.
+    edited_bodies: HashMap<EventId, String>,
+    /// The pretty printed source shown in place of the messages and how far it is scrolled.
+    source_view: Option<(String, u16)>,
+}
+
+/// Why a local echo is still waiting for the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SendState {
+    /// Sent when the homeserver can be reached again.
+    Queued,
+    /// The homeserver refused the message.
+    Failed,
+}
+
+/// Thread replies are only shown in their thread, the thread view shows only the
+/// thread root and its replies.
+fn in_view(
+    open_thread: Option<&EventId>,
+    thread_of: &HashMap<EventId, EventId>,
+    event_id: &EventId,
+) -> bool {
+    match open_thread {
+        Some(root) => root == event_id || thread_of.get(event_id) == Some(root),
+        None => !thread_of.contains_key(event_id),
+    }
+}
+
+/// The index of the first entry of the run of consecutive joins and leaves each message
+/// is part of, `None` for messages.
+fn membership_runs(messages: &[&Message]) -> Vec<Option<usize>> {
+    let mut runs: Vec<Option<usize>> = Vec::with_capacity(messages.len());
+    for (idx, msg) in messages.iter().enumerate() {
+        let start = match (msg.membership, runs.last()) {
+            (None, _) => None,
+            (Some(_), Some(Some(start))) => Some(*start),
+            (Some(_), _) => Some(idx),
+        };
+        runs.push(start);
+    }
+    runs
+}
+
+/// A run of joins and leaves is shown as one summary line until its first entry is
+/// expanded, only that entry can be selected while it is collapsed.
+fn selectable<'a>(messages: Vec<&'a Message>, expanded: &HashSet<EventId>) -> Vec<&'a Message> {
+    let runs = membership_runs(&messages);
+    messages
+        .iter()
+        .zip(runs)
+        .enumerate()
+        .filter(|(idx, (_, run))| match run {
+            Some(start) => start == idx || expanded.contains(&messages[*start].event_id),
+            None => true,
+        })
+        .map(|(_, (msg, _))| *msg)
+        .collect()
 }
 
 impl MessageWidget {
+    pub(crate) fn apply_configs(&mut self, configs: &Configs) {
+        self.collapse_lines = configs.collapse_lines;
+        self.message_history = configs.message_history;
+        self.density = configs.density;
+        self.time_format = configs.time_format().map(str::to_string);
+        self.date_separators = configs.date_separators;
+    }
+
     pub async fn populate_initial_msgs(&mut self, rooms: &HashMap<RoomId, Arc<RwLock<Room>>>) {
         for room in rooms.values() {
             let room = room.read().await;
 
             self.send_msgs.insert(room.room_id.clone(), String::new());
+            if room.is_encrypted() {
+                self.encrypted.insert(room.room_id.clone());
+            }
+            self.member_counts
+                .insert(room.room_id.clone(), room.joined_members.len());
             self.unread_notifications = room.unread_notifications.unwrap_or_default();
             self.unread_notifications += room.unread_highlight.unwrap_or_default();
 
@@ -123,11 +455,92 @@ impl MessageWidget {
     }
 
     pub async fn add_room(&mut self, room: Arc<RwLock<Room>>) {
-        self.send_msgs
-            .insert(room.read().await.room_id.clone(), String::new());
+        let room = room.read().await;
+        self.send_msgs.insert(room.room_id.clone(), String::new());
+        if room.is_encrypted() {
+            self.encrypted.insert(room.room_id.clone());
+        }
+        self.member_counts
+            .insert(room.room_id.clone(), room.joined_members.len());
+    }
+
+    pub(crate) fn set_member_count(&mut self, room: RoomId, count: usize) {
+        self.member_counts.insert(room, count);
+    }
+
+    /// The member count and a lock for encrypted rooms, shown in the header of the
+    /// current room's messages.
+    fn room_header(&self) -> String {
+        let current = self.current_room.borrow();
+        let room = match current.as_ref() {
+            Some(room) => room,
+            None => return String::new(),
+        };
+        let members = match self.member_counts.get(room) {
+            Some(1) => "1 member".to_string(),
+            Some(count) => format!("{} members", count),
+            None => String::new(),
+        };
+        if self.encrypted.contains(room) {
+            format!("{} \u{1f512}", members).trim_start().to_string()
+        } else {
+            members
+        }
+    }
+
+    pub(crate) fn set_encrypted(&mut self, room: RoomId) {
+        self.encrypted.insert(room);
+    }
+
+    /// True when the current room has encryption turned on.
+    pub(crate) fn is_current_room_encrypted(&self) -> bool {
+        self.current_room
+            .borrow()
+            .as_ref()
+            .map_or(false, |room| self.encrypted.contains(room))
+    }
+
+    pub(crate) fn set_unverified_devices(&mut self, room: RoomId, count: usize) {
+        self.unverified_devices.insert(room, count);
+    }
+
+    /// What the send box does, in encrypted rooms with how many devices of the members
+    /// are not verified.
+    fn send_title(&self) -> String {
+        let action = if self.editing.is_some() {
+            "Edit message (Esc to cancel)"
+        } else if self.open_thread.is_some() {
+            "Reply in thread"
+        } else {
+            "Send"
+        };
+        if !self.is_current_room_encrypted() {
+            return action.to_string();
+        }
+        let unverified = self
+            .current_room
+            .borrow()
+            .as_ref()
+            .and_then(|room| self.unverified_devices.get(room).copied());
+        match unverified {
+            None | Some(0) => format!("{} (encrypted)", action),
+            Some(1) => format!("{} (encrypted, 1 unverified device)", action),
+            Some(count) => format!("{} (encrypted, {} unverified devices)", action, count),
+        }
+    }
+
+    /// Set or clear why the local echo `uuid` has not been sent.
+    pub(crate) fn set_send_state(&mut self, uuid: Uuid, state: Option<SendState>) {
+        match state {
+            Some(state) => self.send_states.insert(uuid, state),
+            None => self.send_states.remove(&uuid),
+        };
+    }
+
+    pub(crate) fn is_send_failed(&self, uuid: &Uuid) -> bool {
+        self.send_states.get(uuid) == Some(&SendState::Failed)
     }
 
-    // TODO factor out with AppWidget::process_room_events and MessageWidget::echo_sent_msg
     fn add_message_event(&mut self, event: &SyncMessageEvent<MessageEventContent>, room: &Room) {
         let SyncMessageEvent {
             content,
@@ -142,71 +555,230 @@ impl MessageWidget {
         } else {
             sender.localpart().into()
         };
-        match content {
-            MessageEventContent::Text(TextMessageEventContent {
-                body, formatted, ..
-            }) => {
-                let msg = if formatted
-                    .as_ref()
-                    .map(|f| f.body.to_string())
-                    .unwrap_or(body.to_string())
-                    != *body
-                {
-                    crate::widgets::utils::markdown_to_terminal(body).unwrap_or(body.clone())
-                // None.unwrap_or(body.clone())
-                } else {
-                    body.clone()
-                };
-                let txn_id = unsigned
-                    .transaction_id
-                    .as_ref()
-                    .cloned()
-                    .unwrap_or_default();
-
-                self.add_message(
-                    Message {
-                        name,
-                        user: sender.clone(),
-                        text: msg,
-                        event_id: event_id.clone(),
-                        timestamp: *origin_server_ts,
-                        uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
-                        read: false,
-                        reactions: vec![],
-                        sent_receipt: false,
-                    },
-                    &room.room_id,
-                );
-            }
-            _ => {}
+        if let Some(text) = utils::message_text(content) {
+            let txn_id = unsigned
+                .transaction_id
+                .as_ref()
+                .cloned()
+                .unwrap_or_default();
+
+            self.add_message(
+                Message {
+                    name,
+                    user: sender.clone(),
+                    text,
+                    event_id: event_id.clone(),
+                    timestamp: *origin_server_ts,
+                    uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
+                    read: false,
+                    reactions: vec![],
+                    sent_receipt: false,
+                    emote: matches!(content, MessageEventContent::Emote(_)),
+                    membership: None,
+                },
+                &room.room_id,
+            );
         }
     }
 
     pub fn add_message(&mut self, msg: Message, room: &RoomId) {
         if let Some(messages) = self.messages.get_mut(room) {
+            // the same event can arrive from a sync and from a backfill
+            if !msg.is_local_echo() && messages.iter().any(|m| m.event_id == msg.event_id) {
+                return;
+            }
             // remove the message echo when user sends a message and we display the text before
             // the server responds
-            if let Some(idx) = messages.iter().position(|m| m.uuid == msg.uuid) {
+            if let Some(idx) = messages
+                .iter()
+                .position(|m| m.is_local_echo() && m.uuid == msg.uuid)
+            {
                 messages[idx] = msg;
                 return;
             }
         }
         self.messages.entry(room.clone()).or_default().push(msg);
+        self.trim_history(room);
         // TODO scroll seems to keep up but keep an eye on it
         // self.calculate_scroll_down();
     }
 
+    /// Drop the oldest messages of a room once it holds more than `message_history`.
+    ///
+    /// Nothing is dropped while the user is scrolled back reading old messages or while
+    /// backfilling towards a permalink.
+    fn trim_history(&mut self, room: &RoomId) {
+        let scrolled = self
+            .scroll
+            .get(room)
+            .map_or(false, |scroll| scroll.pos != 0);
+        if self.message_history == 0 || scrolled || self.jump_to.is_some() {
+            return;
+        }
+        if let Some(messages) = self.messages.get_mut(room) {
+            if messages.len() > self.message_history {
+                messages.sort_by(|msg, msg2| msg.timestamp.cmp(&msg2.timestamp));
+                let extra = messages.len() - self.message_history;
+                for msg in messages.drain(..extra) {
+                    self.sources.remove(&msg.event_id);
+                    self.edited_bodies.remove(&msg.event_id);
+                }
+                self.reached_start.remove(room);
+                self.trimmed.insert(room.clone());
+            }
+        }
+    }
+
+    /// Returns true while a notification is displayed or waiting to be.
+    pub(crate) fn has_notification(&self) -> bool {
+        !self.notifications.is_empty()
+    }
+
+    /// The number of messages loaded for each room.
+    pub(crate) fn loaded_counts(&self) -> Vec<(RoomId, usize)> {
+        self.messages
+            .iter()
+            .map(|(room, msgs)| (room.clone(), msgs.len()))
+            .collect()
+    }
+
+    /// Returns true once after old messages of `room` were dropped.
+    pub(crate) fn take_trimmed(&mut self, room: &RoomId) -> bool {
+        self.trimmed.remove(room)
+    }
+
     pub fn edit_message(&mut self, room: &RoomId, event_id: &EventId, msg: String) {
         if let Some(messages) = self.messages.get_mut(room) {
             if let Some(idx) = messages.iter().position(|m| &m.event_id == event_id) {
+                self.edited_bodies.insert(event_id.clone(), msg.clone());
                 messages[idx].text =
-                    crate::widgets::utils::markdown_to_terminal(&msg).unwrap_or(msg);
+                    utils::markdown_to_terminal(&msg, utils::message_pane_width()).unwrap_or(msg);
             }
         }
     }
 
+    /// Add the message that started a poll, its text shows the poll's answers and tallies.
+    pub(crate) fn add_poll(&mut self, mut msg: Message, poll: Poll, room: &RoomId) {
+        let event_id = msg.event_id.clone();
+        let early = self.early_poll_events.remove(&event_id);
+        msg.text = utils::poll_text(&poll, self.me.as_ref());
+        self.polls.insert(event_id.clone(), poll);
+        self.add_message(msg, room);
+
+        for event in early.into_iter().flatten() {
+            match event {
+                EarlyPollEvent::Vote(user, selections) => {
+                    self.poll_vote(room, &event_id, &user, selections);
+                }
+                EarlyPollEvent::End(sender, moderator) => {
+                    self.end_poll(room, &event_id, &sender, moderator)
+                }
+            }
+        }
+    }
+
+    pub(crate) fn poll(&self, event_id: &EventId) -> Option<&Poll> {
+        self.polls.get(event_id)
+    }
+
+    /// Record `user`'s vote replacing any earlier vote, returns false if the poll has
+    /// ended or is not loaded yet, the vote is then counted once the poll arrives.
+    pub(crate) fn poll_vote(
+        &mut self,
+        room: &RoomId,
+        event_id: &EventId,
+        user: &UserId,
+        selections: Vec<String>,
+    ) -> bool {
+        match self.polls.get_mut(event_id) {
+            Some(poll) if !poll.closed => {
+                poll.votes.insert(user.clone(), selections);
+            }
+            Some(_) => return false,
+            None => {
+                self.early_poll_events
+                    .entry(event_id.clone())
+                    .or_default()
+                    .push(EarlyPollEvent::Vote(user.clone(), selections));
+                return false;
+            }
+        }
+        self.refresh_poll(room, event_id);
+        true
+    }
+
+    /// End the poll, the user that started the poll can end it and so can a
+    /// `moderator`, anyone whose power level lets them redact the poll.
+    pub(crate) fn end_poll(
+        &mut self,
+        room: &RoomId,
+        event_id: &EventId,
+        sender: &UserId,
+        moderator: bool,
+    ) {
+        if !self.polls.contains_key(event_id) {
+            self.early_poll_events
+                .entry(event_id.clone())
+                .or_default()
+                .push(EarlyPollEvent::End(sender.clone(), moderator));
+            return;
+        }
+        let started_by = self
+            .messages
+            .get(room)
+            .and_then(|msgs| msgs.iter().find(|m| &m.event_id == event_id))
+            .map(|m| &m.user);
+        if !moderator && started_by != Some(sender) {
+            return;
+        }
+        if let Some(poll) = self.polls.get_mut(event_id) {
+            poll.closed = true;
+        }
+        self.refresh_poll(room, event_id);
+    }
+
+    fn refresh_poll(&mut self, room: &RoomId, event_id: &EventId) {
+        let text = match self.polls.get(event_id) {
+            Some(poll) => utils::poll_text(poll, self.me.as_ref()),
+            None => return,
+        };
+        if let Some(msg) = self
+            .messages
+            .get_mut(room)
+            .and_then(|msgs| msgs.iter_mut().find(|m| &m.event_id == event_id))
+        {
+            msg.text = text;
+        }
+    }
+
     pub fn add_notify(&mut self, notify: &str) {
         self.notifications.push_back((None, notify.to_string()));
+        if self.notify_history.len() == NOTIFY_HISTORY_LEN {
+            self.notify_history.pop_front();
+        }
+        self.notify_history.push_back(notify.to_string());
+    }
+
+    pub(crate) fn is_showing_history(&self) -> bool {
+        self.show_history
+    }
+
+    /// Open or close the notification history.
+    pub(crate) fn toggle_notify_history(&mut self) {
+        self.show_history = !self.show_history;
+        self.history_scroll = 0;
+    }
+
+    /// Scroll towards older notifications.
+    pub(crate) fn history_scroll_up(&mut self) {
+        if self.history_scroll + 1 < self.notify_history.len() {
+            self.history_scroll += 1;
+        }
+    }
+
+    /// Scroll towards newer notifications.
+    pub(crate) fn history_scroll_down(&mut self) {
+        self.history_scroll = self.history_scroll.saturating_sub(1);
     }
 
     pub fn set_reaction_event(
@@ -214,30 +786,47 @@ impl MessageWidget {
         room: &RoomId,
         relates_to: &EventId,
         event_id: &EventId,
+        sender: &UserId,
         reaction: &str,
     ) {
         if let Some(messages) = self.messages.get_mut(room) {
-            if let Some(idx) = messages.iter().position(|m| &m.event_id == relates_to) {
-                messages[idx].reactions.push(Reaction {
+            if let Some(msg) = messages.iter_mut().find(|m| &m.event_id == relates_to) {
+                // a reaction can arrive from both sync and a backfill
+                if msg.reactions.iter().any(|r| &r.event_id == event_id) {
+                    return;
+                }
+                msg.reactions.push(Reaction {
                     key: reaction.to_string(),
                     event_id: event_id.clone(),
+                    sender: sender.clone(),
                 });
             }
         }
     }
 
-    pub fn redaction_event(&mut self, room: &RoomId, event_id: &EventId) {
+    /// Scrub the redacted message or remove the redacted reaction, returns false if
+    /// `event_id` is neither.
+    pub fn redaction_event(&mut self, room: &RoomId, event_id: &EventId) -> bool {
+        let mut found = false;
         if let Some(messages) = self.messages.get_mut(room) {
             for message in messages {
                 if &message.event_id == event_id {
                     message.text = "**R**E**D**A**C**T**E**D**".to_string();
+                    // the reactions and the original content went with the message
+                    message.reactions.clear();
+                    self.sources.remove(event_id);
+                    self.edited_bodies.remove(event_id);
+                    found = true;
                 }
+                let reactions = message.reactions.len();
                 // TODO PR rust for better docs on `.retain()` method yee...
                 message
                     .reactions
                     .retain(|emoji| &emoji.event_id != event_id);
+                found |= reactions != message.reactions.len();
             }
         }
+        found
     }
 
     pub fn clear_send_msg(&mut self) {
@@ -252,7 +841,9 @@ impl MessageWidget {
     fn process_message(&self) -> Result<MsgType> {
         if let Some(room_id) = self.current_room.borrow().deref() {
             if let Some(msg) = self.send_msgs.get(room_id) {
-                if msg.contains('`') {
+                if let Some(SendCommand::Emote(_)) = utils::send_command(msg) {
+                    Ok(MsgType::Emote)
+                } else if msg.contains('`') {
                     Ok(MsgType::FormattedText)
                 } else {
                     Ok(MsgType::PlainText)
@@ -284,6 +875,25 @@ impl MessageWidget {
                             relates_to: None::<RelatesTo>,
                         }))
                     }
+                    MsgType::Emote => {
+                        let body = match utils::send_command(to_send) {
+                            Some(SendCommand::Emote(body)) => body,
+                            _ => to_send,
+                        };
+                        // backticks are sent as markdown like any other message
+                        let formatted = if body.contains('`') {
+                            Some(FormattedBody {
+                                format: MessageFormat::Html,
+                                body: markdown_to_html(body),
+                            })
+                        } else {
+                            None
+                        };
+                        Ok(MessageEventContent::Emote(EmoteMessageEventContent {
+                            body: body.to_string(),
+                            formatted,
+                        }))
+                    }
                     _ => todo!("implement more sending messages"),
                 }
             } else {
@@ -303,49 +913,43 @@ impl MessageWidget {
         uuid: Uuid,
         content: MessageEventContent,
     ) {
-        match content {
-            MessageEventContent::Text(TextMessageEventContent {
-                body, formatted, ..
-            }) => {
-                let msg = if formatted
-                    .as_ref()
-                    .map(|f| f.body.to_string())
-                    .unwrap_or(body.to_string())
-                    != body
-                {
-                    // This is extremely expensive
-                    // TODO cache these results somehow
-                    crate::widgets::utils::markdown_to_terminal(&body).unwrap_or(body.clone())
-                } else {
-                    body
-                };
-                let timestamp = SystemTime::now();
-
-                let msg = Message {
-                    text: msg,
-                    user: self.me.as_ref().unwrap().clone(),
-                    timestamp,
-                    name,
-                    event_id: EventId::try_from("$fakeeventid:rumatui.client").unwrap(),
-                    uuid,
-                    read: true,
-                    reactions: vec![],
-                    sent_receipt: true,
-                };
-                self.add_message(msg, id)
-            }
-            _ => {}
+        if let Some(text) = utils::message_text(&content) {
+            let msg = Message {
+                text,
+                user: self.me.as_ref().unwrap().clone(),
+                timestamp: SystemTime::now(),
+                name,
+                event_id: EventId::try_from(ECHO_EVENT_ID).unwrap(),
+                uuid,
+                read: true,
+                reactions: vec![],
+                sent_receipt: true,
+                emote: matches!(content, MessageEventContent::Emote(_)),
+                membership: None,
+            };
+            self.add_message(msg, id)
         }
     }
 
+    /// Is `event_id` the newest message of the room, the messages are only sorted
+    /// when rendered so the newest is looked for.
     pub(crate) fn read_to_end(&self, room: &RoomId, event_id: &EventId) -> bool {
         if let Some(messages) = self.messages.get(room) {
-            messages.last().map(|msg| &msg.event_id) == Some(event_id)
+            messages
+                .iter()
+                .max_by_key(|msg| msg.timestamp)
+                .map(|msg| &msg.event_id)
+                == Some(event_id)
         } else {
             false
         }
     }
 
+    /// The current room was read to the end, on this or another device.
+    pub(crate) fn clear_unread(&mut self) {
+        self.unread_notifications = UInt::MIN;
+    }
+
     pub(crate) fn last_3_msg_event_ids(&self, room: &RoomId) -> Vec<&EventId> {
         if let Some(messages) = self.messages.get(room) {
             messages[self.messages.len() - 4..]
@@ -357,6 +961,305 @@ impl MessageWidget {
         }
     }
 
+    pub(crate) fn reached_start(&self, room: &RoomId) -> bool {
+        self.reached_start.contains(room)
+    }
+
+    pub(crate) fn set_reached_start(&mut self, room: &RoomId) {
+        self.reached_start.insert(room.clone());
+    }
+
+    /// Move the message cursor to the message before the selected one, starting
+    /// from the most recent message.
+    pub(crate) fn select_previous_message(&mut self) {
+        let room = self.current_room.borrow().clone();
+        if let Some(messages) = room.as_ref().and_then(|id| self.messages.get(id)) {
+            let (open_thread, thread_of) = (self.open_thread.as_ref(), &self.thread_of);
+            let messages = messages
+                .iter()
+                .filter(|m| in_view(open_thread, thread_of, &m.event_id))
+                .collect::<Vec<_>>();
+            let messages = selectable(messages, &self.expanded);
+            let idx = match self.selected_event() {
+                Some(selected) => messages
+                    .iter()
+                    .position(|m| &m.event_id == selected)
+                    .map(|idx| idx.saturating_sub(1)),
+                None => messages.len().checked_sub(1),
+            };
+            let selected = idx.and_then(|idx| messages.get(idx).map(|m| m.event_id.clone()));
+            self.set_selected(selected);
+        }
+    }
+
+    /// Move the message cursor to the next message, moving past the most recent
+    /// message clears the selection.
+    pub(crate) fn select_next_message(&mut self) {
+        let room = self.current_room.borrow().clone();
+        if let Some(messages) = room.as_ref().and_then(|id| self.messages.get(id)) {
+            let (open_thread, thread_of) = (self.open_thread.as_ref(), &self.thread_of);
+            let messages = messages
+                .iter()
+                .filter(|m| in_view(open_thread, thread_of, &m.event_id))
+                .collect::<Vec<_>>();
+            let messages = selectable(messages, &self.expanded);
+            let selected = self
+                .selected_event()
+                .and_then(|selected| messages.iter().position(|m| &m.event_id == selected))
+                .and_then(|idx| messages.get(idx + 1))
+                .map(|m| m.event_id.clone());
+            self.set_selected(selected);
+        }
+    }
+
+    /// Remember `reply` was sent in the thread started by `root`.
+    pub(crate) fn add_thread_reply(&mut self, root: EventId, reply: EventId) {
+        if self.thread_of.insert(reply.clone(), root.clone()).is_none() {
+            self.threads.entry(root).or_default().push(reply);
+        }
+    }
+
+    /// How many replies the thread started by `root` has.
+    pub(crate) fn thread_replies(&self, root: &EventId) -> usize {
+        self.threads.get(root).map_or(0, Vec::len)
+    }
+
+    /// Show the thread the selected message started or was sent in, returns false
+    /// when it is not part of a thread.
+    pub(crate) fn open_selected_thread(&mut self) -> bool {
+        let selected = match self.selected_event() {
+            Some(selected) => selected,
+            None => return false,
+        };
+        let root = match self.thread_of.get(selected) {
+            Some(root) => root.clone(),
+            None if self.threads.contains_key(selected) => selected.clone(),
+            None => return false,
+        };
+        // keep the selection so replying or reacting still targets the same message
+        self.open_thread = Some(root);
+        self.scroll_to_bottom();
+        true
+    }
+
+    /// Go back to the room's messages, returns false when no thread was open.
+    pub(crate) fn close_thread(&mut self) -> bool {
+        let open = self.open_thread.take().is_some();
+        if open {
+            self.scroll_to_bottom();
+        }
+        open
+    }
+
+    pub(crate) fn open_thread(&self) -> Option<&EventId> {
+        self.open_thread.as_ref()
+    }
+
+    /// Keep the JSON the server sent for an event so it can be shown with Ctrl-v.
+    pub(crate) fn add_source(&mut self, event_id: EventId, json: String) {
+        self.sources.insert(event_id, json);
+    }
+
+    /// Show the source of the selected message, returns false when there is no selected
+    /// message or its source was not kept, like messages loaded from the store.
+    pub(crate) fn show_selected_source(&mut self) -> bool {
+        let source = match self.selected_event().and_then(|id| self.sources.get(id)) {
+            Some(source) => source,
+            None => return false,
+        };
+        let pretty = serde_json::from_str::<serde_json::Value>(source)
+            .and_then(|json| serde_json::to_string_pretty(&json))
+            .unwrap_or_else(|_| source.clone());
+        self.source_view = Some((pretty, 0));
+        true
+    }
+
+    pub(crate) fn is_showing_source(&self) -> bool {
+        self.source_view.is_some()
+    }
+
+    /// Go back to the messages, returns false when no source was shown.
+    pub(crate) fn close_source(&mut self) -> bool {
+        self.source_view.take().is_some()
+    }
+
+    pub(crate) fn source_scroll_up(&mut self) {
+        if let Some((_, scroll)) = self.source_view.as_mut() {
+            *scroll = scroll.saturating_sub(1);
+        }
+    }
+
+    pub(crate) fn source_scroll_down(&mut self) {
+        if let Some((source, scroll)) = self.source_view.as_mut() {
+            if (*scroll as usize + 1) < source.lines().count() {
+                *scroll += 1;
+            }
+        }
+    }
+
+    /// The event id of the message under the message cursor.
+    pub(crate) fn selected_event(&self) -> Option<&EventId> {
+        let room = self.current_room.borrow();
+        self.selected.get(room.as_ref()?)
+    }
+
+    /// Load the text of the selected message into the send box to edit it, returns false
+    /// when the selected message is not the user's or has not been sent yet.
+    pub(crate) fn edit_selected(&mut self) -> bool {
+        let msg = match self.selected_message() {
+            Some(msg) if Some(&msg.user) == self.me.as_ref() && !msg.is_local_echo() => msg,
+            _ => return false,
+        };
+        // the shown text of a formatted message is rendered markdown, edit the body sent
+        // by the latest edit or the original message
+        let body = self
+            .edited_bodies
+            .get(&msg.event_id)
+            .cloned()
+            .or_else(|| {
+                serde_json::from_str::<serde_json::Value>(self.sources.get(&msg.event_id)?)
+                    .ok()?
+                    .pointer("/content/body")?
+                    .as_str()
+                    .map(String::from)
+            })
+            .unwrap_or_else(|| msg.text.clone());
+        let event_id = msg.event_id.clone();
+        self.editing = Some(event_id);
+        self.set_draft(body);
+        true
+    }
+
+    /// The message being edited.
+    pub(crate) fn editing(&self) -> Option<&EventId> {
+        self.editing.as_ref()
+    }
+
+    /// Stop editing and clear the send box, returns false when nothing was edited.
+    pub(crate) fn cancel_editing(&mut self) -> bool {
+        let editing = self.editing.take().is_some();
+        if editing {
+            self.clear_send_msg();
+        }
+        editing
+    }
+
+    /// Has the user already reacted to the current room's message `event_id` with `key`.
+    pub(crate) fn has_reacted(&self, event_id: &EventId, key: &str) -> bool {
+        let room = self.current_room.borrow();
+        room.as_ref()
+            .and_then(|room| self.messages.get(room))
+            .and_then(|messages| messages.iter().find(|m| &m.event_id == event_id))
+            .map_or(false, |msg| {
+                msg.reactions
+                    .iter()
+                    .any(|r| r.key == key && Some(&r.sender) == self.me.as_ref())
+            })
+    }
+
+    /// Move the current room's message cursor, `None` clears it.
+    fn set_selected(&mut self, event_id: Option<EventId>) {
+        if let Some(room) = self.current_room.borrow().clone() {
+            match event_id {
+                Some(event_id) => {
+                    self.selected.insert(room, event_id);
+                }
+                None => {
+                    self.selected.remove(&room);
+                }
+            }
+        }
+    }
+
+    /// The message under the message cursor.
+    pub(crate) fn selected_message(&self) -> Option<&Message> {
+        let selected = self.selected_event()?;
+        let room = self.current_room.borrow();
+        self.messages
+            .get(room.as_ref()?)?
+            .iter()
+            .find(|m| &m.event_id == selected)
+    }
+
+    /// The text of the message under the message cursor.
+    pub(crate) fn selected_text(&self) -> Option<&str> {
+        self.selected_message().map(|m| m.text.as_str())
+    }
+
+    /// Show the selected message in full or collapse it again, for a run of joins and
+    /// leaves this lists every change of the run.
+    pub(crate) fn toggle_expand_selected(&mut self) {
+        if let Some(selected) = self.selected_event().cloned() {
+            // a run is expanded and collapsed by its first entry, which stays selected
+            let selected = self.membership_run_start(&selected).unwrap_or(selected);
+            if !self.expanded.remove(&selected) {
+                self.expanded.insert(selected.clone());
+            }
+            self.set_selected(Some(selected));
+        }
+    }
+
+    /// The first entry of the run of joins and leaves `event_id` is part of.
+    fn membership_run_start(&self, event_id: &EventId) -> Option<EventId> {
+        let room = self.current_room.borrow();
+        let (open_thread, thread_of) = (self.open_thread.as_ref(), &self.thread_of);
+        let messages = self
+            .messages
+            .get(room.as_ref()?)?
+            .iter()
+            .filter(|m| in_view(open_thread, thread_of, &m.event_id))
+            .collect::<Vec<_>>();
+        let idx = messages.iter().position(|m| &m.event_id == event_id)?;
+        let start = membership_runs(&messages)[idx]?;
+        Some(messages[start].event_id.clone())
+    }
+
+    /// Select `event_id` in the current room and scroll it into view.
+    ///
+    /// When the event is not loaded yet it is remembered and `false` is returned,
+    /// the caller backfills and tries again.
+    pub(crate) fn scroll_to_event(&mut self, event_id: EventId) -> bool {
+        let room = self.current_room.borrow().clone();
+        let messages = match room.as_ref().and_then(|id| self.messages.get_mut(id)) {
+            Some(messages) => messages,
+            None => {
+                self.jump_to = Some(event_id);
+                return false;
+            }
+        };
+        messages.sort_by(|msg, msg2| msg.timestamp.cmp(&msg2.timestamp));
+        let idx = match messages.iter().position(|m| m.event_id == event_id) {
+            Some(idx) => idx,
+            None => {
+                self.jump_to = Some(event_id);
+                return false;
+            }
+        };
+        // the scroll position counts lines up from the newest message, bring
+        // the event to the bottom of the pane
+        let below: usize = messages[idx + 1..]
+            .iter()
+            .map(|m| m.text.lines().count().max(1) + usize::from(!m.reactions.is_empty()))
+            .sum();
+        if let Some(scroll) = self.current_scroll() {
+            if scroll.did_overflow.get() {
+                scroll.pos = below;
+            }
+        }
+        self.set_selected(Some(event_id));
+        self.jump_to = None;
+        true
+    }
+
+    /// The event a permalink is waiting on backfilling for.
+    pub(crate) fn pending_jump(&self) -> Option<&EventId> {
+        self.jump_to.as_ref()
+    }
+
+    pub(crate) fn cancel_jump(&mut self) {
+        self.jump_to = None;
+    }
+
     pub fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) -> bool {
         if self.send_area.intersects(Rect::new(x, y, 1, 1)) {
             if let MouseButton::Left = btn {
@@ -366,14 +1269,47 @@ impl MessageWidget {
         false
     }
 
-    pub fn reset_scroll(&mut self) {
-        self.scroll_pos = 0;
-        if let Some(over) = self.did_overflow.as_ref() {
-            over.set(false);
+    /// The scroll state of the current room.
+    fn current_scroll(&mut self) -> Option<&mut RoomScroll> {
+        let room = self.current_room.borrow().clone()?;
+        Some(self.scroll.entry(room).or_default())
+    }
+
+    fn scroll_to_bottom(&mut self) {
+        if let Some(scroll) = self.current_scroll() {
+            scroll.pos = 0;
+            scroll.at_top.set(false);
         }
-        if let Some(top) = self.at_top.as_ref() {
-            top.set(false);
+    }
+
+    /// Called after switching rooms, the room keeps the scroll position and message
+    /// cursor it was left with.
+    pub fn room_changed(&mut self) {
+        // the open thread and edit belong to the room we switched away from
+        self.open_thread = None;
+        self.editing = None;
+        self.jump_to = None;
+    }
+
+    /// The user sent a read receipt for `event_id` from another session, everything up
+    /// to it is read and needs no receipt from here. Returns false if the event is not loaded.
+    pub(crate) fn mark_read_up_to(&mut self, room_id: &RoomId, event_id: &EventId) -> bool {
+        let messages = match self.messages.get_mut(room_id) {
+            Some(messages) => messages,
+            None => return false,
+        };
+        let read_until = match messages.iter().find(|msg| &msg.event_id == event_id) {
+            Some(msg) => msg.timestamp,
+            None => return false,
+        };
+        for msg in messages
+            .iter_mut()
+            .filter(|msg| msg.timestamp <= read_until)
+        {
+            msg.read = true;
+            msg.sent_receipt = true;
         }
+        true
     }
 
     // TODO remove this or `check_unread` eventually
@@ -434,34 +1370,24 @@ impl MessageWidget {
     pub fn on_scroll_up(&mut self, x: u16, y: u16) -> bool {
         let intersects = self.msg_area.intersects(Rect::new(x, y, 1, 1));
         if intersects {
-            if let Some(overflow) = self.did_overflow.as_ref() {
-                if overflow.get() {
-                    if let Some(at_top) = self.at_top.as_ref() {
-                        if at_top.get() {
-                            at_top.set(false);
-                            return true;
-                        } else {
-                            self.scroll_pos += 1;
-                            return false;
-                        }
-                    } else {
-                        self.scroll_pos += 1;
-                        return false;
-                    }
-                } else {
+            if let Some(scroll) = self.current_scroll() {
+                if !scroll.did_overflow.get() {
                     return true;
                 }
-            } else {
-                unreachable!("did_overflow was not set")
+                if scroll.at_top.get() {
+                    scroll.at_top.set(false);
+                    return true;
+                }
+                scroll.pos += 1;
             }
         }
         false
     }
 
     fn calculate_scroll_down(&mut self) {
-        if let Some(overflow) = self.did_overflow.as_ref() {
-            if overflow.get() && self.scroll_pos != 0 {
-                self.scroll_pos -= 1;
+        if let Some(scroll) = self.current_scroll() {
+            if scroll.did_overflow.get() && scroll.pos != 0 {
+                scroll.pos -= 1;
             }
         }
     }
@@ -472,6 +1398,34 @@ impl MessageWidget {
         }
     }
 
+    /// The unsent message of the current room.
+    pub(crate) fn draft(&self) -> String {
+        self.current_room
+            .borrow()
+            .as_ref()
+            .and_then(|room| self.send_msgs.get(room))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the unsent message of the current room.
+    pub(crate) fn set_draft(&mut self, draft: String) {
+        if let Some(room) = self.current_room.borrow().as_ref() {
+            if let Some(m) = self.send_msgs.get_mut(room) {
+                *m = draft;
+            }
+        }
+    }
+
+    /// Is the message input of the current room empty.
+    pub(crate) fn is_input_empty(&self) -> bool {
+        self.current_room
+            .borrow()
+            .as_ref()
+            .and_then(|room| self.send_msgs.get(room))
+            .map_or(true, |msg| msg.is_empty())
+    }
+
     pub fn add_char(&mut self, ch: char) {
         if let Some(room) = self.current_room.borrow().as_ref() {
             if let Some(m) = self.send_msgs.get_mut(room) {
@@ -493,13 +1447,14 @@ impl RenderWidget for MessageWidget {
     fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
         use itertools::Itertools;
 
-        if self.did_overflow.is_none() {
-            self.did_overflow = Some(Rc::new(Cell::new(false)));
-        }
-        if self.at_top.is_none() {
-            self.at_top = Some(Rc::new(Cell::new(false)));
+        if too_small(area) {
+            self.msg_area = Rect::default();
+            self.send_area = Rect::default();
+            return;
         }
 
+        let scroll = self.current_scroll().cloned().unwrap_or_default();
+
         // TODO handle getting the textbox message better
         let sending_text = if let Some(room_id) = self.current_room.borrow().as_ref() {
             self.send_msgs
@@ -511,21 +1466,18 @@ impl RenderWidget for MessageWidget {
             String::new()
         };
 
-        let mut lines = sending_text.chars().filter(|c| *c == '\n').count();
-        if lines <= 1 {
-            lines = 2;
-        }
-        let (msg_height, send_height, notify_height) = {
-            let send = ((lines + 1) * 5) as u16;
-            let notify = if area.height < 25 { 0 } else { 15 };
-            (100 - (send + 15), send, notify)
-        };
+        // the text is wrapped beside the send button and inside the borders
+        let text_width = (area.width * 9 / 10).saturating_sub(2);
+        let send_rows = wrapped_rows(&sending_text, text_width);
+        let (msg_height, notify_height, send_height) = split_heights(area.height, send_rows);
+        // keep the end of the draft, where the cursor is, in view
+        let send_scroll = send_rows.saturating_sub(send_height.saturating_sub(2));
         let chunks = Layout::default()
             .constraints(
                 [
-                    Constraint::Percentage(msg_height),
-                    Constraint::Percentage(notify_height),
-                    Constraint::Percentage(send_height),
+                    Constraint::Length(msg_height),
+                    Constraint::Length(notify_height),
+                    Constraint::Length(send_height),
                 ]
                 .as_ref(),
             )
@@ -542,8 +1494,32 @@ impl RenderWidget for MessageWidget {
         };
 
         let mut msg_copy = vec![];
+        let selected = current_room_id
+            .as_ref()
+            .and_then(|room| self.selected.get(room));
+        let expanded = &self.expanded;
+        let max_lines = Some(self.collapse_lines).filter(|lines| *lines > 0);
+        let density = self.density;
+        let me = self.me.as_ref();
+        let (open_thread, threads, thread_of) =
+            (self.open_thread.as_ref(), &self.threads, &self.thread_of);
+        let send_states = &self.send_states;
+        let time_format = self.time_format.as_deref();
+        let date_separators = self.date_separators;
+        // the divider spans the message box between its borders
+        let divider_width = chunks[0].width.saturating_sub(2) as usize;
+        utils::set_message_pane_width(chunks[0].width.saturating_sub(2));
+        let today = utils::local_day(SystemTime::now());
+        let mut last_day = None;
+        let mut last_sender: Option<UserId> = None;
         // TODO no alloc split messages up by hashmap of roomid to message vec?
         if let Some(room_id) = current_room_id {
+            if self.reached_start.contains(&room_id) {
+                msg_copy.push(Text::styled(
+                    "\u{2500}\u{2500}\u{2500} beginning of room \u{2500}\u{2500}\u{2500}\n",
+                    Style::default().modifier(Modifier::DIM),
+                ));
+            }
             if let Some(messages) = self.messages.get_mut(&room_id) {
                 messages.sort_by(|msg, msg2| msg.timestamp.cmp(&msg2.timestamp));
                 // make sure the messages we have seen are marked read.
@@ -551,27 +1527,118 @@ impl RenderWidget for MessageWidget {
                     // this message has been read and a read receipt will be sent for it
                     mark_msg.read = true;
                 }
-                for msg in messages
-                    .iter_mut()
-                    .unique_by(|msg| msg.event_id.clone())
-                    .flat_map(|msg| ctrl_char::process_text(msg))
-                {
-                    msg_copy.push(msg);
+                let messages = messages
+                    .iter()
+                    // every local echo has the same event id
+                    .unique_by(|msg| {
+                        let echo = Some(msg.uuid).filter(|_| msg.is_local_echo());
+                        (msg.event_id.clone(), echo)
+                    })
+                    .filter(|msg| in_view(open_thread, thread_of, &msg.event_id))
+                    .collect::<Vec<_>>();
+                let runs = membership_runs(&messages);
+                for (idx, msg) in messages.iter().enumerate() {
+                    let run = runs[idx].map(|start| {
+                        let expanded = expanded.contains(&messages[start].event_id);
+                        (start, expanded)
+                    });
+                    // the rest of a collapsed run is counted in its summary line
+                    if let Some((start, false)) = run {
+                        if start != idx {
+                            continue;
+                        }
+                    }
+                    let opts = DisplayOptions {
+                        me,
+                        selected: selected == Some(&msg.event_id),
+                        max_lines: max_lines.filter(|_| !expanded.contains(&msg.event_id)),
+                        density,
+                        time_format,
+                    };
+                    let day = utils::local_day(msg.timestamp);
+                    // only between messages, the first message starts a new day
+                    if date_separators && last_day.map_or(false, |last| last != day) {
+                        msg_copy.push(Text::styled(
+                            utils::date_separator(day, today, divider_width),
+                            Style::default().modifier(Modifier::DIM),
+                        ));
+                    }
+                    last_day = Some(day);
+                    if let Some((start, listed)) = run {
+                        let len = runs[idx..]
+                            .iter()
+                            .take_while(|run| **run == Some(start))
+                            .count();
+                        let line = if listed || len == 1 {
+                            msg.text.clone()
+                        } else {
+                            let mut burst = MembershipBurst::default();
+                            for notice in
+                                messages[idx..idx + len].iter().filter_map(|m| m.membership)
+                            {
+                                burst.add(notice);
+                            }
+                            format!("{} (press Ctrl-x to expand)", burst.summary())
+                        };
+                        let style = if opts.selected {
+                            Style::default().modifier(Modifier::DIM | Modifier::REVERSED)
+                        } else {
+                            Style::default().modifier(Modifier::DIM)
+                        };
+                        msg_copy.push(Text::styled(format!("\u{2500} {}\n", line), style));
+                        continue;
+                    }
+                    // cozy mode leaves a blank line when the sender changes
+                    let new_sender = last_sender.as_ref().map_or(false, |u| *u != msg.user);
+                    if density == Density::Cozy && new_sender {
+                        msg_copy.push(Text::raw("\n"));
+                    }
+                    last_sender = Some(msg.user.clone());
+                    msg_copy.extend(ctrl_char::process_text(msg, opts));
+                    match send_states.get(&msg.uuid).filter(|_| msg.is_local_echo()) {
+                        Some(SendState::Queued) => msg_copy.push(Text::styled(
+                            "  queued until the homeserver is back\n",
+                            Style::default().modifier(Modifier::DIM),
+                        )),
+                        Some(SendState::Failed) => msg_copy.push(Text::styled(
+                            "  not sent, :retry sends it again\n",
+                            Style::default().fg(Color::Red),
+                        )),
+                        None => {}
+                    }
+                    // the replies are listed in the thread view
+                    let replies = threads.get(&msg.event_id).map_or(0, Vec::len);
+                    if open_thread.is_none() && replies > 0 {
+                        msg_copy.push(Text::styled(
+                            format!(
+                                "  \u{2514} {} {}\n",
+                                replies,
+                                if replies == 1 { "reply" } else { "replies" }
+                            ),
+                            Style::default().modifier(Modifier::DIM),
+                        ));
+                    }
                 }
             }
         }
 
-        let (title, style) = if self.unread_notifications > UInt::MIN {
+        let (title, style) = if self.open_thread.is_some() {
+            (
+                "-----Thread-----(Esc to close)".to_string(),
+                Style::default().fg(Color::Yellow).modifier(Modifier::BOLD),
+            )
+        } else if self.unread_notifications > UInt::MIN {
             (
                 format!(
-                    "-----Messages-----unread {}",
+                    "-----Messages-----{}-----unread {}",
+                    self.room_header(),
                     self.unread_notifications.to_string()
                 ),
                 Style::default().fg(Color::Red).modifier(Modifier::BOLD),
             )
         } else {
             (
-                "-----Messages-----".to_string(),
+                format!("-----Messages-----{}", self.room_header()),
                 Style::default().fg(Color::Yellow).modifier(Modifier::BOLD),
             )
         };
@@ -584,12 +1651,45 @@ impl RenderWidget for MessageWidget {
                     .title_style(style),
             )
             .wrap(true)
-            .scroll(self.scroll_pos as u16)
+            .scroll(scroll.pos as u16)
             .scroll_mode(ScrollMode::Tail)
-            .did_overflow(Rc::clone(self.did_overflow.as_ref().unwrap()))
-            .at_top(Rc::clone(self.at_top.as_ref().unwrap()));
+            .did_overflow(scroll.did_overflow)
+            .at_top(scroll.at_top);
 
-        f.render_widget(messages, chunks[0]);
+        if let Some((source, scroll)) = self.source_view.as_ref() {
+            let text = [Text::raw(source.as_str())];
+            let source = Paragraph::new(text.iter())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                        .title("-----Event Source-----(Esc to close)")
+                        .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                )
+                .wrap(true)
+                .scroll(*scroll);
+            f.render_widget(source, chunks[0]);
+        } else if self.show_history {
+            let history = self
+                .notify_history
+                .iter()
+                .rev()
+                .skip(self.history_scroll)
+                .map(|item| Text::raw(format!("{}\n", item)))
+                .collect::<Vec<_>>();
+            let history = Paragraph::new(history.iter())
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                        .title("-----Notification History-----")
+                        .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                )
+                .wrap(true);
+            f.render_widget(history, chunks[0]);
+        } else {
+            f.render_widget(messages, chunks[0]);
+        }
 
         // display each notification for 6 seconds
         if let Some((time, _item)) = self.notifications.get_mut(0) {
@@ -631,15 +1731,17 @@ impl RenderWidget for MessageWidget {
                     .modifier(Modifier::RAPID_BLINK),
             ),
         ];
+        let send_title = self.send_title();
         let text_box = Paragraph::new(t3.iter())
             .block(
                 Block::default()
                     .borders(Borders::ALL)
                     .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                    .title("Send")
+                    .title(&send_title)
                     .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
             )
-            .wrap(true);
+            .wrap(true)
+            .scroll(send_scroll);
 
         f.render_widget(text_box, chunks[2]);
 
@@ -660,3 +1762,557 @@ impl RenderWidget for MessageWidget {
         f.render_widget(button, btn[1]);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(event_id: &str, uuid: Uuid) -> Message {
+        Message {
+            name: "example".into(),
+            text: "hello".into(),
+            user: UserId::try_from("@example:localhost").unwrap(),
+            event_id: EventId::try_from(event_id).unwrap(),
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            emote: false,
+            membership: None,
+            timestamp: SystemTime::now(),
+            uuid,
+        }
+    }
+
+    /// A widget showing the room `!room:localhost`.
+    fn widget_in_room() -> (MessageWidget, RoomId) {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let widget = MessageWidget::default();
+        *widget.current_room.borrow_mut() = Some(room.clone());
+        (widget, room)
+    }
+
+    #[test]
+    fn backfill_does_not_duplicate_synced_message() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+
+        // from sync
+        widget.add_message(message("$event:localhost", Uuid::new_v4()), &room);
+        // the same event from a backfill without a transaction id gets a random uuid
+        widget.add_message(message("$event:localhost", Uuid::new_v4()), &room);
+
+        assert_eq!(widget.messages[&room].len(), 1);
+    }
+
+    #[test]
+    fn history_is_trimmed_to_the_newest() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.message_history = 2;
+
+        for id in &["$1:localhost", "$2:localhost", "$3:localhost"] {
+            widget.add_message(message(id, Uuid::new_v4()), &room);
+        }
+
+        let ids = widget.messages[&room]
+            .iter()
+            .map(|m| m.event_id.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["$2:localhost", "$3:localhost"], ids);
+        assert!(widget.take_trimmed(&room));
+        assert!(!widget.take_trimmed(&room));
+    }
+
+    #[test]
+    fn fully_read_needs_the_newest_message() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+
+        let mut newest = message("$2:localhost", Uuid::new_v4());
+        newest.timestamp = SystemTime::now() + Duration::from_secs(1);
+        widget.add_message(newest, &room);
+        widget.add_message(message("$1:localhost", Uuid::new_v4()), &room);
+
+        let read = |id| EventId::try_from(id).unwrap();
+        assert!(widget.read_to_end(&room, &read("$2:localhost")));
+        assert!(!widget.read_to_end(&room, &read("$1:localhost")));
+    }
+
+    #[test]
+    fn receipts_from_other_sessions_mark_older_messages_read() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+
+        let now = SystemTime::now();
+        for (secs, id) in [
+            (0, "$1:localhost"),
+            (1, "$2:localhost"),
+            (2, "$3:localhost"),
+        ]
+        .iter()
+        {
+            let mut msg = message(id, Uuid::new_v4());
+            msg.timestamp = now + Duration::from_secs(*secs);
+            widget.add_message(msg, &room);
+        }
+
+        assert!(!widget.mark_read_up_to(&room, &EventId::try_from("$9:localhost").unwrap()));
+        assert!(widget.mark_read_up_to(&room, &EventId::try_from("$2:localhost").unwrap()));
+        let read = widget.messages[&room]
+            .iter()
+            .map(|msg| (msg.read, msg.sent_receipt))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(true, true), (true, true), (false, false)], read);
+    }
+
+    #[test]
+    fn event_source_is_shown_for_the_selected_message() {
+        let (mut widget, room) = widget_in_room();
+        widget.add_message(message("$1:localhost", Uuid::new_v4()), &room);
+
+        widget.select_previous_message();
+        assert!(!widget.show_selected_source());
+
+        widget.add_source(
+            EventId::try_from("$1:localhost").unwrap(),
+            r#"{"type":"m.room.message","content":{"body":"hello"}}"#.to_string(),
+        );
+        assert!(widget.show_selected_source());
+        let (source, _) = widget.source_view.clone().unwrap();
+        assert!(source.contains("\n  \"content\": {\n    \"body\": \"hello\""));
+
+        widget.source_scroll_down();
+        widget.source_scroll_down();
+        assert_eq!(2, widget.source_view.as_ref().unwrap().1);
+        assert!(widget.close_source());
+        assert!(!widget.close_source());
+    }
+
+    #[test]
+    fn room_header_shows_members_and_encryption() {
+        // without a current room there is no header
+        let widget = MessageWidget::default();
+        assert_eq!("", widget.room_header());
+
+        let (mut widget, room) = widget_in_room();
+        widget.set_member_count(room.clone(), 1);
+        assert_eq!("1 member", widget.room_header());
+
+        widget.set_member_count(room.clone(), 12);
+        widget.set_encrypted(room);
+        assert_eq!("12 members \u{1f512}", widget.room_header());
+    }
+
+    #[test]
+    fn send_title_counts_unverified_devices() {
+        let (mut widget, room) = widget_in_room();
+        assert_eq!("Send", widget.send_title());
+
+        widget.set_encrypted(room.clone());
+        assert_eq!("Send (encrypted)", widget.send_title());
+
+        widget.set_unverified_devices(room.clone(), 1);
+        assert_eq!("Send (encrypted, 1 unverified device)", widget.send_title());
+
+        widget.set_unverified_devices(room.clone(), 3);
+        assert_eq!(
+            "Send (encrypted, 3 unverified devices)",
+            widget.send_title()
+        );
+
+        widget.set_unverified_devices(room, 0);
+        assert_eq!("Send (encrypted)", widget.send_title());
+    }
+
+    #[test]
+    fn me_sends_an_emote() {
+        let (mut widget, room) = widget_in_room();
+
+        widget
+            .send_msgs
+            .insert(room.clone(), "/me waves hello".into());
+        match widget.get_sending_message().unwrap() {
+            MessageEventContent::Emote(emote) => {
+                assert_eq!("waves hello", emote.body);
+                assert!(emote.formatted.is_none());
+            }
+            _ => panic!("/me did not send an emote"),
+        }
+
+        widget
+            .send_msgs
+            .insert(room, "/me runs `cargo test`".into());
+        match widget.get_sending_message().unwrap() {
+            MessageEventContent::Emote(emote) => {
+                assert_eq!("runs `cargo test`", emote.body);
+                assert!(emote
+                    .formatted
+                    .unwrap()
+                    .body
+                    .contains("<code>cargo test</code>"));
+            }
+            _ => panic!("/me did not send an emote"),
+        }
+    }
+
+    #[test]
+    fn scroll_position_is_kept_per_room() {
+        let first = RoomId::try_from("!first:localhost").unwrap();
+        let second = RoomId::try_from("!second:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+
+        *widget.current_room.borrow_mut() = Some(first.clone());
+        widget.current_scroll().unwrap().pos = 7;
+
+        *widget.current_room.borrow_mut() = Some(second);
+        widget.room_changed();
+        assert_eq!(0, widget.current_scroll().unwrap().pos);
+
+        *widget.current_room.borrow_mut() = Some(first);
+        widget.room_changed();
+        assert_eq!(7, widget.current_scroll().unwrap().pos);
+    }
+
+    #[test]
+    fn message_cursor_is_kept_per_room() {
+        let first = RoomId::try_from("!first:localhost").unwrap();
+        let second = RoomId::try_from("!second:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.add_message(message("$first:localhost", Uuid::new_v4()), &first);
+        widget.add_message(message("$second:localhost", Uuid::new_v4()), &second);
+
+        *widget.current_room.borrow_mut() = Some(first.clone());
+        widget.select_previous_message();
+        let selected = EventId::try_from("$first:localhost").unwrap();
+        assert_eq!(Some(&selected), widget.selected_event());
+
+        *widget.current_room.borrow_mut() = Some(second);
+        widget.room_changed();
+        assert_eq!(None, widget.selected_event());
+
+        *widget.current_room.borrow_mut() = Some(first);
+        widget.room_changed();
+        assert_eq!(Some(&selected), widget.selected_event());
+    }
+
+    #[test]
+    fn server_event_replaces_local_echo() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let uuid = Uuid::new_v4();
+        let mut widget = MessageWidget::default();
+
+        widget.add_message(message(ECHO_EVENT_ID, uuid), &room);
+        widget.add_message(message("$event:localhost", uuid), &room);
+
+        let messages = &widget.messages[&room];
+        assert_eq!(messages.len(), 1);
+        assert!(!messages[0].is_local_echo());
+    }
+
+    #[test]
+    fn redaction_of_unknown_event_is_reported() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.add_message(message("$event:localhost", Uuid::new_v4()), &room);
+
+        let member_event = EventId::try_from("$member:localhost").unwrap();
+        assert!(!widget.redaction_event(&room, &member_event));
+        let unknown_room = RoomId::try_from("!other:localhost").unwrap();
+        assert!(!widget.redaction_event(&unknown_room, &member_event));
+
+        let event = EventId::try_from("$event:localhost").unwrap();
+        assert!(widget.redaction_event(&room, &event));
+    }
+
+    #[test]
+    fn only_own_sent_messages_are_edited() {
+        let (mut widget, room) = widget_in_room();
+        widget.send_msgs.insert(room.clone(), String::new());
+        widget.add_message(message("$mine:localhost", Uuid::new_v4()), &room);
+
+        widget.select_previous_message();
+        assert!(!widget.edit_selected());
+
+        widget.me = Some(UserId::try_from("@example:localhost").unwrap());
+        assert!(widget.edit_selected());
+        let mine = EventId::try_from("$mine:localhost").unwrap();
+        assert_eq!(Some(&mine), widget.editing());
+        assert_eq!("hello", widget.draft());
+
+        assert!(widget.cancel_editing());
+        assert_eq!(None, widget.editing());
+        assert_eq!("", widget.draft());
+    }
+
+    #[test]
+    fn editing_again_starts_from_the_latest_edit() {
+        let (mut widget, room) = widget_in_room();
+        widget.send_msgs.insert(room.clone(), String::new());
+        widget.me = Some(UserId::try_from("@example:localhost").unwrap());
+        widget.add_message(message("$mine:localhost", Uuid::new_v4()), &room);
+        let mine = EventId::try_from("$mine:localhost").unwrap();
+        widget.add_source(
+            mine.clone(),
+            r#"{"event_id":"$mine:localhost","content":{"body":"hello"}}"#.to_string(),
+        );
+
+        widget.edit_message(&room, &mine, "hello **there**".to_string());
+        widget.select_previous_message();
+        assert!(widget.edit_selected());
+        assert_eq!("hello **there**", widget.draft());
+    }
+
+    #[test]
+    fn redacted_message_loses_its_reactions() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.add_message(message("$event:localhost", Uuid::new_v4()), &room);
+        widget.add_message(message("$other:localhost", Uuid::new_v4()), &room);
+        let event = EventId::try_from("$event:localhost").unwrap();
+        let other = EventId::try_from("$other:localhost").unwrap();
+        let sender = UserId::try_from("@friend:localhost").unwrap();
+        for (relates_to, reaction) in &[(&event, "$r1:localhost"), (&other, "$r2:localhost")] {
+            widget.set_reaction_event(
+                &room,
+                relates_to,
+                &EventId::try_from(*reaction).unwrap(),
+                &sender,
+                "\u{1f44d}",
+            );
+        }
+
+        assert!(widget.redaction_event(&room, &event));
+        let messages = &widget.messages[&room];
+        let redacted = messages.iter().find(|m| m.event_id == event).unwrap();
+        assert_eq!("**R**E**D**A**C**T**E**D**", redacted.text);
+        assert!(redacted.reactions.is_empty());
+        // only the redacted message changes
+        let kept = messages.iter().find(|m| m.event_id == other).unwrap();
+        assert_eq!("hello", kept.text);
+        assert_eq!(1, kept.reactions.len());
+    }
+
+    #[test]
+    fn scroll_to_event_waits_for_backfill() {
+        let room = RoomId::try_from("!room:example.org").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.current_room = Rc::new(RefCell::new(Some(room.clone())));
+        widget.add_message(message("$new:example.org", Uuid::new_v4()), &room);
+
+        let old = EventId::try_from("$old:example.org").unwrap();
+        assert!(!widget.scroll_to_event(old.clone()));
+        assert_eq!(Some(&old), widget.pending_jump());
+
+        widget.add_message(message("$old:example.org", Uuid::new_v4()), &room);
+        assert!(widget.scroll_to_event(old.clone()));
+        assert_eq!(None, widget.pending_jump());
+        assert_eq!(Some(&old), widget.selected_event());
+    }
+
+    #[test]
+    fn poll_votes_are_tallied_until_ended() {
+        let room = RoomId::try_from("!room:example.org").unwrap();
+        let alice = UserId::try_from("@alice:example.org").unwrap();
+        let mut widget = MessageWidget::default();
+        let msg = message("$poll:example.org", Uuid::new_v4());
+        let event_id = msg.event_id.clone();
+        let starter = msg.user.clone();
+        let poll = Poll {
+            question: "lunch?".into(),
+            answers: vec![
+                ("pizza".into(), "Pizza".into()),
+                ("soup".into(), "Soup".into()),
+            ],
+            disclosed: true,
+            votes: HashMap::new(),
+            closed: false,
+        };
+        widget.add_poll(msg, poll, &room);
+
+        assert!(widget.poll_vote(&room, &event_id, &alice, vec!["pizza".into()]));
+        assert!(widget.poll_vote(&room, &event_id, &starter, vec!["pizza".into()]));
+        // a newer response replaces the earlier one
+        assert!(widget.poll_vote(&room, &event_id, &alice, vec!["soup".into()]));
+        let poll = widget.poll(&event_id).unwrap();
+        assert_eq!((1, 1), (poll.tally("pizza"), poll.tally("soup")));
+        assert_eq!(Some("soup"), poll.answer_id(2));
+        assert_eq!(None, poll.answer_id(0));
+
+        // only the user that started the poll ends it
+        widget.end_poll(&room, &event_id, &alice, false);
+        assert!(!widget.poll(&event_id).unwrap().closed);
+        widget.end_poll(&room, &event_id, &starter, false);
+        assert!(!widget.poll_vote(&room, &event_id, &alice, vec!["pizza".into()]));
+        assert_eq!(1, widget.poll(&event_id).unwrap().tally("soup"));
+    }
+
+    #[test]
+    fn poll_events_before_the_start_are_kept() {
+        let room = RoomId::try_from("!room:example.org").unwrap();
+        let alice = UserId::try_from("@alice:example.org").unwrap();
+        let moderator = UserId::try_from("@mod:example.org").unwrap();
+        let mut widget = MessageWidget::default();
+        let msg = message("$poll:example.org", Uuid::new_v4());
+        let event_id = msg.event_id.clone();
+        let poll = Poll {
+            question: "lunch?".into(),
+            answers: vec![("pizza".into(), "Pizza".into())],
+            disclosed: true,
+            votes: HashMap::new(),
+            closed: false,
+        };
+
+        // backfilling sees the responses and the end before the poll itself
+        assert!(!widget.poll_vote(&room, &event_id, &alice, vec!["pizza".into()]));
+        widget.end_poll(&room, &event_id, &moderator, true);
+        widget.add_poll(msg, poll, &room);
+
+        let poll = widget.poll(&event_id).unwrap();
+        assert_eq!(1, poll.tally("pizza"));
+        assert!(poll.closed);
+    }
+
+    #[test]
+    fn send_box_rows_follow_the_wrapped_text() {
+        assert_eq!(1, wrapped_rows("", 10));
+        // the cursor wraps onto its own row
+        assert_eq!(2, wrapped_rows("0123456789", 10));
+        assert_eq!(3, wrapped_rows("one\n\nthree", 10));
+        assert_eq!(3, wrapped_rows("0123456789abcdefghijk", 10));
+        assert_eq!(21, wrapped_rows("01234567890123456789", 0));
+        // double width characters and emoji sequences take two columns
+        assert_eq!(2, wrapped_rows("文字文字文", 10));
+        assert_eq!(1, wrapped_rows(&"👨\u{200d}👩\u{200d}👧".repeat(4), 10));
+    }
+
+    #[test]
+    fn long_drafts_leave_room_for_messages() {
+        let draft = vec!["line"; 30].join("\n");
+        let rows = wrapped_rows(&draft, 80);
+        assert_eq!(30, rows);
+        for height in 0..60 {
+            let (msgs, notify, send) = split_heights(height, rows);
+            assert!(msgs + notify + send <= height);
+            assert!(send <= MAX_SEND_ROWS + 2);
+            if height >= MIN_MSG_ROWS + 3 {
+                assert!(msgs >= MIN_MSG_ROWS);
+            }
+        }
+    }
+
+    #[test]
+    fn thread_replies_are_grouped_under_the_root() {
+        let (mut widget, room) = widget_in_room();
+        for id in &["$root:localhost", "$reply:localhost", "$other:localhost"] {
+            widget.add_message(message(id, Uuid::new_v4()), &room);
+        }
+        let root = EventId::try_from("$root:localhost").unwrap();
+        let reply = EventId::try_from("$reply:localhost").unwrap();
+        widget.add_thread_reply(root.clone(), reply.clone());
+        // a reply seen twice, from sync and backfill, is counted once
+        widget.add_thread_reply(root.clone(), reply.clone());
+        assert_eq!(1, widget.thread_replies(&root));
+
+        // the reply is skipped when moving the cursor through the room
+        widget.select_previous_message();
+        widget.select_previous_message();
+        assert_eq!(Some(&root), widget.selected_event());
+
+        assert!(widget.open_selected_thread());
+        assert_eq!(Some(&root), widget.open_thread());
+        widget.select_next_message();
+        assert_eq!(Some(&reply), widget.selected_event());
+        assert!(widget.close_thread());
+        assert!(!widget.close_thread());
+    }
+
+    #[test]
+    fn membership_runs_are_collapsed_until_expanded() {
+        let (mut widget, room) = widget_in_room();
+        widget.add_message(message("$1:localhost", Uuid::new_v4()), &room);
+        for (id, user, notice) in &[
+            ("$2:localhost", "@a:localhost", MemberNotice::Joined),
+            ("$3:localhost", "@b:localhost", MemberNotice::Joined),
+            ("$4:localhost", "@c:localhost", MemberNotice::Left),
+        ] {
+            let msg = Message::membership(
+                UserId::try_from(*user).unwrap(),
+                EventId::try_from(*id).unwrap(),
+                SystemTime::now(),
+                *notice,
+            );
+            widget.add_message(msg, &room);
+        }
+        widget.add_message(message("$5:localhost", Uuid::new_v4()), &room);
+
+        let messages = widget.messages[&room].iter().collect::<Vec<_>>();
+        assert_eq!(
+            vec![None, Some(1), Some(1), Some(1), None],
+            membership_runs(&messages)
+        );
+        let mut burst = MembershipBurst::default();
+        messages
+            .iter()
+            .filter_map(|m| m.membership)
+            .for_each(|n| burst.add(n));
+        assert_eq!("2 users joined, 1 left", burst.summary());
+
+        // only the first entry of a collapsed run is selected
+        let id = |id: &str| EventId::try_from(id).unwrap();
+        widget.select_previous_message();
+        widget.select_previous_message();
+        assert_eq!(Some(&id("$2:localhost")), widget.selected_event());
+        widget.select_previous_message();
+        assert_eq!(Some(&id("$1:localhost")), widget.selected_event());
+
+        widget.select_next_message();
+        widget.toggle_expand_selected();
+        widget.select_next_message();
+        assert_eq!(Some(&id("$3:localhost")), widget.selected_event());
+
+        // collapsing from inside the run moves the cursor to its first entry
+        widget.toggle_expand_selected();
+        assert_eq!(Some(&id("$2:localhost")), widget.selected_event());
+        widget.select_next_message();
+        assert_eq!(Some(&id("$5:localhost")), widget.selected_event());
+    }
+
+    #[test]
+    fn reactions_are_counted_by_key() {
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let me = UserId::try_from("@me:localhost").unwrap();
+        let other = UserId::try_from("@other:localhost").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.add_message(message("$event:localhost", Uuid::new_v4()), &room);
+
+        let relates_to = EventId::try_from("$event:localhost").unwrap();
+        let reactions = [
+            ("$r1:localhost", &other, "👍"),
+            ("$r2:localhost", &other, "❤️"),
+            ("$r3:localhost", &me, "👍"),
+            // the same event again is not counted twice
+            ("$r3:localhost", &me, "👍"),
+        ];
+        for (id, sender, key) in reactions.iter() {
+            let id = EventId::try_from(*id).unwrap();
+            widget.set_reaction_event(&room, &relates_to, &id, sender, key);
+        }
+
+        let groups = widget.messages[&room][0].reaction_groups(Some(&me));
+        assert_eq!(
+            vec![
+                ReactionGroup {
+                    key: "👍",
+                    count: 2,
+                    mine: true
+                },
+                ReactionGroup {
+                    key: "❤️",
+                    count: 1,
+                    mine: false
+                },
+            ],
+            groups
+        );
+    }
+}
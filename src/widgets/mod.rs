@@ -2,16 +2,27 @@ use std::io;
 
 use rumatui_tui::{backend::Backend, layout::Rect, Frame, Terminal};
 
+mod account;
 pub mod app;
 pub mod chat;
+mod debug;
 mod error;
+pub mod list_state;
 pub mod login;
+mod member_list;
 pub mod message;
+mod pinned;
 pub mod register;
 pub mod room_search;
 pub mod rooms;
 pub mod utils;
 
+/// True when `area` can not fit a bordered box with anything inside it, widgets draw
+/// nothing rather than laying out zero sized chunks.
+pub(crate) fn too_small(area: Rect) -> bool {
+    area.width < 3 || area.height < 3
+}
+
 pub trait RenderWidget {
     fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
     where
@@ -0,0 +1,54 @@
+use rumatui_tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+
+use crate::{client::PinnedEvent, widgets::RenderWidget};
+
+/// Lists the pinned messages of a room, opened with `:pinned`.
+#[derive(Debug)]
+pub struct PinnedWidget<'p> {
+    events: &'p [PinnedEvent],
+}
+
+impl<'p> PinnedWidget<'p> {
+    pub fn new(events: &'p [PinnedEvent]) -> Self {
+        Self { events }
+    }
+}
+
+impl<'p> RenderWidget for PinnedWidget<'p> {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let sender = Style::default().fg(Color::Magenta);
+        let mut text = vec![];
+        if self.events.is_empty() {
+            text.push(Text::styled(
+                "no messages are pinned in this room",
+                Style::default().modifier(Modifier::DIM),
+            ));
+        }
+        for event in self.events {
+            if let Some(user) = event.sender.as_ref() {
+                text.push(Text::styled(format!("{}: ", user.localpart()), sender));
+            }
+            text.push(Text::raw(format!("{}\n", event.body)));
+        }
+
+        let p = Paragraph::new(text.iter())
+            .block(
+                Block::default()
+                    .title("Pinned Messages (Esc to close)")
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+            )
+            .wrap(true);
+        f.render_widget(p, area)
+    }
+}
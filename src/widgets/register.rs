@@ -7,13 +7,18 @@ use rumatui_tui::{
 };
 use termion::event::MouseButton;
 
-use crate::widgets::{login::Loading, RenderWidget};
+use crate::{
+    client::{check_email, uiaa::Policy, Username},
+    widgets::{login::Loading, too_small, RenderWidget},
+};
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RegisterSelect {
     Username = 0,
     Password,
+    /// Optional, used when the homeserver asks to validate an email.
+    Email,
 }
 impl Default for RegisterSelect {
     fn default() -> Self {
@@ -25,24 +30,192 @@ pub struct Register {
     pub selected: RegisterSelect,
     pub username: String,
     pub password: String,
+    pub email: String,
+}
+
+/// How hard the password typed at registration would be to guess.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strength {
+    Weak,
+    Fair,
+    Strong,
+}
+
+/// Rate a password by its length and how many of lower case, upper case, digits and
+/// symbols it uses, with a hint for making it stronger.
+pub fn password_strength(password: &str) -> (Strength, Option<&'static str>) {
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|has| **has)
+    .count();
+    let len = password.chars().count();
+
+    if len < 8 {
+        (Strength::Weak, Some("use at least 8 characters"))
+    } else if classes < 2 {
+        (Strength::Weak, Some("mix letters, numbers and symbols"))
+    } else if len >= 12 && classes >= 3 {
+        (Strength::Strong, None)
+    } else if len < 12 {
+        (Strength::Fair, Some("12 or more characters is stronger"))
+    } else {
+        (Strength::Fair, Some("mix letters, numbers and symbols"))
+    }
+}
+
+/// A registration stage waiting on the user.
+#[derive(Clone, Debug)]
+pub enum UiaaPrompt {
+    /// The policies to accept before registering.
+    Terms {
+        session: String,
+        policies: Vec<Policy>,
+    },
+    /// Asking for the address to send the validation email to.
+    EmailAddress {
+        session: String,
+        email: String,
+        client_secret: String,
+        /// The validation emails requested with `client_secret`, the homeserver only
+        /// sends another email for a higher attempt.
+        send_attempt: u32,
+    },
+    /// The validation email was sent, waiting for the link to be opened.
+    EmailSent {
+        session: String,
+        email: String,
+        sid: String,
+        client_secret: String,
+    },
+    /// A stage finished in the browser.
+    Fallback {
+        session: String,
+        stage: String,
+        url: String,
+    },
+}
+
+impl UiaaPrompt {
+    fn lines(&self) -> Vec<String> {
+        match self {
+            Self::Terms { policies, .. } => {
+                let mut lines = vec!["To register you must accept:".to_string(), String::new()];
+                lines.extend(
+                    policies
+                        .iter()
+                        .map(|policy| format!("{} {}", policy.name, policy.url)),
+                );
+                lines.push(String::new());
+                lines.push("Press Enter to accept or Esc to cancel".to_string());
+                lines
+            }
+            Self::EmailAddress { email, .. } => vec![
+                "The homeserver needs an email address to register".to_string(),
+                String::new(),
+                format!("Email: {}", email),
+                String::new(),
+                "Press Enter to send the validation email or Esc to cancel".to_string(),
+            ],
+            Self::EmailSent { email, .. } => vec![
+                format!("A validation link was sent to {}", email),
+                String::new(),
+                "Open the link then press Enter to continue".to_string(),
+            ],
+            Self::Fallback { stage, url, .. } => vec![
+                format!("The {} step has to be completed in a browser at", stage),
+                String::new(),
+                url.clone(),
+                String::new(),
+                "Press Enter once it is done".to_string(),
+            ],
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct RegisterWidget {
     user_area: Rect,
     password_area: Rect,
+    email_area: Rect,
     pub register: Register,
     pub registering: bool,
     pub registered: bool,
     pub waiting: Loading,
     pub homeserver: Option<String>,
+    /// Set when the homeserver could not be reached.
+    pub server_error: Option<String>,
+    /// Show the password as typed instead of masked.
+    show_password: bool,
+    /// The registration stage waiting on the user.
+    pub uiaa: Option<UiaaPrompt>,
+    /// Shown under the prompt when a stage has to be retried.
+    pub uiaa_notice: Option<String>,
 }
 
 impl RegisterWidget {
     pub(crate) fn try_register(&self) -> bool {
-        RegisterSelect::Password == self.register.selected
+        self.server_error.is_none()
+            && RegisterSelect::Username != self.register.selected
             && !self.register.password.is_empty()
             && !self.register.username.is_empty()
+            && self.username_hint().is_none()
+            && self.email_hint().is_none()
+    }
+
+    /// The email to pass along with the registration, if one was typed.
+    pub(crate) fn email(&self) -> Option<String> {
+        Some(self.register.email.trim())
+            .filter(|email| !email.is_empty())
+            .map(ToString::to_string)
+    }
+
+    fn email_hint(&self) -> Option<&'static str> {
+        self.email().and_then(|email| check_email(&email).err())
+    }
+
+    /// The text box typing goes to.
+    pub(crate) fn selected_text(&mut self) -> &mut String {
+        match self.register.selected {
+            RegisterSelect::Username => &mut self.register.username,
+            RegisterSelect::Password => &mut self.register.password,
+            RegisterSelect::Email => &mut self.register.email,
+        }
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        self.register.selected = match self.register.selected {
+            RegisterSelect::Username => RegisterSelect::Password,
+            RegisterSelect::Password => RegisterSelect::Email,
+            RegisterSelect::Email => RegisterSelect::Username,
+        };
+    }
+
+    pub(crate) fn select_previous(&mut self) {
+        self.register.selected = match self.register.selected {
+            RegisterSelect::Username => RegisterSelect::Email,
+            RegisterSelect::Password => RegisterSelect::Username,
+            RegisterSelect::Email => RegisterSelect::Password,
+        };
+    }
+
+    /// What is wrong with the user name, shown before the request is sent.
+    fn username_hint(&self) -> Option<&'static str> {
+        if self.register.username.is_empty() {
+            return None;
+        }
+        Username::parse(&self.register.username)
+            .and_then(|user| user.check_registerable())
+            .err()
+    }
+
+    /// Reveal or mask the password.
+    pub(crate) fn toggle_show_password(&mut self) {
+        self.show_password = !self.show_password;
     }
 
     pub(crate) fn clear_register(&mut self) {
@@ -58,6 +231,8 @@ impl RegisterWidget {
                 self.register.selected = RegisterSelect::Username;
             } else if self.password_area.intersects(Rect::new(x, y, 1, 1)) {
                 self.register.selected = RegisterSelect::Password;
+            } else if self.email_area.intersects(Rect::new(x, y, 1, 1)) {
+                self.register.selected = RegisterSelect::Email;
             }
         }
     }
@@ -68,8 +243,15 @@ impl RenderWidget for RegisterWidget {
     where
         B: Backend,
     {
+        if too_small(area) {
+            self.user_area = Rect::default();
+            self.password_area = Rect::default();
+            self.email_area = Rect::default();
+            return;
+        }
         let chunks = Layout::default()
-            .horizontal_margin(40)
+            // narrow terminals keep half their width for the form
+            .horizontal_margin((area.width / 4).min(40))
             .constraints(
                 [
                     Constraint::Percentage(15),
@@ -92,15 +274,49 @@ impl RenderWidget for RegisterWidget {
             .direction(Direction::Vertical)
             .constraints(
                 [
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(20),
+                    Constraint::Percentage(10),
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(15),
                 ]
                 .as_ref(),
             )
             .split(chunks[1]);
 
+        if let Some(err) = self.server_error.as_ref() {
+            let t = [Text::styled(
+                format!("{}, press Enter to try again", err),
+                Style::default().fg(Color::Red).modifier(Modifier::BOLD),
+            )];
+            let p = Paragraph::new(t.iter())
+                .alignment(Alignment::Center)
+                .wrap(true);
+            f.render_widget(p, height_chunk[4]);
+        } else if let Some(hint) = self.username_hint().or_else(|| self.email_hint()) {
+            let t = [Text::styled(hint, Style::default().fg(Color::Yellow))];
+            let p = Paragraph::new(t.iter())
+                .alignment(Alignment::Center)
+                .wrap(true);
+            f.render_widget(p, height_chunk[4]);
+        } else if !self.register.password.is_empty() {
+            let (strength, hint) = password_strength(&self.register.password);
+            let (label, color) = match strength {
+                Strength::Weak => ("weak", Color::Red),
+                Strength::Fair => ("fair", Color::Yellow),
+                Strength::Strong => ("strong", Color::Green),
+            };
+            let t = [
+                Text::raw("Password strength: "),
+                Text::styled(label, Style::default().fg(color).modifier(Modifier::BOLD)),
+                Text::raw(hint.map(|h| format!(", {}", h)).unwrap_or_default()),
+            ];
+            let p = Paragraph::new(t.iter())
+                .alignment(Alignment::Center)
+                .wrap(true);
+            f.render_widget(p, height_chunk[4]);
+        }
+
         let width_chunk1 = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(
@@ -113,6 +329,25 @@ impl RenderWidget for RegisterWidget {
             )
             .split(height_chunk[1]);
 
+        if let (false, Some(prompt)) = (self.registering, self.uiaa.as_ref()) {
+            let mut t = prompt
+                .lines()
+                .into_iter()
+                .map(|line| Text::raw(format!("{}\n", line)))
+                .collect::<Vec<_>>();
+            if let Some(notice) = self.uiaa_notice.as_ref() {
+                t.push(Text::styled(
+                    format!("\n{}", notice),
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
+            let p = Paragraph::new(t.iter())
+                .alignment(Alignment::Center)
+                .wrap(true);
+            f.render_widget(p, chunks[1].inner(1));
+            return;
+        }
+
         if self.registering {
             self.waiting.tick(width_chunk1[1].width);
             let blk = Block::default()
@@ -130,23 +365,17 @@ impl RenderWidget for RegisterWidget {
 
             f.render_widget(p, width_chunk1[1]);
         } else {
-            let (high_user, high_pass) = if self.register.selected == RegisterSelect::Username {
-                (
-                    Block::default()
-                        .title("User Name")
-                        .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
-                        .borders(Borders::ALL),
-                    Block::default().title("Password").borders(Borders::ALL),
-                )
-            } else {
-                (
-                    Block::default().title("User Name").borders(Borders::ALL),
-                    Block::default()
-                        .title("Password")
-                        .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
-                        .borders(Borders::ALL),
-                )
+            let selected = |title: &'static str, select: RegisterSelect| {
+                let blk = Block::default().title(title).borders(Borders::ALL);
+                if self.register.selected == select {
+                    blk.border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
+                } else {
+                    blk
+                }
             };
+            let high_user = selected("User Name", RegisterSelect::Username);
+            let high_pass = selected("Password", RegisterSelect::Password);
+            let high_email = selected("Email (optional)", RegisterSelect::Email);
 
             // password width using password height
             let width_chunk2 = Layout::default()
@@ -160,9 +389,21 @@ impl RenderWidget for RegisterWidget {
                     .as_ref(),
                 )
                 .split(height_chunk[2]);
+            let width_chunk3 = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(
+                    [
+                        Constraint::Percentage(25),
+                        Constraint::Percentage(50),
+                        Constraint::Percentage(25),
+                    ]
+                    .as_ref(),
+                )
+                .split(height_chunk[3]);
 
             self.user_area = width_chunk1[1];
             self.password_area = width_chunk2[1];
+            self.email_area = width_chunk3[1];
 
             // User name
             let t = [Text::styled(
@@ -174,13 +415,37 @@ impl RenderWidget for RegisterWidget {
             f.render_widget(p, width_chunk1[1]);
 
             // Password from here down
-            let t2 = [Text::styled(
-                "*".repeat(self.register.password.len()),
+            let password = if self.show_password {
+                self.register.password.clone()
+            } else {
+                "*".repeat(self.register.password.len())
+            };
+            let t2 = [Text::styled(password, Style::default().fg(Color::Cyan))];
+            let p2 = Paragraph::new(t2.iter()).block(high_pass);
+
+            f.render_widget(p2, width_chunk2[1]);
+
+            let t3 = [Text::styled(
+                &self.register.email,
                 Style::default().fg(Color::Cyan),
             )];
-            let p2 = Paragraph::new(t2.iter()).block(high_pass);
+            let p3 = Paragraph::new(t3.iter()).block(high_email);
 
-            f.render_widget(p2, width_chunk2[1])
+            f.render_widget(p3, width_chunk3[1])
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn password_strength_counts_length_and_classes() {
+        assert_eq!(Strength::Weak, password_strength("Ab1!").0);
+        assert_eq!(Strength::Weak, password_strength("abcdefghijkl").0);
+        assert_eq!(Strength::Fair, password_strength("abcdefg1").0);
+        assert_eq!(Strength::Strong, password_strength("correct Horse 9").0);
+        assert_eq!(None, password_strength("correct Horse 9").1);
+    }
+}
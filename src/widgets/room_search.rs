@@ -1,4 +1,8 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use matrix_sdk::{
     api::r0::directory::{
@@ -15,7 +19,13 @@ use rumatui_tui::{
     Frame,
 };
 
-use crate::widgets::{rooms::ListState, RenderWidget};
+use crate::{
+    config::SearchNetwork,
+    widgets::{list_state::ListState, RenderWidget},
+};
+
+/// How many recent search terms are remembered.
+const SEARCH_HISTORY_LEN: usize = 20;
 
 #[derive(Clone, Debug, Default)]
 pub struct RoomSearchWidget {
@@ -26,6 +36,20 @@ pub struct RoomSearchWidget {
     list_state: ListTrack,
     search_term: String,
     next_batch_tkn: Option<String>,
+    /// A `RoomSearch` request has been sent and not answered yet.
+    searching: bool,
+    /// At least one response arrived for the current search.
+    searched: bool,
+    /// When the search term last changed, cleared once a search for it is sent.
+    last_edit: Option<Instant>,
+    /// The server whose room directory is searched, `None` is the homeserver.
+    server: Option<String>,
+    /// The networks the search covers.
+    network: SearchNetwork,
+    /// Recent search terms, newest last.
+    history: Vec<String>,
+    /// The history entry shown in the search box while browsing with up and down.
+    history_idx: Option<usize>,
     area: Rect,
 }
 
@@ -54,14 +78,129 @@ impl RoomSearchWidget {
     pub(crate) fn push_search_text(&mut self, ch: char) {
         // TODO only push if it meets criteria?
         self.search_term.push(ch);
+        self.history_idx = None;
+        self.term_changed();
     }
 
     pub(crate) fn pop_search_text(&mut self) {
-        self.search_term.pop();
+        if self.search_term.pop().is_some() {
+            self.history_idx = None;
+            self.term_changed();
+        }
+    }
+
+    pub(crate) fn server(&self) -> Option<&str> {
+        self.server.as_deref()
+    }
+
+    /// Search the room directory of `server` instead of the homeserver's.
+    pub(crate) fn set_server(&mut self, server: Option<String>) {
+        if self.server != server {
+            self.server = server;
+            self.term_changed();
+        }
+    }
+
+    pub(crate) fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub(crate) fn set_network(&mut self, network: SearchNetwork) {
+        self.network = network;
+    }
+
+    pub(crate) fn network(&self) -> RoomNetwork {
+        self.network.into()
+    }
+
+    pub(crate) fn set_history(&mut self, history: Vec<String>) {
+        self.history = history;
+    }
+
+    /// Add the current search term to the history, moving it to the front if it was
+    /// searched for before.
+    pub(crate) fn remember_search(&mut self) {
+        if self.search_term.is_empty() {
+            return;
+        }
+        let term = self.search_term.clone();
+        self.history.retain(|t| *t != term);
+        self.history.push(term);
+        if self.history.len() > SEARCH_HISTORY_LEN {
+            self.history.remove(0);
+        }
+    }
+
+    /// Up and down browse the history when the search box is empty or already
+    /// showing a history entry.
+    pub(crate) fn is_browsing_history(&self) -> bool {
+        !self.history.is_empty() && (self.search_term.is_empty() || self.history_idx.is_some())
+    }
+
+    /// Show the next older search term.
+    pub(crate) fn history_previous(&mut self) {
+        let idx = match self.history_idx {
+            Some(idx) => idx.saturating_sub(1),
+            None => self.history.len().saturating_sub(1),
+        };
+        if let Some(term) = self.history.get(idx) {
+            self.search_term = term.clone();
+            self.history_idx = Some(idx);
+            self.term_changed();
+        }
+    }
+
+    /// Show the next newer search term, past the newest the search box is emptied.
+    pub(crate) fn history_next(&mut self) {
+        if let Some(idx) = self.history_idx {
+            if let Some(term) = self.history.get(idx + 1) {
+                self.search_term = term.clone();
+                self.history_idx = Some(idx + 1);
+            } else {
+                self.search_term.clear();
+                self.history_idx = None;
+            }
+            self.term_changed();
+        }
+    }
+
+    /// The results belong to the old term so they are dropped.
+    fn term_changed(&mut self) {
+        self.last_edit = Some(Instant::now());
+        self.clear_search_result();
+    }
+
+    /// Returns the search term once it has not changed for `delay` and no request
+    /// is in flight.
+    pub(crate) fn debounced_search(&mut self, delay: Duration) -> Option<String> {
+        if self.searching || self.last_edit?.elapsed() < delay {
+            return None;
+        }
+        self.last_edit = None;
+        Some(self.search_term.clone()).filter(|term| !term.is_empty())
     }
 
     pub(crate) fn clear_search_result(&mut self) {
         self.names.clear();
+        self.next_batch_tkn = None;
+        self.searched = false;
+    }
+
+    pub(crate) fn is_searching(&self) -> bool {
+        self.searching
+    }
+
+    pub(crate) fn set_searching(&mut self, searching: bool) {
+        self.searching = searching;
+        if searching {
+            // the current term is being searched for, nothing is left to debounce
+            self.last_edit = None;
+        }
+    }
+
+    /// Returns true once the server has no more results for the current search.
+    pub(crate) fn reached_end(&self) -> bool {
+        self.searched && !self.searching && self.next_batch_tkn.is_none()
     }
 
     pub(crate) fn selected_room(&self) -> Option<RoomId> {
@@ -70,6 +209,8 @@ impl RoomSearchWidget {
 
     pub(crate) fn room_search_results(&mut self, response: get_public_rooms_filtered::Response) {
         self.next_batch_tkn = response.next_batch.clone();
+        self.searching = false;
+        self.searched = true;
         // TODO only push if it meets criteria?
         for room in response.chunk {
             self.names.items.push(room);
@@ -105,11 +246,16 @@ impl RoomSearchWidget {
 
     /// Passes the remembered filter, room network, and since token to make
     /// the room search request again.
+    ///
+    /// Returns `None` at the end of the results or while a request is already in flight.
     pub fn next_request(&mut self) -> Option<(String, RoomNetwork, String)> {
+        if self.searching {
+            return None;
+        }
         if let Some(tkn) = self.next_batch_tkn() {
             Some((
                 self.search_term.to_string(),
-                RoomNetwork::Matrix,
+                self.network.into(),
                 tkn.to_string(),
             ))
         } else {
@@ -194,12 +340,30 @@ impl RenderWidget for RoomSearchWidget {
                     Text::styled(format!(" {}", name), style)
                 }
             })
+            .chain(if self.reached_end() {
+                Some(Text::styled(
+                    " \u{2500}\u{2500} end of results \u{2500}\u{2500}",
+                    Style::default().fg(Color::Gray).modifier(Modifier::DIM),
+                ))
+            } else {
+                None
+            })
             .skip(offset as usize);
+
+        let mut title = match &self.server {
+            Some(server) => format!("Public Rooms on {}", server),
+            None => String::from("Public Rooms"),
+        };
+        if self.searching {
+            title.push_str(&format!(" ({} loaded, searching…)", self.names.items.len()));
+        } else if self.searched {
+            title.push_str(&format!(" ({} loaded)", self.names.items.len()));
+        }
         let list = List::new(items)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title("Public Rooms")
+                    .title(&title)
                     .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
                     .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
             )
@@ -246,3 +410,40 @@ impl RenderWidget for RoomSearchWidget {
         f.render_widget(text_box, chunks[2]);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn search_history_browsing() {
+        let mut widget = RoomSearchWidget::default();
+        for term in &["rust", "matrix", "rust"] {
+            widget.search_term = term.to_string();
+            widget.remember_search();
+        }
+        widget.search_term.clear();
+        assert_eq!(
+            &["matrix".to_string(), "rust".to_string()],
+            widget.history()
+        );
+
+        assert!(widget.is_browsing_history());
+        widget.history_previous();
+        assert_eq!("rust", widget.search_term());
+        widget.history_previous();
+        assert_eq!("matrix", widget.search_term());
+        // the oldest entry stays selected
+        widget.history_previous();
+        assert_eq!("matrix", widget.search_term());
+
+        widget.history_next();
+        assert_eq!("rust", widget.search_term());
+        widget.history_next();
+        assert_eq!("", widget.search_term());
+        assert!(widget.is_browsing_history());
+
+        widget.push_search_text('x');
+        assert!(!widget.is_browsing_history());
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     cell::RefCell,
-    collections::HashMap,
-    ops::{DerefMut, Index, IndexMut},
+    collections::{HashMap, VecDeque},
+    ops::DerefMut,
     rc::Rc,
     sync::Arc,
 };
@@ -18,105 +18,30 @@ use rumatui_tui::{
     widgets::{Block, Borders, List, Paragraph, Text},
     Frame,
 };
-use serde::{Deserialize, Serialize};
 use termion::event::MouseButton;
 use tokio::sync::RwLock;
 
-use crate::widgets::RenderWidget;
+use crate::{
+    client::{
+        ruma_ext::tag::{FAVOURITE, LOW_PRIORITY, SERVER_NOTICE},
+        RoomTags,
+    },
+    widgets::{list_state::ListState, too_small, RenderWidget},
+};
 
 // TODO: Use format_simple to highlight which characters have been matched in the quick-select search
 #[allow(unused_imports)]
 use sublime_fuzzy::{best_match, format_simple};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct ListState<I> {
-    pub items: Vec<I>,
-    pub selected: usize,
-}
-
-impl<I: std::fmt::Debug> Default for ListState<I> {
-    fn default() -> Self {
-        Self::new(Vec::new())
-    }
-}
-
-impl<I: std::fmt::Debug> ListState<I> {
-    pub fn new(items: Vec<I>) -> ListState<I> {
-        ListState { items, selected: 0 }
-    }
-
-    pub fn len(&self) -> usize {
-        self.items.len()
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.items.is_empty()
-    }
-
-    pub(crate) fn clear(&mut self) {
-        self.selected = 0;
-        self.items.clear();
-    }
-
-    /// Scrolls back up the list
-    pub fn select_previous(&mut self) {
-        if self.selected != 0 {
-            self.selected -= 1;
-        }
-    }
-
-    /// Scrolls down the list
-    pub fn select_next(&mut self) {
-        if self.is_empty() {
-            return;
-        }
-        if self.selected < self.len() - 1 {
-            self.selected += 1
-        }
-    }
-    /// Gets the index of the selected item.
-    pub fn selected_idx(&self) -> usize {
-        self.selected
-    }
-
-    pub fn get_selected(&self) -> Option<&I> {
-        self.items.get(self.selected)
-    }
-    pub fn get_selected_mut(&mut self) -> Option<&mut I> {
-        self.items.get_mut(self.selected)
-    }
-
-    pub fn iter(&self) -> impl Iterator<Item = &I> {
-        self.items.iter()
-    }
-}
-
-impl ListState<(String, RoomId)> {
-    pub(crate) fn add_unique(&mut self, name: String, room_id: RoomId) {
-        // TODO not great to clone for a Eq...
-        if !self.items.contains(&(name.clone(), room_id.clone())) {
-            self.items.push((name, room_id));
-        }
-    }
-}
-
-impl<I> Index<usize> for ListState<I> {
-    type Output = I;
-    fn index(&self, idx: usize) -> &Self::Output {
-        &self.items[idx]
-    }
-}
-impl<I> IndexMut<usize> for ListState<I> {
-    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
-        &mut self.items[idx]
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct Invitation {
     pub(crate) room_id: RoomId,
     room_name: String,
     sender: UserId,
+    /// Messages in the room are end to end encrypted.
+    encrypted: bool,
+    /// The topic from the invite's room state.
+    topic: Option<String>,
 }
 
 pub enum Invite {
@@ -136,13 +61,15 @@ pub struct RoomsWidget {
     pub names: ListState<(String, RoomId)>,
     /// Map of room id and matrix_sdk::Room
     pub(crate) rooms: HashMap<RoomId, Arc<RwLock<Room>>>,
-    /// When a user receives an invitation an alert pops up in the `RoomsWidget` pane
-    /// this signals to show that pop up.
-    pub(crate) invite: Option<Invitation>,
+    /// When a user receives an invitation an alert pops up in the `RoomsWidget` pane,
+    /// invitations are shown one at a time oldest first.
+    pub(crate) invites: VecDeque<Invitation>,
     /// Are we filtering (if none: No. If some (even empty string): Yes)
     pub filter_string: Option<String>,
     // For restoring the original names-list after quick-select finished
     names_backup: ListState<(String, RoomId)>,
+    /// The tags of each room, favourites are listed first and low priority rooms last.
+    tags: RoomTags,
 }
 
 impl RoomsWidget {
@@ -170,6 +97,7 @@ impl RoomsWidget {
         }
 
         self.names = ListState::new(items);
+        self.sort_by_tags();
         self.names.items.first().map(|r| &r.1)
     }
 
@@ -183,7 +111,52 @@ impl RoomsWidget {
 
         self.rooms.insert(room_id.clone(), Arc::clone(&room));
 
-        self.names.add_unique(name, room_id)
+        self.names.add_unique(name, room_id);
+        self.sort_by_tags();
+    }
+
+    /// Replace the tags of each room in `tags` and move the rooms to their new group.
+    pub(crate) fn set_tags(&mut self, tags: RoomTags) {
+        self.tags.extend(tags);
+        self.sort_by_tags();
+    }
+
+    pub(crate) fn tags(&self, room_id: &RoomId) -> &[String] {
+        self.tags
+            .get(room_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The room the homeserver sends its notices to, it can not be left.
+    pub(crate) fn is_server_notice(&self, room_id: &RoomId) -> bool {
+        self.tags(room_id).iter().any(|t| t == SERVER_NOTICE)
+    }
+
+    /// Favourites sort first, then untagged rooms, then low priority rooms.
+    fn tag_rank(tags: &RoomTags, room_id: &RoomId) -> u8 {
+        match tags.get(room_id) {
+            Some(tags) if tags.iter().any(|t| t == FAVOURITE) => 0,
+            Some(tags) if tags.iter().any(|t| t == LOW_PRIORITY) => 2,
+            _ => 1,
+        }
+    }
+
+    fn sort_by_tags(&mut self) {
+        let tags = &self.tags;
+        // the quick-select list is rebuilt from the backup when it finishes
+        let names = if self.filter_string.is_some() {
+            &mut self.names_backup
+        } else {
+            &mut self.names
+        };
+        // stable so rooms keep their order within a group
+        names.items.sort_by_key(|(_, id)| Self::tag_rank(tags, id));
+
+        let id = self.current_room.borrow().clone();
+        if let (None, Some(id)) = (&self.filter_string, id) {
+            self.set_room_selected(&id);
+        }
     }
 
     pub(crate) fn remove_room(&mut self, room_id: &RoomId) {
@@ -220,15 +193,43 @@ impl RoomsWidget {
         let r = room.read().await;
         let room_id = r.room_id.clone();
         let room_name = r.display_name();
-        self.invite = Some(Invitation {
+        let encrypted = r.is_encrypted();
+        // the same invite can show up in more than one sync
+        if self.invites.iter().any(|inv| inv.room_id == room_id) {
+            return;
+        }
+        self.invites.push_back(Invitation {
             sender,
             room_id,
             room_name,
+            encrypted,
+            topic: None,
         });
     }
 
+    /// The topic of the room is sent after the invite in the same sync.
+    pub(crate) fn set_invite_topic(&mut self, room_id: &RoomId, topic: String) {
+        if let Some(invite) = self.invites.iter_mut().find(|inv| &inv.room_id == room_id) {
+            invite.topic = Some(topic);
+        }
+    }
+
+    /// The invitation currently being shown.
+    pub(crate) fn invite(&self) -> Option<&Invitation> {
+        self.invites.front()
+    }
+
+    /// Removes the answered invitation and moves on to the next.
     pub(crate) fn remove_invite(&mut self) {
-        self.invite.take();
+        self.invites.pop_front();
+    }
+
+    /// Forget where the list was drawn, used when it is hidden so clicks and scrolls
+    /// no longer land on it.
+    pub(crate) fn clear_areas(&mut self) {
+        self.area = Rect::default();
+        self.yes_area = Rect::default();
+        self.no_area = Rect::default();
     }
 
     pub fn on_click(&mut self, _btn: MouseButton, x: u16, y: u16) -> Invite {
@@ -273,6 +274,15 @@ impl RoomsWidget {
         }
     }
 
+    /// Select the room at `idx` in the list as it is shown, `None` when the list is shorter.
+    pub fn select_nth(&mut self, idx: usize) -> Option<RoomId> {
+        let (_name, id) = self.names.items.get(idx)?;
+        let id = id.clone();
+        self.names.selected = idx;
+        *self.current_room.borrow_mut() = Some(id.clone());
+        Some(id)
+    }
+
     pub fn set_room_selected(&mut self, room_id: &RoomId) {
         if let Some(idx) = self.names.items.iter().position(|(_, id)| room_id == id) {
             self.names.selected = idx;
@@ -357,7 +367,11 @@ impl RenderWidget for RoomsWidget {
     where
         B: Backend,
     {
-        let chunks = if self.invite.is_some() {
+        if too_small(area) {
+            self.clear_areas();
+            return;
+        }
+        let chunks = if !self.invites.is_empty() {
             Layout::default()
                 .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
                 .split(area)
@@ -396,13 +410,28 @@ impl RenderWidget for RoomsWidget {
             .iter()
             .unique_by(|(_, id)| id)
             .enumerate()
-            .map(|(i, (name, _id))| {
+            .map(|(i, (name, id))| {
+                let rank = Self::tag_rank(&self.tags, id);
+                let notices = self.is_server_notice(id);
+                let name = if notices {
+                    format!("\u{26a0} {}", name)
+                } else if rank == 0 {
+                    format!("\u{2605} {}", name)
+                } else {
+                    name.to_string()
+                };
                 if i == selected {
                     let style = Style::default()
                         .bg(highlight_style.bg)
                         .fg(highlight_style.fg)
                         .modifier(highlight_style.modifier);
                     Text::styled(format!("{} {}", highlight_symbol, name), style)
+                } else if notices {
+                    let style = Style::default().fg(Color::Yellow).modifier(Modifier::BOLD);
+                    Text::styled(format!(" {}", name), style)
+                } else if rank == 2 {
+                    let style = Style::default().fg(Color::Gray).modifier(Modifier::DIM);
+                    Text::styled(format!(" {}", name), style)
                 } else {
                     let style = Style::default().fg(Color::Blue);
                     Text::styled(format!(" {}", name), style)
@@ -421,8 +450,17 @@ impl RenderWidget for RoomsWidget {
 
         f.render_widget(list, chunks[0]);
 
-        if let Some(invite) = self.invite.as_ref() {
-            let label_text = format!("Invited to {}", invite.room_name);
+        if let Some(invite) = self.invites.front() {
+            let mut label_text = format!("Invited to {} by {}", invite.room_name, invite.sender);
+            if invite.encrypted {
+                label_text.push_str(" (encrypted)");
+            }
+            if let Some(topic) = invite.topic.as_ref() {
+                label_text.push_str(&format!(": {}", topic.replace('\n', " ")));
+            }
+            if self.invites.len() > 1 {
+                label_text.push_str(&format!(", {} more waiting", self.invites.len() - 1));
+            }
             let label = Block::default().title(&label_text);
             f.render_widget(label, chunks[1]);
 
@@ -471,7 +509,7 @@ impl RenderWidget for RoomsWidget {
             self.no_area = width_chunk2[1];
 
             let t = [Text::styled(
-                "Accept invite",
+                "Accept invite (Alt-a)",
                 Style::default().fg(Color::Cyan),
             )];
             let ok = Paragraph::new(t.iter()).block(yes);
@@ -479,7 +517,7 @@ impl RenderWidget for RoomsWidget {
 
             // Password from here down
             let t2 = [Text::styled(
-                "Decline invite",
+                "Decline invite (Alt-d)",
                 Style::default().fg(Color::Cyan),
             )];
             let nope = Paragraph::new(t2.iter()).block(no);
@@ -511,3 +549,73 @@ impl RenderWidget for RoomsWidget {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn rooms_are_grouped_by_tag() {
+        let ids = [
+            "!a:localhost",
+            "!b:localhost",
+            "!c:localhost",
+            "!d:localhost",
+        ]
+        .iter()
+        .map(|id| RoomId::try_from(*id).unwrap())
+        .collect::<Vec<_>>();
+        let mut widget = RoomsWidget::default();
+        widget.names = ListState::new(ids.iter().map(|id| (id.to_string(), id.clone())).collect());
+
+        let mut tags = RoomTags::new();
+        tags.insert(ids[0].clone(), vec![LOW_PRIORITY.to_string()]);
+        tags.insert(ids[2].clone(), vec![FAVOURITE.to_string()]);
+        widget.set_tags(tags);
+
+        let order = widget
+            .names
+            .iter()
+            .map(|(_, id)| id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ids[2].clone(),
+                ids[1].clone(),
+                ids[3].clone(),
+                ids[0].clone()
+            ],
+            order
+        );
+
+        // Alt-1 goes to the first room shown
+        assert_eq!(Some(ids[2].clone()), widget.select_nth(0));
+        assert_eq!(None, widget.select_nth(4));
+        assert_eq!(0, widget.names.selected_idx());
+    }
+
+    #[test]
+    fn invites_get_the_topic_of_their_room() {
+        let room_id = RoomId::try_from("!a:localhost").unwrap();
+        let mut widget = RoomsWidget::default();
+        widget.invites.push_back(Invitation {
+            room_id: room_id.clone(),
+            room_name: "a".to_string(),
+            sender: UserId::try_from("@example:localhost").unwrap(),
+            encrypted: false,
+            topic: None,
+        });
+
+        let other = RoomId::try_from("!b:localhost").unwrap();
+        widget.set_invite_topic(&other, "not this one".to_string());
+        assert_eq!(None, widget.invite().unwrap().topic);
+
+        widget.set_invite_topic(&room_id, "all about a".to_string());
+        assert_eq!(
+            Some("all about a"),
+            widget.invite().unwrap().topic.as_deref()
+        );
+    }
+}
@@ -1,14 +1,33 @@
 use std::{
+    convert::TryFrom,
     fmt::{self, Display},
     io::{self, ErrorKind, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
 };
 
+use chrono::{DateTime, Duration, Local, NaiveDate};
 use comrak;
+use itertools::Itertools;
+use matrix_sdk::{
+    events::room::message::{
+        EmoteMessageEventContent, FormattedBody, ImageMessageEventContent,
+        LocationMessageEventContent, MessageEventContent, ServerNoticeMessageEventContent,
+        TextMessageEventContent,
+    },
+    identifiers::{EventId, RoomIdOrAliasId, UserId},
+};
 use mdcat::{self, ResourceAccess, Settings, TerminalCapabilities, TerminalSize};
 use pulldown_cmark::{Options, Parser};
+use rumatui_tui::buffer::str_width;
 use syntect::parsing::SyntaxSet;
 
-use crate::error::{Error, Result};
+use crate::{
+    client::event_stream::MemberProfile,
+    error::{Error, Result},
+    widgets::message::Poll,
+};
 
 #[derive(Default)]
 pub struct Writer(Vec<u8>);
@@ -34,19 +53,78 @@ impl Display for Writer {
     }
 }
 
-pub(crate) fn markdown_to_terminal(input: &str) -> Result<String> {
+/// The width of the messages pane inside its borders, 0 until it is first drawn.
+static MESSAGE_PANE_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Remember the width of the messages pane inside its borders, set every time it is drawn
+/// so messages rendered by the client task fit it too.
+pub(crate) fn set_message_pane_width(width: u16) {
+    MESSAGE_PANE_WIDTH.store(usize::from(width), Ordering::Relaxed);
+}
+
+/// The width markdown is laid out for, the messages pane inside its borders or the
+/// terminal's width before the pane is drawn.
+pub(crate) fn message_pane_width() -> usize {
+    match MESSAGE_PANE_WIDTH.load(Ordering::Relaxed) {
+        0 => TerminalSize::detect().map_or(80, |size| size.width.saturating_sub(2)),
+        width => width,
+    }
+}
+
+/// A block of a markdown message that mdcat renders poorly so it is laid out by hand.
+#[derive(Clone, Debug, PartialEq)]
+enum MdBlock {
+    Markdown(String),
+    Table(Vec<Vec<String>>),
+    List(Vec<ListItem>),
+}
+
+/// One item of a possibly nested markdown list.
+#[derive(Clone, Debug, PartialEq)]
+struct ListItem {
+    depth: usize,
+    /// The number of an ordered list item.
+    number: Option<usize>,
+    text: String,
+}
+
+/// Render markdown for the terminal, laid out to fit `width` columns.
+pub(crate) fn markdown_to_terminal(input: &str, width: usize) -> Result<String> {
+    let mut out = String::new();
+    for block in split_md_blocks(input) {
+        match block {
+            MdBlock::Markdown(md) => out.push_str(&mdcat_to_terminal(&md, width)?),
+            MdBlock::Table(rows) => {
+                out.push_str(&render_table(&rows, width));
+                out.push('\n');
+            }
+            MdBlock::List(mut items) => {
+                for item in items.iter_mut() {
+                    // keep inline formatting like `code` and **bold** in list items
+                    item.text = mdcat_to_terminal(&item.text, width)?.trim().to_string();
+                }
+                out.push_str(&render_list(&items));
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn mdcat_to_terminal(input: &str, width: usize) -> Result<String> {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_TASKLISTS);
     options.insert(Options::ENABLE_STRIKETHROUGH);
     let parser = Parser::new_ext(&input, options);
     let syntax_set = SyntaxSet::load_defaults_nonewlines();
 
+    let size = TerminalSize::detect().ok_or(Error::from(io::Error::new(
+        ErrorKind::Other,
+        "could not detect terminal",
+    )))?;
     let settings = Settings {
         terminal_capabilities: TerminalCapabilities::detect(),
-        terminal_size: TerminalSize::detect().ok_or(Error::from(io::Error::new(
-            ErrorKind::Other,
-            "could not detect terminal",
-        )))?,
+        terminal_size: TerminalSize { width, ..size },
         resource_access: ResourceAccess::LocalOnly,
         syntax_set,
     };
@@ -57,6 +135,866 @@ pub(crate) fn markdown_to_terminal(input: &str) -> Result<String> {
     Ok(w.to_string())
 }
 
+/// Split markdown into tables, lists and everything else, code blocks are never split.
+fn split_md_blocks(input: &str) -> Vec<MdBlock> {
+    let lines = input.lines().collect::<Vec<_>>();
+    let mut blocks = vec![];
+    let mut md = String::new();
+    let mut in_code = false;
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if !in_code {
+            let is_table = line.contains('|')
+                && lines
+                    .get(idx + 1)
+                    .map_or(false, |next| is_table_separator(next));
+            if is_table {
+                let mut rows = vec![table_cells(line)];
+                idx += 2;
+                while idx < lines.len() && lines[idx].contains('|') {
+                    rows.push(table_cells(lines[idx]));
+                    idx += 1;
+                }
+                if !md.is_empty() {
+                    blocks.push(MdBlock::Markdown(std::mem::take(&mut md)));
+                }
+                blocks.push(MdBlock::Table(rows));
+                continue;
+            }
+            if list_item(line).is_some() {
+                let mut items = vec![];
+                while let Some(item) = lines.get(idx).and_then(|l| list_item(l)) {
+                    items.push(item);
+                    idx += 1;
+                }
+                if !md.is_empty() {
+                    blocks.push(MdBlock::Markdown(std::mem::take(&mut md)));
+                }
+                blocks.push(MdBlock::List(items));
+                continue;
+            }
+        }
+        md.push_str(line);
+        md.push('\n');
+        idx += 1;
+    }
+    if !md.is_empty() {
+        blocks.push(MdBlock::Markdown(md));
+    }
+    blocks
+}
+
+/// Is this the `|---|:---:|` line that follows the header of a table.
+fn is_table_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.contains('-')
+        && line
+            .chars()
+            .all(|c| c == '|' || c == '-' || c == ':' || c == ' ')
+}
+
+fn table_cells(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|')
+        .map(|cell| cell.trim().to_string())
+        .collect()
+}
+
+/// Parse a `- item`, `* item`, `+ item` or `1. item` line, each two spaces of
+/// indent nests the item one level deeper.
+fn list_item(line: &str) -> Option<ListItem> {
+    let trimmed = line.trim_start();
+    let depth = (line.len() - trimmed.len()) / 2;
+    if let Some(text) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| trimmed.strip_prefix(bullet))
+    {
+        return Some(ListItem {
+            depth,
+            number: None,
+            text: text.trim().to_string(),
+        });
+    }
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let text = trimmed[digits..].strip_prefix(". ")?;
+    Some(ListItem {
+        depth,
+        number: trimmed[..digits].parse().ok(),
+        text: text.trim().to_string(),
+    })
+}
+
+/// Lay out a list with a different bullet for each level of nesting.
+fn render_list(items: &[ListItem]) -> String {
+    const BULLETS: [char; 3] = ['\u{2022}', '\u{25e6}', '\u{25aa}'];
+    items
+        .iter()
+        .map(|item| {
+            let indent = "  ".repeat(item.depth);
+            let marker = match item.number {
+                Some(num) => format!("{}.", num),
+                None => BULLETS[item.depth % BULLETS.len()].to_string(),
+            };
+            // continuation lines line up with the text not the bullet
+            let hang = " ".repeat(indent.len() + marker.chars().count() + 1);
+            let text = item.text.lines().join(&format!("\n{}", hang));
+            format!("{}{} {}", indent, marker, text)
+        })
+        .join("\n")
+}
+
+/// Draw a table with box drawing characters, columns are shrunk to fit `width`.
+fn render_table(rows: &[Vec<String>], width: usize) -> String {
+    let cols = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if cols == 0 {
+        return String::new();
+    }
+    let mut widths = (0..cols)
+        .map(|col| {
+            rows.iter()
+                .filter_map(|row| row.get(col))
+                .map(|cell| str_width(cell))
+                .max()
+                .unwrap_or(0)
+                .max(1)
+        })
+        .collect::<Vec<_>>();
+    // each column has a space of padding either side and a border
+    let overhead = cols * 3 + 1;
+    while widths.iter().sum::<usize>() + overhead > width {
+        let (widest, w) = widths
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by_key(|(_, w)| *w)
+            .unwrap();
+        if w <= 3 {
+            break;
+        }
+        widths[widest] -= 1;
+    }
+
+    let border = |left: char, mid: char, right: char| {
+        let inner = widths
+            .iter()
+            .map(|w| "\u{2500}".repeat(w + 2))
+            .join(&mid.to_string());
+        format!("{}{}{}", left, inner, right)
+    };
+    let row_line = |row: &Vec<String>| {
+        let cells = widths
+            .iter()
+            .enumerate()
+            .map(|(col, w)| {
+                let cell = row.get(col).map(|c| c.as_str()).unwrap_or("");
+                // pad and cut by the columns a cell takes, wide characters take two
+                let cell = if str_width(cell) > *w {
+                    let mut cut = String::new();
+                    for c in cell.chars() {
+                        if str_width(&cut) + str_width(c.encode_utf8(&mut [0; 4])) > w - 1 {
+                            break;
+                        }
+                        cut.push(c);
+                    }
+                    format!("{}\u{2026}", cut)
+                } else {
+                    cell.to_string()
+                };
+                let pad = w.saturating_sub(str_width(&cell));
+                format!(" {}{} ", cell, " ".repeat(pad))
+            })
+            .join("\u{2502}");
+        format!("\u{2502}{}\u{2502}", cells)
+    };
+
+    let mut lines = vec![border('\u{250c}', '\u{252c}', '\u{2510}')];
+    for (idx, row) in rows.iter().enumerate() {
+        lines.push(row_line(row));
+        // the first row is the header
+        if idx == 0 && rows.len() > 1 {
+            lines.push(border('\u{251c}', '\u{253c}', '\u{2524}'));
+        }
+    }
+    lines.push(border('\u{2514}', '\u{2534}', '\u{2518}'));
+    lines.join("\n")
+}
+
 pub(crate) fn markdown_to_html(input: &str) -> String {
     comrak::markdown_to_html(input, &comrak::ComrakOptions::default())
 }
+
+/// Describe what changed between two member events of `user`, `None` if neither the
+/// display name nor the avatar did.
+pub(crate) fn profile_change_notice(
+    user: &UserId,
+    prev: Option<&MemberProfile>,
+    new: &MemberProfile,
+) -> Option<String> {
+    let prev = prev.cloned().unwrap_or_default();
+    let name = prev
+        .displayname
+        .clone()
+        .unwrap_or_else(|| user.localpart().to_string());
+
+    let mut changes = vec![];
+    if prev.displayname != new.displayname {
+        changes.push(match &new.displayname {
+            Some(new_name) if prev.displayname.is_some() => {
+                format!("changed their display name to {}", new_name)
+            }
+            Some(new_name) => format!("set their display name to {}", new_name),
+            None => String::from("removed their display name"),
+        });
+    }
+    if prev.avatar_url != new.avatar_url {
+        changes.push(if new.avatar_url.is_some() {
+            String::from("changed their avatar")
+        } else {
+            String::from("removed their avatar")
+        });
+    }
+
+    if changes.is_empty() {
+        None
+    } else {
+        Some(format!("{} {}", name, changes.join(" and ")))
+    }
+}
+
+/// Does `text` mention the user either by their full user id or their display name.
+pub(crate) fn mentions_user(text: &str, user: &UserId, display_name: Option<&str>) -> bool {
+    let text = text.to_lowercase();
+    if text.contains(&user.to_string().to_lowercase()) {
+        return true;
+    }
+    display_name
+        .filter(|name| !name.trim().is_empty())
+        .map(|name| text.contains(&name.to_lowercase()))
+        .unwrap_or(false)
+}
+
+/// Complete `word` to the candidate it starts, or to the longest prefix shared by
+/// every candidate it starts. `None` when nothing longer than `word` fits.
+pub(crate) fn complete_word<'a>(
+    word: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    let mut matches = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word));
+    let mut prefix = matches.next()?.to_string();
+    for candidate in matches {
+        let common = prefix
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.len()), |((idx, _), _)| idx);
+        prefix.truncate(common);
+    }
+    Some(prefix).filter(|prefix| prefix.len() > word.len())
+}
+
+/// The text shown for a message, `None` for the kinds of message rumatui does not show.
+pub(crate) fn message_text(content: &MessageEventContent) -> Option<String> {
+    Some(match content {
+        MessageEventContent::Text(TextMessageEventContent {
+            body, formatted, ..
+        }) => formatted_text(body, formatted.as_ref()),
+        MessageEventContent::Emote(EmoteMessageEventContent { body, formatted }) => {
+            formatted_text(body, formatted.as_ref())
+        }
+        MessageEventContent::ServerNotice(ServerNoticeMessageEventContent {
+            body,
+            admin_contact,
+            ..
+        }) => server_notice_text(body, admin_contact.as_deref()),
+        MessageEventContent::Location(LocationMessageEventContent { body, geo_uri, .. }) => {
+            location_text(body, geo_uri)
+        }
+        // the picture can not be drawn in the terminal
+        MessageEventContent::Image(ImageMessageEventContent { body, .. }) => {
+            format!("[image: {}]", body)
+        }
+        _ => return None,
+    })
+}
+
+/// The body of a text or emote message, rendered as markdown when it was sent
+/// with a formatted body.
+fn formatted_text(body: &str, formatted: Option<&FormattedBody>) -> String {
+    if formatted.map_or(body, |f| f.body.as_str()) != body {
+        // This is extremely expensive
+        // TODO cache these results somehow
+        markdown_to_terminal(body, message_pane_width()).unwrap_or_else(|_| body.to_string())
+    } else {
+        body.to_string()
+    }
+}
+
+/// Split a send box command like `/kick @user:server reason` into the command name,
+/// its first argument and the rest, `None` when the input is not a command.
+pub(crate) fn split_command(input: &str) -> Option<(&str, Option<&str>, Option<&str>)> {
+    let input = input.trim().strip_prefix('/')?;
+    let mut parts = input.splitn(2, char::is_whitespace);
+    let command = parts.next().filter(|command| !command.is_empty())?;
+    let mut args = parts
+        .next()
+        .unwrap_or_default()
+        .trim_start()
+        .splitn(2, char::is_whitespace);
+    let target = args.next().filter(|target| !target.is_empty());
+    let rest = args.next().map(str::trim).filter(|rest| !rest.is_empty());
+    Some((command, target, rest))
+}
+
+/// A command typed in the send box in place of a message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SendCommand<'a> {
+    /// `/image <path>` uploads and sends the image at the path.
+    Image(Option<&'a str>),
+    /// `/kick <user id> [reason]` kicks the member out of the room.
+    Kick(Option<&'a str>, Option<&'a str>),
+    /// `/me <action>` sends the action as an emote.
+    Emote(&'a str),
+}
+
+/// Parse the send box commands, `None` when the input is a message to send as is.
+pub(crate) fn send_command(input: &str) -> Option<SendCommand<'_>> {
+    let (command, target, rest) = split_command(input)?;
+    // a path or an action keeps its spaces, it is everything after the command
+    let args = input.trim()[1 + command.len()..].trim_start();
+    match command {
+        "image" => Some(SendCommand::Image(target.map(|_| args))),
+        "kick" => Some(SendCommand::Kick(target, rest)),
+        "me" => target.map(|_| SendCommand::Emote(args)),
+        _ => None,
+    }
+}
+
+/// A path typed by the user, a leading `~` is the home directory.
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The text shown for a shared location, the geo URI can be opened as a link.
+pub(crate) fn location_text(body: &str, geo_uri: &str) -> String {
+    format!("[location] {} \u{2192} {}", body, geo_uri)
+}
+
+/// A map of the point in a `geo:lat,lon` URI that a browser can open.
+pub(crate) fn geo_map_url(geo_uri: &str) -> Option<String> {
+    let coords = geo_uri.strip_prefix("geo:")?.split(';').next()?;
+    let mut coords = coords.split(',');
+    let lat = coords.next()?.trim().parse::<f64>().ok()?;
+    let lon = coords.next()?.trim().parse::<f64>().ok()?;
+    if lat.abs() > 90.0 || lon.abs() > 180.0 {
+        return None;
+    }
+    Some(format!(
+        "https://www.openstreetmap.org/?mlat={}&mlon={}",
+        lat, lon
+    ))
+}
+
+/// The text of a notice from the homeserver administrators, with who to contact
+/// about it when the server says.
+pub(crate) fn server_notice_text(body: &str, admin_contact: Option<&str>) -> String {
+    match admin_contact {
+        Some(contact) => format!("[server notice] {}\ncontact: {}", body, contact),
+        None => format!("[server notice] {}", body),
+    }
+}
+
+/// The text shown in place of a sticker, the terminal can't draw the image so its
+/// `mxc://` URL can be opened as a link.
+pub(crate) fn sticker_text(body: &str, url: &str) -> String {
+    format!("[sticker] {} \u{2192} {}", body, url)
+}
+
+/// The homeserver's download URL of an `mxc://server/media_id` URI that a browser can open.
+pub(crate) fn media_url(mxc_uri: &str, homeserver: &str) -> Option<String> {
+    let mut parts = mxc_uri.strip_prefix("mxc://")?.splitn(2, '/');
+    let (server, media_id) = (parts.next()?, parts.next()?);
+    if server.is_empty() || media_id.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}/_matrix/media/r0/download/{}/{}",
+        homeserver.trim_end_matches('/'),
+        server,
+        media_id
+    ))
+}
+
+/// The question and numbered answers of a poll with how many votes each answer has,
+/// the answers `me` voted for are marked.
+pub(crate) fn poll_text(poll: &Poll, me: Option<&UserId>) -> String {
+    let header = if poll.closed {
+        "[poll ended]"
+    } else {
+        "[poll]"
+    };
+    let mine = me.and_then(|me| poll.votes.get(me));
+    let mut text = format!("{} {}\n", header, poll.question);
+    for (idx, (id, answer)) in poll.answers.iter().enumerate() {
+        text.push_str(&format!("\u{2800}   {}. {}", idx + 1, answer));
+        // undisclosed polls keep the results hidden until the poll ends
+        if poll.disclosed || poll.closed {
+            text.push_str(&format!(" ({})", poll.tally(id)));
+        }
+        if mine.map_or(false, |selections| selections.contains(id)) {
+            text.push_str(" \u{2714}");
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// A link to a matrix room, user or event from a `https://matrix.to` or `matrix:` URI.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum MatrixLink {
+    Room(RoomIdOrAliasId),
+    User(UserId),
+    /// A permalink to an event in a room.
+    Event(RoomIdOrAliasId, EventId),
+}
+
+/// Find the links in a message, both plain and the OSC 8 links mdcat creates.
+pub(crate) fn find_links(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || c == '\u{1b}' || c == '\u{7}')
+        .map(|word| word.trim_start_matches("]8;;"))
+        .map(|word| word.trim_end_matches(|c: char| ",.;:!?)>\"'".contains(c)))
+        .filter(|word| {
+            word.starts_with("https://")
+                || word.starts_with("http://")
+                || word.starts_with("matrix:")
+                || word.starts_with("geo:")
+                || word.starts_with("mxc://")
+        })
+        .map(|word| word.to_string())
+        .unique()
+        .collect()
+}
+
+/// Parse a `https://matrix.to/#/...` or `matrix:` URI into the room, user or event it points to.
+pub(crate) fn matrix_link(url: &str) -> Option<MatrixLink> {
+    let (id, event) = if let Some(rest) = url
+        .strip_prefix("https://matrix.to/#/")
+        .or_else(|| url.strip_prefix("http://matrix.to/#/"))
+    {
+        // an event id can follow the room `!room:server/$event`
+        let mut parts = rest.split('?').next()?.splitn(2, '/');
+        let id = percent_decode(parts.next()?);
+        (id, parts.next().map(percent_decode))
+    } else {
+        let rest = url.strip_prefix("matrix:")?;
+        let rest = rest.split('?').next()?;
+        let mut parts = rest.splitn(4, '/');
+        let sigil = match parts.next()? {
+            "r" => '#',
+            "roomid" => '!',
+            "u" => '@',
+            _ => return None,
+        };
+        let id = format!("{}{}", sigil, percent_decode(parts.next()?));
+        // `matrix:roomid/room:server/e/event`
+        let event = match (parts.next(), parts.next()) {
+            (Some("e"), Some(event)) => Some(format!("${}", percent_decode(event))),
+            _ => None,
+        };
+        (id, event)
+    };
+
+    if id.starts_with('@') {
+        UserId::try_from(id.as_str()).ok().map(MatrixLink::User)
+    } else {
+        let room = RoomIdOrAliasId::try_from(id.as_str()).ok()?;
+        match event.and_then(|ev| EventId::try_from(ev.as_str()).ok()) {
+            Some(event) => Some(MatrixLink::Event(room, event)),
+            None => Some(MatrixLink::Room(room)),
+        }
+    }
+}
+
+/// Decode the `%XX` escapes used in matrix.to links.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' {
+            if let Some(byte) = input
+                .get(idx + 1..idx + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                out.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Ring the terminal bell.
+pub(crate) fn ring_bell() {
+    let mut stdout = io::stdout();
+    if let Err(e) = stdout.write_all(b"\x07").and_then(|_| stdout.flush()) {
+        tracing::warn!("failed to ring the terminal bell {}", e);
+    }
+}
+
+/// The time a message was sent in local time, `format` was checked by `Configs::time_format`.
+pub(crate) fn format_time(time: SystemTime, format: &str) -> String {
+    DateTime::<Local>::from(time).format(format).to_string()
+}
+
+/// The day a message was sent in local time.
+pub(crate) fn local_day(time: SystemTime) -> NaiveDate {
+    DateTime::<Local>::from(time).date().naive_local()
+}
+
+/// The line shown between messages sent on different days, the day is centered in a
+/// divider `width` columns wide.
+pub(crate) fn date_separator(day: NaiveDate, today: NaiveDate, width: usize) -> String {
+    let day = if day == today {
+        "Today".to_string()
+    } else if Some(day) == today.checked_sub_signed(Duration::days(1)) {
+        "Yesterday".to_string()
+    } else {
+        day.format("%Y-%m-%d").to_string()
+    };
+    let label = format!(" {} ", day);
+    let fill = width.saturating_sub(label.len()).max(6);
+    format!(
+        "{}{}{}\n",
+        "\u{2500}".repeat(fill / 2),
+        label,
+        "\u{2500}".repeat(fill - fill / 2)
+    )
+}
+
+/// The escape code that sets the terminal window title, control characters in a room
+/// name could end the sequence early so they are dropped.
+pub(crate) fn window_title_escape(title: &str) -> String {
+    let title = title
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>();
+    format!("\x1b]0;{}\x07", title)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn words_complete_to_the_shared_prefix() {
+        let commands = ["pin", "pinned", "passwd", "unpin"];
+        assert_eq!(
+            Some("pin".to_string()),
+            complete_word("pi", commands.iter().copied())
+        );
+        assert_eq!(
+            Some("passwd".to_string()),
+            complete_word("pa", commands.iter().copied())
+        );
+        // `pin` is already as far as both go
+        assert_eq!(None, complete_word("pin", commands.iter().copied()));
+        assert_eq!(None, complete_word("x", commands.iter().copied()));
+    }
+
+    #[test]
+    fn commands_split_into_target_and_reason() {
+        assert_eq!(
+            Some(("kick", Some("@user:server.org"), Some("being rude"))),
+            split_command("/kick @user:server.org being rude")
+        );
+        assert_eq!(
+            Some(("kick", Some("@user:server.org"), Some("being   very rude"))),
+            split_command("  /kick   @user:server.org  being   very rude ")
+        );
+        assert_eq!(
+            Some(("kick", Some("@user:server.org"), None)),
+            split_command("/kick @user:server.org")
+        );
+        assert_eq!(Some(("kick", None, None)), split_command("/kick"));
+        assert_eq!(None, split_command("kick @user:server.org"));
+        assert_eq!(None, split_command("/"));
+    }
+
+    #[test]
+    fn send_box_commands_are_dispatched() {
+        assert_eq!(
+            Some(SendCommand::Image(Some("~/my pictures/cat.png"))),
+            send_command("/image ~/my pictures/cat.png")
+        );
+        assert_eq!(Some(SendCommand::Image(None)), send_command("/image"));
+        assert_eq!(
+            Some(SendCommand::Kick(
+                Some("@user:server.org"),
+                Some("being rude")
+            )),
+            send_command("/kick @user:server.org being rude")
+        );
+        assert_eq!(
+            Some(SendCommand::Emote("runs  `cargo test`")),
+            send_command("/me runs  `cargo test`")
+        );
+        // without an action it is sent as a message
+        assert_eq!(None, send_command("/me"));
+        assert_eq!(None, send_command("/shrug"));
+        assert_eq!(None, send_command("hello"));
+    }
+
+    #[test]
+    fn home_is_expanded_in_paths() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(home.join("pics/cat.png"), expand_home("~/pics/cat.png"));
+        assert_eq!(PathBuf::from("/tmp/cat.png"), expand_home("/tmp/cat.png"));
+        assert_eq!(PathBuf::from("cat~/x.png"), expand_home("cat~/x.png"));
+    }
+
+    #[test]
+    fn locations_open_as_a_map() {
+        let text = location_text("the office", "geo:51.5008,0.1247;u=35");
+        assert_eq!(
+            vec!["geo:51.5008,0.1247;u=35".to_string()],
+            find_links(&text)
+        );
+        assert_eq!(
+            Some("https://www.openstreetmap.org/?mlat=51.5008&mlon=0.1247".to_string()),
+            geo_map_url("geo:51.5008,0.1247;u=35")
+        );
+        assert_eq!(None, geo_map_url("geo:91,0"));
+        assert_eq!(None, geo_map_url("geo:somewhere"));
+    }
+
+    #[test]
+    fn stickers_open_from_the_homeserver() {
+        let text = sticker_text("a cat", "mxc://localhost/AQwafuaFswefuhsfAFAgsw");
+        assert_eq!(
+            vec!["mxc://localhost/AQwafuaFswefuhsfAFAgsw".to_string()],
+            find_links(&text)
+        );
+        assert_eq!(
+            Some(
+                "https://matrix.org/_matrix/media/r0/download/localhost/AQwafuaFswefuhsfAFAgsw"
+                    .to_string()
+            ),
+            media_url(
+                "mxc://localhost/AQwafuaFswefuhsfAFAgsw",
+                "https://matrix.org/"
+            )
+        );
+        assert_eq!(None, media_url("mxc://localhost", "https://matrix.org"));
+    }
+
+    #[test]
+    fn server_notices_name_the_admin_contact() {
+        assert_eq!(
+            "[server notice] over quota",
+            server_notice_text("over quota", None)
+        );
+        assert_eq!(
+            "[server notice] over quota\ncontact: mailto:admin@example.org",
+            server_notice_text("over quota", Some("mailto:admin@example.org"))
+        );
+    }
+
+    #[test]
+    fn date_separators_name_recent_days() {
+        let today = NaiveDate::from_ymd(2024, 3, 2);
+        assert_eq!(
+            "\u{2500}\u{2500}\u{2500} Today \u{2500}\u{2500}\u{2500}\u{2500}\n",
+            date_separator(today, today, 14)
+        );
+        // a narrow box still gets some of the line
+        assert_eq!(
+            "\u{2500}\u{2500}\u{2500} Yesterday \u{2500}\u{2500}\u{2500}\n",
+            date_separator(NaiveDate::from_ymd(2024, 3, 1), today, 0)
+        );
+        let divider = date_separator(NaiveDate::from_ymd(2024, 2, 29), today, 80);
+        assert!(divider.contains(" 2024-02-29 "));
+        assert_eq!(81, divider.chars().count());
+    }
+
+    #[test]
+    fn window_title_drops_control_characters() {
+        assert_eq!(
+            "\x1b]0;rumatui: room\x07",
+            window_title_escape("rumatui: room")
+        );
+        assert_eq!(
+            "\x1b]0;rumatui: bad]0;room\x07",
+            window_title_escape("rumatui: bad\x07\x1b]0;room")
+        );
+    }
+
+    #[test]
+    fn table_golden() {
+        let input = "| name | value |\n|------|:-----:|\n| one | 1 |\n| two | 2 |";
+        let blocks = split_md_blocks(input);
+        let rows = match &blocks[..] {
+            [MdBlock::Table(rows)] => rows,
+            other => panic!("expected a table found {:?}", other),
+        };
+
+        let expected = "\
+┌──────┬───────┐
+│ name │ value │
+├──────┼───────┤
+│ one  │ 1     │
+│ two  │ 2     │
+└──────┴───────┘";
+        assert_eq!(expected, render_table(rows, 80));
+    }
+
+    #[test]
+    fn narrow_table_is_shrunk() {
+        let rows = vec![vec!["a long header".to_string(), "b".to_string()]];
+        let expected = "\
+┌─────────┬───┐
+│ a long… │ b │
+└─────────┴───┘";
+        assert_eq!(expected, render_table(&rows, 15));
+    }
+
+    #[test]
+    fn wide_table_cells_line_up() {
+        let rows = vec![
+            vec!["emoji".to_string(), "name".to_string()],
+            vec!["\u{1f600}".to_string(), "grin".to_string()],
+        ];
+        let expected = "\
+┌───────┬──────┐
+│ emoji │ name │
+├───────┼──────┤
+│ \u{1f600}    │ grin │
+└───────┴──────┘";
+        assert_eq!(expected, render_table(&rows, 80));
+    }
+
+    #[test]
+    fn a_dot_without_a_number_is_not_a_list() {
+        assert_eq!(None, list_item(". not a list"));
+        assert_eq!(Some(1), list_item("1. first").and_then(|item| item.number));
+    }
+
+    #[test]
+    fn nested_list_golden() {
+        let input = "- one\n  - nested\n    - deeper\n- two\n1. first\n2. second";
+        let blocks = split_md_blocks(input);
+        let items = match &blocks[..] {
+            [MdBlock::List(items)] => items,
+            other => panic!("expected a list found {:?}", other),
+        };
+
+        let expected = "\
+\u{2022} one
+  \u{25e6} nested
+    \u{25aa} deeper
+\u{2022} two
+1. first
+2. second";
+        assert_eq!(expected, render_list(items));
+    }
+
+    #[test]
+    fn profile_changes_are_described() {
+        let user = UserId::try_from("@example:localhost").unwrap();
+        let profile = |name: Option<&str>, avatar: Option<&str>| MemberProfile {
+            displayname: name.map(ToString::to_string),
+            avatar_url: avatar.map(ToString::to_string),
+        };
+
+        let prev = profile(Some("Old"), Some("mxc://localhost/a"));
+        assert_eq!(
+            Some("Old changed their display name to New".to_string()),
+            profile_change_notice(
+                &user,
+                Some(&prev),
+                &profile(Some("New"), Some("mxc://localhost/a"))
+            )
+        );
+        assert_eq!(
+            Some("Old removed their display name and changed their avatar".to_string()),
+            profile_change_notice(
+                &user,
+                Some(&prev),
+                &profile(None, Some("mxc://localhost/b"))
+            )
+        );
+        assert_eq!(
+            Some("example set their display name to New".to_string()),
+            profile_change_notice(&user, None, &profile(Some("New"), None))
+        );
+        assert_eq!(None, profile_change_notice(&user, Some(&prev), &prev));
+    }
+
+    #[test]
+    fn parse_matrix_links() {
+        assert_eq!(
+            Some(MatrixLink::Room(
+                RoomIdOrAliasId::try_from("#rumatui:matrix.org").unwrap()
+            )),
+            matrix_link("https://matrix.to/#/%23rumatui%3Amatrix.org")
+        );
+        assert_eq!(
+            Some(MatrixLink::Event(
+                RoomIdOrAliasId::try_from("!room:matrix.org").unwrap(),
+                EventId::try_from("$event:matrix.org").unwrap()
+            )),
+            matrix_link("https://matrix.to/#/!room:matrix.org/$event:matrix.org?via=matrix.org")
+        );
+        assert_eq!(
+            Some(MatrixLink::Event(
+                RoomIdOrAliasId::try_from("!room:matrix.org").unwrap(),
+                EventId::try_from("$event:matrix.org").unwrap()
+            )),
+            matrix_link("matrix:roomid/room:matrix.org/e/event:matrix.org?via=matrix.org")
+        );
+        assert_eq!(
+            Some(MatrixLink::User(
+                UserId::try_from("@devin:matrix.org").unwrap()
+            )),
+            matrix_link("matrix:u/devin:matrix.org?action=chat")
+        );
+        assert_eq!(None, matrix_link("https://github.com/DevinR528/rumatui"));
+    }
+
+    #[test]
+    fn find_plain_and_osc8_links() {
+        let text = "see https://matrix.to/#/#rumatui:matrix.org, or \u{1b}]8;;https://example.com/\u{7}example\u{1b}]8;;\u{7}";
+        assert_eq!(
+            vec![
+                "https://matrix.to/#/#rumatui:matrix.org".to_string(),
+                "https://example.com/".to_string(),
+            ],
+            find_links(text)
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_not_split() {
+        let input = "```\n- not a list\n| a | b |\n|---|---|\n```";
+        assert_eq!(
+            vec![MdBlock::Markdown(format!("{}\n", input))],
+            split_md_blocks(input)
+        );
+    }
+}